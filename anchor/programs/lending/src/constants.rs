@@ -4,4 +4,123 @@ use anchor_lang::prelude::*;
 // https://pyth.network/developers/price-feed-ids#solana-stable
 pub const SOL_USD_FEED_ID: &str = "0xef0d8b6fda2ceba41da15d4095d1da392a0d2f8ed0c6c7bc0f4cfac8c280b56d";
 pub const USDC_USD_FEED_ID: &str = "0xeaa020c61cc479712813461ce153894a96a6c00b21ed0cfc2798d1f9a9e9c94a";
-pub const MAXIMUM_AGE: u64 = 100; // allow price feed 100 sec old, to avoid stale price feed errors
\ No newline at end of file
+pub const BPS_DENOMINATOR: u64 = 10_000;
+pub const SECONDS_PER_YEAR: u64 = 31_536_000;
+pub const MAX_ACCRUAL_INTERVAL_SECS: i64 = 3_600; // longest a bank may skip interest accruals for
+pub const SLOTS_PER_YEAR: u64 = 78_840_000; // at the 400ms target slot time, for per-slot compounding
+// USD values are normalized to 18 decimals so amounts of mints with different decimals can be compared
+// without losing the precision of small positions
+pub const VALUE_DECIMALS: u32 = 18;
+pub const MAX_LIQUIDATION_BONUS: u64 = 2_500; // liquidators can receive at most 25% on top of the repaid value
+// Operations that can be paused per bank, combined as a bitmask in `Bank::paused_operations`
+pub const PAUSE_DEPOSITS: u8 = 1 << 0;
+pub const PAUSE_WITHDRAWALS: u8 = 1 << 1;
+pub const PAUSE_BORROWS: u8 = 1 << 2;
+pub const PAUSE_REPAYS: u8 = 1 << 3;
+pub const PAUSE_LIQUIDATIONS: u8 = 1 << 4;
+pub const PAUSE_ALL: u8 = PAUSE_DEPOSITS | PAUSE_WITHDRAWALS | PAUSE_BORROWS | PAUSE_REPAYS | PAUSE_LIQUIDATIONS;
+pub const MAX_POSITIONS: usize = 8; // max number of banks a user can hold a position in
+pub const INITIAL_POSITIONS: usize = 2; // positions a new user account has room for, grown one at a time up to MAX_POSITIONS
+pub const MAX_DELEGATES: usize = 4; // max number of borrow delegations a user can approve at once
+pub const MAX_SWAP_PROGRAMS: usize = 4; // max number of swap programs approved for leveraged deposits
+pub const MAX_UNHEALTHY_USERS: usize = 64; // max number of users tracked by the unhealthy registry
+pub const MAX_LAUNCH_STEPS: usize = 3; // max number of cap steps in a bank's guarded launch schedule
+pub const MAX_DELEVERAGING_SLOTS: u64 = 1_512_000; // longest the risk manager may put the protocol in deleveraging mode for, about a week
+pub const MAX_PRICE_OVERRIDE_SLOTS: u64 = 216_000; // longest a price override may be set for before the risk manager renews it, about a day
+// Caps on the risk parameters of permissionless listings, which also start isolated
+pub const COMMUNITY_MAX_LTV: u64 = 2_500;
+pub const COMMUNITY_LIQUIDATION_THRESHOLD: u64 = 4_000;
+// Rate curve permissionless listings start with, in basis points per year except the utilization kink
+pub const COMMUNITY_BASE_RATE: u64 = 200;
+pub const COMMUNITY_SLOPE1: u64 = 800;
+pub const COMMUNITY_SLOPE2: u64 = 30_000;
+pub const COMMUNITY_OPTIMAL_UTILIZATION: u64 = 7_000;
+pub const COMMUNITY_RESERVE_FACTOR: u64 = 2_000;
+
+// Layout version of `Bank` and `User` accounts, bumped whenever `migrate_account` has an upgrade step to run
+pub const ACCOUNT_VERSION: u8 = 3;
+pub const REWARD_INDEX_SCALE: u128 = 1_000_000_000_000; // precision of the per-share reward index
+pub const FULL_AMOUNT: u64 = u64::MAX; // withdraw or repay everything the user has or owes at execution time
+
+// PDA seeds. Banks are derived from their mint alone and user accounts from their owner and sub-account,
+// every other account prefixes its seeds with one of these.
+#[constant]
+pub const TREASURY_SEED: &[u8] = b"treasury";
+#[constant]
+pub const STATS_SEED: &[u8] = b"stats";
+#[constant]
+pub const POSITION_SEED: &[u8] = b"position";
+#[constant]
+pub const FIXED_LOAN_SEED: &[u8] = b"fixed_loan";
+#[constant]
+pub const RECEIPT_SEED: &[u8] = b"receipt";
+#[constant]
+pub const ORACLE_SEED: &[u8] = b"oracle";
+#[constant]
+pub const PROTOCOL_SEED: &[u8] = b"protocol";
+#[constant]
+pub const PROTOCOL_STATS_SEED: &[u8] = b"protocol_stats";
+#[constant]
+pub const GATE_SEED: &[u8] = b"gate";
+#[constant]
+pub const PENDING_CONFIG_SEED: &[u8] = b"pending_config";
+#[constant]
+pub const REWARD_VAULT_SEED: &[u8] = b"reward_vault";
+#[constant]
+pub const POSITION_NFT_SEED: &[u8] = b"position_nft";
+#[constant]
+pub const UNHEALTHY_SEED: &[u8] = b"unhealthy";
+#[constant]
+pub const HEALTH_REFRESH_SEED: &[u8] = b"health_refresh";
+#[constant]
+pub const PRICE_OVERRIDE_SEED: &[u8] = b"price_override";
+
+/// Bank of `mint` and its bump
+pub fn find_bank_address(mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[mint.as_ref()], &crate::ID)
+}
+
+/// Token account holding the deposits of the bank of `mint` and its bump
+pub fn find_bank_token_account_address(mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[TREASURY_SEED, mint.as_ref()], &crate::ID)
+}
+
+/// Stats of `bank` and their bump
+pub fn find_bank_stats_address(bank: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[STATS_SEED, bank.as_ref()], &crate::ID)
+}
+
+/// Seed a user account adds after its owner, the little endian `sub_account_id` or nothing for
+/// sub-account zero so main accounts keep the address derived from the owner alone
+pub fn sub_account_seed(sub_account_id: u16) -> Vec<u8> {
+    if sub_account_id == 0 {
+        Vec::new()
+    } else {
+        sub_account_id.to_le_bytes().to_vec()
+    }
+}
+
+/// Sub-account `sub_account_id` of `owner` and its bump, zero for the owner's main account
+pub fn find_user_address(owner: &Pubkey, sub_account_id: u16) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[owner.as_ref(), sub_account_seed(sub_account_id).as_slice()], &crate::ID)
+}
+
+/// Position of `user_account` in the bank of `mint` and its bump
+pub fn find_position_address(user_account: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[POSITION_SEED, user_account.as_ref(), mint.as_ref()], &crate::ID)
+}
+
+/// Protocol config and its bump
+pub fn find_protocol_config_address() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[PROTOCOL_SEED], &crate::ID)
+}
+
+/// Price override of `bank` and its bump
+pub fn find_price_override_address(bank: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[PRICE_OVERRIDE_SEED, bank.as_ref()], &crate::ID)
+}
+
+/// Gate entry letting `wallet` into the gated `bank` and its bump
+pub fn find_gate_entry_address(bank: &Pubkey, wallet: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[GATE_SEED, bank.as_ref(), wallet.as_ref()], &crate::ID)
+}
@@ -2,16 +2,192 @@ use anchor_lang::prelude::*;
 
 #[error_code]
 pub enum ErrorCode {
-    #[msg("Borrowed amount exceeds the maximum LTV.")]
-    OverLTV,
-    #[msg("Borrowed amount results in an under collateralized loan.")]
-    UnderCollateralized,
+    #[msg("Collateral does not cover the debt at max LTV.")]
+    InsufficientCollateral,
+    #[msg("Health factor would fall below the borrow limit.")]
+    HealthFactorTooLow,
     #[msg("Insufficient funds to withdraw.")]
     InsufficientFunds,
     #[msg("Attempting to repay more than borrowed.")]
     OverRepay,
-    #[msg("Attempting to borrow more than allowed.")]
-    OverBorrowableAmount,
+    #[msg("Amount received or paid is worse than the signed limit.")]
+    SlippageExceeded,
     #[msg("User is not undercollateralized.")]
-    NotUndercollateralized
-}
\ No newline at end of file
+    NotUndercollateralized,
+    #[msg("Liquidation amount exceeds the close factor.")]
+    OverCloseFactor,
+    #[msg("User still has collateral or has no debt in this bank.")]
+    NoBadDebt,
+    #[msg("User has not been marked unhealthy.")]
+    NotMarkedUnhealthy,
+    #[msg("User is still within the collateral bank's liquidation grace window.")]
+    LiquidationGracePeriod,
+    #[msg("Fixed-rate loan term is zero or longer than the bank allows.")]
+    InvalidFixedTerm,
+    #[msg("Max LTV must be below the liquidation threshold, which must be at most 100%.")]
+    InvalidRiskConfig,
+    #[msg("Close factor must be between 0 and 100%.")]
+    InvalidCloseFactor,
+    #[msg("Liquidation bonus exceeds the maximum allowed.")]
+    InvalidLiquidationBonus,
+    #[msg("Interest rate configuration is invalid.")]
+    InvalidInterestRateConfig,
+    #[msg("This operation is paused for the bank.")]
+    BankPaused,
+    #[msg("Pause flags contain unknown operations.")]
+    InvalidPauseFlags,
+    #[msg("Signer does not hold the required role.")]
+    Unauthorized,
+    #[msg("No admin has been nominated.")]
+    NoPendingAdmin,
+    #[msg("Bank config changes must go through the timelock.")]
+    TimelockActive,
+    #[msg("Timelock for the proposed bank config has not passed yet.")]
+    TimelockNotElapsed,
+    #[msg("Timelock delay cannot be negative.")]
+    InvalidTimelock,
+    #[msg("A flash loan is already in progress for this bank.")]
+    FlashLoanInProgress,
+    #[msg("No flash loan is in progress for this bank.")]
+    NoFlashLoanInProgress,
+    #[msg("Flash loan must be repaid by a later flash_repay instruction in the same transaction.")]
+    FlashLoanNotRepaid,
+    #[msg("Flash loans cannot be taken through CPI.")]
+    FlashLoanCpiNotAllowed,
+    #[msg("Deposit would exceed the bank's deposit cap.")]
+    DepositCapExceeded,
+    #[msg("Borrow would exceed the bank's borrow cap.")]
+    BorrowCapExceeded,
+    #[msg("Deposit position would be below the bank's minimum deposit.")]
+    DepositBelowMinimum,
+    #[msg("Debt would be below the bank's minimum borrow.")]
+    BorrowBelowMinimum,
+    #[msg("Bank does not allow borrowing the asset deposited in the same position.")]
+    SelfBorrowNotAllowed,
+    #[msg("Swap program is not approved.")]
+    SwapProgramNotApproved,
+    #[msg("Max number of approved swap programs reached.")]
+    MaxSwapProgramsReached,
+    #[msg("Reserve factor must be at most 100%.")]
+    InvalidReserveFactor,
+    #[msg("Referral fee must not exceed the reserve factor.")]
+    InvalidReferralFee,
+    #[msg("Users cannot refer themselves.")]
+    InvalidReferrer,
+    #[msg("No referral fees to claim.")]
+    NoReferralFees,
+    #[msg("Attempting to withdraw more than the bank's reserves.")]
+    InsufficientReserves,
+    #[msg("Flash loan fee must be at most 100%.")]
+    InvalidFlashLoanFee,
+    #[msg("Reward mint does not match the bank's reward mint.")]
+    RewardMintMismatch,
+    #[msg("Position has no rewards to claim.")]
+    NoRewardsToClaim,
+    #[msg("Oracle max price age and max confidence must be nonzero, confidence at most 100%.")]
+    InvalidOracleConfig,
+    #[msg("E-mode max LTV and liquidation threshold must be valid and at least the bank's regular ones.")]
+    InvalidEModeConfig,
+    #[msg("Community banks must stay isolated until promoted by the admin.")]
+    CommunityBankNotPromoted,
+    #[msg("Bank is not a community listing.")]
+    NotCommunityBank,
+    #[msg("Mint has a Token-2022 extension the bank does not support.")]
+    UnsupportedMintExtension,
+    #[msg("Only the owner can open a new position.")]
+    PositionNotOpen,
+    #[msg("Position is not enabled as collateral.")]
+    CollateralDisabled,
+    #[msg("User already holds the maximum number of positions.")]
+    MaxPositionsReached,
+    #[msg("Position still holds deposits or debt.")]
+    PositionNotEmpty,
+    #[msg("Bank still holds deposits, debt or reserves.")]
+    BankNotEmpty,
+    #[msg("Health check accounts do not match the user's positions.")]
+    InvalidHealthAccounts,
+    #[msg("Position does not belong to the user or bank.")]
+    PositionNotFound,
+    #[msg("Account cannot be migrated.")]
+    InvalidMigrationAccount,
+    #[msg("Max number of borrow delegates reached.")]
+    MaxDelegatesReached,
+    #[msg("Borrow exceeds the delegate's allowance.")]
+    DelegateAllowanceExceeded,
+    #[msg("Bank has a withdrawal cooldown, withdrawals must be requested first.")]
+    WithdrawCooldownRequired,
+    #[msg("Position has no pending withdrawal.")]
+    NoPendingWithdrawal,
+    #[msg("Withdrawal cooldown has not elapsed yet.")]
+    WithdrawCooldownActive,
+    #[msg("Math operation overflowed.")]
+    MathOverflow,
+    #[msg("Oracle returned a zero or negative price.")]
+    OraclePriceInvalid,
+    #[msg("Oracle price update is older than the bank's max price age.")]
+    StaleOracle,
+    #[msg("Oracle confidence interval is wider than the bank allows.")]
+    PriceConfidenceTooWide,
+    #[msg("Price account belongs to none of the bank's oracles.")]
+    InvalidOracleAccount,
+    #[msg("Origination, deposit and withdrawal fees must be below 100%.")]
+    InvalidOriginationFee,
+    #[msg("Receiving collateral as a deposit needs the liquidator's own user account and collateral position.")]
+    LiquidatorPositionRequired,
+    #[msg("Withdrawal would push the bank's utilization above its limit.")]
+    InsufficientLiquidity,
+    #[msg("User account is tokenized, only the position NFT holder can take it over.")]
+    AccountTokenized,
+    #[msg("User account is not tokenized.")]
+    AccountNotTokenized,
+    #[msg("Health refresh pages must follow the user's positions in order, restart from the first position.")]
+    HealthRefreshOutOfOrder,
+    #[msg("Bank is collateral-only and can't be borrowed from.")]
+    BankNotBorrowable,
+    #[msg("Deposits in a borrow-only bank can't be used as collateral.")]
+    BankNotCollateral,
+    #[msg("Deposits can't be withdrawn or borrowed against in the slot they were made.")]
+    DepositLocked,
+    #[msg("Insurance factor and referral fee must fit in the reserve factor, withdrawals in the fund.")]
+    InvalidInsuranceConfig,
+    #[msg("Insurance fund withdrawal exceeds the fund or the bank's withdrawal limit.")]
+    InsuranceWithdrawalTooLarge,
+    #[msg("Insurance fund withdrawal is still timelocked or none is queued.")]
+    InsuranceWithdrawalLocked,
+    #[msg("Bank is permissioned and the user account's owner has no valid gate pass.")]
+    GatePassRequired,
+    #[msg("Max positions per user can't exceed the positions a user account has room for.")]
+    InvalidMaxPositions,
+    #[msg("Fee splits must add up to 100%.")]
+    InvalidFeeSplits,
+    #[msg("Risk tier has not been set by the admin.")]
+    RiskTierNotSet,
+    #[msg("User is healthy or worth more than the dust threshold.")]
+    NotDust,
+    #[msg("Guarded launch steps must come in slot order and only raise the caps.")]
+    InvalidLaunchSchedule,
+    #[msg("Bank is deprecated and takes no new deposits or borrows.")]
+    BankDeprecated,
+    #[msg("Bank must be deprecated and free of debt before its depositors are paid out.")]
+    BankNotWoundDown,
+    #[msg("Bank's token account balance does not match the transfers made.")]
+    VaultBalanceMismatch,
+    #[msg("Debt can't be moved to this borrow rate mode now.")]
+    RateModeSwapNotAllowed,
+    #[msg("Protocol is deleveraging, new borrows and withdrawals of collateral backing debt are blocked.")]
+    DeleveragingMode,
+    #[msg("Deleveraging mode must expire after the current slot and within the max deleveraging period.")]
+    InvalidDeleveragingExpiry,
+    #[msg("Price override must be positive and expire after the current slot and within the max override period.")]
+    InvalidPriceOverride,
+    #[msg("Price override can only be cleared by the risk manager, once expired or once an oracle of the bank works again.")]
+    PriceOverrideInUse,
+    #[msg("Liquidations must seize the user's collateral with the lowest liquidation weight first.")]
+    CollateralNotLowestWeight,
+    #[msg("Price override of the bank expired, it has to be cleared before the bank is valued at its oracles again.")]
+    PriceOverrideExpired,
+    #[msg("Early repayment fee on fixed loans must be below 100%.")]
+    InvalidEarlyRepayFee,
+    #[msg("Fixed loan can only move to the variable rate at maturity while the bank charges an early repayment fee, repay it early instead.")]
+    EarlyRepayFeeDue,
+}
@@ -0,0 +1,963 @@
+use anchor_lang::prelude::*;
+use crate::state::{LiquidationOrder, OracleSource, PositionPnl};
+
+// Every event carries the `event_seq` of the stream it belongs to, so indexers can order events and spot the
+// gaps RPCs leave when they drop logs. Each stream counts up by one from 1 on the account it belongs to:
+// - events about a bank are numbered by `Bank::next_event_seq`, those spanning two banks by the bank noted on
+//   their `event_seq`
+// - protocol-wide events by `ProtocolConfig::next_event_seq`
+// - events of instructions that write no bank, which are about a user account, by `User::next_event_seq`
+// Streams live on accounts the instructions already write, so numbering never adds a write lock.
+// `PriceOverrideUsed` is only numbered where the instruction writes the bank, see its doc.
+
+#[event]
+pub struct BankInitialized {
+    pub bank: Pubkey,
+    pub mint: Pubkey,
+    pub authority: Pubkey,
+    pub liquidation_threshold: u64,
+    pub max_ltv: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CommunityBankListed {
+    pub bank: Pubkey,
+    pub mint: Pubkey,
+    pub creator: Pubkey,
+    pub isolated_debt_bank: Pubkey,
+    pub listing_fee_lamports: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CommunityBankPromoted {
+    pub bank: Pubkey,
+    pub admin: Pubkey,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ListingFeeUpdated {
+    pub admin: Pubkey,
+    pub listing_fee_lamports: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BorrowHeadroomUpdated {
+    pub admin: Pubkey,
+    pub borrow_headroom_bps: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LiquidationOrderUpdated {
+    pub admin: Pubkey,
+    pub liquidation_order: LiquidationOrder,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DustThresholdUpdated {
+    pub admin: Pubkey,
+    /// In USD with `VALUE_DECIMALS`, zero when clearing dust positions is disabled
+    pub dust_threshold_value: u128,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FeeSplitsUpdated {
+    pub admin: Pubkey,
+    pub insurance_bps: u64,
+    pub staking_bps: u64,
+    pub treasury_bps: u64,
+    pub staking_vault: Pubkey,
+    pub dao_treasury: Pubkey,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct MaxPositionsUpdated {
+    pub admin: Pubkey,
+    /// Zero for the hard cap of `MAX_POSITIONS`
+    pub max_positions_per_user: u8,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BankConfigUpdated {
+    pub bank: Pubkey,
+    pub authority: Pubkey,
+    pub liquidation_threshold: u64,
+    pub max_ltv: u64,
+    pub liquidation_close_factor: u64,
+    pub liquidation_bonus: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BankConfigProposed {
+    pub bank: Pubkey,
+    pub proposer: Pubkey,
+    pub executable_at: i64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ConfigTimelockUpdated {
+    pub admin: Pubkey,
+    pub config_timelock_secs: i64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ProtocolInitialized {
+    pub admin: Pubkey,
+    pub emergency_authority: Pubkey,
+    pub fee_receiver: Pubkey,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AdminNominated {
+    pub admin: Pubkey,
+    pub pending_admin: Pubkey,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AdminTransferred {
+    pub previous_admin: Pubkey,
+    pub admin: Pubkey,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ProtocolRolesUpdated {
+    pub admin: Pubkey,
+    pub risk_manager: Pubkey,
+    pub emergency_authority: Pubkey,
+    pub fee_receiver: Pubkey,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DefaultRiskParamsUpdated {
+    pub admin: Pubkey,
+    pub liquidation_threshold: u64,
+    pub max_ltv: u64,
+    pub liquidation_close_factor: u64,
+    pub liquidation_bonus: u64,
+    pub max_price_age_secs: u64,
+    pub max_confidence_bps: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RiskTierUpdated {
+    pub admin: Pubkey,
+    /// Index of the tier, 0 conservative, 1 standard, 2 aggressive
+    pub tier: u8,
+    pub liquidation_threshold: u64,
+    pub max_ltv: u64,
+    pub liquidation_close_factor: u64,
+    pub liquidation_bonus: u64,
+    pub max_price_age_secs: u64,
+    pub max_confidence_bps: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PriceOverrideSet {
+    pub bank: Pubkey,
+    pub risk_manager: Pubkey,
+    /// `price * 10^exponent` USD per token valuations fall back to while the bank's oracles fail
+    pub price: i64,
+    pub exponent: i32,
+    pub expiry_slot: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+/// Emitted by every valuation at a price override. Numbered by the bank where the instruction writes it,
+/// health checks only read their banks and leave `event_seq` empty.
+#[event]
+pub struct PriceOverrideUsed {
+    pub mint: Pubkey,
+    pub price: i64,
+    pub exponent: i32,
+    pub expiry_slot: u64,
+    pub event_seq: Option<u64>,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PriceOverrideCleared {
+    pub bank: Pubkey,
+    /// Risk manager, or anyone clearing an expired override or one whose bank's oracle works again
+    pub signer: Pubkey,
+    pub expired: bool,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DeleveragingModeSet {
+    pub risk_manager: Pubkey,
+    /// Slot the mode expires at, zero when it was turned off
+    pub until_slot: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BankGraduated {
+    pub bank: Pubkey,
+    pub risk_manager: Pubkey,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DepegSwitchUpdated {
+    pub bank: Pubkey,
+    pub risk_manager: Pubkey,
+    /// Whether the bank is valued at raw oracle prices despite its pegged price mode
+    pub depegged: bool,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OracleApproved {
+    pub admin: Pubkey,
+    pub mint: Pubkey,
+    pub oracle: OracleSource,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BankOraclesUpdated {
+    pub bank: Pubkey,
+    pub admin: Pubkey,
+    pub oracle: OracleSource,
+    pub fallback_oracle: Option<OracleSource>,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OracleRemoved {
+    pub admin: Pubkey,
+    pub mint: Pubkey,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SwapProgramUpdated {
+    pub admin: Pubkey,
+    pub program: Pubkey,
+    pub approved: bool,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BankPauseUpdated {
+    pub bank: Pubkey,
+    pub authority: Pubkey,
+    pub paused_operations: u8,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UserInitialized {
+    pub user: Pubkey,
+    pub owner: Pubkey,
+    pub sub_account_id: u16,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CollateralToggled {
+    pub user: Pubkey,
+    pub bank: Pubkey,
+    pub collateral_enabled: bool,
+    /// Health factor of the user after the change, in basis points
+    pub health_factor: u64,
+    /// Numbered by the user account, the toggle writes no bank
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DelegateApproved {
+    pub user: Pubkey,
+    pub delegate: Pubkey,
+    pub bank: Pubkey,
+    pub allowance: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DelegateRevoked {
+    pub user: Pubkey,
+    pub delegate: Pubkey,
+    pub bank: Pubkey,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UserEModeSet {
+    pub user: Pubkey,
+    pub emode_category: u8,
+    /// Health factor of the user under the new category, in basis points
+    pub health_factor: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AccountTokenized {
+    pub user: Pubkey,
+    pub owner: Pubkey,
+    pub position_nft: Pubkey,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PositionTransferred {
+    pub old_user: Pubkey,
+    pub new_user: Pubkey,
+    pub previous_owner: Pubkey,
+    /// Holder of the position NFT the account moved to
+    pub new_owner: Pubkey,
+    pub positions: u8,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UserClosed {
+    pub user: Pubkey,
+    pub owner: Pubkey,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AccountMigrated {
+    pub account: Pubkey,
+    /// Layout version before the migration, zero for positions, which carry no version
+    pub from_version: u8,
+    pub to_version: u8,
+    /// Account size after the migration, in bytes
+    pub size: u64,
+    /// Sequence number of the migrated bank's or user account's events, zero for positions, which keep none
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BankDeprecated {
+    pub bank: Pubkey,
+    pub authority: Pubkey,
+    /// Floor of the variable borrow rate pushing borrowers to repay, in basis points per year
+    pub min_borrow_rate: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BankDrained {
+    pub bank: Pubkey,
+    /// Depositors paid out by this call
+    pub depositors: u32,
+    /// Tokens paid out to them
+    pub amount: u64,
+    /// Deposit shares still owed to depositors not yet paid out
+    pub remaining_deposit_shares: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BankClosed {
+    pub bank: Pubkey,
+    pub mint: Pubkey,
+    pub authority: Pubkey,
+    /// Rounding dust swept out of the bank's token account before closing
+    pub swept_dust: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DepositEvent {
+    pub user: Pubkey,
+    /// Owner of the token account the deposit was paid from
+    pub payer: Pubkey,
+    pub mint: Pubkey,
+    /// Tokens credited to the position, after the deposit fee
+    pub amount: u64,
+    /// Deposit fee added to the bank's reserves
+    pub fee: u64,
+    pub shares: u64,
+    /// User's deposited tokens in the bank after the deposit
+    pub user_deposited: u64,
+    /// Lifetime interest and fees of the user's position after the instruction
+    pub position_pnl: PositionPnl,
+    pub bank_total_deposits: u64,
+    /// Tag the client passed for its own accounting, echoed as is
+    pub client_tag: Option<[u8; 16]>,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ReceiptDeposited {
+    pub user: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    /// Deposit shares minted as receipt tokens
+    pub shares: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ReceiptWithdrawn {
+    pub user: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    /// Receipt tokens burned
+    pub shares: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ReceiptStaked {
+    pub user: Pubkey,
+    pub mint: Pubkey,
+    pub shares: u64,
+    /// Whether the receipt tokens were staked into the position or unstaked out of it
+    pub staked: bool,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WithdrawRequested {
+    pub user: Pubkey,
+    pub mint: Pubkey,
+    /// Tokens to withdraw, `FULL_AMOUNT` for the whole position, zero when the request was cancelled
+    pub amount: u64,
+    /// Earliest time `execute_withdraw` accepts the request
+    pub executable_at: i64,
+    /// Numbered by the user account, the request writes no bank
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WithdrawEvent {
+    pub user: Pubkey,
+    pub mint: Pubkey,
+    /// Tokens debited from the position, including the withdrawal fee
+    pub amount: u64,
+    /// Withdrawal fee added to the bank's reserves, withheld from the tokens sent out
+    pub fee: u64,
+    pub shares: u64,
+    /// User's deposited tokens in the bank after the withdrawal
+    pub user_deposited: u64,
+    /// Lifetime interest and fees of the user's position after the instruction
+    pub position_pnl: PositionPnl,
+    pub bank_total_deposits: u64,
+    /// Token account the withdrawn tokens were sent to, or the wallet native SOL was unwrapped to
+    pub destination: Pubkey,
+    /// Tag the client passed for its own accounting, echoed as is
+    pub client_tag: Option<[u8; 16]>,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BorrowEvent {
+    pub user: Pubkey,
+    /// Wallet that signed the borrow and received the tokens, the user or one of their delegates
+    pub borrower: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub shares: u64,
+    /// User's borrowed tokens in the bank after the borrow
+    pub user_borrowed: u64,
+    /// Lifetime interest and fees of the user's position after the instruction
+    pub position_pnl: PositionPnl,
+    pub bank_total_borrowed: u64,
+    /// Tag the client passed for its own accounting, echoed as is
+    pub client_tag: Option<[u8; 16]>,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LeverageDepositEvent {
+    pub user: Pubkey,
+    pub collateral_mint: Pubkey,
+    pub borrow_mint: Pubkey,
+    pub deposit_amount: u64,
+    pub borrow_amount: u64,
+    pub swap_program: Pubkey,
+    /// Collateral tokens the swap returned for the borrowed tokens
+    pub swapped_collateral: u64,
+    /// Health factor of the user after the leveraged deposit, in basis points
+    pub health_factor: u64,
+    /// Numbered by the collateral bank, the borrow leg's `BorrowEvent` by the borrow bank
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CollateralSwapped {
+    pub user: Pubkey,
+    pub source_mint: Pubkey,
+    pub target_mint: Pubkey,
+    /// Collateral withdrawn from the source bank
+    pub withdrawn: u64,
+    pub swap_program: Pubkey,
+    /// Swap output credited to the target bank after any transfer fee
+    pub deposited: u64,
+    /// Health factor of the user after the swap, in basis points
+    pub health_factor: u64,
+    /// Numbered by the source bank
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DebtSwapped {
+    pub user: Pubkey,
+    pub repay_mint: Pubkey,
+    pub borrow_mint: Pubkey,
+    /// New debt taken in the borrow bank
+    pub borrowed: u64,
+    pub swap_program: Pubkey,
+    /// Old debt repaid with the swap output
+    pub repaid: u64,
+    /// Health factor of the user after the swap, in basis points
+    pub health_factor: u64,
+    /// Numbered by the bank the debt is repaid in
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SelfLiquidated {
+    pub user: Pubkey,
+    pub collateral_mint: Pubkey,
+    pub debt_mint: Pubkey,
+    /// Collateral withdrawn from the collateral bank
+    pub withdrawn: u64,
+    /// Swap program the collateral went through, none when the user paid the debt tokens themselves
+    pub swap_program: Option<Pubkey>,
+    /// Debt repaid after any transfer fee
+    pub repaid: u64,
+    /// Health factor of the user afterwards, in basis points
+    pub health_factor: u64,
+    /// Numbered by the debt bank
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OriginationFeeCharged {
+    pub user: Pubkey,
+    pub mint: Pubkey,
+    /// Tokens borrowed the fee was charged on
+    pub amount: u64,
+    pub fee: u64,
+    /// Whether the fee was withheld from the borrowed tokens rather than added to the debt
+    pub skimmed: bool,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FixedBorrowEvent {
+    pub user: Pubkey,
+    pub mint: Pubkey,
+    pub loan_id: u64,
+    pub amount: u64,
+    /// Locked in rate, in basis points per year
+    pub rate: u64,
+    pub maturity_slot: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FixedRepayEvent {
+    pub user: Pubkey,
+    pub payer: Pubkey,
+    pub mint: Pubkey,
+    pub loan_id: u64,
+    pub amount: u64,
+    /// Interest settled with the repayment, accrued since the loan was last updated
+    pub interest: u64,
+    /// Early repayment fee paid on top of `amount` into the reserves, zero once the loan has matured
+    pub early_repay_fee: u64,
+    /// Tokens still owed on the loan after the repayment
+    pub loan_debt: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BorrowRateModeSwapped {
+    pub user: Pubkey,
+    /// Owner switching their own debt, or anyone rebalancing a fixed loan that drifted below the fixed rate
+    pub signer: Pubkey,
+    pub mint: Pubkey,
+    pub loan_id: u64,
+    /// Whether variable debt was moved into the fixed loan, otherwise the fixed loan was moved to variable debt
+    pub to_fixed: bool,
+    pub amount: u64,
+    /// Rate of the fixed loan, locked in for a move to fixed or the rate it carried before a move to variable
+    pub rate: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RepayEvent {
+    pub user: Pubkey,
+    /// Owner of the token account the repayment was paid from
+    pub payer: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub shares: u64,
+    /// User's borrowed tokens in the bank after the repayment
+    pub user_borrowed: u64,
+    /// Lifetime interest and fees of the user's position after the instruction
+    pub position_pnl: PositionPnl,
+    pub bank_total_borrowed: u64,
+    /// Tag the client passed for its own accounting, echoed as is
+    pub client_tag: Option<[u8; 16]>,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UnhealthyReported {
+    pub user: Pubkey,
+    pub health_factor: u64,
+    /// Whether the user is in the registry after the report, false once healthy or when outranked in a full registry
+    pub listed: bool,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct HealthRefreshed {
+    pub user: Pubkey,
+    /// Positions valued so far, the refresh is complete once all of the user's positions are
+    pub valued_positions: u8,
+    pub total_positions: u8,
+    /// Health factor cached on the user account, in basis points, only updated when the refresh is complete
+    pub health_factor: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UserHealthMarked {
+    pub user: Pubkey,
+    pub health_factor: u64,
+    /// Slot the liquidation auction started at, zero if the user is healthy
+    pub unhealthy_since_slot: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DustPositionCleared {
+    pub bank: Pubkey,
+    pub user: Pubkey,
+    /// Deposit moved to the bank's reserves, in bank tokens
+    pub collateral_seized: u64,
+    /// Debt written off, in bank tokens
+    pub debt_written_off: u64,
+    /// Part of the debt absorbed by the bank's reserves
+    pub covered_by_reserves: u64,
+    /// Part of the debt absorbed by the bank's insurance fund, after the reserves
+    pub covered_by_insurance: u64,
+    /// Part of the debt taken from depositors by lowering the deposit share value
+    pub socialized: u64,
+    /// Collateral and debt value of the whole user account before clearing, in USD with `VALUE_DECIMALS`
+    pub total_value: u128,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BadDebtWrittenOff {
+    pub bank: Pubkey,
+    pub user: Pubkey,
+    /// Debt written off, in bank tokens
+    pub amount: u64,
+    /// Part of the debt absorbed by the bank's reserves
+    pub covered_by_reserves: u64,
+    /// Part of the debt absorbed by the bank's insurance fund, after the reserves
+    pub covered_by_insurance: u64,
+    /// Part of the debt taken from depositors by lowering the deposit share value
+    pub socialized: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BadDebtCovered {
+    pub bank: Pubkey,
+    pub user: Pubkey,
+    /// Debt repaid from the insurance fund, in bank tokens
+    pub amount: u64,
+    /// User's debt in the bank the fund couldn't cover
+    pub remaining_debt: u64,
+    /// Insurance fund left afterwards
+    pub insurance_fund: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct InsuranceFunded {
+    pub bank: Pubkey,
+    pub funder: Pubkey,
+    /// Tokens credited to the fund after any transfer fee
+    pub amount: u64,
+    pub insurance_fund: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct InsuranceWithdrawalQueued {
+    pub bank: Pubkey,
+    pub amount: u64,
+    /// Earliest time the withdrawal can be executed
+    pub executable_at: i64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct InsuranceWithdrawn {
+    pub bank: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+    pub insurance_fund: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BorrowRateClamped {
+    /// Mint of the bank
+    pub bank: Pubkey,
+    /// Utilization the clamp started or stopped binding at, in basis points
+    pub utilization: u64,
+    /// Curve rate after the clamp, in basis points per year
+    pub rate: u64,
+    /// Whether the floor or cap binds from now on
+    pub clamped: bool,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BorrowRatePinned {
+    pub bank: Pubkey,
+    pub authority: Pubkey,
+    /// Rate borrowers pay while pinned, in basis points per year
+    pub rate: u64,
+    /// End of the pin, zero when unpinned
+    pub pinned_until: i64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct StakePoolRateRefreshed {
+    pub bank: Pubkey,
+    pub pool: Pubkey,
+    /// SOL per pool token, scaled by 10^9
+    pub rate: u64,
+    /// Epoch the pool last updated its balances in
+    pub epoch: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PriceEmaUpdated {
+    pub bank: Pubkey,
+    /// Oracle price the EMA moved towards
+    pub price: i64,
+    pub ema_price: i64,
+    pub exponent: i32,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BankCheckpointed {
+    pub bank: Pubkey,
+    /// Deposit exchange rate after the checkpoint, as a WAD
+    pub supply_index: u128,
+    /// Borrow exchange rate after the checkpoint, as a WAD
+    pub borrow_index: u128,
+    pub total_reserves: u64,
+    pub insurance_fund: u64,
+    /// Borrow rate after accrual, in basis points per year
+    pub borrow_rate: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct InterestAccrued {
+    pub bank: Pubkey,
+    pub total_deposits: u64,
+    pub total_borrowed: u64,
+    pub total_reserves: u64,
+    /// Borrow rate after accrual, in basis points per year
+    pub borrow_rate: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RewardsFunded {
+    pub bank: Pubkey,
+    pub reward_mint: Pubkey,
+    pub amount: u64,
+    /// Reward tokens emitted per second
+    pub reward_rate: u64,
+    pub rewards_remaining: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RewardsClaimed {
+    pub user: Pubkey,
+    pub bank: Pubkey,
+    pub reward_mint: Pubkey,
+    pub amount: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ReferralFeesClaimed {
+    pub referrer: Pubkey,
+    pub user: Pubkey,
+    pub bank: Pubkey,
+    pub amount: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FlashLoanEvent {
+    pub user: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ReservesWithdrawn {
+    pub bank: Pubkey,
+    pub authority: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+    pub remaining_reserves: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FeesDistributed {
+    pub bank: Pubkey,
+    /// Reserves moved into the bank's insurance fund
+    pub insurance: u64,
+    /// Reserves paid to the staking rewards vault
+    pub staking: u64,
+    /// Reserves paid to the DAO treasury
+    pub treasury: u64,
+    pub insurance_fund: u64,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LiquidateEvent {
+    pub liquidator: Pubkey,
+    pub user: Pubkey,
+    pub collateral_mint: Pubkey,
+    pub borrowed_mint: Pubkey,
+    /// Debt repaid by the liquidator, in borrowed tokens
+    pub repaid_amount: u64,
+    /// Collateral transferred to the liquidator including the bonus, in collateral tokens
+    pub seized_collateral: u64,
+    pub liquidation_bonus: u64,
+    /// Collateral seized from the user on top of `seized_collateral` and kept as protocol reserves of the collateral bank
+    pub protocol_fee: u64,
+    /// Health factor of the user before liquidation, in basis points
+    pub health_factor: u64,
+    /// Whether the collateral was credited to the liquidator's position as deposit shares instead of paid out
+    pub received_as_deposit: bool,
+    /// Numbered by the borrowed bank
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct GateEntryUpdated {
+    pub bank: Pubkey,
+    pub wallet: Pubkey,
+    /// Whether the wallet was added to the bank's whitelist or removed from it
+    pub allowed: bool,
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
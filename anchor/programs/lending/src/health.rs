@@ -0,0 +1,224 @@
+use anchor_lang::prelude::*;
+use crate::constants::BPS_DENOMINATOR;
+use crate::state::{Bank, FixedLoan, User, UserPosition};
+use crate::error::ErrorCode;
+use crate::interest::{accrue_fixed_loan, accrue_interest_view};
+use crate::oracle::get_valuation_prices;
+use crate::math::{aggregate_health, PositionRisk};
+use crate::pricing::bank_value;
+use crate::safe_math::SafeMath;
+use crate::wad::{wad_div, wad_to_bps};
+
+/// Aggregated value of all of a user's positions, in USD with `VALUE_DECIMALS`
+#[derive(Default)]
+pub struct Health {
+    /// Value of all deposits
+    pub collateral_value: u128,
+    /// Collateral enabled deposits weighted by each bank's max LTV, debt may not exceed this when borrowing or withdrawing
+    pub borrow_limit: u128,
+    /// Collateral enabled deposits weighted by each bank's liquidation threshold, debt above this can be liquidated
+    pub liquidation_limit: u128,
+    /// Value of all debt, weighted by each bank's debt weight
+    pub debt_value: u128,
+    /// Weighted value of all debt at the prices liquidation checks use, differs from `debt_value` for banks valued at their price EMA
+    pub liquidation_debt_value: u128,
+}
+
+impl Health {
+    /// Liquidation limit over debt as a WAD, `u128::MAX` when the user has no debt
+    pub fn health_factor_wad(&self) -> u128 {
+        if self.liquidation_debt_value == 0 {
+            return u128::MAX;
+        }
+        wad_div(self.liquidation_limit, self.liquidation_debt_value).unwrap_or(u128::MAX)
+    }
+
+    /// Health factor in basis points, `u64::MAX` when the user has no debt
+    pub fn health_factor(&self) -> u64 {
+        wad_to_bps(self.health_factor_wad()).unwrap_or(u64::MAX)
+    }
+
+    pub fn is_liquidatable(&self) -> bool {
+        self.liquidation_debt_value > self.liquidation_limit
+    }
+
+    pub fn is_within_borrow_limit(&self) -> bool {
+        self.debt_value <= self.borrow_limit
+    }
+
+    /// Fails with `error` when the debt exceeds the borrow limit, logging both values
+    pub fn require_within_borrow_limit(&self, error: ErrorCode) -> Result<()> {
+        if !self.is_within_borrow_limit() {
+            msg!("Debt value {} exceeds borrow limit {}, health factor {}", self.debt_value, self.borrow_limit, self.health_factor());
+            return Err(error.into());
+        }
+        Ok(())
+    }
+
+    /// Liquidation limit less `headroom_bps` of it, the most debt new borrows may leave at liquidation prices
+    pub fn headroom_limit(&self, headroom_bps: u64) -> u128 {
+        let headroom = self.liquidation_limit.saturating_mul(headroom_bps as u128) / BPS_DENOMINATOR as u128;
+        self.liquidation_limit.saturating_sub(headroom)
+    }
+
+    /// Like `require_within_borrow_limit`, also failing when the debt comes within `headroom_bps` of the liquidation limit
+    pub fn require_within_borrow_headroom(&self, headroom_bps: u64, error: ErrorCode) -> Result<()> {
+        self.require_within_borrow_limit(error)?;
+        let headroom_limit = self.headroom_limit(headroom_bps);
+        if self.liquidation_debt_value > headroom_limit {
+            msg!("Debt value {} exceeds {}, the liquidation limit {} less the borrow headroom", self.liquidation_debt_value, headroom_limit, self.liquidation_limit);
+            return Err(error.into());
+        }
+        Ok(())
+    }
+}
+
+/// Sums collateral and debt across every position of the user.
+///
+/// When the user has an e-mode category and every position is in a bank of that category,
+/// the banks' e-mode max LTV and liquidation threshold are used instead of the regular ones.
+///
+/// Collateral in an isolated bank only counts towards the limits while all of the user's debt
+/// is in that bank or in its `isolated_debt_bank`, so it can never back debt elsewhere.
+///
+/// `accounts` must hold, for each bank in `user.positions` and in the same order, the bank, the user's position
+/// in that bank, the account of one of the bank's oracles and the position's fixed loans, see `value_position`.
+/// Banks, positions and loans changed by the calling instruction must be written back with `exit` first.
+pub fn calculate_health(user: &User, user_key: &Pubkey, accounts: &[AccountInfo], clock: &Clock) -> Result<Health> {
+    // Value every position first, the limits depend on the user's positions as a whole
+    let positions = value_positions(&user.positions, user_key, accounts, clock)?;
+    aggregate_health(&positions, user.emode_category)
+}
+
+/// Values the user's positions in `banks`, a slice of `user.positions` when paginating.
+/// `accounts` must hold the health accounts of each bank, in the same order, see `value_position`.
+pub fn value_positions(banks: &[Pubkey], user_key: &Pubkey, accounts: &[AccountInfo], clock: &Clock) -> Result<Vec<PositionRisk>> {
+    let (positions, used) = value_leading_positions(banks, user_key, accounts, clock)?;
+    if used != accounts.len() {
+        msg!("Expected {} health accounts for {} positions, got {}", used, banks.len(), accounts.len());
+        return Err(ErrorCode::InvalidHealthAccounts.into());
+    }
+    Ok(positions)
+}
+
+/// Like `value_positions` for health accounts followed by other accounts, also returning how many accounts were used
+pub fn value_leading_positions(banks: &[Pubkey], user_key: &Pubkey, accounts: &[AccountInfo], clock: &Clock) -> Result<(Vec<PositionRisk>, usize)> {
+    let mut positions = Vec::with_capacity(banks.len());
+    let mut used = 0;
+    for bank_key in banks {
+        let (position, position_accounts) = value_position(bank_key, user_key, &accounts[used..], clock)?;
+        positions.push(position);
+        used += position_accounts;
+    }
+    Ok((positions, used))
+}
+
+/// Values the user's position in `bank_key` from the leading `accounts`: the bank, the user's position, the account
+/// of one of the bank's oracles, then the position's fixed loans until their debts add up to its `fixed_debt`.
+/// Also returns how many accounts were used.
+pub fn value_position(bank_key: &Pubkey, user_key: &Pubkey, accounts: &[AccountInfo], clock: &Clock) -> Result<(PositionRisk, usize)> {
+    let [bank_info, position_info, price_info, loan_infos @ ..] = accounts else {
+        msg!("Expected the bank, position and price accounts of bank {}, got {} accounts", bank_key, accounts.len());
+        return Err(ErrorCode::InvalidHealthAccounts.into());
+    };
+
+    if bank_info.key != bank_key {
+        msg!("Expected bank {}, got {}", bank_key, bank_info.key);
+        return Err(ErrorCode::InvalidHealthAccounts.into());
+    }
+
+    let mut bank: Bank = load_account(bank_info, &crate::ID)?;
+    let mut position: UserPosition = load_account(position_info, &crate::ID)?;
+
+    if position.user != *user_key || position.bank != *bank_key {
+        msg!("Position {} does not belong to user {} in bank {}", position_info.key, user_key, bank_key);
+        return Err(ErrorCode::PositionNotFound.into());
+    }
+
+    // Value read-only banks and fixed loans as if interest had been accrued up to now
+    accrue_interest_view(&mut bank, clock.unix_timestamp)?;
+    let loans = accrue_fixed_loans_view(&mut bank, &mut position, loan_infos, clock)?;
+    let prices = get_valuation_prices(&bank, price_info, clock)?;
+
+    let deposited = bank.deposit_amount_for(position.deposit_shares)?;
+    let borrowed = bank.borrow_amount_for(position.borrow_shares)?.safe_add(position.fixed_debt)?;
+    let collateral_value = bank_value(&bank, deposited, &prices.borrow_collateral)?;
+    let debt_value = bank_value(&bank, borrowed, &prices.borrow_debt)?;
+
+    let risk = PositionRisk {
+        bank: *bank_key,
+        collateral_enabled: position.collateral_enabled && bank.is_collateral(),
+        collateral_value,
+        debt_value,
+        liquidation_collateral_value: bank_value(&bank, deposited, &prices.liquidation_collateral)?,
+        liquidation_debt_value: bank_value(&bank, borrowed, &prices.liquidation_debt)?,
+        deposit_locked: bank.is_deposit_locked(&position, clock.slot),
+        collateral_weight: bank.collateral_weight(),
+        debt_weight: bank.debt_weight(),
+        max_ltv: bank.max_ltv,
+        liquidation_threshold: bank.liquidation_threshold,
+        emode_category: bank.emode_category,
+        emode_max_ltv: bank.emode_max_ltv,
+        emode_liquidation_threshold: bank.emode_liquidation_threshold,
+        isolated_debt_bank: bank.isolated.then_some(bank.isolated_debt_bank),
+    };
+    Ok((risk, 3 + loans))
+}
+
+/// Accrues the copies of a bank and position with `accrue_fixed_loan` over the position's fixed loans, read from
+/// the leading `loan_infos` until their debts add up to the position's `fixed_debt`, so none can be left out.
+/// Returns how many loans were read.
+fn accrue_fixed_loans_view(bank: &mut Bank, position: &mut UserPosition, loan_infos: &[AccountInfo], clock: &Clock) -> Result<usize> {
+    let recorded_debt = position.fixed_debt;
+    let mut loans_debt = 0u64;
+    let mut loans = 0;
+    while loans_debt < recorded_debt {
+        let Some(loan_info) = loan_infos.get(loans) else {
+            msg!("Fixed loans of position {} in bank {} add up to {} of its {} fixed debt", position.user, position.bank, loans_debt, recorded_debt);
+            return Err(ErrorCode::InvalidHealthAccounts.into());
+        };
+        let mut loan: FixedLoan = load_account(loan_info, &crate::ID)?;
+        if loan.user != position.user || loan.bank != position.bank || loan_infos[..loans].iter().any(|info| info.key == loan_info.key) {
+            msg!("Fixed loan {} is not another loan of user {} in bank {}", loan_info.key, position.user, position.bank);
+            return Err(ErrorCode::InvalidHealthAccounts.into());
+        }
+        loans_debt = loans_debt.safe_add(loan.debt)?;
+        accrue_fixed_loan(bank, &mut loan, position, clock)?;
+        loans += 1;
+    }
+    if loans_debt != recorded_debt {
+        msg!("Fixed loans of position {} in bank {} add up to {}, more than its {} fixed debt", position.user, position.bank, loans_debt, recorded_debt);
+        return Err(ErrorCode::InvalidHealthAccounts.into());
+    }
+    Ok(loans)
+}
+
+fn load_account<T: AccountDeserialize>(info: &AccountInfo, owner: &Pubkey) -> Result<T> {
+    if info.owner != owner {
+        msg!("Account {} is owned by {}, expected {}", info.key, info.owner, owner);
+        return Err(ErrorCode::InvalidHealthAccounts.into());
+    }
+    let data = info.try_borrow_data()?;
+    T::try_deserialize(&mut &data[..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn borrow_headroom_tightens_the_liquidation_limit() {
+        // Within max LTV but less than 10% below the liquidation limit
+        let health = Health {
+            collateral_value: 1_000,
+            borrow_limit: 750,
+            liquidation_limit: 800,
+            debt_value: 740,
+            liquidation_debt_value: 740,
+        };
+        assert_eq!(health.headroom_limit(0), 800);
+        assert_eq!(health.headroom_limit(1_000), 720);
+        assert!(health.require_within_borrow_headroom(500, ErrorCode::InsufficientCollateral).is_ok());
+        assert!(health.require_within_borrow_headroom(1_000, ErrorCode::InsufficientCollateral).is_err());
+    }
+}
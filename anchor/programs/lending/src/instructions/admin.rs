@@ -1,12 +1,55 @@
 use anchor_lang::prelude::*;
+use anchor_lang::system_program::{self, Transfer};
 use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+use crate::constants::{sub_account_seed, ACCOUNT_VERSION, BPS_DENOMINATOR, INITIAL_POSITIONS, MAX_ACCRUAL_INTERVAL_SECS, MAX_LIQUIDATION_BONUS, ORACLE_SEED, PROTOCOL_SEED, PROTOCOL_STATS_SEED, STATS_SEED, TREASURY_SEED};
 use crate::state::*;
+use crate::error::ErrorCode;
+use crate::interest::accrue_interest;
+use crate::mint::validate_mint_extensions;
+use crate::events::{BankConfigUpdated, BankInitialized, ProtocolInitialized, UserInitialized};
+use crate::safe_math::SafeMath;
+use crate::wad::WAD;
+
+#[derive(Accounts)]
+pub struct InitProtocol<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        init,
+        payer = signer,
+        space = 8 + ProtocolConfig::INIT_SPACE,
+        seeds = [PROTOCOL_SEED],
+        bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(
+        init,
+        payer = signer,
+        space = 8 + ProtocolStats::INIT_SPACE,
+        seeds = [PROTOCOL_STATS_SEED],
+        bump,
+    )]
+    pub protocol_stats: Account<'info, ProtocolStats>,
+    pub system_program: Program<'info, System>,
+}
 
 #[derive(Accounts)]
 pub struct InitBank<'info> {
     #[account(mut)]
     pub signer: Signer<'info>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+        constraint = protocol_config.admin == signer.key() @ ErrorCode::Unauthorized,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
     pub mint: InterfaceAccount<'info, Mint>,
+    /// Registry entry holding the price feed approved for the mint
+    #[account(
+        seeds = [ORACLE_SEED, mint.key().as_ref()],
+        bump = approved_oracle.bump,
+    )]
+    pub approved_oracle: Account<'info, ApprovedOracle>,
     #[account(
         init,
         space = 8 + Bank::INIT_SPACE,
@@ -15,12 +58,26 @@ pub struct InitBank<'info> {
         bump
     )]
     pub bank: Account<'info, Bank>,
+    #[account(
+        init,
+        space = 8 + BankStats::INIT_SPACE,
+        payer = signer,
+        seeds = [STATS_SEED, bank.key().as_ref()],
+        bump
+    )]
+    pub bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [PROTOCOL_STATS_SEED],
+        bump,
+    )]
+    pub protocol_stats: Account<'info, ProtocolStats>,
     #[account(
         init,
         token::mint = mint,
         token::authority = bank_token_account,
         payer = signer,
-        seeds = [b"treasury", mint.key().as_ref()],
+        seeds = [TREASURY_SEED, mint.key().as_ref()],
         bump
     )]
     pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
@@ -28,37 +85,764 @@ pub struct InitBank<'info> {
     pub system_program: Program<'info, System>,
 }
 
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct InitProtocolParams {
+    /// Can pause operations without going through the admin
+    pub emergency_authority: Pubkey,
+    /// Owner of the token accounts protocol reserves are paid out to
+    pub fee_receiver: Pubkey,
+    /// Risk parameters `init_bank` falls back to
+    pub default_risk: RiskParams,
+}
+
+// Risk and oracle fields passed as zero take the protocol default
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct InitBankParams {
+    /// LTV at which positions become liquidatable, in basis points, zero for the protocol default
+    pub liquidation_threshold: u64,
+    /// Max LTV a borrow may open at, in basis points, zero for the protocol default
+    pub max_ltv: u64,
+    /// Max share of a borrower's debt repayable in one liquidation, in basis points, zero for the protocol default
+    pub liquidation_close_factor: u64,
+    /// Extra collateral paid to liquidators on top of the repaid value, in basis points, zero for the protocol default
+    pub liquidation_bonus: u64,
+    /// Borrow rate at zero utilization, in basis points per year
+    pub base_rate: u64,
+    /// Rate added between zero and optimal utilization, in basis points per year
+    pub slope1: u64,
+    /// Rate added between optimal and full utilization, in basis points per year
+    pub slope2: u64,
+    /// Utilization at which the rate curve kinks, in basis points
+    pub optimal_utilization: u64,
+    /// Max tokens that can be deposited in the bank, zero for no cap
+    pub max_total_deposits: u64,
+    /// Max tokens that can be borrowed from the bank, zero for no cap
+    pub max_total_borrows: u64,
+    /// Share of borrower interest kept as protocol reserves, in basis points
+    pub reserve_factor: u64,
+    /// Fee charged on flash loans, in basis points
+    pub flash_loan_fee: u64,
+    /// Oldest price update accepted for the asset, in seconds, zero for the protocol default
+    pub max_price_age_secs: u64,
+    /// Widest Pyth confidence interval accepted, in basis points of the price, zero for the protocol default
+    pub max_confidence_bps: u64,
+    /// Whether collateral in the bank only backs debt in the bank itself or in `isolated_debt_bank`
+    pub isolated: bool,
+    /// Bank whose debt isolated collateral may also back, default pubkey for none
+    pub isolated_debt_bank: Pubkey,
+    /// Share of borrower interest paid to referrers out of the reserve cut, in basis points
+    pub referral_fee_bps: u64,
+    /// Smallest deposit accepted and smallest deposit position that can be left open, zero for no minimum
+    pub min_deposit_amount: u64,
+    /// Smallest borrow accepted and smallest debt that can be left open, zero for no minimum
+    pub min_borrow_amount: u64,
+    /// Bonus auction liquidations start at once a user is marked unhealthy, in basis points
+    pub auction_start_bonus: u64,
+    /// Bonus added per slot the user stays unhealthy, in basis points, capped at `liquidation_bonus`
+    pub auction_bonus_step: u64,
+    /// Premium over the variable borrow rate charged to lock in a fixed rate, in basis points per year
+    pub fixed_rate_premium: u64,
+    /// Rate added to the variable borrow rate on fixed loans past maturity, in basis points per year
+    pub fixed_penalty_rate: u64,
+    /// Longest fixed-rate loan term accepted, in slots, zero disables fixed-rate borrowing
+    pub max_fixed_term_slots: u64,
+    /// Whether a position may borrow the asset it deposits
+    pub allow_self_borrow: bool,
+    /// Delay between `request_withdraw` and `execute_withdraw`, in seconds, zero allows instant withdrawals
+    pub withdraw_cooldown_secs: i64,
+    /// Slots a user must stay flagged unhealthy by `flag_position` before the bank's collateral can be liquidated, zero for none
+    pub liquidation_grace_slots: u64,
+    /// Fee charged on every borrow, in basis points of the borrowed amount
+    pub origination_fee_bps: u64,
+    /// Whether the origination fee is withheld from the borrowed tokens rather than added to the debt
+    pub skim_origination_fee: bool,
+    /// How the oracle price is turned into the price the bank is valued at
+    pub price_mode: PriceMode,
+    /// Utilization withdrawals may not push the bank above, in basis points, zero for 100%
+    pub max_utilization_for_withdraw: u64,
+    /// Which prices, oracle or its EMA, deposits and debt are valued at
+    pub price_basis: PriceBasis,
+    /// Time for the EMA to fully catch up with the oracle price, in seconds, required unless valued at spot
+    pub ema_window_secs: u64,
+    /// Whether the asset can be borrowed, used as collateral or both
+    pub bank_type: BankType,
+    /// Whether deposits can't be withdrawn or borrowed against in the slot they were made
+    pub same_slot_deposit_lock: bool,
+    /// Floor of the variable borrow rate, in basis points per year
+    pub min_borrow_rate: u64,
+    /// Cap of the variable borrow rate, in basis points per year, zero for uncapped
+    pub max_borrow_rate: u64,
+    /// Share of borrower interest set aside in the insurance fund, in basis points, paid out of the reserve cut
+    pub insurance_factor: u64,
+    /// Share of the insurance fund a single withdrawal may take, in basis points, zero to disallow withdrawals
+    pub max_insurance_withdrawal_bps: u64,
+    /// Who may deposit into and borrow from the bank, open to everyone unless it is permissioned
+    pub gate: BankGate,
+    /// Most the variable borrow rate moves towards the curve rate per second, in basis points per year, zero to follow the curve immediately
+    pub max_rate_change_per_sec: u64,
+    /// How borrower interest accrues, simple interest on the debt or compounded every slot
+    pub interest_mode: InterestMode,
+    /// Least time between interest accruals, in seconds, zero to accrue on every interaction
+    pub min_accrual_interval_secs: i64,
+    /// Share of the liquidation bonus kept as protocol reserves instead of paid to the liquidator, in basis points
+    pub liquidation_protocol_fee_bps: u64,
+    /// Fee on deposits added to the reserves, in basis points, zero for none
+    pub deposit_fee_bps: u64,
+    /// Fee on withdrawals added to the reserves, in basis points, zero for none
+    pub withdraw_fee_bps: u64,
+    /// Tighter caps the bank launches under until graduated, inactive by default
+    pub guarded_launch: GuardedLaunch,
+    /// Valuation haircut on deposits in the health limits, in basis points, zero for none
+    pub collateral_weight_bps: u64,
+    /// Valuation markup on debt in the health limits, in basis points of at least 100%, zero for none
+    pub debt_weight_bps: u64,
+    /// Margin over an open fixed loan's rate past which anyone can move it to the variable rate, in basis points per year, zero disables rebalancing
+    pub fixed_rate_rebalance_bps: u64,
+    /// Fee on fixed loan debt repaid before maturity, in basis points of the debt repaid, zero for none
+    pub fixed_early_repay_fee_bps: u64,
+    /// E-mode category of correlated assets the bank belongs to, zero for none
+    pub emode_category: u8,
+    /// Max LTV for users in the bank's e-mode category, in basis points
+    pub emode_max_ltv: u64,
+    /// Liquidation threshold for users in the bank's e-mode category, in basis points
+    pub emode_liquidation_threshold: u64,
+    /// Preset the risk and oracle fields passed as zero fall back to, `None` for the protocol default
+    pub risk_tier: Option<RiskTier>,
+}
+
 #[derive(Accounts)]
+#[instruction(referrer: Option<Pubkey>, sub_account_id: u16)]
 pub struct InitUser<'info> {
     #[account(mut)]
     pub signer: Signer<'info>,
     #[account(
         init,
         payer = signer,
-        space = 8 + User::INIT_SPACE,
-        seeds = [signer.key().as_ref()],
+        space = User::space_for(INITIAL_POSITIONS),
+        seeds = [signer.key().as_ref(), sub_account_seed(sub_account_id).as_slice()],
         bump,
     )]
     pub user_account: Account<'info, User>,
+    #[account(
+        mut,
+        seeds = [PROTOCOL_STATS_SEED],
+        bump,
+    )]
+    pub protocol_stats: Account<'info, ProtocolStats>,
     pub system_program: Program<'info, System>,
 }
 
-pub fn process_init_bank(ctx: Context<InitBank>, liquidation_threshold: u64, max_ltv: u64) -> Result<()> {
+#[derive(Accounts)]
+pub struct UpdateBankConfig<'info> {
+    /// Either the bank's authority or the protocol's risk manager
+    #[account(
+        constraint = authority.key() == bank.authority
+            || authority.key() == protocol_config.risk_manager @ ErrorCode::Unauthorized,
+    )]
+    pub authority: Signer<'info>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(
+        mut,
+        seeds = [bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+}
+
+/// Bank settings to change, fields left as `None` keep their current value
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct UpdateBankConfigParams {
+    pub liquidation_threshold: Option<u64>,
+    pub max_ltv: Option<u64>,
+    pub liquidation_close_factor: Option<u64>,
+    pub liquidation_bonus: Option<u64>,
+    pub base_rate: Option<u64>,
+    pub slope1: Option<u64>,
+    pub slope2: Option<u64>,
+    pub optimal_utilization: Option<u64>,
+    pub max_total_deposits: Option<u64>,
+    pub max_total_borrows: Option<u64>,
+    pub reserve_factor: Option<u64>,
+    pub flash_loan_fee: Option<u64>,
+    pub max_price_age_secs: Option<u64>,
+    pub max_confidence_bps: Option<u64>,
+    pub isolated: Option<bool>,
+    pub isolated_debt_bank: Option<Pubkey>,
+    pub referral_fee_bps: Option<u64>,
+    pub min_deposit_amount: Option<u64>,
+    pub min_borrow_amount: Option<u64>,
+    pub auction_start_bonus: Option<u64>,
+    pub auction_bonus_step: Option<u64>,
+    pub fixed_rate_premium: Option<u64>,
+    pub fixed_penalty_rate: Option<u64>,
+    pub max_fixed_term_slots: Option<u64>,
+    pub allow_self_borrow: Option<bool>,
+    pub withdraw_cooldown_secs: Option<i64>,
+    pub liquidation_grace_slots: Option<u64>,
+    pub origination_fee_bps: Option<u64>,
+    pub skim_origination_fee: Option<bool>,
+    pub price_mode: Option<PriceMode>,
+    pub max_utilization_for_withdraw: Option<u64>,
+    pub price_basis: Option<PriceBasis>,
+    pub ema_window_secs: Option<u64>,
+    pub bank_type: Option<BankType>,
+    pub same_slot_deposit_lock: Option<bool>,
+    pub min_borrow_rate: Option<u64>,
+    pub max_borrow_rate: Option<u64>,
+    pub insurance_factor: Option<u64>,
+    pub max_insurance_withdrawal_bps: Option<u64>,
+    pub gate: Option<BankGate>,
+    pub max_rate_change_per_sec: Option<u64>,
+    pub interest_mode: Option<InterestMode>,
+    pub min_accrual_interval_secs: Option<i64>,
+    pub liquidation_protocol_fee_bps: Option<u64>,
+    pub deposit_fee_bps: Option<u64>,
+    pub withdraw_fee_bps: Option<u64>,
+    pub guarded_launch: Option<GuardedLaunch>,
+    pub collateral_weight_bps: Option<u64>,
+    pub debt_weight_bps: Option<u64>,
+    pub fixed_rate_rebalance_bps: Option<u64>,
+    pub fixed_early_repay_fee_bps: Option<u64>,
+    pub emode_category: Option<u8>,
+    pub emode_max_ltv: Option<u64>,
+    pub emode_liquidation_threshold: Option<u64>,
+}
+
+pub fn process_init_protocol(ctx: Context<InitProtocol>, params: InitProtocolParams) -> Result<()> {
+    params.default_risk.validate()?;
+
+    let protocol_config = &mut ctx.accounts.protocol_config;
+    protocol_config.admin = ctx.accounts.signer.key();
+    protocol_config.risk_manager = ctx.accounts.signer.key();
+    protocol_config.emergency_authority = params.emergency_authority;
+    protocol_config.fee_receiver = params.fee_receiver;
+    protocol_config.default_risk = params.default_risk;
+    protocol_config.bump = ctx.bumps.protocol_config;
+
+    emit!(ProtocolInitialized {
+        admin: protocol_config.admin,
+        emergency_authority: protocol_config.emergency_authority,
+        fee_receiver: protocol_config.fee_receiver,
+        event_seq: protocol_config.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn process_init_bank(ctx: Context<InitBank>, params: InitBankParams) -> Result<()> {
     let bank = &mut ctx.accounts.bank;
-    bank.mint_address = ctx.accounts.mint.key();
-    bank.authority = ctx.accounts.signer.key();
-    bank.liquidation_threshold = liquidation_threshold;
-    bank.max_ltv = max_ltv;
+    initialize_bank(
+        bank,
+        &ctx.accounts.mint,
+        ctx.accounts.signer.key(),
+        &ctx.accounts.approved_oracle,
+        ctx.accounts.protocol_config.risk_defaults(params.risk_tier)?,
+        &params,
+    )?;
+
+    ctx.accounts.bank_stats.bank = bank.key();
+    ctx.accounts.bank_stats.last_updated = bank.last_updated;
+    ctx.accounts.protocol_stats.total_banks = ctx.accounts.protocol_stats.total_banks.safe_add(1)?;
+
+    emit!(BankInitialized {
+        bank: bank.key(),
+        mint: bank.mint_address,
+        authority: bank.authority,
+        liquidation_threshold: bank.liquidation_threshold,
+        max_ltv: bank.max_ltv,
+        event_seq: bank.next_event_seq(),
+        timestamp: bank.last_updated,
+    });
+
     Ok(())
 }
 
-pub fn process_init_user(ctx: Context<InitUser>, usdc_address: Pubkey) -> Result<()> {
-    let user = &mut ctx.accounts.user_account;
-    user.owner = ctx.accounts.signer.key();
-    user.usdc_address = usdc_address;
+/// Writes a new bank's settings, shared by admin and permissionless listings
+pub(crate) fn initialize_bank(
+    bank: &mut Bank,
+    mint: &InterfaceAccount<Mint>,
+    authority: Pubkey,
+    approved_oracle: &ApprovedOracle,
+    defaults: &RiskParams,
+    params: &InitBankParams,
+) -> Result<()> {
+    validate_mint_extensions(&mint.to_account_info())?;
 
+    let or_default = |value: u64, default: u64| if value == 0 { default } else { value };
+
+    bank.mint_address = mint.key();
+    bank.mint_decimals = mint.decimals;
+    bank.supply_index = WAD;
+    bank.borrow_index = WAD;
+    bank.authority = authority;
+    bank.oracle = approved_oracle.oracle;
+    bank.max_price_age_secs = or_default(params.max_price_age_secs, defaults.max_price_age_secs);
+    bank.max_confidence_bps = or_default(params.max_confidence_bps, defaults.max_confidence_bps);
+    bank.isolated = params.isolated;
+    bank.isolated_debt_bank = params.isolated_debt_bank;
+    bank.referral_fee_bps = params.referral_fee_bps;
+    bank.min_deposit_amount = params.min_deposit_amount;
+    bank.min_borrow_amount = params.min_borrow_amount;
+    bank.auction_start_bonus = params.auction_start_bonus;
+    bank.auction_bonus_step = params.auction_bonus_step;
+    bank.fixed_rate_premium = params.fixed_rate_premium;
+    bank.fixed_penalty_rate = params.fixed_penalty_rate;
+    bank.max_fixed_term_slots = params.max_fixed_term_slots;
+    bank.allow_self_borrow = params.allow_self_borrow;
+    bank.withdraw_cooldown_secs = params.withdraw_cooldown_secs;
+    bank.liquidation_grace_slots = params.liquidation_grace_slots;
+    bank.origination_fee_bps = params.origination_fee_bps;
+    bank.skim_origination_fee = params.skim_origination_fee;
+    bank.price_mode = params.price_mode;
+    bank.max_utilization_for_withdraw = params.max_utilization_for_withdraw;
+    bank.price_basis = params.price_basis;
+    bank.ema_window_secs = params.ema_window_secs;
+    bank.bank_type = params.bank_type;
+    bank.same_slot_deposit_lock = params.same_slot_deposit_lock;
+    bank.min_borrow_rate = params.min_borrow_rate;
+    bank.max_borrow_rate = params.max_borrow_rate;
+    bank.insurance_factor = params.insurance_factor;
+    bank.max_insurance_withdrawal_bps = params.max_insurance_withdrawal_bps;
+    bank.gate = params.gate;
+    bank.max_rate_change_per_sec = params.max_rate_change_per_sec;
+    bank.interest_mode = params.interest_mode;
+    bank.min_accrual_interval_secs = params.min_accrual_interval_secs;
+    bank.liquidation_protocol_fee_bps = params.liquidation_protocol_fee_bps;
+    bank.deposit_fee_bps = params.deposit_fee_bps;
+    bank.withdraw_fee_bps = params.withdraw_fee_bps;
+    bank.guarded_launch = params.guarded_launch;
+    bank.collateral_weight_bps = params.collateral_weight_bps;
+    bank.debt_weight_bps = params.debt_weight_bps;
+    bank.fixed_rate_rebalance_bps = params.fixed_rate_rebalance_bps;
+    bank.fixed_early_repay_fee_bps = params.fixed_early_repay_fee_bps;
+    bank.emode_category = params.emode_category;
+    bank.emode_max_ltv = params.emode_max_ltv;
+    bank.emode_liquidation_threshold = params.emode_liquidation_threshold;
+    bank.liquidation_threshold = or_default(params.liquidation_threshold, defaults.liquidation_threshold);
+    bank.max_ltv = or_default(params.max_ltv, defaults.max_ltv);
+    bank.liquidation_close_factor = or_default(params.liquidation_close_factor, defaults.liquidation_close_factor);
+    bank.liquidation_bonus = or_default(params.liquidation_bonus, defaults.liquidation_bonus);
+    bank.base_rate = params.base_rate;
+    bank.slope1 = params.slope1;
+    bank.slope2 = params.slope2;
+    bank.optimal_utilization = params.optimal_utilization;
+    bank.max_total_deposits = params.max_total_deposits;
+    bank.max_total_borrows = params.max_total_borrows;
+    bank.reserve_factor = params.reserve_factor;
+    bank.flash_loan_fee = params.flash_loan_fee;
+    bank.last_updated = Clock::get()?.unix_timestamp;
+    bank.rewards_last_updated = bank.last_updated;
+    bank.smoothed_borrow_rate = bank.rate_curve().rate_at(0);
+    bank.version = ACCOUNT_VERSION;
+
+    validate_bank_config(bank)
+}
+
+// Every sub-account of a wallet is a separate user account with its own positions and health,
+// so liquidating one leaves the others untouched. Sub-account zero is the wallet's main account.
+pub fn process_init_user(ctx: Context<InitUser>, referrer: Option<Pubkey>, sub_account_id: u16) -> Result<()> {
     let now = Clock::get()?.unix_timestamp;
+    initialize_user(&mut ctx.accounts.user_account, &mut ctx.accounts.protocol_stats, ctx.accounts.signer.key(), sub_account_id, referrer, now)
+}
+
+/// Sets up a freshly created user account for `owner` and counts it in the protocol stats
+pub(crate) fn initialize_user(
+    user: &mut Account<User>,
+    protocol_stats: &mut ProtocolStats,
+    owner: Pubkey,
+    sub_account_id: u16,
+    referrer: Option<Pubkey>,
+    now: i64,
+) -> Result<()> {
+    user.owner = owner;
+    user.sub_account_id = sub_account_id;
+    user.version = ACCOUNT_VERSION;
+
+    // Referring yourself would hand the protocol's reserve cut back to the borrower
+    if let Some(referrer) = referrer {
+        if referrer == user.owner {
+            return Err(ErrorCode::InvalidReferrer.into());
+        }
+        user.referrer = referrer;
+    }
+
     user.last_updated = now;
+    protocol_stats.total_users = protocol_stats.total_users.safe_add(1)?;
+
+    emit!(UserInitialized {
+        user: user.key(),
+        owner: user.owner,
+        sub_account_id,
+        event_seq: user.next_event_seq(),
+        timestamp: now,
+    });
     
     Ok(())
-}
\ No newline at end of file
+}
+
+// User accounts start with room for `INITIAL_POSITIONS` positions rather than `MAX_POSITIONS`.
+// Opening a position beyond the room the account has grows it by one position, the signer opening it
+// paying the extra rent, and `close_position` shrinks it back to the positions still held.
+
+/// Checks the user can open a position in `bank` under the protocol's cap and grows the account to fit it,
+/// topping up its rent from `payer`
+pub(crate) fn reserve_position<'info>(
+    protocol_config: &ProtocolConfig,
+    user: &Account<'info, User>,
+    bank: &Pubkey,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<()> {
+    protocol_config.require_position_capacity(user, bank)?;
+    if user.positions.contains(bank) {
+        return Ok(());
+    }
+
+    let info = user.to_account_info();
+    let size = User::space_for(user.positions.len() + 1);
+    if info.data_len() >= size {
+        return Ok(());
+    }
+    let rent_due = Rent::get()?.minimum_balance(size).saturating_sub(info.lamports());
+    if rent_due > 0 {
+        let transfer_accounts = Transfer {
+            from: payer.clone(),
+            to: info.clone(),
+        };
+        system_program::transfer(CpiContext::new(system_program.clone(), transfer_accounts), rent_due)?;
+    }
+    info.realloc(size, true)?;
+    Ok(())
+}
+
+/// Shrinks the user account to the positions it still holds, never below `INITIAL_POSITIONS`,
+/// and returns the rent freed to `refund`
+pub(crate) fn shrink_user_account<'info>(user: &Account<'info, User>, refund: &AccountInfo<'info>) -> Result<()> {
+    let info = user.to_account_info();
+    let size = User::space_for(user.positions.len().max(INITIAL_POSITIONS));
+    if info.data_len() <= size {
+        return Ok(());
+    }
+    info.realloc(size, false)?;
+
+    let freed = info.lamports().saturating_sub(Rent::get()?.minimum_balance(size));
+    **info.try_borrow_mut_lamports()? -= freed;
+    **refund.try_borrow_mut_lamports()? += freed;
+    Ok(())
+}
+
+// Immediate updates are only allowed while the protocol has no config timelock,
+// otherwise changes go through `propose_bank_config` / `execute_bank_config`
+pub fn process_update_bank_config(ctx: Context<UpdateBankConfig>, params: UpdateBankConfigParams) -> Result<()> {
+    if ctx.accounts.protocol_config.config_timelock_secs > 0 {
+        return Err(ErrorCode::TimelockActive.into());
+    }
+    let authority = ctx.accounts.authority.key();
+    apply_bank_config(&mut ctx.accounts.bank, &params, authority)
+}
+
+/// Applies `params` to the bank after accruing interest at the old configuration,
+/// validates the result and emits `BankConfigUpdated`
+pub(crate) fn apply_bank_config(bank: &mut Account<Bank>, params: &UpdateBankConfigParams, authority: Pubkey) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+
+    // Interest up to now is owed at the old rate curve
+    accrue_interest(bank, now)?;
+
+    if let Some(liquidation_threshold) = params.liquidation_threshold {
+        bank.liquidation_threshold = liquidation_threshold;
+    }
+    if let Some(max_ltv) = params.max_ltv {
+        bank.max_ltv = max_ltv;
+    }
+    if let Some(liquidation_close_factor) = params.liquidation_close_factor {
+        bank.liquidation_close_factor = liquidation_close_factor;
+    }
+    if let Some(liquidation_bonus) = params.liquidation_bonus {
+        bank.liquidation_bonus = liquidation_bonus;
+    }
+    if let Some(base_rate) = params.base_rate {
+        bank.base_rate = base_rate;
+    }
+    if let Some(slope1) = params.slope1 {
+        bank.slope1 = slope1;
+    }
+    if let Some(slope2) = params.slope2 {
+        bank.slope2 = slope2;
+    }
+    if let Some(optimal_utilization) = params.optimal_utilization {
+        bank.optimal_utilization = optimal_utilization;
+    }
+    if let Some(max_total_deposits) = params.max_total_deposits {
+        bank.max_total_deposits = max_total_deposits;
+    }
+    if let Some(max_total_borrows) = params.max_total_borrows {
+        bank.max_total_borrows = max_total_borrows;
+    }
+    if let Some(reserve_factor) = params.reserve_factor {
+        bank.reserve_factor = reserve_factor;
+    }
+    if let Some(flash_loan_fee) = params.flash_loan_fee {
+        bank.flash_loan_fee = flash_loan_fee;
+    }
+    if let Some(max_price_age_secs) = params.max_price_age_secs {
+        bank.max_price_age_secs = max_price_age_secs;
+    }
+    if let Some(max_confidence_bps) = params.max_confidence_bps {
+        bank.max_confidence_bps = max_confidence_bps;
+    }
+    if let Some(isolated) = params.isolated {
+        bank.isolated = isolated;
+    }
+    if let Some(isolated_debt_bank) = params.isolated_debt_bank {
+        bank.isolated_debt_bank = isolated_debt_bank;
+    }
+    if let Some(referral_fee_bps) = params.referral_fee_bps {
+        bank.referral_fee_bps = referral_fee_bps;
+    }
+    if let Some(min_deposit_amount) = params.min_deposit_amount {
+        bank.min_deposit_amount = min_deposit_amount;
+    }
+    if let Some(min_borrow_amount) = params.min_borrow_amount {
+        bank.min_borrow_amount = min_borrow_amount;
+    }
+    if let Some(auction_start_bonus) = params.auction_start_bonus {
+        bank.auction_start_bonus = auction_start_bonus;
+    }
+    if let Some(auction_bonus_step) = params.auction_bonus_step {
+        bank.auction_bonus_step = auction_bonus_step;
+    }
+    if let Some(fixed_rate_premium) = params.fixed_rate_premium {
+        bank.fixed_rate_premium = fixed_rate_premium;
+    }
+    if let Some(fixed_penalty_rate) = params.fixed_penalty_rate {
+        bank.fixed_penalty_rate = fixed_penalty_rate;
+    }
+    if let Some(max_fixed_term_slots) = params.max_fixed_term_slots {
+        bank.max_fixed_term_slots = max_fixed_term_slots;
+    }
+    if let Some(allow_self_borrow) = params.allow_self_borrow {
+        bank.allow_self_borrow = allow_self_borrow;
+    }
+    if let Some(withdraw_cooldown_secs) = params.withdraw_cooldown_secs {
+        bank.withdraw_cooldown_secs = withdraw_cooldown_secs;
+    }
+    if let Some(liquidation_grace_slots) = params.liquidation_grace_slots {
+        bank.liquidation_grace_slots = liquidation_grace_slots;
+    }
+    if let Some(origination_fee_bps) = params.origination_fee_bps {
+        bank.origination_fee_bps = origination_fee_bps;
+    }
+    if let Some(skim_origination_fee) = params.skim_origination_fee {
+        bank.skim_origination_fee = skim_origination_fee;
+    }
+    if let Some(price_mode) = params.price_mode {
+        bank.price_mode = price_mode;
+    }
+    if let Some(max_utilization_for_withdraw) = params.max_utilization_for_withdraw {
+        bank.max_utilization_for_withdraw = max_utilization_for_withdraw;
+    }
+    if let Some(price_basis) = params.price_basis {
+        bank.price_basis = price_basis;
+    }
+    if let Some(ema_window_secs) = params.ema_window_secs {
+        bank.ema_window_secs = ema_window_secs;
+    }
+    if let Some(bank_type) = params.bank_type {
+        bank.bank_type = bank_type;
+    }
+    if let Some(same_slot_deposit_lock) = params.same_slot_deposit_lock {
+        bank.same_slot_deposit_lock = same_slot_deposit_lock;
+    }
+    if let Some(min_borrow_rate) = params.min_borrow_rate {
+        bank.min_borrow_rate = min_borrow_rate;
+    }
+    if let Some(max_borrow_rate) = params.max_borrow_rate {
+        bank.max_borrow_rate = max_borrow_rate;
+    }
+    if let Some(insurance_factor) = params.insurance_factor {
+        bank.insurance_factor = insurance_factor;
+    }
+    if let Some(max_insurance_withdrawal_bps) = params.max_insurance_withdrawal_bps {
+        bank.max_insurance_withdrawal_bps = max_insurance_withdrawal_bps;
+    }
+    if let Some(gate) = params.gate {
+        bank.gate = gate;
+    }
+    if let Some(max_rate_change_per_sec) = params.max_rate_change_per_sec {
+        bank.max_rate_change_per_sec = max_rate_change_per_sec;
+    }
+    if let Some(interest_mode) = params.interest_mode {
+        bank.interest_mode = interest_mode;
+    }
+    if let Some(min_accrual_interval_secs) = params.min_accrual_interval_secs {
+        bank.min_accrual_interval_secs = min_accrual_interval_secs;
+    }
+    if let Some(liquidation_protocol_fee_bps) = params.liquidation_protocol_fee_bps {
+        bank.liquidation_protocol_fee_bps = liquidation_protocol_fee_bps;
+    }
+    if let Some(deposit_fee_bps) = params.deposit_fee_bps {
+        bank.deposit_fee_bps = deposit_fee_bps;
+    }
+    if let Some(withdraw_fee_bps) = params.withdraw_fee_bps {
+        bank.withdraw_fee_bps = withdraw_fee_bps;
+    }
+    if let Some(guarded_launch) = params.guarded_launch {
+        bank.guarded_launch = guarded_launch;
+    }
+    if let Some(collateral_weight_bps) = params.collateral_weight_bps {
+        bank.collateral_weight_bps = collateral_weight_bps;
+    }
+    if let Some(debt_weight_bps) = params.debt_weight_bps {
+        bank.debt_weight_bps = debt_weight_bps;
+    }
+    if let Some(fixed_rate_rebalance_bps) = params.fixed_rate_rebalance_bps {
+        bank.fixed_rate_rebalance_bps = fixed_rate_rebalance_bps;
+    }
+    if let Some(fixed_early_repay_fee_bps) = params.fixed_early_repay_fee_bps {
+        bank.fixed_early_repay_fee_bps = fixed_early_repay_fee_bps;
+    }
+    if let Some(emode_category) = params.emode_category {
+        bank.emode_category = emode_category;
+    }
+    if let Some(emode_max_ltv) = params.emode_max_ltv {
+        bank.emode_max_ltv = emode_max_ltv;
+    }
+    if let Some(emode_liquidation_threshold) = params.emode_liquidation_threshold {
+        bank.emode_liquidation_threshold = emode_liquidation_threshold;
+    }
+
+    validate_bank_config(bank)?;
+
+    emit!(BankConfigUpdated {
+        bank: bank.key(),
+        authority,
+        liquidation_threshold: bank.liquidation_threshold,
+        max_ltv: bank.max_ltv,
+        liquidation_close_factor: bank.liquidation_close_factor,
+        liquidation_bonus: bank.liquidation_bonus,
+        event_seq: bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
+
+// Rejects configurations that would leave existing positions incoherent,
+// e.g. a max LTV above the liquidation threshold would let new borrows open already liquidatable
+fn validate_bank_config(bank: &Bank) -> Result<()> {
+    if bank.liquidation_threshold >= BPS_DENOMINATOR || bank.max_ltv >= bank.liquidation_threshold {
+        msg!("Max LTV {} must be below liquidation threshold {}, below {}", bank.max_ltv, bank.liquidation_threshold, BPS_DENOMINATOR);
+        return Err(ErrorCode::InvalidRiskConfig.into());
+    }
+    // Weights only ever make the health limits stricter, haircutting collateral and marking up debt
+    if bank.collateral_weight_bps > BPS_DENOMINATOR || (bank.debt_weight_bps != 0 && bank.debt_weight_bps < BPS_DENOMINATOR) {
+        msg!("Collateral weight {} must be at most {}, debt weight {} at least", bank.collateral_weight_bps, BPS_DENOMINATOR, bank.debt_weight_bps);
+        return Err(ErrorCode::InvalidRiskConfig.into());
+    }
+    if bank.liquidation_close_factor == 0 || bank.liquidation_close_factor > BPS_DENOMINATOR {
+        msg!("Close factor {} must be between 1 and {}", bank.liquidation_close_factor, BPS_DENOMINATOR);
+        return Err(ErrorCode::InvalidCloseFactor.into());
+    }
+    if bank.liquidation_bonus > MAX_LIQUIDATION_BONUS {
+        msg!("Liquidation bonus {} exceeds the maximum {}", bank.liquidation_bonus, MAX_LIQUIDATION_BONUS);
+        return Err(ErrorCode::InvalidLiquidationBonus.into());
+    }
+    if bank.liquidation_protocol_fee_bps > BPS_DENOMINATOR {
+        msg!("Liquidation protocol fee {} exceeds {}", bank.liquidation_protocol_fee_bps, BPS_DENOMINATOR);
+        return Err(ErrorCode::InvalidLiquidationBonus.into());
+    }
+    if bank.auction_start_bonus > bank.liquidation_bonus {
+        msg!("Auction start bonus {} exceeds the liquidation bonus {}", bank.auction_start_bonus, bank.liquidation_bonus);
+        return Err(ErrorCode::InvalidLiquidationBonus.into());
+    }
+    if bank.optimal_utilization == 0 || bank.optimal_utilization >= BPS_DENOMINATOR {
+        msg!("Optimal utilization {} must be between 1 and {}", bank.optimal_utilization, BPS_DENOMINATOR - 1);
+        return Err(ErrorCode::InvalidInterestRateConfig.into());
+    }
+    if bank.reserve_factor > BPS_DENOMINATOR {
+        msg!("Reserve factor {} exceeds {}", bank.reserve_factor, BPS_DENOMINATOR);
+        return Err(ErrorCode::InvalidReserveFactor.into());
+    }
+    // Referral fees are paid out of the reserve cut
+    if bank.referral_fee_bps > bank.reserve_factor {
+        msg!("Referral fee {} exceeds the reserve factor {}", bank.referral_fee_bps, bank.reserve_factor);
+        return Err(ErrorCode::InvalidReferralFee.into());
+    }
+    // So is the insurance fund's share, alongside referral fees
+    if bank.referral_fee_bps.saturating_add(bank.insurance_factor) > bank.reserve_factor {
+        msg!("Referral fee {} and insurance factor {} exceed the reserve factor {}", bank.referral_fee_bps, bank.insurance_factor, bank.reserve_factor);
+        return Err(ErrorCode::InvalidInsuranceConfig.into());
+    }
+    if bank.max_insurance_withdrawal_bps > BPS_DENOMINATOR {
+        msg!("Max insurance withdrawal {} exceeds {}", bank.max_insurance_withdrawal_bps, BPS_DENOMINATOR);
+        return Err(ErrorCode::InvalidInsuranceConfig.into());
+    }
+    if let PriceMode::Pegged { band_bps } = bank.price_mode {
+        if band_bps == 0 || band_bps >= BPS_DENOMINATOR {
+            msg!("Peg band {} must be between 1 and {}", band_bps, BPS_DENOMINATOR - 1);
+            return Err(ErrorCode::InvalidOracleConfig.into());
+        }
+    }
+    if bank.min_accrual_interval_secs < 0 || bank.min_accrual_interval_secs > MAX_ACCRUAL_INTERVAL_SECS {
+        msg!("Min accrual interval {} must be between 0 and {}", bank.min_accrual_interval_secs, MAX_ACCRUAL_INTERVAL_SECS);
+        return Err(ErrorCode::InvalidInterestRateConfig.into());
+    }
+    if bank.max_borrow_rate != 0 && bank.min_borrow_rate > bank.max_borrow_rate {
+        msg!("Min borrow rate {} exceeds max borrow rate {}", bank.min_borrow_rate, bank.max_borrow_rate);
+        return Err(ErrorCode::InvalidInterestRateConfig.into());
+    }
+    if bank.price_basis != PriceBasis::Spot && bank.ema_window_secs == 0 {
+        msg!("Banks valued at their price EMA need a nonzero EMA window");
+        return Err(ErrorCode::InvalidOracleConfig.into());
+    }
+    if bank.max_utilization_for_withdraw > BPS_DENOMINATOR {
+        msg!("Max utilization for withdrawals {} exceeds {}", bank.max_utilization_for_withdraw, BPS_DENOMINATOR);
+        return Err(ErrorCode::InvalidInterestRateConfig.into());
+    }
+    if bank.origination_fee_bps >= BPS_DENOMINATOR {
+        msg!("Origination fee {} must be below {}", bank.origination_fee_bps, BPS_DENOMINATOR);
+        return Err(ErrorCode::InvalidOriginationFee.into());
+    }
+    if bank.fixed_early_repay_fee_bps >= BPS_DENOMINATOR {
+        msg!("Early repayment fee {} must be below {}", bank.fixed_early_repay_fee_bps, BPS_DENOMINATOR);
+        return Err(ErrorCode::InvalidEarlyRepayFee.into());
+    }
+    bank.guarded_launch.validate()?;
+    if bank.deposit_fee_bps >= BPS_DENOMINATOR || bank.withdraw_fee_bps >= BPS_DENOMINATOR {
+        msg!("Deposit fee {} and withdrawal fee {} must be below {}", bank.deposit_fee_bps, bank.withdraw_fee_bps, BPS_DENOMINATOR);
+        return Err(ErrorCode::InvalidOriginationFee.into());
+    }
+    if bank.flash_loan_fee > BPS_DENOMINATOR {
+        msg!("Flash loan fee {} exceeds {}", bank.flash_loan_fee, BPS_DENOMINATOR);
+        return Err(ErrorCode::InvalidFlashLoanFee.into());
+    }
+    // E-mode exists to allow more borrowing against correlated assets, never less
+    if bank.emode_category != 0
+        && (bank.emode_liquidation_threshold > BPS_DENOMINATOR
+            || bank.emode_max_ltv >= bank.emode_liquidation_threshold
+            || bank.emode_max_ltv < bank.max_ltv
+            || bank.emode_liquidation_threshold < bank.liquidation_threshold)
+    {
+        msg!(
+            "E-mode max LTV {} and liquidation threshold {} must be valid and at least {} and {}",
+            bank.emode_max_ltv, bank.emode_liquidation_threshold, bank.max_ltv, bank.liquidation_threshold,
+        );
+        return Err(ErrorCode::InvalidEModeConfig.into());
+    }
+    // Community listings stay isolated until the admin promotes them
+    if bank.community && !bank.isolated {
+        msg!("Community bank {} must stay isolated until promoted", bank.mint_address);
+        return Err(ErrorCode::CommunityBankNotPromoted.into());
+    }
+    if bank.max_price_age_secs == 0 || bank.max_confidence_bps == 0 || bank.max_confidence_bps > BPS_DENOMINATOR {
+        msg!("Max price age {} and max confidence {} must be nonzero, confidence at most {}", bank.max_price_age_secs, bank.max_confidence_bps, BPS_DENOMINATOR);
+        return Err(ErrorCode::InvalidOracleConfig.into());
+    }
+    Ok(())
+}
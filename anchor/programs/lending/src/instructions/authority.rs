@@ -0,0 +1,273 @@
+use anchor_lang::prelude::*;
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::constants::{BPS_DENOMINATOR, MAX_POSITIONS, MAX_SWAP_PROGRAMS, PROTOCOL_SEED};
+use crate::events::{AdminNominated, AdminTransferred, BorrowHeadroomUpdated, DefaultRiskParamsUpdated, DustThresholdUpdated, FeeSplitsUpdated, LiquidationOrderUpdated, ListingFeeUpdated, MaxPositionsUpdated, ProtocolRolesUpdated, RiskTierUpdated, SwapProgramUpdated};
+
+#[derive(Accounts)]
+pub struct AdminOnly<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        has_one = admin,
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAuthority<'info> {
+    #[account(
+        constraint = pending_admin.key() == protocol_config.pending_admin @ ErrorCode::Unauthorized,
+    )]
+    pub pending_admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+/// Protocol roles to change, fields left as `None` keep their current holder
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ProtocolRolesParams {
+    pub risk_manager: Option<Pubkey>,
+    pub emergency_authority: Option<Pubkey>,
+    pub fee_receiver: Option<Pubkey>,
+}
+
+// The nominee only becomes admin once it accepts, so a mistyped key can be nominated over again
+pub fn process_nominate_authority(ctx: Context<AdminOnly>, new_admin: Pubkey) -> Result<()> {
+    let protocol_config = &mut ctx.accounts.protocol_config;
+    protocol_config.pending_admin = new_admin;
+
+    emit!(AdminNominated {
+        admin: protocol_config.admin,
+        pending_admin: new_admin,
+        event_seq: protocol_config.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn process_accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+    let protocol_config = &mut ctx.accounts.protocol_config;
+    if protocol_config.pending_admin == Pubkey::default() {
+        return Err(ErrorCode::NoPendingAdmin.into());
+    }
+
+    let previous_admin = protocol_config.admin;
+    protocol_config.admin = protocol_config.pending_admin;
+    protocol_config.pending_admin = Pubkey::default();
+
+    emit!(AdminTransferred {
+        previous_admin,
+        admin: protocol_config.admin,
+        event_seq: protocol_config.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn process_set_protocol_roles(ctx: Context<AdminOnly>, params: ProtocolRolesParams) -> Result<()> {
+    let protocol_config = &mut ctx.accounts.protocol_config;
+
+    if let Some(risk_manager) = params.risk_manager {
+        protocol_config.risk_manager = risk_manager;
+    }
+    if let Some(emergency_authority) = params.emergency_authority {
+        protocol_config.emergency_authority = emergency_authority;
+    }
+    if let Some(fee_receiver) = params.fee_receiver {
+        protocol_config.fee_receiver = fee_receiver;
+    }
+
+    emit!(ProtocolRolesUpdated {
+        admin: protocol_config.admin,
+        risk_manager: protocol_config.risk_manager,
+        emergency_authority: protocol_config.emergency_authority,
+        fee_receiver: protocol_config.fee_receiver,
+        event_seq: protocol_config.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Approved swap programs receive the user's borrowed tokens during `leverage_deposit`
+pub fn process_set_swap_program(ctx: Context<AdminOnly>, program: Pubkey, approved: bool) -> Result<()> {
+    let protocol_config = &mut ctx.accounts.protocol_config;
+
+    if !approved {
+        protocol_config.swap_programs.retain(|p| *p != program);
+    } else if !protocol_config.swap_programs.contains(&program) {
+        if protocol_config.swap_programs.len() >= MAX_SWAP_PROGRAMS {
+            msg!("Already {} of {} swap programs approved", protocol_config.swap_programs.len(), MAX_SWAP_PROGRAMS);
+            return Err(ErrorCode::MaxSwapProgramsReached.into());
+        }
+        protocol_config.swap_programs.push(program);
+    }
+
+    emit!(SwapProgramUpdated {
+        admin: protocol_config.admin,
+        program,
+        approved,
+        event_seq: protocol_config.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Rewriting a tier only affects banks initialized from it afterwards
+pub fn process_set_risk_tier(ctx: Context<AdminOnly>, tier: RiskTier, params: RiskParams) -> Result<()> {
+    params.validate()?;
+
+    let protocol_config = &mut ctx.accounts.protocol_config;
+    protocol_config.risk_tiers[tier as usize] = params;
+
+    let event_seq = protocol_config.next_event_seq();
+    let preset = &protocol_config.risk_tiers[tier as usize];
+    emit!(RiskTierUpdated {
+        admin: protocol_config.admin,
+        tier: tier as u8,
+        liquidation_threshold: preset.liquidation_threshold,
+        max_ltv: preset.max_ltv,
+        liquidation_close_factor: preset.liquidation_close_factor,
+        liquidation_bonus: preset.liquidation_bonus,
+        max_price_age_secs: preset.max_price_age_secs,
+        max_confidence_bps: preset.max_confidence_bps,
+        event_seq,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn process_set_default_risk_params(ctx: Context<AdminOnly>, params: RiskParams) -> Result<()> {
+    params.validate()?;
+
+    let protocol_config = &mut ctx.accounts.protocol_config;
+    protocol_config.default_risk = params;
+
+    let event_seq = protocol_config.next_event_seq();
+    let defaults = &protocol_config.default_risk;
+    emit!(DefaultRiskParamsUpdated {
+        admin: protocol_config.admin,
+        liquidation_threshold: defaults.liquidation_threshold,
+        max_ltv: defaults.max_ltv,
+        liquidation_close_factor: defaults.liquidation_close_factor,
+        liquidation_bonus: defaults.liquidation_bonus,
+        max_price_age_secs: defaults.max_price_age_secs,
+        max_confidence_bps: defaults.max_confidence_bps,
+        event_seq,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn process_set_listing_fee(ctx: Context<AdminOnly>, listing_fee_lamports: u64) -> Result<()> {
+    let protocol_config = &mut ctx.accounts.protocol_config;
+    protocol_config.listing_fee_lamports = listing_fee_lamports;
+
+    emit!(ListingFeeUpdated {
+        admin: protocol_config.admin,
+        listing_fee_lamports,
+        event_seq: protocol_config.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn process_set_fee_splits(ctx: Context<AdminOnly>, splits: FeeSplits) -> Result<()> {
+    splits.validate()?;
+    let protocol_config = &mut ctx.accounts.protocol_config;
+
+    emit!(FeeSplitsUpdated {
+        admin: protocol_config.admin,
+        insurance_bps: splits.insurance_bps,
+        staking_bps: splits.staking_bps,
+        treasury_bps: splits.treasury_bps,
+        staking_vault: splits.staking_vault,
+        dao_treasury: splits.dao_treasury,
+        event_seq: protocol_config.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    protocol_config.fee_splits = splits;
+
+    Ok(())
+}
+
+// Lowering the maximum doesn't touch users already above it, they just can't open further positions
+pub fn process_set_max_positions_per_user(ctx: Context<AdminOnly>, max_positions_per_user: u8) -> Result<()> {
+    if max_positions_per_user as usize > MAX_POSITIONS {
+        msg!("Max positions per user {} exceeds {}", max_positions_per_user, MAX_POSITIONS);
+        return Err(ErrorCode::InvalidMaxPositions.into());
+    }
+    let protocol_config = &mut ctx.accounts.protocol_config;
+    protocol_config.max_positions_per_user = max_positions_per_user;
+
+    emit!(MaxPositionsUpdated {
+        admin: protocol_config.admin,
+        max_positions_per_user,
+        event_seq: protocol_config.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Applies to every instruction taking on new debt, zero leaves only the max LTV limit
+pub fn process_set_borrow_headroom(ctx: Context<AdminOnly>, borrow_headroom_bps: u64) -> Result<()> {
+    if borrow_headroom_bps >= BPS_DENOMINATOR {
+        msg!("Borrow headroom {} must be below {}", borrow_headroom_bps, BPS_DENOMINATOR);
+        return Err(ErrorCode::InvalidRiskConfig.into());
+    }
+    let protocol_config = &mut ctx.accounts.protocol_config;
+    protocol_config.borrow_headroom_bps = borrow_headroom_bps;
+
+    emit!(BorrowHeadroomUpdated {
+        admin: protocol_config.admin,
+        borrow_headroom_bps,
+        event_seq: protocol_config.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Zero disables `clear_dust_position`
+pub fn process_set_dust_threshold(ctx: Context<AdminOnly>, dust_threshold_value: u128) -> Result<()> {
+    let protocol_config = &mut ctx.accounts.protocol_config;
+    protocol_config.dust_threshold_value = dust_threshold_value;
+
+    emit!(DustThresholdUpdated {
+        admin: protocol_config.admin,
+        dust_threshold_value,
+        event_seq: protocol_config.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Applies to `liquidate`, `liquidate_auction` and `liquidate_many` from their next call
+pub fn process_set_liquidation_order(ctx: Context<AdminOnly>, liquidation_order: LiquidationOrder) -> Result<()> {
+    let protocol_config = &mut ctx.accounts.protocol_config;
+    protocol_config.liquidation_order = liquidation_order;
+
+    emit!(LiquidationOrderUpdated {
+        admin: protocol_config.admin,
+        liquidation_order,
+        event_seq: protocol_config.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
@@ -0,0 +1,323 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::Mint;
+use crate::constants::{POSITION_SEED, PROTOCOL_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::{BadDebtCovered, BadDebtWrittenOff, DustPositionCleared};
+use crate::interest::{accrue_fixed_loan, accrue_interest};
+use crate::health::calculate_health;
+use crate::referral::snapshot_debt;
+use crate::rewards::settle_rewards;
+use crate::safe_math::SafeMath;
+use super::fixed_rate::remove_fixed_debt;
+
+#[derive(Accounts)]
+pub struct HandleBadDebt<'info> {
+    pub authority: Signer<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [mint.key().as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [user_account.owner.as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+    )]
+    /// Account of the borrower whose remaining debt is written off
+    pub user_account: Account<'info, User>,
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), mint.key().as_ref()],
+        bump,
+    )]
+    pub user_position: Account<'info, UserPosition>,
+    /// Fixed-rate loan of the user in the bank, written off along with the variable debt
+    #[account(
+        mut,
+        constraint = fixed_loan.user == user_account.key() && fixed_loan.bank == bank.key() @ ErrorCode::PositionNotFound,
+    )]
+    pub fixed_loan: Option<Account<'info, FixedLoan>>,
+}
+
+#[derive(Accounts)]
+pub struct ClearDustPosition<'info> {
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [mint.key().as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [user_account.owner.as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+    )]
+    /// Account of the user whose dust position is closed
+    pub user_account: Account<'info, User>,
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), mint.key().as_ref()],
+        bump,
+    )]
+    pub user_position: Account<'info, UserPosition>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    /// Fixed-rate loan of the user in the bank, written off along with the variable debt, required while the position has fixed debt
+    #[account(
+        mut,
+        constraint = fixed_loan.user == user_account.key() && fixed_loan.bank == bank.key() @ ErrorCode::PositionNotFound,
+    )]
+    pub fixed_loan: Option<Account<'info, FixedLoan>>,
+}
+
+#[derive(Accounts)]
+pub struct CoverBadDebt<'info> {
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [mint.key().as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [user_account.owner.as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+    )]
+    /// Account of the borrower whose debt the insurance fund covers
+    pub user_account: Account<'info, User>,
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), mint.key().as_ref()],
+        bump,
+    )]
+    pub user_position: Account<'info, UserPosition>,
+    /// Fixed-rate loan of the user in the bank, covered once the variable debt is
+    #[account(
+        mut,
+        constraint = fixed_loan.user == user_account.key() && fixed_loan.bank == bank.key() @ ErrorCode::PositionNotFound,
+    )]
+    pub fixed_loan: Option<Account<'info, FixedLoan>>,
+}
+
+// 1. Check the user has no collateral left in any bank but still owes debt
+//    (remaining accounts: bank, position, price update and fixed loans for each of the user's positions)
+// 2. Cover the user's debt in this bank, its variable debt and the passed fixed loan accrued up to now, from the
+//    bank's reserves as far as they go, then from its insurance fund
+// 3. Socialize the rest by reducing total deposits, lowering the value of every deposit share
+// 4. Clear the user's debt in this bank, the fixed loan is left open without debt for `repay_fixed` to close
+
+pub fn process_handle_bad_debt(ctx: Context<HandleBadDebt>) -> Result<()> {
+    let clock = Clock::get()?;
+    accrue_interest(&mut ctx.accounts.bank, clock.unix_timestamp)?;
+
+    let user = &ctx.accounts.user_account;
+    require_bad_debt(user, &ctx.accounts.user_position, ctx.remaining_accounts, &clock)?;
+
+    let bank = &mut ctx.accounts.bank;
+    let position = &mut ctx.accounts.user_position;
+    let mut loan = ctx.accounts.fixed_loan.as_deref_mut();
+    if let Some(loan) = loan.as_deref_mut() {
+        accrue_fixed_loan(bank, loan, position, &clock)?;
+    }
+    let (bad_debt, covered_by_reserves, covered_by_insurance, socialized) = write_off_debt(bank, position, loan)?;
+
+    let user = &mut ctx.accounts.user_account;
+    position.touch(bank, clock.unix_timestamp);
+    if position.is_empty() {
+        user.remove_position(bank.key());
+    }
+    user.last_updated = clock.unix_timestamp;
+
+    emit!(BadDebtWrittenOff {
+        bank: bank.key(),
+        user: user.owner,
+        amount: bad_debt,
+        covered_by_reserves,
+        covered_by_insurance,
+        socialized,
+        event_seq: bank.next_event_seq(),
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Permissionless, so bad debt stops accruing against depositors as soon as anyone spots it
+// 1. Check the user has no collateral left in any bank but still owes debt
+//    (remaining accounts: bank, position, price update and fixed loans for each of the user's positions)
+// 2. Repay as much of the user's debt in this bank as the bank's insurance fund holds, the variable debt before
+//    the passed fixed loan accrued up to now, the fund's tokens already sit in the bank's token account so only
+//    the accounting moves
+// 3. Anything the fund can't cover is left for `handle_bad_debt`
+
+pub fn process_cover_bad_debt(ctx: Context<CoverBadDebt>) -> Result<()> {
+    let clock = Clock::get()?;
+    accrue_interest(&mut ctx.accounts.bank, clock.unix_timestamp)?;
+
+    let user = &ctx.accounts.user_account;
+    require_bad_debt(user, &ctx.accounts.user_position, ctx.remaining_accounts, &clock)?;
+
+    let bank = &mut ctx.accounts.bank;
+    let position = &mut ctx.accounts.user_position;
+    let mut loan = ctx.accounts.fixed_loan.as_deref_mut();
+    if let Some(loan) = loan.as_deref_mut() {
+        accrue_fixed_loan(bank, loan, position, &clock)?;
+    }
+    let variable_debt = bank.borrow_amount_for(position.borrow_shares)?.min(bank.total_borrowed);
+    let fixed_debt = loan.as_ref().map_or(0, |loan| loan.debt);
+    let covered = variable_debt.safe_add(fixed_debt)?.min(bank.insurance_fund);
+    if covered == 0 {
+        msg!("Insurance fund of bank {} is empty", bank.key());
+        return Err(ErrorCode::InsufficientReserves.into());
+    }
+
+    // Shares of a partial cover round down like a repayment, the full cover clears them all
+    let variable_covered = covered.min(variable_debt);
+    let covered_shares = if variable_covered == variable_debt {
+        position.borrow_shares
+    } else {
+        bank.borrow_shares_burned(variable_covered)?.min(position.borrow_shares)
+    };
+    bank.insurance_fund = bank.insurance_fund.safe_sub(covered)?;
+    bank.total_borrowed = bank.total_borrowed.safe_sub(variable_covered)?;
+    bank.total_borrowed_shares = bank.total_borrowed_shares.safe_sub(covered_shares)?;
+    if let Some(loan) = loan {
+        remove_fixed_debt(bank, loan, position, covered.safe_sub(variable_covered)?)?;
+    }
+
+    let user = &mut ctx.accounts.user_account;
+    position.borrow_shares = position.borrow_shares.safe_sub(covered_shares)?;
+    position.touch(bank, clock.unix_timestamp);
+    // Like written off debt, interest covered by the fund was never paid and owes no referral fee
+    snapshot_debt(bank, position)?;
+    if position.is_empty() {
+        user.remove_position(bank.key());
+    }
+    user.last_updated = clock.unix_timestamp;
+
+    emit!(BadDebtCovered {
+        bank: bank.key(),
+        user: user.owner,
+        amount: covered,
+        remaining_debt: bank.borrow_amount_for(position.borrow_shares)?.safe_add(position.fixed_debt)?,
+        insurance_fund: bank.insurance_fund,
+        event_seq: bank.next_event_seq(),
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Permissionless, positions worth less than the gas of liquidating them would otherwise sit as bad debt
+// 1. Check the user is liquidatable and their collateral and debt across all banks add up to less than the
+//    protocol's dust threshold
+//    (remaining accounts: bank, position, price update and fixed loans for each of the user's positions)
+// 2. Move the user's deposit in this bank to the bank's reserves, its tokens stay in the bank's token account
+// 3. Write off the user's debt in this bank, its variable debt and the passed fixed loan accrued up to now, from
+//    the reserves, then the insurance fund, socializing the rest
+// 4. Close the position in the user account, the fixed loan is left open without debt for `repay_fixed` to close
+// Collateral positions should be cleared before debt ones, a user left without debt is no longer liquidatable
+
+pub fn process_clear_dust_position(ctx: Context<ClearDustPosition>) -> Result<()> {
+    let clock = Clock::get()?;
+    accrue_interest(&mut ctx.accounts.bank, clock.unix_timestamp)?;
+
+    let user = &ctx.accounts.user_account;
+    let health = calculate_health(user, &user.key(), ctx.remaining_accounts, &clock)?;
+    let total_value = health.collateral_value.safe_add(health.debt_value)?;
+    let dust_threshold = ctx.accounts.protocol_config.dust_threshold_value;
+    if !health.is_liquidatable() || total_value >= dust_threshold {
+        msg!("Total value {}, dust threshold {}, health factor {}", total_value, dust_threshold, health.health_factor());
+        return Err(ErrorCode::NotDust.into());
+    }
+
+    let bank = &mut ctx.accounts.bank;
+    let position = &mut ctx.accounts.user_position;
+    let mut loan = ctx.accounts.fixed_loan.as_deref_mut();
+    if let Some(loan) = loan.as_deref_mut() {
+        accrue_fixed_loan(bank, loan, position, &clock)?;
+    }
+    // Fixed-rate loans are tracked in their own accounts, the position can only close with a single one
+    let loan_debt = loan.as_ref().map_or(0, |loan| loan.debt);
+    if position.fixed_debt > loan_debt {
+        msg!("Position has {} of fixed-rate debt, {} of it in the passed loan", position.fixed_debt, loan_debt);
+        return Err(ErrorCode::NotDust.into());
+    }
+
+    settle_rewards(bank, position)?;
+    let collateral_seized = bank.deposit_amount_for(position.deposit_shares)?.min(bank.total_deposits);
+    bank.total_deposits = bank.total_deposits.safe_sub(collateral_seized)?;
+    bank.total_deposit_shares = bank.total_deposit_shares.safe_sub(position.deposit_shares)?;
+    bank.total_reserves = bank.total_reserves.safe_add(collateral_seized)?;
+    position.deposit_shares = 0;
+    position.pending_withdraw_amount = 0;
+    position.withdraw_requested_at = 0;
+
+    let (debt_written_off, covered_by_reserves, covered_by_insurance, socialized) = write_off_debt(bank, position, loan)?;
+    position.touch(bank, clock.unix_timestamp);
+
+    let user = &mut ctx.accounts.user_account;
+    user.remove_position(bank.key());
+    user.last_updated = clock.unix_timestamp;
+
+    emit!(DustPositionCleared {
+        bank: bank.key(),
+        user: user.owner,
+        collateral_seized,
+        debt_written_off,
+        covered_by_reserves,
+        covered_by_insurance,
+        socialized,
+        total_value,
+        event_seq: bank.next_event_seq(),
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+/// Clears the position's variable debt and the debt of its fixed `loan`, if passed, against the bank's reserves,
+/// then its insurance fund, socializing the rest across depositors. The loan must have been accrued.
+/// Returns the debt written off and the parts covered by reserves, insurance and depositors.
+fn write_off_debt(bank: &mut Bank, position: &mut UserPosition, loan: Option<&mut FixedLoan>) -> Result<(u64, u64, u64, u64)> {
+    let variable_debt = bank.borrow_amount_for(position.borrow_shares)?.min(bank.total_borrowed);
+    let fixed_debt = loan.as_ref().map_or(0, |loan| loan.debt);
+    let bad_debt = variable_debt.safe_add(fixed_debt)?;
+
+    let covered_by_reserves = bad_debt.min(bank.total_reserves);
+    let covered_by_insurance = bad_debt.safe_sub(covered_by_reserves)?.min(bank.insurance_fund);
+    let socialized = bad_debt.safe_sub(covered_by_reserves)?.safe_sub(covered_by_insurance)?;
+
+    bank.total_reserves = bank.total_reserves.safe_sub(covered_by_reserves)?;
+    bank.insurance_fund = bank.insurance_fund.safe_sub(covered_by_insurance)?;
+    bank.total_deposits = bank.total_deposits.saturating_sub(socialized);
+    bank.total_borrowed = bank.total_borrowed.safe_sub(variable_debt)?;
+    bank.total_borrowed_shares = bank.total_borrowed_shares.safe_sub(position.borrow_shares)?;
+    if let Some(loan) = loan {
+        remove_fixed_debt(bank, loan, position, fixed_debt)?;
+    }
+
+    // Interest on written off debt was never paid, so no referral fee is owed on it
+    position.borrow_shares = 0;
+    position.debt_snapshot = 0;
+    Ok((bad_debt, covered_by_reserves, covered_by_insurance, socialized))
+}
+
+/// Fails unless the user has no collateral value left and still owes debt in the position's bank
+fn require_bad_debt(user: &Account<User>, position: &UserPosition, health_accounts: &[AccountInfo], clock: &Clock) -> Result<()> {
+    let health = calculate_health(user, &user.key(), health_accounts, clock)?;
+    if health.collateral_value > 0 || (position.borrow_shares == 0 && position.fixed_debt == 0) {
+        msg!("Collateral value {}, borrow shares {}, fixed debt {}", health.collateral_value, position.borrow_shares, position.fixed_debt);
+        return Err(ErrorCode::NoBadDebt.into());
+    }
+    Ok(())
+}
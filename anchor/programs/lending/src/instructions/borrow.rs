@@ -1,15 +1,22 @@
-use std::f32::consts::E;
-
 use anchor_lang::prelude::*;
 use anchor_spl::associated_token::AssociatedToken;
 use anchor_spl::token_interface::{ self, Mint, TokenAccount, TokenInterface, TransferChecked };
-use pyth_solana_receiver_sdk::price_update::{get_feed_id_from_hex, PriceUpdateV2};
-use crate::constants::{MAXIMUM_AGE, SOL_USD_FEED_ID, USDC_USD_FEED_ID};
+use crate::constants::{BPS_DENOMINATOR, PAUSE_BORROWS, POSITION_SEED, PROTOCOL_SEED, STATS_SEED, TREASURY_SEED};
 use crate::state::*;
 use crate::error::ErrorCode;
+use crate::interest::accrue_interest;
+use crate::events::{BorrowEvent, OriginationFeeCharged};
+use crate::health::calculate_health;
+use crate::referral::{settle_referral_fees, snapshot_debt};
+use crate::mint::{calculate_transfer_fee, reconcile_vault};
+use crate::safe_math::{mul_div, SafeMath};
+use super::admin::reserve_position;
+use super::gate::require_gate_pass;
+use super::view::BorrowPreview;
 
 #[derive(Accounts)]
 pub struct Borrow<'info> {
+    /// The user or a delegate they approved, receives the borrowed tokens
     #[account(mut)]
     pub signer: Signer<'info>,
     pub mint: InterfaceAccount<'info, Mint>,
@@ -19,18 +26,34 @@ pub struct Borrow<'info> {
         bump,
     )]  
     pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, bank.key().as_ref()],
+        bump,
+    )]
+    pub bank_stats: Account<'info, BankStats>,
     #[account(
         mut, 
-        seeds = [b"treasury", mint.key().as_ref()],
+        seeds = [TREASURY_SEED, mint.key().as_ref()],
         bump, 
     )]  
     pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
     #[account(
         mut, 
-        seeds = [signer.key().as_ref()],
+        seeds = [user_account.owner.as_ref(), user_account.sub_account_seed().as_slice()],
         bump,
+        constraint = !user_account.is_tokenized() @ ErrorCode::AccountTokenized,
     )]  
+    /// Account of the user the debt accrues to
     pub user_account: Account<'info, User>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), mint.key().as_ref()],
+        bump,
+    )]
+    pub user_position: Account<'info, UserPosition>,
     #[account( 
         init_if_needed, 
         payer = signer,
@@ -39,48 +62,52 @@ pub struct Borrow<'info> {
         associated_token::token_program = token_program,
     )]
     pub user_token_account: InterfaceAccount<'info, TokenAccount>, 
-    pub price_update: Account<'info, PriceUpdateV2>,
     pub token_program: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
+    /// CHECK: Gate pass of the user account's owner for a permissioned bank, validated by `require_gate_pass`
+    pub gate_pass: Option<UncheckedAccount<'info>>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
 }
 
-// 1. Check if user has enough collateral to borrow
-// 2. Warn if borrowing beyond the safe amount but still allow if within the max borrowable amount
+// 1. Charge the bank's origination fee, withheld from the borrowed tokens or added to the debt
+// 2. Check the debt stays within the bank's borrow cap, delegates also spend their allowance
 // 3. Make a CPI transfer from the bank's token account to the user's token account
-// 4. Update the user's borrowed amount and total borrowed value
-// 5. Update the bank's total borrows and total borrow shares
-
-pub fn process_borrow(ctx: Context<Borrow>, amount: u64) -> Result<()> {
-    // Check if user has enough collateral to borrow
-    let bank = &mut ctx.accounts.bank;
-    let user = &mut ctx.accounts.user_account;
-
-    let price_update = &mut ctx.accounts.price_update;
-
-    let total_collateral: u64;
-
-    match ctx.accounts.mint.to_account_info().key() {
-        key if key == user.usdc_address => {
-            let sol_feed_id = get_feed_id_from_hex(SOL_USD_FEED_ID)?; 
-            let sol_price = price_update.get_price_no_older_than(&Clock::get()?, MAXIMUM_AGE, &sol_feed_id)?;
-            let accrued_interest = calculate_accrued_interest(user.deposited_sol, bank.interest_rate, user.last_updated)?;
-            total_collateral = sol_price.price as u64 * (user.deposited_sol + accrued_interest);
-        },
-        _ => {
-            let usdc_feed_id = get_feed_id_from_hex(USDC_USD_FEED_ID)?;
-            let usdc_price = price_update.get_price_no_older_than(&Clock::get()?, MAXIMUM_AGE, &usdc_feed_id)?;
-            total_collateral = usdc_price.price as u64 * user.deposited_usdc;
-
-        }
+// 4. Update the user's position, opening it on the first borrow
+// 5. Update the bank's total borrows and total borrow shares, minted shares are rounded up,
+//    and add the fee to the protocol reserves
+// 6. Check the user's collateral across all positions still covers the new debt at max LTV
+//    (remaining accounts: bank, position, price update and fixed loans for each of the user's positions)
+
+pub fn process_borrow(ctx: Context<Borrow>, amount: u64, client_tag: Option<[u8; 16]>) -> Result<()> {
+    ctx.accounts.bank.require_not_paused(PAUSE_BORROWS)?;
+    ctx.accounts.protocol_config.require_not_deleveraging()?;
+    require_gate_pass(&ctx.accounts.bank, &ctx.accounts.user_account.owner, ctx.accounts.gate_pass.as_deref())?;
+    reserve_position(
+        &ctx.accounts.protocol_config,
+        &ctx.accounts.user_account,
+        &ctx.accounts.bank.key(),
+        &ctx.accounts.signer.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+    )?;
+
+    let clock = Clock::get()?;
+    accrue_interest(&mut ctx.accounts.bank, clock.unix_timestamp)?;
+
+    let bank = &ctx.accounts.bank;
+    let BorrowPreview { debt_amount, fee, amount_sent, .. } = quote_borrow(bank, &ctx.accounts.mint.to_account_info(), amount)?;
+    bank.require_borrow_cap(debt_amount)?;
+
+    let signer_key = ctx.accounts.signer.key();
+    if signer_key != ctx.accounts.user_account.owner {
+        let bank_key = ctx.accounts.bank.key();
+        ctx.accounts.user_account.spend_allowance(signer_key, bank_key, debt_amount)?;
     }
 
-    let borrowable_amount = total_collateral as u64 *  bank.liquidation_threshold;
-
-    if borrowable_amount < amount {
-        return Err(ErrorCode::OverBorrowableAmount.into());
-    }       
-
     let transfer_cpi_accounts = TransferChecked {
         from: ctx.accounts.bank_token_account.to_account_info(),
         mint: ctx.accounts.mint.to_account_info(),
@@ -92,7 +119,7 @@ pub fn process_borrow(ctx: Context<Borrow>, amount: u64) -> Result<()> {
     let mint_key = ctx.accounts.mint.key();
     let signer_seeds: &[&[&[u8]]] = &[
         &[
-            b"treasury",
+            TREASURY_SEED,
             mint_key.as_ref(),
             &[ctx.bumps.bank_token_account],
         ],
@@ -100,36 +127,106 @@ pub fn process_borrow(ctx: Context<Borrow>, amount: u64) -> Result<()> {
     let cpi_ctx = CpiContext::new(cpi_program, transfer_cpi_accounts).with_signer(signer_seeds);
     let decimals = ctx.accounts.mint.decimals;
 
-    token_interface::transfer_checked(cpi_ctx, amount, decimals)?;
+    token_interface::transfer_checked(cpi_ctx, amount_sent, decimals)?;
+    reconcile_vault(&mut ctx.accounts.bank, &mut ctx.accounts.bank_token_account, 0, amount_sent)?;
+
+    let bank = &mut ctx.accounts.bank;
+    let user = &mut ctx.accounts.user_account;
+    let position = &mut ctx.accounts.user_position;
+    let users_shares = mint_borrow_shares(bank, &mut ctx.accounts.bank_stats, user, position, debt_amount, clock.unix_timestamp)?;
+    bank.total_reserves = bank.total_reserves.safe_add(fee)?;
+    position.pnl.fees_paid = position.pnl.fees_paid.safe_add(fee)?;
+
+    // Persist the updated accounts so the health check below sees the post-borrow state
+    bank.exit(&crate::ID)?;
+    position.exit(&crate::ID)?;
+
+    let health = calculate_health(user, &user.key(), ctx.remaining_accounts, &clock)?;
+    health.require_within_borrow_headroom(ctx.accounts.protocol_config.borrow_headroom_bps, ErrorCode::InsufficientCollateral)?;
+    user.health_factor = health.health_factor();
+    user.last_updated = clock.unix_timestamp;
+
+    if fee > 0 {
+        emit!(OriginationFeeCharged {
+            user: user.owner,
+            mint: ctx.accounts.mint.key(),
+            amount,
+            fee,
+            skimmed: bank.skim_origination_fee,
+            event_seq: bank.next_event_seq(),
+            timestamp: clock.unix_timestamp,
+        });
+    }
+
+    emit!(BorrowEvent {
+        user: user.owner,
+        borrower: signer_key,
+        mint: ctx.accounts.mint.key(),
+        amount,
+        shares: users_shares,
+        user_borrowed: bank.borrow_amount_for(position.borrow_shares)?,
+        position_pnl: position.pnl,
+        bank_total_borrowed: bank.total_borrowed,
+        client_tag,
+        event_seq: bank.next_event_seq(),
+        timestamp: clock.unix_timestamp,
+    });
 
-    if bank.total_borrowed == 0 {
-        bank.total_borrowed = amount;
-        bank.total_borrowed_shares = amount;
-    } 
+    Ok(())
+}
 
-    let borrow_ratio = amount.checked_div(bank.total_borrowed).unwrap();
-    let users_shares = bank.total_borrowed_shares.checked_mul(borrow_ratio).unwrap();
+/// Resolves a borrow of `amount` tokens into the debt taken on, the origination fee, the shares minted and the
+/// tokens the borrower ends up with. Shared with `preview_borrow`, the bank must have accrued interest up to now.
+pub(crate) fn quote_borrow(bank: &Bank, mint: &AccountInfo, amount: u64) -> Result<BorrowPreview> {
+    let fee = mul_div(amount, bank.origination_fee_bps, BPS_DENOMINATOR, true)?;
+    let (debt_amount, amount_sent) = if bank.skim_origination_fee {
+        (amount, amount.safe_sub(fee)?)
+    } else {
+        (amount.safe_add(fee)?, amount)
+    };
 
-    bank.total_borrowed += amount;
-    bank.total_borrowed_shares += users_shares; 
+    Ok(BorrowPreview {
+        debt_amount,
+        fee,
+        shares: bank.borrow_shares_minted(debt_amount)?,
+        amount_sent,
+        amount_out: amount_sent.safe_sub(calculate_transfer_fee(mint, amount_sent)?)?,
+    })
+}
 
-    match ctx.accounts.mint.to_account_info().key() {
-        key if key == user.usdc_address => {
-            user.borrowed_usdc += amount;
-            user.deposited_usdc_shares += users_shares;
-        },
-        _ => {
-            user.borrowed_sol += amount;
-            user.deposited_sol_shares += users_shares;
-        }
+/// Adds `amount` tokens of debt to the position and the bank totals, opening the position on the first borrow.
+/// Returns the minted shares, rounded up so the debt recorded never falls short of the tokens lent.
+pub(crate) fn mint_borrow_shares<'info>(
+    bank: &mut Account<'info, Bank>,
+    stats: &mut BankStats,
+    user: &mut Account<'info, User>,
+    position: &mut Account<'info, UserPosition>,
+    amount: u64,
+    now: i64,
+) -> Result<u64> {
+    bank.require_borrowable()?;
+    bank.require_borrow_minimum(amount)?;
+    bank.require_self_borrow_allowed(position, false)?;
+    settle_referral_fees(bank, user, position)?;
+    let users_shares = bank.borrow_shares_minted(amount)?;
+
+    bank.total_borrowed = bank.total_borrowed.safe_add(amount)?;
+    bank.total_borrowed_shares = bank.total_borrowed_shares.safe_add(users_shares)?;
+
+    if position.user == Pubkey::default() {
+        position.user = user.key();
+        position.bank = bank.key();
+        position.collateral_enabled = bank.is_collateral();
+        stats.unique_users = stats.unique_users.safe_add(1)?;
     }
+    user.add_position(bank.key())?;
 
-    Ok(())
-}
+    position.borrow_shares = position.borrow_shares.safe_add(users_shares)?;
+    position.touch(bank, now);
+    snapshot_debt(bank, position)?;
 
-fn calculate_accrued_interest(deposited: u64, interest_rate: u64, last_update: i64) -> Result<u64> {
-    let current_time = Clock::get()?.unix_timestamp;
-    let time_elapsed = current_time - last_update;
-    let new_value = (deposited as f64 * E.powf(interest_rate as f32 * time_elapsed as f32) as f64) as u64;
-    Ok(new_value)
-}
\ No newline at end of file
+    stats.cumulative_borrows = stats.cumulative_borrows.safe_add(amount as u128)?;
+    stats.last_updated = now;
+
+    Ok(users_shares)
+}
@@ -0,0 +1,306 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{self, CloseAccount, Mint, TokenAccount, TokenInterface, TransferChecked};
+use crate::constants::{POSITION_SEED, STATS_SEED, TREASURY_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::{BankClosed, BankDeprecated, BankDrained, UserClosed};
+use crate::interest::accrue_interest;
+use crate::mint::reconcile_vault;
+use crate::rewards::settle_rewards;
+use crate::safe_math::SafeMath;
+use super::admin::shrink_user_account;
+
+#[derive(Accounts)]
+pub struct ClosePosition<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    /// Shrunk to the positions it still holds, refunding the freed rent to the signer
+    #[account(
+        mut,
+        seeds = [signer.key().as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+        constraint = !user_account.is_tokenized() @ ErrorCode::AccountTokenized,
+    )]
+    pub user_account: Account<'info, User>,
+    #[account(
+        mut,
+        close = signer,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), mint.key().as_ref()],
+        bump,
+    )]
+    pub user_position: Account<'info, UserPosition>,
+}
+
+#[derive(Accounts)]
+pub struct CloseUserAccount<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        mut,
+        close = signer,
+        seeds = [signer.key().as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+        constraint = !user_account.is_tokenized() @ ErrorCode::AccountTokenized,
+    )]
+    pub user_account: Account<'info, User>,
+}
+
+#[derive(Accounts)]
+pub struct DeprecateBank<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+}
+
+#[derive(Accounts)]
+pub struct CloseBank<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    /// Closed along with its stats once no deposits are left, a deprecated bank stays open while depositors remain
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [mint.key().as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, bank.key().as_ref()],
+        bump,
+    )]
+    pub bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, mint.key().as_ref()],
+        bump,
+    )]
+    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// Receives any rounding dust left in the bank's token account
+    #[account(
+        mut,
+        token::mint = mint,
+        token::token_program = token_program,
+    )]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+// Empty positions are kept open by deposit and borrow, closing one returns its rent to the user
+// along with the rent of the user account's room for positions it no longer holds
+pub fn process_close_position(ctx: Context<ClosePosition>) -> Result<()> {
+    let position = &ctx.accounts.user_position;
+    if !position.is_empty() || position.rewards_accrued > 0 || position.referral_fees_accrued > 0 {
+        return Err(ErrorCode::PositionNotEmpty.into());
+    }
+
+    shrink_user_account(&ctx.accounts.user_account, &ctx.accounts.signer.to_account_info())
+}
+
+// A user can only be closed once every position has been fully withdrawn and repaid
+pub fn process_close_user_account(ctx: Context<CloseUserAccount>) -> Result<()> {
+    let user = &mut ctx.accounts.user_account;
+    if user.active_positions > 0 {
+        msg!("User still holds {} positions", user.active_positions);
+        return Err(ErrorCode::PositionNotEmpty.into());
+    }
+
+    emit!(UserClosed {
+        user: user.key(),
+        owner: user.owner,
+        event_seq: user.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Starts winding the bank down: no new deposits or borrows, existing ones can still be withdrawn, repaid
+// and liquidated. `min_borrow_rate` raises the variable rate's floor to push borrowers to repay, interest
+// up to now is accrued at the previous rate first. Calling it again only changes the floor.
+
+pub fn process_deprecate_bank(ctx: Context<DeprecateBank>, min_borrow_rate: Option<u64>) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    let bank = &mut ctx.accounts.bank;
+    accrue_interest(bank, now)?;
+
+    if let Some(min_borrow_rate) = min_borrow_rate {
+        if bank.max_borrow_rate != 0 && min_borrow_rate > bank.max_borrow_rate {
+            msg!("Min borrow rate {} exceeds max borrow rate {}", min_borrow_rate, bank.max_borrow_rate);
+            return Err(ErrorCode::InvalidInterestRateConfig.into());
+        }
+        bank.min_borrow_rate = min_borrow_rate;
+    }
+    if bank.deprecated_at == 0 {
+        bank.deprecated_at = now;
+    }
+
+    emit!(BankDeprecated {
+        bank: bank.key(),
+        authority: ctx.accounts.authority.key(),
+        min_borrow_rate: bank.min_borrow_rate,
+        event_seq: bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
+
+// 1. For a deprecated bank without debt, pay out the depositors passed as remaining accounts
+//    (user account, position in the bank and a token account of the user's owner for each depositor),
+//    stopping there while deposits of other depositors remain
+// 2. Check no deposits, debt or reserves are left in the bank
+// 3. Sweep any rounding dust left in the bank's token account to the destination
+// 4. Close the bank's token account, stats and the bank, returning their rent to the authority
+
+pub fn process_close_bank<'info>(mut ctx: Context<'_, '_, 'info, 'info, CloseBank<'info>>) -> Result<()> {
+    if !ctx.remaining_accounts.is_empty() {
+        drain_depositors(&mut ctx)?;
+        if ctx.accounts.bank.total_deposit_shares > 0 {
+            return Ok(());
+        }
+    }
+
+    let bank = &ctx.accounts.bank;
+    if bank.total_deposit_shares > 0
+        || bank.total_borrowed_shares > 0
+        || bank.total_fixed_borrowed > 0
+        || bank.total_reserves > 0
+        || bank.insurance_fund > 0
+        || bank.total_referral_fees > 0
+        || bank.flash_loan_amount > 0
+    {
+        msg!(
+            "Deposits {}, borrowed {}, reserves {}, insurance fund {}, referral fees {}, flash loan {}",
+            bank.total_deposits, bank.total_borrowed, bank.total_reserves, bank.insurance_fund, bank.total_referral_fees, bank.flash_loan_amount,
+        );
+        return Err(ErrorCode::BankNotEmpty.into());
+    }
+
+    let cpi_program = ctx.accounts.token_program.to_account_info();
+    let mint_key = ctx.accounts.mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[
+        &[
+            TREASURY_SEED,
+            mint_key.as_ref(),
+            &[ctx.bumps.bank_token_account],
+        ],
+    ];
+
+    let dust = ctx.accounts.bank_token_account.amount;
+    if dust > 0 {
+        let transfer_cpi_accounts = TransferChecked {
+            from: ctx.accounts.bank_token_account.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to: ctx.accounts.destination.to_account_info(),
+            authority: ctx.accounts.bank_token_account.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(cpi_program.clone(), transfer_cpi_accounts).with_signer(signer_seeds);
+        token_interface::transfer_checked(cpi_ctx, dust, ctx.accounts.mint.decimals)?;
+    }
+
+    let close_accounts = CloseAccount {
+        account: ctx.accounts.bank_token_account.to_account_info(),
+        destination: ctx.accounts.authority.to_account_info(),
+        authority: ctx.accounts.bank_token_account.to_account_info(),
+    };
+    token_interface::close_account(CpiContext::new(cpi_program, close_accounts).with_signer(signer_seeds))?;
+
+    let authority = ctx.accounts.authority.to_account_info();
+    ctx.accounts.bank.close(authority.clone())?;
+    ctx.accounts.bank_stats.close(authority)?;
+
+    emit!(BankClosed {
+        bank: bank.key(),
+        mint: mint_key,
+        authority: ctx.accounts.authority.key(),
+        swept_dust: dust,
+        event_seq: ctx.accounts.bank.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+/// Pays each depositor in the remaining accounts their whole deposit out of the bank's token account
+fn drain_depositors<'info>(ctx: &mut Context<'_, '_, 'info, 'info, CloseBank<'info>>) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    let bank = &mut ctx.accounts.bank;
+    accrue_interest(bank, now)?;
+    if bank.deprecated_at == 0 || bank.total_borrowed_shares > 0 || bank.total_fixed_borrowed > 0 || bank.flash_loan_amount > 0 {
+        msg!("Bank deprecated at {}, borrowed {}, fixed-rate borrowed {}", bank.deprecated_at, bank.total_borrowed, bank.total_fixed_borrowed);
+        return Err(ErrorCode::BankNotWoundDown.into());
+    }
+    if !ctx.remaining_accounts.len().is_multiple_of(3) {
+        msg!("Expected 3 accounts per depositor, got {}", ctx.remaining_accounts.len());
+        return Err(ErrorCode::InvalidHealthAccounts.into());
+    }
+
+    let mint_key = ctx.accounts.mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[
+        &[
+            TREASURY_SEED,
+            mint_key.as_ref(),
+            &[ctx.bumps.bank_token_account],
+        ],
+    ];
+
+    let mut paid_out = 0u64;
+    for depositor in ctx.remaining_accounts.chunks(3) {
+        let mut user: Account<User> = Account::try_from(&depositor[0])?;
+        let mut position: Account<UserPosition> = Account::try_from(&depositor[1])?;
+        let destination: InterfaceAccount<TokenAccount> = InterfaceAccount::try_from(&depositor[2])?;
+        if position.user != user.key() || position.bank != bank.key() {
+            msg!("Position {} does not belong to user {} in bank {}", position.key(), user.key(), bank.key());
+            return Err(ErrorCode::PositionNotFound.into());
+        }
+        if destination.mint != mint_key || destination.owner != user.owner {
+            msg!("Token account {} is not a {} account of {}", destination.key(), mint_key, user.owner);
+            return Err(ErrorCode::InvalidHealthAccounts.into());
+        }
+
+        let amount = bank.deposit_amount_for(position.deposit_shares)?.min(bank.total_deposits);
+        let transfer_cpi_accounts = TransferChecked {
+            from: ctx.accounts.bank_token_account.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to: destination.to_account_info(),
+            authority: ctx.accounts.bank_token_account.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), transfer_cpi_accounts).with_signer(signer_seeds);
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+
+        settle_rewards(bank, &mut position)?;
+        bank.total_deposits = bank.total_deposits.safe_sub(amount)?;
+        bank.total_deposit_shares = bank.total_deposit_shares.safe_sub(position.deposit_shares)?;
+        position.deposit_shares = 0;
+        position.pending_withdraw_amount = 0;
+        position.withdraw_requested_at = 0;
+        position.touch(bank, now);
+        if position.is_empty() {
+            user.remove_position(bank.key());
+        }
+        user.last_updated = now;
+        paid_out = paid_out.safe_add(amount)?;
+
+        position.exit(&crate::ID)?;
+        user.exit(&crate::ID)?;
+    }
+
+    emit!(BankDrained {
+        bank: bank.key(),
+        depositors: (ctx.remaining_accounts.len() / 3) as u32,
+        amount: paid_out,
+        remaining_deposit_shares: bank.total_deposit_shares,
+        event_seq: bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    reconcile_vault(&mut ctx.accounts.bank, &mut ctx.accounts.bank_token_account, 0, paid_out)
+}
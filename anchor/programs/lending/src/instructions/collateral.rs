@@ -0,0 +1,67 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::Mint;
+use crate::constants::POSITION_SEED;
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::CollateralToggled;
+use crate::health::calculate_health;
+
+#[derive(Accounts)]
+pub struct SetCollateral<'info> {
+    pub signer: Signer<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        seeds = [mint.key().as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [signer.key().as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+        constraint = !user_account.is_tokenized() @ ErrorCode::AccountTokenized,
+    )]
+    pub user_account: Account<'info, User>,
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), mint.key().as_ref()],
+        bump,
+    )]
+    pub user_position: Account<'info, UserPosition>,
+}
+
+// 1. Enable or disable the position's deposits as collateral, borrow-only banks can't be enabled
+// 2. Check the user's debt is still covered by the remaining collateral
+//    (remaining accounts: bank, position, price update and fixed loans for each of the user's positions)
+
+pub fn process_set_collateral(ctx: Context<SetCollateral>, enabled: bool) -> Result<()> {
+    let clock = Clock::get()?;
+    if enabled && !ctx.accounts.bank.is_collateral() {
+        msg!("Bank {} is borrow-only", ctx.accounts.bank.mint_address);
+        return Err(ErrorCode::BankNotCollateral.into());
+    }
+
+    let position = &mut ctx.accounts.user_position;
+    position.collateral_enabled = enabled;
+    position.last_updated = clock.unix_timestamp;
+
+    // Persist the position so the health check below sees the new flag
+    position.exit(&crate::ID)?;
+
+    let user = &mut ctx.accounts.user_account;
+    let health = calculate_health(user, &user.key(), ctx.remaining_accounts, &clock)?;
+    health.require_within_borrow_limit(ErrorCode::HealthFactorTooLow)?;
+    user.health_factor = health.health_factor();
+    user.last_updated = clock.unix_timestamp;
+
+    emit!(CollateralToggled {
+        user: user.owner,
+        bank: position.bank,
+        collateral_enabled: enabled,
+        health_factor: user.health_factor,
+        event_seq: user.next_event_seq(),
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
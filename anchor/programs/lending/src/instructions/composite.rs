@@ -0,0 +1,452 @@
+use anchor_lang::prelude::*;
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+use crate::constants::{FULL_AMOUNT, PAUSE_BORROWS, PAUSE_DEPOSITS, PAUSE_REPAYS, PAUSE_WITHDRAWALS, POSITION_SEED, PROTOCOL_SEED, STATS_SEED, TREASURY_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::{BorrowEvent, RepayEvent, WithdrawEvent};
+use crate::interest::accrue_interest;
+use crate::health::calculate_health;
+use crate::mint::{calculate_transfer_fee, gross_up_for_transfer_fee, reconcile_vault};
+use crate::safe_math::SafeMath;
+use super::admin::reserve_position;
+use super::borrow::mint_borrow_shares;
+use super::deposit::record_deposit;
+use super::repay::burn_borrow_shares;
+use super::withdraw::{burn_deposit_shares, require_withdrawable_while_deleveraging};
+
+#[derive(Accounts)]
+pub struct DepositAndBorrow<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = borrow_mint.key() != collateral_mint.key())]
+    pub borrow_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [collateral_mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, collateral_bank.key().as_ref()],
+        bump,
+    )]
+    pub collateral_bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, collateral_mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [borrow_mint.key().as_ref()],
+        bump,
+    )]
+    pub borrow_bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, borrow_bank.key().as_ref()],
+        bump,
+    )]
+    pub borrow_bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, borrow_mint.key().as_ref()],
+        bump,
+    )]
+    pub borrow_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [signer.key().as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+        constraint = !user_account.is_tokenized() @ ErrorCode::AccountTokenized,
+    )]
+    pub user_account: Account<'info, User>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), collateral_mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_position: Account<'info, UserPosition>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), borrow_mint.key().as_ref()],
+        bump,
+    )]
+    pub borrow_position: Account<'info, UserPosition>,
+    #[account(
+        mut,
+        associated_token::mint = collateral_mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_collateral_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = borrow_mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_borrow_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+#[derive(Accounts)]
+pub struct RepayAndWithdraw<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = repay_mint.key() != collateral_mint.key())]
+    pub repay_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [collateral_mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, collateral_bank.key().as_ref()],
+        bump,
+    )]
+    pub collateral_bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, collateral_mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [repay_mint.key().as_ref()],
+        bump,
+    )]
+    pub repay_bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, repay_bank.key().as_ref()],
+        bump,
+    )]
+    pub repay_bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, repay_mint.key().as_ref()],
+        bump,
+    )]
+    pub repay_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [signer.key().as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+        constraint = !user_account.is_tokenized() @ ErrorCode::AccountTokenized,
+    )]
+    pub user_account: Account<'info, User>,
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), collateral_mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_position: Account<'info, UserPosition>,
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), repay_mint.key().as_ref()],
+        bump,
+    )]
+    pub repay_position: Account<'info, UserPosition>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = collateral_mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_collateral_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        associated_token::mint = repay_mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_repay_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+// 1. Accrue interest on both banks up to now
+// 2. Deposit into the collateral bank, crediting the tokens received after any transfer fee
+// 3. Borrow from the borrow bank
+// 4. Check once, after both legs, that the user's collateral covers the new debt at max LTV
+//    (remaining accounts: bank, position, price update and fixed loans for each of the user's positions
+//    after the deposit and borrow have opened theirs)
+
+pub fn process_deposit_and_borrow(ctx: Context<DepositAndBorrow>, deposit_amount: u64, borrow_amount: u64) -> Result<()> {
+    ctx.accounts.collateral_bank.require_not_paused(PAUSE_DEPOSITS)?;
+    ctx.accounts.borrow_bank.require_not_paused(PAUSE_BORROWS)?;
+    ctx.accounts.protocol_config.require_not_deleveraging()?;
+    ctx.accounts.collateral_bank.require_ungated()?;
+    ctx.accounts.borrow_bank.require_ungated()?;
+
+    let clock = Clock::get()?;
+    let now = clock.unix_timestamp;
+    accrue_interest(&mut ctx.accounts.collateral_bank, now)?;
+    accrue_interest(&mut ctx.accounts.borrow_bank, now)?;
+
+    let collateral_bank = &ctx.accounts.collateral_bank;
+    collateral_bank.require_deposit_cap(deposit_amount)?;
+    let borrow_bank = &ctx.accounts.borrow_bank;
+    borrow_bank.require_borrow_cap(borrow_amount)?;
+
+    let cpi_program = ctx.accounts.token_program.to_account_info();
+
+    let deposit_cpi_accounts = TransferChecked {
+        from: ctx.accounts.user_collateral_token_account.to_account_info(),
+        mint: ctx.accounts.collateral_mint.to_account_info(),
+        to: ctx.accounts.collateral_bank_token_account.to_account_info(),
+        authority: ctx.accounts.signer.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(cpi_program.clone(), deposit_cpi_accounts);
+    token_interface::transfer_checked(cpi_ctx, deposit_amount, ctx.accounts.collateral_mint.decimals)?;
+
+    let received = deposit_amount.safe_sub(calculate_transfer_fee(&ctx.accounts.collateral_mint.to_account_info(), deposit_amount)?)?;
+    reconcile_vault(&mut ctx.accounts.collateral_bank, &mut ctx.accounts.collateral_bank_token_account, received, 0)?;
+    reserve_position(
+        &ctx.accounts.protocol_config,
+        &ctx.accounts.user_account,
+        &ctx.accounts.collateral_bank.key(),
+        &ctx.accounts.signer.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+    )?;
+    record_deposit(
+        &mut ctx.accounts.collateral_bank,
+        &mut ctx.accounts.collateral_bank_stats,
+        &mut ctx.accounts.user_account,
+        &mut ctx.accounts.collateral_position,
+        ctx.accounts.signer.key(),
+        received,
+        now,
+        None,
+    )?;
+
+    let borrow_cpi_accounts = TransferChecked {
+        from: ctx.accounts.borrow_bank_token_account.to_account_info(),
+        mint: ctx.accounts.borrow_mint.to_account_info(),
+        to: ctx.accounts.user_borrow_token_account.to_account_info(),
+        authority: ctx.accounts.borrow_bank_token_account.to_account_info(),
+    };
+    let borrow_mint_key = ctx.accounts.borrow_mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[
+        &[
+            TREASURY_SEED,
+            borrow_mint_key.as_ref(),
+            &[ctx.bumps.borrow_bank_token_account],
+        ],
+    ];
+    let cpi_ctx = CpiContext::new(cpi_program, borrow_cpi_accounts).with_signer(signer_seeds);
+    token_interface::transfer_checked(cpi_ctx, borrow_amount, ctx.accounts.borrow_mint.decimals)?;
+    reconcile_vault(&mut ctx.accounts.borrow_bank, &mut ctx.accounts.borrow_bank_token_account, 0, borrow_amount)?;
+
+    reserve_position(
+        &ctx.accounts.protocol_config,
+        &ctx.accounts.user_account,
+        &ctx.accounts.borrow_bank.key(),
+        &ctx.accounts.signer.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+    )?;
+
+    let borrow_shares = mint_borrow_shares(
+        &mut ctx.accounts.borrow_bank,
+        &mut ctx.accounts.borrow_bank_stats,
+        &mut ctx.accounts.user_account,
+        &mut ctx.accounts.borrow_position,
+        borrow_amount,
+        now,
+    )?;
+
+    // Persist the updated accounts so the health check below sees the state after both legs
+    ctx.accounts.collateral_bank.exit(&crate::ID)?;
+    ctx.accounts.collateral_position.exit(&crate::ID)?;
+    ctx.accounts.borrow_bank.exit(&crate::ID)?;
+    ctx.accounts.borrow_position.exit(&crate::ID)?;
+
+    let user = &mut ctx.accounts.user_account;
+    let health = calculate_health(user, &user.key(), ctx.remaining_accounts, &clock)?;
+    health.require_within_borrow_headroom(ctx.accounts.protocol_config.borrow_headroom_bps, ErrorCode::InsufficientCollateral)?;
+    user.health_factor = health.health_factor();
+    user.last_updated = now;
+
+    let borrow_bank = &mut ctx.accounts.borrow_bank;
+    emit!(BorrowEvent {
+        user: user.owner,
+        borrower: user.owner,
+        mint: borrow_mint_key,
+        amount: borrow_amount,
+        shares: borrow_shares,
+        user_borrowed: borrow_bank.borrow_amount_for(ctx.accounts.borrow_position.borrow_shares)?,
+        position_pnl: ctx.accounts.borrow_position.pnl,
+        bank_total_borrowed: borrow_bank.total_borrowed,
+        client_tag: None,
+        event_seq: borrow_bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
+
+// 1. Accrue interest on both banks up to now
+// 2. Repay the debt in the repay bank, `u64::MAX` repays all of it
+// 3. Withdraw from the collateral bank, `u64::MAX` withdraws all of it, less the bank's withdrawal fee
+// 4. Check once, after both legs, that the remaining collateral covers the remaining debt
+//    (remaining accounts: bank, position, price update and fixed loans for each of the user's positions
+//    after the repay and withdrawal have closed theirs)
+
+pub fn process_repay_and_withdraw(ctx: Context<RepayAndWithdraw>, repay_amount: u64, withdraw_amount: u64) -> Result<()> {
+    ctx.accounts.repay_bank.require_not_paused(PAUSE_REPAYS)?;
+    ctx.accounts.collateral_bank.require_not_paused(PAUSE_WITHDRAWALS)?;
+    ctx.accounts.collateral_bank.require_no_withdraw_cooldown()?;
+
+    let clock = Clock::get()?;
+    let now = clock.unix_timestamp;
+    accrue_interest(&mut ctx.accounts.repay_bank, now)?;
+    accrue_interest(&mut ctx.accounts.collateral_bank, now)?;
+
+    let borrowed_asset = ctx.accounts.repay_bank.borrow_amount_for(ctx.accounts.repay_position.borrow_shares)?;
+    let repay_mint_info = ctx.accounts.repay_mint.to_account_info();
+    let (repay_amount, repaid) = if repay_amount == FULL_AMOUNT {
+        (gross_up_for_transfer_fee(&repay_mint_info, borrowed_asset)?, borrowed_asset)
+    } else {
+        (repay_amount, repay_amount.safe_sub(calculate_transfer_fee(&repay_mint_info, repay_amount)?)?)
+    };
+    if repaid > borrowed_asset {
+        msg!("Repaying {} but only {} is borrowed", repaid, borrowed_asset);
+        return Err(ErrorCode::OverRepay.into());
+    }
+
+    let deposited_value = ctx.accounts.collateral_bank.deposit_amount_for(ctx.accounts.collateral_position.deposit_shares)?;
+    let withdraw_amount = if withdraw_amount == FULL_AMOUNT { deposited_value } else { withdraw_amount };
+    if withdraw_amount > deposited_value {
+        msg!("Withdrawing {} but only {} is deposited", withdraw_amount, deposited_value);
+        return Err(ErrorCode::InsufficientFunds.into());
+    }
+
+    let cpi_program = ctx.accounts.token_program.to_account_info();
+
+    let repay_cpi_accounts = TransferChecked {
+        from: ctx.accounts.user_repay_token_account.to_account_info(),
+        mint: repay_mint_info,
+        to: ctx.accounts.repay_bank_token_account.to_account_info(),
+        authority: ctx.accounts.signer.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(cpi_program.clone(), repay_cpi_accounts);
+    token_interface::transfer_checked(cpi_ctx, repay_amount, ctx.accounts.repay_mint.decimals)?;
+    let received = repay_amount.safe_sub(calculate_transfer_fee(&ctx.accounts.repay_mint.to_account_info(), repay_amount)?)?;
+    reconcile_vault(&mut ctx.accounts.repay_bank, &mut ctx.accounts.repay_bank_token_account, received, 0)?;
+
+    let repaid_shares = burn_borrow_shares(
+        &mut ctx.accounts.repay_bank,
+        &mut ctx.accounts.repay_bank_stats,
+        &mut ctx.accounts.user_account,
+        &mut ctx.accounts.repay_position,
+        repaid,
+        borrowed_asset,
+        now,
+    )?;
+
+    let withdraw_fee = ctx.accounts.collateral_bank.withdraw_fee(withdraw_amount)?;
+    let withdraw_cpi_accounts = TransferChecked {
+        from: ctx.accounts.collateral_bank_token_account.to_account_info(),
+        mint: ctx.accounts.collateral_mint.to_account_info(),
+        to: ctx.accounts.user_collateral_token_account.to_account_info(),
+        authority: ctx.accounts.collateral_bank_token_account.to_account_info(),
+    };
+    let collateral_mint_key = ctx.accounts.collateral_mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[
+        &[
+            TREASURY_SEED,
+            collateral_mint_key.as_ref(),
+            &[ctx.bumps.collateral_bank_token_account],
+        ],
+    ];
+    let cpi_ctx = CpiContext::new(cpi_program, withdraw_cpi_accounts).with_signer(signer_seeds);
+    token_interface::transfer_checked(cpi_ctx, withdraw_amount.safe_sub(withdraw_fee)?, ctx.accounts.collateral_mint.decimals)?;
+    reconcile_vault(&mut ctx.accounts.collateral_bank, &mut ctx.accounts.collateral_bank_token_account, 0, withdraw_amount.safe_sub(withdraw_fee)?)?;
+
+    let withdrawn_shares = burn_deposit_shares(
+        &mut ctx.accounts.collateral_bank,
+        &mut ctx.accounts.collateral_bank_stats,
+        &mut ctx.accounts.user_account,
+        &mut ctx.accounts.collateral_position,
+        withdraw_amount,
+        now,
+    )?;
+    let collateral_bank = &mut ctx.accounts.collateral_bank;
+    collateral_bank.total_reserves = collateral_bank.total_reserves.safe_add(withdraw_fee)?;
+    let collateral_position = &mut ctx.accounts.collateral_position;
+    collateral_position.pnl.fees_paid = collateral_position.pnl.fees_paid.safe_add(withdraw_fee)?;
+
+    // Persist the updated accounts so the health check below sees the state after both legs
+    ctx.accounts.repay_bank.exit(&crate::ID)?;
+    ctx.accounts.repay_position.exit(&crate::ID)?;
+    ctx.accounts.collateral_bank.exit(&crate::ID)?;
+    ctx.accounts.collateral_position.exit(&crate::ID)?;
+
+    let user = &mut ctx.accounts.user_account;
+    let health = calculate_health(user, &user.key(), ctx.remaining_accounts, &clock)?;
+    health.require_within_borrow_limit(ErrorCode::HealthFactorTooLow)?;
+    require_withdrawable_while_deleveraging(&ctx.accounts.protocol_config, &health, &clock)?;
+    user.health_factor = health.health_factor();
+    user.last_updated = now;
+
+    let repay_bank = &mut ctx.accounts.repay_bank;
+    emit!(RepayEvent {
+        user: user.owner,
+        payer: ctx.accounts.signer.key(),
+        mint: ctx.accounts.repay_mint.key(),
+        amount: repaid,
+        shares: repaid_shares,
+        user_borrowed: repay_bank.borrow_amount_for(ctx.accounts.repay_position.borrow_shares)?,
+        position_pnl: ctx.accounts.repay_position.pnl,
+        bank_total_borrowed: repay_bank.total_borrowed,
+        client_tag: None,
+        event_seq: repay_bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    let collateral_bank = &mut ctx.accounts.collateral_bank;
+    emit!(WithdrawEvent {
+        user: user.owner,
+        mint: collateral_mint_key,
+        amount: withdraw_amount,
+        fee: withdraw_fee,
+        shares: withdrawn_shares,
+        user_deposited: collateral_bank.deposit_amount_for(ctx.accounts.collateral_position.deposit_shares)?,
+        position_pnl: ctx.accounts.collateral_position.pnl,
+        bank_total_deposits: collateral_bank.total_deposits,
+        destination: ctx.accounts.user_collateral_token_account.key(),
+        client_tag: None,
+        event_seq: collateral_bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
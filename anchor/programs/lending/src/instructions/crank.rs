@@ -0,0 +1,264 @@
+use anchor_lang::prelude::*;
+use crate::constants::HEALTH_REFRESH_SEED;
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::{BankCheckpointed, HealthRefreshed, InterestAccrued, PriceEmaUpdated, StakePoolRateRefreshed, UserHealthMarked};
+use crate::interest::{accrue_interest, borrow_rate, checkpoint_interest};
+use crate::health::{calculate_health, value_position};
+use crate::math::aggregate_health;
+use crate::oracle::{get_oracle_price, read_stake_pool_rate, update_price_ema};
+
+#[derive(Accounts)]
+pub struct AccrueInterest<'info> {
+    #[account(
+        mut,
+        seeds = [bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+}
+
+#[derive(Accounts)]
+pub struct CheckpointBank<'info> {
+    #[account(
+        mut,
+        seeds = [bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePriceEma<'info> {
+    #[account(
+        mut,
+        seeds = [bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    /// CHECK: Account of one of the bank's oracles, validated by `get_oracle_price`
+    pub price_update: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RefreshStakePoolRate<'info> {
+    #[account(
+        mut,
+        seeds = [bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    /// CHECK: Stake pool of the bank's `StakePool` oracle, validated by `read_stake_pool_rate`
+    pub stake_pool: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct FlagPosition<'info> {
+    #[account(
+        mut,
+        seeds = [user_account.owner.as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+    )]
+    pub user_account: Account<'info, User>,
+}
+
+#[derive(Accounts)]
+pub struct RefreshPositions<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [user_account.owner.as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+    )]
+    pub user_account: Account<'info, User>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + HealthRefresh::INIT_SPACE,
+        seeds = [HEALTH_REFRESH_SEED, user_account.key().as_ref()],
+        bump,
+    )]
+    pub health_refresh: Account<'info, HealthRefresh>,
+    pub system_program: Program<'info, System>,
+}
+
+// Permissionless, lets keepers checkpoint interest on banks nobody has interacted with recently
+pub fn process_accrue_interest(ctx: Context<AccrueInterest>) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    let bank = &mut ctx.accounts.bank;
+    accrue_interest(bank, now)?;
+
+    emit!(InterestAccrued {
+        bank: bank.key(),
+        total_deposits: bank.total_deposits,
+        total_borrowed: bank.total_borrowed,
+        total_reserves: bank.total_reserves,
+        borrow_rate: borrow_rate(bank),
+        event_seq: bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
+
+// Permissionless, lets keepers realize depositor yield on inactive banks, including banks that only accrue
+// every `min_accrual_interval_secs`. Interest up to now is folded into the deposit and borrow exchange rates
+// and the reserve cut is split off to the reserves and insurance fund, `distribute_fees` pays the reserves out.
+pub fn process_checkpoint_bank(ctx: Context<CheckpointBank>) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    let bank = &mut ctx.accounts.bank;
+    checkpoint_interest(bank, now)?;
+
+    emit!(BankCheckpointed {
+        bank: bank.key(),
+        supply_index: bank.supply_index,
+        borrow_index: bank.borrow_index,
+        total_reserves: bank.total_reserves,
+        insurance_fund: bank.insurance_fund,
+        borrow_rate: borrow_rate(bank),
+        event_seq: bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
+
+// Permissionless, moves the bank's price EMA towards the current oracle price. Keepers call it regularly,
+// banks valued at their EMA reject health checks once it is older than the EMA window
+pub fn process_update_price_ema(ctx: Context<UpdatePriceEma>) -> Result<()> {
+    let clock = Clock::get()?;
+    let bank = &mut ctx.accounts.bank;
+    // The EMA only follows the oracles, never a price override
+    let price = get_oracle_price(bank, &ctx.accounts.price_update.to_account_info(), &clock)?;
+    update_price_ema(bank, &price, clock.unix_timestamp)?;
+
+    emit!(PriceEmaUpdated {
+        bank: bank.key(),
+        price: price.price,
+        ema_price: bank.ema_price,
+        exponent: bank.ema_exponent,
+        event_seq: bank.next_event_seq(),
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Permissionless, caches the SOL per pool token of the stake pool behind the bank's primary or fallback `StakePool`
+// oracle. Keepers call it once the pool has updated its balances for the epoch, the bank stops being priced
+// through the pool once its cached rate is more than an epoch old
+pub fn process_refresh_stake_pool_rate(ctx: Context<RefreshStakePoolRate>) -> Result<()> {
+    let bank = &mut ctx.accounts.bank;
+    let pool = ctx.accounts.stake_pool.key();
+    let uses_pool = std::iter::once(bank.oracle)
+        .chain(bank.fallback_oracle)
+        .any(|source| source == OracleSource::StakePool { pool });
+    if !uses_pool {
+        msg!("Stake pool {} is not an oracle of {}", pool, bank.mint_address);
+        return Err(ErrorCode::InvalidOracleAccount.into());
+    }
+
+    let (rate, epoch) = read_stake_pool_rate(&ctx.accounts.stake_pool.to_account_info(), &bank.mint_address)?;
+    bank.stake_pool_rate = rate;
+    bank.stake_pool_rate_epoch = epoch;
+
+    emit!(StakePoolRateRefreshed {
+        bank: bank.key(),
+        pool,
+        rate,
+        epoch,
+        event_seq: bank.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Permissionless, flags a liquidatable user at the current slot, starting both the liquidation auction and the
+// grace window of banks that have one, or clears the flag once the user is healthy again so a later auction
+// and grace window start from scratch
+// (remaining accounts: bank, position, price update and fixed loans for each of the user's positions)
+pub fn process_flag_position(ctx: Context<FlagPosition>) -> Result<()> {
+    let clock = Clock::get()?;
+    let user = &mut ctx.accounts.user_account;
+    let health = calculate_health(user, &user.key(), ctx.remaining_accounts, &clock)?;
+
+    if !health.is_liquidatable() {
+        user.unhealthy_since_slot = 0;
+    } else if user.unhealthy_since_slot == 0 {
+        user.unhealthy_since_slot = clock.slot;
+    }
+    user.record_health_checkpoint(&health, clock.slot);
+    user.last_updated = clock.unix_timestamp;
+
+    emit!(UserHealthMarked {
+        user: user.owner,
+        health_factor: user.health_factor,
+        unhealthy_since_slot: user.unhealthy_since_slot,
+        event_seq: user.next_event_seq(),
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Permissionless, values one page of the user's positions so accounts whose health check doesn't fit a single
+// transaction can be recalculated across several. Pages follow the order of the user's positions and a page
+// starting at zero restarts the refresh. Once every position is valued, the aggregate is cached on the user account.
+// (remaining accounts: bank, position, price update and fixed loans for each position of the page)
+pub fn process_refresh_positions(ctx: Context<RefreshPositions>, start: u8) -> Result<()> {
+    let clock = Clock::get()?;
+    let user = &mut ctx.accounts.user_account;
+    let refresh = &mut ctx.accounts.health_refresh;
+    let start = start as usize;
+
+    if start == 0 {
+        refresh.user = user.key();
+        refresh.positions.clear();
+        refresh.started_slot = clock.slot;
+        refresh.bump = ctx.bumps.health_refresh;
+    } else if start != refresh.positions.len() {
+        msg!("Refresh of {} has valued {} positions, got a page starting at {}", user.key(), refresh.positions.len(), start);
+        return Err(ErrorCode::HealthRefreshOutOfOrder.into());
+    }
+
+    // Positions opened or closed since the first page invalidate the pages valued so far
+    let unchanged = refresh.positions.iter().zip(&user.positions).all(|(valued, bank)| valued.bank == *bank);
+    if start > user.positions.len() || !unchanged {
+        msg!("Page of positions from {} doesn't match the {} positions of {}", start, user.positions.len(), user.key());
+        return Err(ErrorCode::HealthRefreshOutOfOrder.into());
+    }
+
+    // The page covers as many positions as it has health accounts for
+    let mut accounts = ctx.remaining_accounts;
+    for bank_key in &user.positions[start..] {
+        if accounts.is_empty() {
+            break;
+        }
+        let (position, used) = value_position(bank_key, &user.key(), accounts, &clock)?;
+        refresh.positions.push(position);
+        accounts = &accounts[used..];
+    }
+    if !accounts.is_empty() {
+        msg!("{} health accounts left over after the last position of {}", accounts.len(), user.key());
+        return Err(ErrorCode::InvalidHealthAccounts.into());
+    }
+
+    if refresh.positions.len() == user.positions.len() {
+        let health = aggregate_health(&refresh.positions, user.emode_category)?;
+        user.record_health_checkpoint(&health, refresh.started_slot);
+        user.last_updated = clock.unix_timestamp;
+    }
+
+    emit!(HealthRefreshed {
+        user: user.owner,
+        valued_positions: refresh.positions.len() as u8,
+        total_positions: user.positions.len() as u8,
+        health_factor: user.health_factor,
+        event_seq: user.next_event_seq(),
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
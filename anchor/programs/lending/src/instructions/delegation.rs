@@ -0,0 +1,54 @@
+use anchor_lang::prelude::*;
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::{DelegateApproved, DelegateRevoked};
+
+#[derive(Accounts)]
+pub struct UpdateDelegate<'info> {
+    pub signer: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [signer.key().as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+        constraint = !user_account.is_tokenized() @ ErrorCode::AccountTokenized,
+    )]
+    pub user_account: Account<'info, User>,
+}
+
+// Lets `delegate` borrow up to `allowance` tokens from `bank` against the user's collateral,
+// the debt is the user's and the borrowed tokens go to the delegate
+
+pub fn process_approve_delegate(ctx: Context<UpdateDelegate>, delegate: Pubkey, bank: Pubkey, allowance: u64) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    let user = &mut ctx.accounts.user_account;
+    user.approve_delegate(delegate, bank, allowance)?;
+    user.last_updated = now;
+
+    emit!(DelegateApproved {
+        user: user.owner,
+        delegate,
+        bank,
+        allowance,
+        event_seq: user.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
+
+pub fn process_revoke_delegate(ctx: Context<UpdateDelegate>, delegate: Pubkey, bank: Pubkey) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    let user = &mut ctx.accounts.user_account;
+    user.revoke_delegate(delegate, bank);
+    user.last_updated = now;
+
+    emit!(DelegateRevoked {
+        user: user.owner,
+        delegate,
+        bank,
+        event_seq: user.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
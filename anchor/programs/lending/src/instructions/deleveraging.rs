@@ -0,0 +1,41 @@
+use anchor_lang::prelude::*;
+use crate::constants::{MAX_DELEVERAGING_SLOTS, PROTOCOL_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::DeleveragingModeSet;
+
+// Deleveraging mode lets the protocol shed exposure during market stress without a full pause: new borrows
+// and withdrawals of collateral still backing debt are blocked, while deposits, repayments, liquidations and
+// withdrawals by users without debt carry on. The risk manager turns it on until a slot, after which it lapses
+// on its own unless renewed.
+
+#[derive(Accounts)]
+pub struct SetDeleveragingMode<'info> {
+    pub risk_manager: Signer<'info>,
+    #[account(
+        mut,
+        has_one = risk_manager @ ErrorCode::Unauthorized,
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+// `until_slot` of zero turns the mode off, otherwise it must be in the future and within `MAX_DELEVERAGING_SLOTS`
+pub fn process_set_deleveraging_mode(ctx: Context<SetDeleveragingMode>, until_slot: u64) -> Result<()> {
+    let clock = Clock::get()?;
+    if until_slot != 0 && (until_slot <= clock.slot || until_slot - clock.slot > MAX_DELEVERAGING_SLOTS) {
+        msg!("Deleveraging until slot {} must be after slot {} and within {} slots", until_slot, clock.slot, MAX_DELEVERAGING_SLOTS);
+        return Err(ErrorCode::InvalidDeleveragingExpiry.into());
+    }
+    ctx.accounts.protocol_config.deleveraging_until_slot = until_slot;
+
+    emit!(DeleveragingModeSet {
+        risk_manager: ctx.accounts.risk_manager.key(),
+        until_slot,
+        event_seq: ctx.accounts.protocol_config.next_event_seq(),
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
@@ -1,10 +1,20 @@
 use anchor_lang::prelude::*;
 use anchor_spl::associated_token::AssociatedToken;
 use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+use crate::constants::{sub_account_seed, INITIAL_POSITIONS, PAUSE_DEPOSITS, POSITION_SEED, PROTOCOL_SEED, PROTOCOL_STATS_SEED, STATS_SEED, TREASURY_SEED};
 use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::DepositEvent;
+use crate::interest::accrue_interest;
+use crate::mint::{calculate_transfer_fee, reconcile_vault};
+use crate::rewards::settle_rewards;
+use crate::safe_math::SafeMath;
+use super::admin::{initialize_user, reserve_position};
+use super::gate::require_gate_pass;
 
 #[derive(Accounts)]
 pub struct Deposit<'info> {
+    /// Payer of the deposit, may deposit into another user's existing position
     #[account(mut)]
     pub signer: Signer<'info>,
     pub mint: InterfaceAccount<'info, Mint>,
@@ -16,16 +26,31 @@ pub struct Deposit<'info> {
     pub bank: Account<'info, Bank>,
     #[account(
         mut,
-        seeds = [b"treasury", mint.key().as_ref()],
+        seeds = [STATS_SEED, bank.key().as_ref()],
+        bump,
+    )]
+    pub bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, mint.key().as_ref()],
         bump
     )]
     pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
     #[account(
         mut,
-        seeds = [signer.key().as_ref()],
+        seeds = [user_account.owner.as_ref(), user_account.sub_account_seed().as_slice()],
         bump
     )]
+    /// Account of the user credited with the deposit
     pub user_account: Account<'info, User>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), mint.key().as_ref()],
+        bump,
+    )]
+    pub user_position: Account<'info, UserPosition>,
     #[account(
         mut,
         associated_token::mint = mint,
@@ -36,53 +61,275 @@ pub struct Deposit<'info> {
     pub token_program: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
+    /// CHECK: Gate pass of the user account's owner for a permissioned bank, validated by `require_gate_pass`
+    pub gate_pass: Option<UncheckedAccount<'info>>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
 }
 
-// 1. CPI transfer from user's token account to bank's token account
-// 2. Calculate new shares to be added to the bank
-// 3. Update user's deposited amount and total collateral value
-// 4. Update bank's total depoait and total depoait shares
-// 5. Update users health factor ??
+#[derive(Accounts)]
+#[instruction(amount: u64, referrer: Option<Pubkey>, client_tag: Option<[u8; 16]>, sub_account_id: u16)]
+pub struct DepositWithInit<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [mint.key().as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, bank.key().as_ref()],
+        bump,
+    )]
+    pub bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, mint.key().as_ref()],
+        bump
+    )]
+    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// Sub-account of the signer, created on its first deposit
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = User::space_for(INITIAL_POSITIONS),
+        seeds = [signer.key().as_ref(), sub_account_seed(sub_account_id).as_slice()],
+        bump,
+    )]
+    pub user_account: Account<'info, User>,
+    #[account(
+        mut,
+        seeds = [PROTOCOL_STATS_SEED],
+        bump,
+    )]
+    pub protocol_stats: Account<'info, ProtocolStats>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), mint.key().as_ref()],
+        bump,
+    )]
+    pub user_position: Account<'info, UserPosition>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    /// CHECK: Gate pass of the user account's owner for a permissioned bank, validated by `require_gate_pass`
+    pub gate_pass: Option<UncheckedAccount<'info>>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+// 1. Accrue interest on the bank up to now, only the owner may open a new position
+// 2. Check the deposit stays within the bank's deposit cap
+// 3. CPI transfer from user's token account to bank's token account
+// 4. Calculate new shares to be minted for the tokens received after any Token-2022 transfer fee and the bank's
+//    deposit fee, rounded down, the deposit fee goes to the reserves
+// 5. Update user's position in the bank, opening it on the first deposit
+// 6. Update bank's total deposits and total deposit shares
+
+pub fn process_deposit(ctx: Context<Deposit>, amount: u64, client_tag: Option<[u8; 16]>) -> Result<()> {
+    ctx.accounts.bank.require_not_paused(PAUSE_DEPOSITS)?;
+    require_gate_pass(&ctx.accounts.bank, &ctx.accounts.user_account.owner, ctx.accounts.gate_pass.as_deref())?;
+    reserve_position(
+        &ctx.accounts.protocol_config,
+        &ctx.accounts.user_account,
+        &ctx.accounts.bank.key(),
+        &ctx.accounts.signer.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+    )?;
+
+    // Third parties can top up an existing position but not add new ones to the user's position list
+    if ctx.accounts.signer.key() != ctx.accounts.user_account.owner && ctx.accounts.user_position.user == Pubkey::default() {
+        return Err(ErrorCode::PositionNotOpen.into());
+    }
+
+    let now = Clock::get()?.unix_timestamp;
+    accrue_interest(&mut ctx.accounts.bank, now)?;
+
+    ctx.accounts.bank.require_deposit_cap(amount)?;
+    let received = transfer_deposit(
+        &ctx.accounts.signer,
+        &ctx.accounts.mint,
+        &mut ctx.accounts.bank,
+        &ctx.accounts.user_token_account,
+        &mut ctx.accounts.bank_token_account,
+        &ctx.accounts.token_program,
+        amount,
+    )?;
+
+    record_deposit(
+        &mut ctx.accounts.bank,
+        &mut ctx.accounts.bank_stats,
+        &mut ctx.accounts.user_account,
+        &mut ctx.accounts.user_position,
+        ctx.accounts.signer.key(),
+        received,
+        now,
+        client_tag,
+    )
+}
+
+// First deposit of a new user in one transaction: creates the user account unless it exists,
+// then deposits like `deposit`. `referrer` only applies when the account is created.
+
+pub fn process_deposit_with_init(
+    ctx: Context<DepositWithInit>,
+    amount: u64,
+    referrer: Option<Pubkey>,
+    client_tag: Option<[u8; 16]>,
+    sub_account_id: u16,
+) -> Result<()> {
+    ctx.accounts.bank.require_not_paused(PAUSE_DEPOSITS)?;
+    require_gate_pass(&ctx.accounts.bank, &ctx.accounts.signer.key(), ctx.accounts.gate_pass.as_deref())?;
+
+    let now = Clock::get()?.unix_timestamp;
+    if ctx.accounts.user_account.owner == Pubkey::default() {
+        initialize_user(
+            &mut ctx.accounts.user_account,
+            &mut ctx.accounts.protocol_stats,
+            ctx.accounts.signer.key(),
+            sub_account_id,
+            referrer,
+            now,
+        )?;
+    }
 
-pub fn process_deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
+    reserve_position(
+        &ctx.accounts.protocol_config,
+        &ctx.accounts.user_account,
+        &ctx.accounts.bank.key(),
+        &ctx.accounts.signer.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+    )?;
+
+    accrue_interest(&mut ctx.accounts.bank, now)?;
+    ctx.accounts.bank.require_deposit_cap(amount)?;
+    let received = transfer_deposit(
+        &ctx.accounts.signer,
+        &ctx.accounts.mint,
+        &mut ctx.accounts.bank,
+        &ctx.accounts.user_token_account,
+        &mut ctx.accounts.bank_token_account,
+        &ctx.accounts.token_program,
+        amount,
+    )?;
+
+    record_deposit(
+        &mut ctx.accounts.bank,
+        &mut ctx.accounts.bank_stats,
+        &mut ctx.accounts.user_account,
+        &mut ctx.accounts.user_position,
+        ctx.accounts.signer.key(),
+        received,
+        now,
+        client_tag,
+    )
+}
+
+/// CPI transfer of `amount` tokens from the signer to the bank's vault, reconciled with the vault's balance.
+/// Returns the tokens that reached the vault after any Token-2022 transfer fee, only those are credited.
+fn transfer_deposit<'info>(
+    signer: &Signer<'info>,
+    mint: &InterfaceAccount<'info, Mint>,
+    bank: &mut Bank,
+    user_token_account: &InterfaceAccount<'info, TokenAccount>,
+    bank_token_account: &mut InterfaceAccount<'info, TokenAccount>,
+    token_program: &Interface<'info, TokenInterface>,
+    amount: u64,
+) -> Result<u64> {
     let transfer_cpi_accounts = TransferChecked {
-        from: ctx.accounts.user_token_account.to_account_info(),
-        mint: ctx.accounts.mint.to_account_info(),
-        to: ctx.accounts.bank_token_account.to_account_info(),
-        authority: ctx.accounts.signer.to_account_info()
+        from: user_token_account.to_account_info(),
+        mint: mint.to_account_info(),
+        to: bank_token_account.to_account_info(),
+        authority: signer.to_account_info()
     };
-    let cpi_program = ctx.accounts.token_program.to_account_info();
-    let cpi_ctx = CpiContext::new(cpi_program, transfer_cpi_accounts);
-    let decimals = ctx.accounts.mint.decimals;
+    let cpi_ctx = CpiContext::new(token_program.to_account_info(), transfer_cpi_accounts);
+    token_interface::transfer_checked(cpi_ctx, amount, mint.decimals)?;
 
-    token_interface::transfer_checked(cpi_ctx, amount, decimals)?;
+    let received = amount.safe_sub(calculate_transfer_fee(&mint.to_account_info(), amount)?)?;
+    reconcile_vault(bank, bank_token_account, received, 0)?;
+    Ok(received)
+}
 
-    let bank = &mut ctx.accounts.bank;
+/// Mints deposit shares for `amount` tokens already received by the bank's vault
+/// and credits them to the user's position, opening it on the first deposit.
+/// The bank's deposit fee is taken out of `amount` and added to the reserves. Deposits by the owner
+/// start the bank's same-slot deposit lock on the position.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn record_deposit<'info>(
+    bank: &mut Account<'info, Bank>,
+    stats: &mut BankStats,
+    user: &mut Account<'info, User>,
+    position: &mut Account<'info, UserPosition>,
+    payer: Pubkey,
+    amount: u64,
+    now: i64,
+    client_tag: Option<[u8; 16]>,
+) -> Result<()> {
+    let fee = bank.deposit_fee(amount)?;
+    let amount = amount.safe_sub(fee)?;
+    bank.require_deposit_minimum(amount)?;
+    bank.require_wallet_deposit_cap(bank.deposit_amount_for(position.deposit_shares)?.safe_add(amount)?)?;
+    bank.require_self_borrow_allowed(position, true)?;
+    let users_shares = bank.deposit_shares_minted(amount)?;
 
-    if bank.total_deposits == 0 {
-        bank.total_deposits = amount;
-        bank.total_deposit_shares = amount;
+    if position.user == Pubkey::default() {
+        position.user = user.key();
+        position.bank = bank.key();
+        position.collateral_enabled = bank.is_collateral();
+        stats.unique_users = stats.unique_users.safe_add(1)?;
     }
+    user.add_position(bank.key())?;
 
-    let deposit_ratio = amount.checked_div(bank.total_deposits).unwrap();
-    let users_shares = bank.total_deposit_shares.checked_mul(deposit_ratio).unwrap();
+    settle_rewards(bank, position)?;
+    position.deposit_shares = position.deposit_shares.safe_add(users_shares)?;
+    position.pnl.fees_paid = position.pnl.fees_paid.safe_add(fee)?;
+    // Only the owner's own deposits lock the position, deposits by others could otherwise keep it locked every slot
+    if payer == user.owner {
+        position.last_deposit_slot = Clock::get()?.slot;
+    }
+    position.touch(bank, now);
 
-    let user = &mut ctx.accounts.user_account;
+    bank.total_deposits = bank.total_deposits.safe_add(amount)?;
+    bank.total_deposit_shares = bank.total_deposit_shares.safe_add(users_shares)?;
+    bank.total_reserves = bank.total_reserves.safe_add(fee)?;
 
-    match ctx.accounts.mint.to_account_info().key() {
-        key if key == user.usdc_address => {
-            user.deposited_usdc += amount;
-            user.deposited_usdc_shares += users_shares;
-        },
-        _ => {
-            user.deposited_sol += amount;
-            user.deposited_sol_shares += users_shares;
-        }
-    }
+    user.last_updated = now;
 
-    bank.total_deposits += amount;
-    bank.total_borrowed_shares += users_shares;
+    stats.cumulative_deposits = stats.cumulative_deposits.safe_add(amount as u128)?;
+    stats.last_updated = now;
+
+    emit!(DepositEvent {
+        user: user.owner,
+        payer,
+        mint: bank.mint_address,
+        amount,
+        fee,
+        shares: users_shares,
+        user_deposited: bank.deposit_amount_for(position.deposit_shares)?,
+        position_pnl: position.pnl,
+        bank_total_deposits: bank.total_deposits,
+        client_tag,
+        event_seq: bank.next_event_seq(),
+        timestamp: now,
+    });
 
-    user.last_updated = Clock::get()?.unix_timestamp;
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,42 @@
+use anchor_lang::prelude::*;
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::UserEModeSet;
+use crate::health::calculate_health;
+
+#[derive(Accounts)]
+pub struct SetUserEMode<'info> {
+    pub signer: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [signer.key().as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+        constraint = !user_account.is_tokenized() @ ErrorCode::AccountTokenized,
+    )]
+    pub user_account: Account<'info, User>,
+}
+
+// 1. Switch the user to the e-mode category, zero leaves e-mode
+// 2. Check the user's debt is still covered under the new category's limits
+//    (remaining accounts: bank, position, price update and fixed loans for each of the user's positions)
+
+pub fn process_set_user_emode(ctx: Context<SetUserEMode>, emode_category: u8) -> Result<()> {
+    let clock = Clock::get()?;
+    let user = &mut ctx.accounts.user_account;
+    user.emode_category = emode_category;
+
+    let health = calculate_health(user, &user.key(), ctx.remaining_accounts, &clock)?;
+    health.require_within_borrow_limit(ErrorCode::HealthFactorTooLow)?;
+    user.health_factor = health.health_factor();
+    user.last_updated = clock.unix_timestamp;
+
+    emit!(UserEModeSet {
+        user: user.owner,
+        emode_category,
+        health_factor: user.health_factor,
+        event_seq: user.next_event_seq(),
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
@@ -0,0 +1,537 @@
+use anchor_lang::prelude::*;
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+use crate::constants::{BPS_DENOMINATOR, FIXED_LOAN_SEED, FULL_AMOUNT, PAUSE_BORROWS, PAUSE_REPAYS, POSITION_SEED, PROTOCOL_SEED, STATS_SEED, TREASURY_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::{BorrowRateModeSwapped, FixedBorrowEvent, FixedRepayEvent};
+use crate::health::calculate_health;
+use crate::interest::{accrue_fixed_loan, accrue_interest, borrow_rate, utilization_rate};
+use crate::mint::{calculate_transfer_fee, gross_up_for_transfer_fee, reconcile_vault};
+use crate::referral::{settle_referral_fees, snapshot_debt};
+use crate::safe_math::{mul_div, SafeMath};
+use super::admin::reserve_position;
+
+#[derive(Accounts)]
+#[instruction(loan_id: u64)]
+pub struct BorrowFixed<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [mint.key().as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, bank.key().as_ref()],
+        bump,
+    )]
+    pub bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, mint.key().as_ref()],
+        bump,
+    )]
+    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [signer.key().as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+        constraint = !user_account.is_tokenized() @ ErrorCode::AccountTokenized,
+    )]
+    pub user_account: Account<'info, User>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), mint.key().as_ref()],
+        bump,
+    )]
+    pub user_position: Account<'info, UserPosition>,
+    #[account(
+        init,
+        payer = signer,
+        space = 8 + FixedLoan::INIT_SPACE,
+        seeds = [FIXED_LOAN_SEED, user_account.key().as_ref(), mint.key().as_ref(), loan_id.to_le_bytes().as_ref()],
+        bump,
+    )]
+    pub fixed_loan: Account<'info, FixedLoan>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+#[derive(Accounts)]
+#[instruction(loan_id: u64)]
+pub struct RepayFixed<'info> {
+    /// Payer of the repayment, may repay another user's loan
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [mint.key().as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, bank.key().as_ref()],
+        bump,
+    )]
+    pub bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, mint.key().as_ref()],
+        bump,
+    )]
+    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [user_account.owner.as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+    )]
+    /// Account of the borrower whose loan is repaid
+    pub user_account: Account<'info, User>,
+    /// Receives the loan account's rent back once it's fully repaid
+    #[account(mut, address = user_account.owner)]
+    pub owner: SystemAccount<'info>,
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), mint.key().as_ref()],
+        bump,
+    )]
+    pub user_position: Account<'info, UserPosition>,
+    #[account(
+        mut,
+        seeds = [FIXED_LOAN_SEED, user_account.key().as_ref(), mint.key().as_ref(), loan_id.to_le_bytes().as_ref()],
+        bump,
+    )]
+    pub fixed_loan: Account<'info, FixedLoan>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(loan_id: u64)]
+pub struct SwapBorrowRateMode<'info> {
+    /// The borrower, or anyone rebalancing a fixed loan to the variable rate
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [mint.key().as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [user_account.owner.as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+        constraint = !user_account.is_tokenized() @ ErrorCode::AccountTokenized,
+    )]
+    pub user_account: Account<'info, User>,
+    /// Receives the loan account's rent back once its debt moves to the variable rate
+    #[account(mut, address = user_account.owner)]
+    pub owner: SystemAccount<'info>,
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), mint.key().as_ref()],
+        bump,
+    )]
+    pub user_position: Account<'info, UserPosition>,
+    /// Opened when moving variable debt to a fixed rate, closed when moving it back
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + FixedLoan::INIT_SPACE,
+        seeds = [FIXED_LOAN_SEED, user_account.key().as_ref(), mint.key().as_ref(), loan_id.to_le_bytes().as_ref()],
+        bump,
+    )]
+    pub fixed_loan: Account<'info, FixedLoan>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Borrow rate mode `swap_borrow_rate_mode` moves debt into
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BorrowRateMode {
+    /// Moves the whole fixed loan to variable debt
+    Variable,
+    /// Moves `amount` of the variable debt, `FULL_AMOUNT` for all of it, into a new fixed loan
+    /// like `borrow_fixed` at the current variable rate plus the bank's premium, up to `max_rate`
+    Fixed { amount: u64, term_slots: u64, max_rate: u64 },
+}
+
+// 1. Check the term is within the bank's max fixed term and the borrow within the bank's borrow cap
+// 2. Lock in the bank's current variable rate plus its fixed rate premium, up to `max_rate`
+// 3. Make a CPI transfer from the bank's token account to the user's token account
+// 4. Open the loan and add its debt to the user's position and the bank's fixed debt
+// 5. Check the user's collateral across all positions still covers the new debt at max LTV
+//    (remaining accounts: bank, position, price update and fixed loans for each of the user's positions)
+
+pub fn process_borrow_fixed(ctx: Context<BorrowFixed>, loan_id: u64, amount: u64, term_slots: u64, max_rate: u64) -> Result<()> {
+    ctx.accounts.bank.require_not_paused(PAUSE_BORROWS)?;
+    ctx.accounts.protocol_config.require_not_deleveraging()?;
+    ctx.accounts.bank.require_ungated()?;
+    reserve_position(
+        &ctx.accounts.protocol_config,
+        &ctx.accounts.user_account,
+        &ctx.accounts.bank.key(),
+        &ctx.accounts.signer.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+    )?;
+
+    let clock = Clock::get()?;
+    accrue_interest(&mut ctx.accounts.bank, clock.unix_timestamp)?;
+
+    let bank = &ctx.accounts.bank;
+    if term_slots == 0 || term_slots > bank.max_fixed_term_slots {
+        msg!("Term {} slots must be between 1 and {}", term_slots, bank.max_fixed_term_slots);
+        return Err(ErrorCode::InvalidFixedTerm.into());
+    }
+    bank.require_borrowable()?;
+    bank.require_borrow_cap(amount)?;
+    bank.require_borrow_minimum(amount)?;
+    bank.require_self_borrow_allowed(&ctx.accounts.user_position, false)?;
+
+    let rate = borrow_rate(bank).safe_add(bank.fixed_rate_premium)?;
+    if rate > max_rate {
+        msg!("Fixed rate {} exceeds the maximum {}", rate, max_rate);
+        return Err(ErrorCode::SlippageExceeded.into());
+    }
+
+    let transfer_cpi_accounts = TransferChecked {
+        from: ctx.accounts.bank_token_account.to_account_info(),
+        mint: ctx.accounts.mint.to_account_info(),
+        to: ctx.accounts.user_token_account.to_account_info(),
+        authority: ctx.accounts.bank_token_account.to_account_info(),
+    };
+    let mint_key = ctx.accounts.mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[
+        &[
+            TREASURY_SEED,
+            mint_key.as_ref(),
+            &[ctx.bumps.bank_token_account],
+        ],
+    ];
+    let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), transfer_cpi_accounts).with_signer(signer_seeds);
+    token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+    reconcile_vault(&mut ctx.accounts.bank, &mut ctx.accounts.bank_token_account, 0, amount)?;
+
+    let bank = &mut ctx.accounts.bank;
+    let user = &mut ctx.accounts.user_account;
+    let position = &mut ctx.accounts.user_position;
+
+    let loan = &mut ctx.accounts.fixed_loan;
+    loan.user = user.key();
+    loan.bank = bank.key();
+    loan.loan_id = loan_id;
+    loan.debt = amount;
+    loan.rate = rate;
+    loan.maturity_slot = clock.slot.safe_add(term_slots)?;
+    loan.last_updated = clock.unix_timestamp;
+
+    if position.user == Pubkey::default() {
+        position.user = user.key();
+        position.bank = bank.key();
+        position.collateral_enabled = bank.is_collateral();
+        ctx.accounts.bank_stats.unique_users = ctx.accounts.bank_stats.unique_users.safe_add(1)?;
+    }
+    user.add_position(bank.key())?;
+    position.fixed_debt = position.fixed_debt.safe_add(amount)?;
+    position.touch(bank, clock.unix_timestamp);
+
+    bank.total_fixed_borrowed = bank.total_fixed_borrowed.safe_add(amount)?;
+
+    let stats = &mut ctx.accounts.bank_stats;
+    stats.cumulative_borrows = stats.cumulative_borrows.safe_add(amount as u128)?;
+    stats.last_updated = clock.unix_timestamp;
+
+    // Persist the updated accounts so the health check below sees the post-borrow state
+    bank.exit(&crate::ID)?;
+    position.exit(&crate::ID)?;
+    loan.exit(&crate::ID)?;
+
+    let health = calculate_health(user, &user.key(), ctx.remaining_accounts, &clock)?;
+    health.require_within_borrow_headroom(ctx.accounts.protocol_config.borrow_headroom_bps, ErrorCode::InsufficientCollateral)?;
+    user.health_factor = health.health_factor();
+    user.last_updated = clock.unix_timestamp;
+
+    emit!(FixedBorrowEvent {
+        user: user.owner,
+        mint: mint_key,
+        loan_id,
+        amount,
+        rate,
+        maturity_slot: loan.maturity_slot,
+        event_seq: bank.next_event_seq(),
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// 1. Accrue the loan's interest up to now, at its fixed rate or at the penalty rate past maturity
+// 2. Before maturity, split the bank's early repayment fee off the tokens paid, the loan only ever owes the
+//    interest accrued to date rather than its full term's
+// 3. Make a CPI transfer from the payer's token account to the bank's token account
+// 4. Remove the repaid debt from the loan, the user's position and the bank's fixed debt, the fee goes to the reserves
+// 5. Close the loan once it's fully repaid, returning its rent to the borrower
+
+pub fn process_repay_fixed(ctx: Context<RepayFixed>, _loan_id: u64, amount: u64, max_amount_in: u64) -> Result<()> {
+    ctx.accounts.bank.require_not_paused(PAUSE_REPAYS)?;
+
+    let clock = Clock::get()?;
+    accrue_interest(&mut ctx.accounts.bank, clock.unix_timestamp)?;
+    let debt_before = ctx.accounts.fixed_loan.debt;
+    accrue_fixed_loan(&mut ctx.accounts.bank, &mut ctx.accounts.fixed_loan, &mut ctx.accounts.user_position, &clock)?;
+
+    let loan_debt = ctx.accounts.fixed_loan.debt;
+    let interest = loan_debt.safe_sub(debt_before)?;
+    let fee_bps = if clock.slot < ctx.accounts.fixed_loan.maturity_slot {
+        ctx.accounts.bank.fixed_early_repay_fee_bps
+    } else {
+        0
+    };
+
+    // Same as `repay`, the debt is reduced by the tokens that reach the vault after any Token-2022 transfer fee,
+    // less the early repayment fee on the debt repaid, rounded up
+    let mint_info = ctx.accounts.mint.to_account_info();
+    let (amount, repaid, fee) = if amount == FULL_AMOUNT {
+        let fee = mul_div(loan_debt, fee_bps, BPS_DENOMINATOR, true)?;
+        (gross_up_for_transfer_fee(&mint_info, loan_debt.safe_add(fee)?)?, loan_debt, fee)
+    } else {
+        let received = amount.safe_sub(calculate_transfer_fee(&mint_info, amount)?)?;
+        let fee = mul_div(received, fee_bps, BPS_DENOMINATOR.safe_add(fee_bps)?, true)?;
+        (amount, received.safe_sub(fee)?, fee)
+    };
+
+    if repaid > loan_debt {
+        msg!("Repaying {} but only {} is owed", repaid, loan_debt);
+        return Err(ErrorCode::OverRepay.into());
+    }
+    if amount > max_amount_in {
+        msg!("Amount in {} exceeds the maximum {}", amount, max_amount_in);
+        return Err(ErrorCode::SlippageExceeded.into());
+    }
+
+    let transfer_cpi_accounts = TransferChecked {
+        from: ctx.accounts.user_token_account.to_account_info(),
+        mint: ctx.accounts.mint.to_account_info(),
+        to: ctx.accounts.bank_token_account.to_account_info(),
+        authority: ctx.accounts.signer.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), transfer_cpi_accounts);
+    token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+    let received = amount.safe_sub(calculate_transfer_fee(&mint_info, amount)?)?;
+    reconcile_vault(&mut ctx.accounts.bank, &mut ctx.accounts.bank_token_account, received, 0)?;
+
+    let bank = &mut ctx.accounts.bank;
+    let user = &mut ctx.accounts.user_account;
+    let position = &mut ctx.accounts.user_position;
+    let loan = &mut ctx.accounts.fixed_loan;
+
+    remove_fixed_debt(bank, loan, position, repaid)?;
+    bank.require_borrow_minimum(loan.debt)?;
+    position.touch(bank, clock.unix_timestamp);
+    bank.total_reserves = bank.total_reserves.safe_add(fee)?;
+    position.pnl.fees_paid = position.pnl.fees_paid.safe_add(fee)?;
+    if position.is_empty() {
+        user.remove_position(bank.key());
+    }
+    user.last_updated = clock.unix_timestamp;
+
+    let stats = &mut ctx.accounts.bank_stats;
+    stats.cumulative_repays = stats.cumulative_repays.safe_add(repaid as u128)?;
+    stats.last_updated = clock.unix_timestamp;
+
+    emit!(FixedRepayEvent {
+        user: user.owner,
+        payer: ctx.accounts.signer.key(),
+        mint: ctx.accounts.mint.key(),
+        loan_id: loan.loan_id,
+        amount: repaid,
+        interest,
+        early_repay_fee: fee,
+        loan_debt: loan.debt,
+        event_seq: bank.next_event_seq(),
+        timestamp: clock.unix_timestamp,
+    });
+
+    if loan.debt == 0 {
+        loan.close(ctx.accounts.owner.to_account_info())?;
+    }
+
+    Ok(())
+}
+
+/// Removes `amount` of the loan's debt from the loan, the user's position and the bank's fixed debt
+pub(crate) fn remove_fixed_debt(bank: &mut Bank, loan: &mut FixedLoan, position: &mut UserPosition, amount: u64) -> Result<()> {
+    loan.debt = loan.debt.safe_sub(amount)?;
+    position.fixed_debt = position.fixed_debt.safe_sub(amount)?;
+    bank.total_fixed_borrowed = bank.total_fixed_borrowed.safe_sub(amount)?;
+    Ok(())
+}
+
+// Re-books debt under the other rate mode without moving any tokens, the debt and the user's health stay the same.
+// Rules against gaming the rate modes:
+// - Variable debt only moves to a fixed rate while the bank is below its optimal utilization,
+//   so borrowers can't lock in a rate just before it jumps up the curve's steep slope
+// - A fixed loan the bank's fixed rate for a new loan has outgrown by more than `fixed_rate_rebalance_bps`
+//   can be moved to the variable rate by anyone, others can only be moved by the borrower
+// - The borrower can't move a fixed loan to the variable rate before maturity while the bank charges an early
+//   repayment fee, which repaying the variable debt would otherwise skip
+
+pub fn process_swap_borrow_rate_mode(ctx: Context<SwapBorrowRateMode>, loan_id: u64, mode: BorrowRateMode) -> Result<()> {
+    let clock = Clock::get()?;
+    accrue_interest(&mut ctx.accounts.bank, clock.unix_timestamp)?;
+
+    let bank = &mut ctx.accounts.bank;
+    let user = &mut ctx.accounts.user_account;
+    let position = &mut ctx.accounts.user_position;
+    let loan = &mut ctx.accounts.fixed_loan;
+    let is_owner = ctx.accounts.signer.key() == user.owner;
+    if position.user != user.key() {
+        return Err(ErrorCode::PositionNotOpen.into());
+    }
+
+    let (amount, rate) = match mode {
+        BorrowRateMode::Fixed { amount, term_slots, max_rate } => {
+            if !is_owner {
+                return Err(ErrorCode::Unauthorized.into());
+            }
+            if loan.user != Pubkey::default() {
+                msg!("Fixed loan {} is already open", loan_id);
+                return Err(ErrorCode::RateModeSwapNotAllowed.into());
+            }
+            bank.require_not_paused(PAUSE_BORROWS)?;
+            bank.require_not_deprecated()?;
+            bank.require_borrowable()?;
+            if term_slots == 0 || term_slots > bank.max_fixed_term_slots {
+                msg!("Term {} slots must be between 1 and {}", term_slots, bank.max_fixed_term_slots);
+                return Err(ErrorCode::InvalidFixedTerm.into());
+            }
+            let utilization = utilization_rate(bank.total_debt(), bank.total_deposits);
+            if utilization > bank.optimal_utilization {
+                msg!("Utilization {} is above the optimal {}", utilization, bank.optimal_utilization);
+                return Err(ErrorCode::RateModeSwapNotAllowed.into());
+            }
+            let rate = borrow_rate(bank).safe_add(bank.fixed_rate_premium)?;
+            if rate > max_rate {
+                msg!("Fixed rate {} exceeds the maximum {}", rate, max_rate);
+                return Err(ErrorCode::SlippageExceeded.into());
+            }
+
+            let borrowed_asset = bank.borrow_amount_for(position.borrow_shares)?;
+            let amount = if amount == FULL_AMOUNT { borrowed_asset } else { amount };
+            if amount == 0 || amount > borrowed_asset {
+                msg!("Moving {} but only {} is borrowed at the variable rate", amount, borrowed_asset);
+                return Err(ErrorCode::OverRepay.into());
+            }
+            bank.require_borrow_minimum(amount)?;
+
+            // Same rounding as a repayment, the shares burned never clear more debt than moves to the loan
+            settle_referral_fees(bank, user, position)?;
+            let shares = if amount == borrowed_asset {
+                position.borrow_shares
+            } else {
+                bank.borrow_shares_burned(amount)?
+            };
+            position.borrow_shares = position.borrow_shares.safe_sub(shares)?;
+            bank.total_borrowed = bank.total_borrowed.safe_sub(amount)?;
+            bank.total_borrowed_shares = bank.total_borrowed_shares.safe_sub(shares)?;
+            snapshot_debt(bank, position)?;
+            bank.require_borrow_minimum(position.debt_snapshot)?;
+
+            loan.user = user.key();
+            loan.bank = bank.key();
+            loan.loan_id = loan_id;
+            loan.debt = amount;
+            loan.rate = rate;
+            loan.maturity_slot = clock.slot.safe_add(term_slots)?;
+            loan.last_updated = clock.unix_timestamp;
+            position.fixed_debt = position.fixed_debt.safe_add(amount)?;
+            bank.total_fixed_borrowed = bank.total_fixed_borrowed.safe_add(amount)?;
+            (amount, rate)
+        }
+        BorrowRateMode::Variable => {
+            if loan.user != user.key() {
+                msg!("Fixed loan {} is not open", loan_id);
+                return Err(ErrorCode::RateModeSwapNotAllowed.into());
+            }
+            accrue_fixed_loan(bank, loan, position, &clock)?;
+            if is_owner && bank.fixed_early_repay_fee_bps > 0 && clock.slot < loan.maturity_slot {
+                msg!("Fixed loan {} matures at slot {}, now {}", loan_id, loan.maturity_slot, clock.slot);
+                return Err(ErrorCode::EarlyRepayFeeDue.into());
+            }
+            if !is_owner {
+                let fixed_rate = borrow_rate(bank).safe_add(bank.fixed_rate_premium)?;
+                let drift = fixed_rate.saturating_sub(loan.rate);
+                if bank.fixed_rate_rebalance_bps == 0 || drift <= bank.fixed_rate_rebalance_bps {
+                    msg!("Loan rate {} is within {} of the fixed rate {}", loan.rate, bank.fixed_rate_rebalance_bps, fixed_rate);
+                    return Err(ErrorCode::RateModeSwapNotAllowed.into());
+                }
+            }
+
+            let amount = loan.debt;
+            position.fixed_debt = position.fixed_debt.safe_sub(amount)?;
+            bank.total_fixed_borrowed = bank.total_fixed_borrowed.safe_sub(amount)?;
+
+            // Same rounding as a borrow, the shares minted never record less debt than the loan held
+            settle_referral_fees(bank, user, position)?;
+            let shares = bank.borrow_shares_minted(amount)?;
+            bank.total_borrowed = bank.total_borrowed.safe_add(amount)?;
+            bank.total_borrowed_shares = bank.total_borrowed_shares.safe_add(shares)?;
+            position.borrow_shares = position.borrow_shares.safe_add(shares)?;
+            snapshot_debt(bank, position)?;
+            loan.debt = 0;
+            (amount, loan.rate)
+        }
+    };
+    position.touch(bank, clock.unix_timestamp);
+    user.last_updated = clock.unix_timestamp;
+
+    emit!(BorrowRateModeSwapped {
+        user: user.owner,
+        signer: ctx.accounts.signer.key(),
+        mint: ctx.accounts.mint.key(),
+        loan_id,
+        to_fixed: matches!(mode, BorrowRateMode::Fixed { .. }),
+        amount,
+        rate,
+        event_seq: bank.next_event_seq(),
+        timestamp: clock.unix_timestamp,
+    });
+
+    if loan.debt == 0 {
+        loan.close(ctx.accounts.owner.to_account_info())?;
+    }
+
+    Ok(())
+}
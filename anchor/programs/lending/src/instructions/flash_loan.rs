@@ -0,0 +1,165 @@
+use anchor_lang::prelude::*;
+use anchor_lang::Discriminator;
+use anchor_lang::solana_program::sysvar::instructions::{
+    self as instructions_sysvar, load_current_index_checked, load_instruction_at_checked,
+};
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+use crate::constants::{BPS_DENOMINATOR, PAUSE_BORROWS, STATS_SEED, TREASURY_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::FlashLoanEvent;
+use crate::instruction::FlashRepay as FlashRepayInstruction;
+use crate::mint::{calculate_transfer_fee, gross_up_for_transfer_fee, reconcile_vault};
+use crate::safe_math::{mul_div, SafeMath};
+
+// Index of the bank account in `FlashLoan`, used to match the repay instruction to the borrow
+const FLASH_LOAN_BANK_INDEX: usize = 2;
+
+#[derive(Accounts)]
+pub struct FlashLoan<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [mint.key().as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, bank.key().as_ref()],
+        bump,
+    )]
+    pub bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, mint.key().as_ref()],
+        bump,
+    )]
+    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = signer,
+        token::token_program = token_program,
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: Instructions sysvar, used to find the matching `flash_repay` in the transaction
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+// 1. Check flash_borrow is called directly and a flash_repay for the same bank follows it in the transaction
+// 2. Record the outstanding flash loan on the bank
+// 3. CPI transfer from the bank's token account to the user's token account
+pub fn process_flash_borrow(ctx: Context<FlashLoan>, amount: u64) -> Result<()> {
+    // Flash loans draw on the same liquidity as borrows and are paused with them
+    ctx.accounts.bank.require_not_paused(PAUSE_BORROWS)?;
+
+    let bank = &mut ctx.accounts.bank;
+
+    if bank.flash_loan_amount > 0 {
+        msg!("Flash loan of {} is still outstanding", bank.flash_loan_amount);
+        return Err(ErrorCode::FlashLoanInProgress.into());
+    }
+    if amount == 0 || amount > ctx.accounts.bank_token_account.amount {
+        msg!("Flash loan {} must be between 1 and the vault balance {}", amount, ctx.accounts.bank_token_account.amount);
+        return Err(ErrorCode::InsufficientFunds.into());
+    }
+
+    let instructions = ctx.accounts.instructions.to_account_info();
+    let current_index = load_current_index_checked(&instructions)? as usize;
+    let current_instruction = load_instruction_at_checked(current_index, &instructions)?;
+
+    // A CPI caller could otherwise wrap the borrow and repay in ways we can't inspect
+    if current_instruction.program_id != crate::ID {
+        return Err(ErrorCode::FlashLoanCpiNotAllowed.into());
+    }
+
+    let mut repay_found = false;
+    let mut index = current_index + 1;
+    while let Ok(instruction) = load_instruction_at_checked(index, &instructions) {
+        if instruction.program_id == crate::ID
+            && instruction.data.starts_with(FlashRepayInstruction::DISCRIMINATOR)
+            && instruction.accounts.get(FLASH_LOAN_BANK_INDEX).map(|meta| meta.pubkey) == Some(bank.key())
+        {
+            repay_found = true;
+            break;
+        }
+        index += 1;
+    }
+
+    if !repay_found {
+        return Err(ErrorCode::FlashLoanNotRepaid.into());
+    }
+
+    bank.flash_loan_amount = amount;
+
+    let transfer_cpi_accounts = TransferChecked {
+        from: ctx.accounts.bank_token_account.to_account_info(),
+        mint: ctx.accounts.mint.to_account_info(),
+        to: ctx.accounts.user_token_account.to_account_info(),
+        authority: ctx.accounts.bank_token_account.to_account_info(),
+    };
+    let cpi_program = ctx.accounts.token_program.to_account_info();
+    let mint_key = ctx.accounts.mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[
+        &[
+            TREASURY_SEED,
+            mint_key.as_ref(),
+            &[ctx.bumps.bank_token_account],
+        ],
+    ];
+    let cpi_ctx = CpiContext::new(cpi_program, transfer_cpi_accounts).with_signer(signer_seeds);
+    token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+    reconcile_vault(&mut ctx.accounts.bank, &mut ctx.accounts.bank_token_account, 0, amount)?;
+
+    Ok(())
+}
+
+// 1. CPI transfer of the flash loan plus fee from the user's token account back to the bank
+// 2. Credit the fee to depositors and clear the outstanding flash loan
+pub fn process_flash_repay(ctx: Context<FlashLoan>) -> Result<()> {
+    let amount = ctx.accounts.bank.flash_loan_amount;
+    if amount == 0 {
+        return Err(ErrorCode::NoFlashLoanInProgress.into());
+    }
+
+    let fee = mul_div(amount, ctx.accounts.bank.flash_loan_fee, BPS_DENOMINATOR, true)?;
+    let repay_amount = amount.safe_add(fee)?;
+    // The vault must receive the full loan plus fee after any Token-2022 transfer fee
+    let repay_amount = gross_up_for_transfer_fee(&ctx.accounts.mint.to_account_info(), repay_amount)?;
+
+    let transfer_cpi_accounts = TransferChecked {
+        from: ctx.accounts.user_token_account.to_account_info(),
+        mint: ctx.accounts.mint.to_account_info(),
+        to: ctx.accounts.bank_token_account.to_account_info(),
+        authority: ctx.accounts.signer.to_account_info(),
+    };
+    let cpi_program = ctx.accounts.token_program.to_account_info();
+    let cpi_ctx = CpiContext::new(cpi_program, transfer_cpi_accounts);
+    token_interface::transfer_checked(cpi_ctx, repay_amount, ctx.accounts.mint.decimals)?;
+    let received = repay_amount.safe_sub(calculate_transfer_fee(&ctx.accounts.mint.to_account_info(), repay_amount)?)?;
+    reconcile_vault(&mut ctx.accounts.bank, &mut ctx.accounts.bank_token_account, received, 0)?;
+
+    let bank = &mut ctx.accounts.bank;
+    bank.total_deposits = bank.total_deposits.safe_add(fee)?;
+    bank.flash_loan_amount = 0;
+
+    let stats = &mut ctx.accounts.bank_stats;
+    stats.cumulative_flash_loan_fees = stats.cumulative_flash_loan_fees.safe_add(fee as u128)?;
+    stats.last_updated = Clock::get()?.unix_timestamp;
+
+    emit!(FlashLoanEvent {
+        user: ctx.accounts.signer.key(),
+        mint: ctx.accounts.mint.key(),
+        amount,
+        fee,
+        event_seq: ctx.accounts.bank.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
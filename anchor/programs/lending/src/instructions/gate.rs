@@ -0,0 +1,110 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::TokenAccount;
+use crate::constants::{find_gate_entry_address, GATE_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::GateEntryUpdated;
+
+// Permissioned banks only take deposits and borrows credited to owners who pass the bank's gate.
+// `deposit`, `deposit_with_init` and `borrow` take the owner's gate pass as their last account: the owner's
+// `GateEntry` for whitelisted banks, or a token account of the gate mint owned by the owner for token-gated ones.
+// Every other way of opening a position or adding to one rejects gated banks.
+
+#[derive(Accounts)]
+#[instruction(wallet: Pubkey)]
+pub struct AddToGate<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + GateEntry::INIT_SPACE,
+        seeds = [GATE_SEED, bank.key().as_ref(), wallet.as_ref()],
+        bump,
+    )]
+    pub gate_entry: Account<'info, GateEntry>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveFromGate<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        close = authority,
+        has_one = bank,
+        seeds = [GATE_SEED, bank.key().as_ref(), gate_entry.wallet.as_ref()],
+        bump = gate_entry.bump,
+    )]
+    pub gate_entry: Account<'info, GateEntry>,
+}
+
+pub fn process_add_to_gate(ctx: Context<AddToGate>, wallet: Pubkey) -> Result<()> {
+    let gate_entry = &mut ctx.accounts.gate_entry;
+    gate_entry.bank = ctx.accounts.bank.key();
+    gate_entry.wallet = wallet;
+    gate_entry.bump = ctx.bumps.gate_entry;
+
+    emit!(GateEntryUpdated {
+        bank: gate_entry.bank,
+        wallet,
+        allowed: true,
+        event_seq: ctx.accounts.bank.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Existing positions are kept, the wallet can still withdraw and repay but no longer deposit or borrow
+pub fn process_remove_from_gate(ctx: Context<RemoveFromGate>) -> Result<()> {
+    emit!(GateEntryUpdated {
+        bank: ctx.accounts.bank.key(),
+        wallet: ctx.accounts.gate_entry.wallet,
+        allowed: false,
+        event_seq: ctx.accounts.bank.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+/// Checks `owner` passes the bank's gate with `gate_pass`, always true for open banks
+pub(crate) fn require_gate_pass(bank: &Account<Bank>, owner: &Pubkey, gate_pass: Option<&AccountInfo>) -> Result<()> {
+    let passed = match (bank.gate, gate_pass) {
+        (BankGate::Open, _) => true,
+        (BankGate::Whitelist, Some(gate_pass)) => {
+            let (entry_key, _) = find_gate_entry_address(&bank.key(), owner);
+            // The entry's address already binds it to the bank and wallet, it only has to exist
+            gate_pass.key() == entry_key
+                && *gate_pass.owner == crate::ID
+                && GateEntry::try_deserialize(&mut &gate_pass.try_borrow_data()?[..]).is_ok()
+        }
+        (BankGate::TokenGate { mint }, Some(gate_pass)) => {
+            (*gate_pass.owner == anchor_spl::token::ID || *gate_pass.owner == anchor_spl::token_2022::ID)
+                && TokenAccount::try_deserialize(&mut &gate_pass.try_borrow_data()?[..])
+                    .is_ok_and(|token_account| token_account.mint == mint && token_account.owner == *owner && token_account.amount > 0)
+        }
+        (_, None) => false,
+    };
+    if !passed {
+        msg!("Owner {} has no valid gate pass for bank {}", owner, bank.key());
+        return Err(ErrorCode::GatePassRequired.into());
+    }
+    Ok(())
+}
@@ -0,0 +1,41 @@
+use anchor_lang::prelude::*;
+use crate::constants::PROTOCOL_SEED;
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::BankGraduated;
+
+// A bank listed with an active `guarded_launch` enforces the launch schedule's wallet, deposit and borrow caps
+// on top of its regular ones, each step raising them from its slot on. The bank's authority sets the schedule
+// through the bank config, the risk manager lifts it with `graduate_bank` once the market has proven itself.
+
+#[derive(Accounts)]
+pub struct GraduateBank<'info> {
+    pub risk_manager: Signer<'info>,
+    #[account(
+        has_one = risk_manager @ ErrorCode::Unauthorized,
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(
+        mut,
+        seeds = [bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+}
+
+// Only the bank's regular caps apply from here on, the schedule is kept for reference
+pub fn process_graduate_bank(ctx: Context<GraduateBank>) -> Result<()> {
+    let bank = &mut ctx.accounts.bank;
+    bank.guarded_launch.active = false;
+
+    emit!(BankGraduated {
+        bank: bank.key(),
+        risk_manager: ctx.accounts.risk_manager.key(),
+        event_seq: bank.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
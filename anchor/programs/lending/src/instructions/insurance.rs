@@ -0,0 +1,205 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+use crate::constants::{PROTOCOL_SEED, TREASURY_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::{InsuranceFunded, InsuranceWithdrawalQueued, InsuranceWithdrawn};
+use crate::interest::accrue_interest;
+use crate::mint::{calculate_transfer_fee, reconcile_vault};
+use crate::safe_math::{bps_of, SafeMath};
+
+// Each bank keeps an insurance fund in its own token account, filled by `insurance_factor` of borrower
+// interest and by the authority, and drawn down by `cover_bad_debt` and `handle_bad_debt` before any bad
+// debt is socialized. Taking tokens back out is queued behind the protocol's config timelock and limited
+// to `max_insurance_withdrawal_bps` of the fund, so depositors see a withdrawal coming.
+
+#[derive(Accounts)]
+pub struct FundInsurance<'info> {
+    pub authority: Signer<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [mint.key().as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, mint.key().as_ref()],
+        bump,
+    )]
+    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = authority,
+        token::token_program = token_program,
+    )]
+    pub source: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct RequestInsuranceWithdrawal<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteInsuranceWithdrawal<'info> {
+    pub authority: Signer<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [mint.key().as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, mint.key().as_ref()],
+        bump,
+    )]
+    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// Token account of the protocol's fee receiver
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = protocol_config.fee_receiver,
+        token::token_program = token_program,
+    )]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+// 1. CPI transfer from the authority's token account into the bank's token account
+// 2. Credit the tokens that arrived after any Token-2022 transfer fee to the insurance fund
+pub fn process_fund_insurance(ctx: Context<FundInsurance>, amount: u64) -> Result<()> {
+    let transfer_cpi_accounts = TransferChecked {
+        from: ctx.accounts.source.to_account_info(),
+        mint: ctx.accounts.mint.to_account_info(),
+        to: ctx.accounts.bank_token_account.to_account_info(),
+        authority: ctx.accounts.authority.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), transfer_cpi_accounts);
+    token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+
+    let received = amount.safe_sub(calculate_transfer_fee(&ctx.accounts.mint.to_account_info(), amount)?)?;
+    reconcile_vault(&mut ctx.accounts.bank, &mut ctx.accounts.bank_token_account, received, 0)?;
+    let bank = &mut ctx.accounts.bank;
+    bank.insurance_fund = bank.insurance_fund.safe_add(received)?;
+
+    emit!(InsuranceFunded {
+        bank: bank.key(),
+        funder: ctx.accounts.authority.key(),
+        amount: received,
+        insurance_fund: bank.insurance_fund,
+        event_seq: bank.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// 1. Accrue interest so the fund includes its share up to now
+// 2. Check the amount is within the bank's withdrawal limit and queue it behind the config timelock,
+//    replacing any withdrawal already queued
+pub fn process_request_insurance_withdrawal(ctx: Context<RequestInsuranceWithdrawal>, amount: u64) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    let bank = &mut ctx.accounts.bank;
+    accrue_interest(bank, now)?;
+    require_insurance_withdrawal_limit(bank, amount)?;
+
+    let executable_at = now
+        .checked_add(ctx.accounts.protocol_config.config_timelock_secs)
+        .ok_or(ErrorCode::MathOverflow)?;
+    bank.pending_insurance_withdrawal = amount;
+    bank.insurance_withdrawable_at = executable_at;
+
+    emit!(InsuranceWithdrawalQueued {
+        bank: bank.key(),
+        amount,
+        executable_at,
+        event_seq: bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
+
+// 1. Check the queued withdrawal's timelock has passed and it still fits the fund, which bad debt may have drawn down
+// 2. CPI transfer from the bank's token account to the destination and clear the queued withdrawal
+pub fn process_execute_insurance_withdrawal(ctx: Context<ExecuteInsuranceWithdrawal>) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    let bank = &mut ctx.accounts.bank;
+    accrue_interest(bank, now)?;
+
+    let amount = bank.pending_insurance_withdrawal;
+    if amount == 0 || now < bank.insurance_withdrawable_at {
+        msg!("Queued insurance withdrawal of {} executable at {}, now {}", amount, bank.insurance_withdrawable_at, now);
+        return Err(ErrorCode::InsuranceWithdrawalLocked.into());
+    }
+    require_insurance_withdrawal_limit(bank, amount)?;
+
+    let transfer_cpi_accounts = TransferChecked {
+        from: ctx.accounts.bank_token_account.to_account_info(),
+        mint: ctx.accounts.mint.to_account_info(),
+        to: ctx.accounts.destination.to_account_info(),
+        authority: ctx.accounts.bank_token_account.to_account_info(),
+    };
+    let cpi_program = ctx.accounts.token_program.to_account_info();
+    let mint_key = ctx.accounts.mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[
+        &[
+            TREASURY_SEED,
+            mint_key.as_ref(),
+            &[ctx.bumps.bank_token_account],
+        ],
+    ];
+    let cpi_ctx = CpiContext::new(cpi_program, transfer_cpi_accounts).with_signer(signer_seeds);
+    token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+    reconcile_vault(&mut ctx.accounts.bank, &mut ctx.accounts.bank_token_account, 0, amount)?;
+
+    let bank = &mut ctx.accounts.bank;
+    bank.insurance_fund = bank.insurance_fund.safe_sub(amount)?;
+    bank.pending_insurance_withdrawal = 0;
+    bank.insurance_withdrawable_at = 0;
+
+    emit!(InsuranceWithdrawn {
+        bank: bank.key(),
+        destination: ctx.accounts.destination.key(),
+        amount,
+        insurance_fund: bank.insurance_fund,
+        event_seq: bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
+
+fn require_insurance_withdrawal_limit(bank: &Bank, amount: u64) -> Result<()> {
+    let limit = bps_of(bank.insurance_fund, bank.max_insurance_withdrawal_bps)?;
+    if amount == 0 || amount > limit {
+        msg!("Withdrawing {} of an insurance fund of {}, at most {}", amount, bank.insurance_fund, limit);
+        return Err(ErrorCode::InsuranceWithdrawalTooLarge.into());
+    }
+    Ok(())
+}
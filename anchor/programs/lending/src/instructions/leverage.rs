@@ -0,0 +1,334 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::program::invoke;
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+use crate::constants::{PAUSE_BORROWS, PAUSE_DEPOSITS, POSITION_SEED, PROTOCOL_SEED, STATS_SEED, TREASURY_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::{BorrowEvent, LeverageDepositEvent};
+use crate::interest::accrue_interest;
+use crate::health::calculate_health;
+use crate::mint::{calculate_transfer_fee, reconcile_vault};
+use crate::safe_math::SafeMath;
+use super::admin::reserve_position;
+use super::borrow::mint_borrow_shares;
+use super::deposit::record_deposit;
+
+#[derive(Accounts)]
+pub struct LeverageDeposit<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    /// CHECK: Only called when approved in the protocol config
+    #[account(
+        executable,
+        constraint = protocol_config.swap_programs.contains(&swap_program.key()) @ ErrorCode::SwapProgramNotApproved,
+    )]
+    pub swap_program: UncheckedAccount<'info>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = borrow_mint.key() != collateral_mint.key())]
+    pub borrow_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [collateral_mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, collateral_bank.key().as_ref()],
+        bump,
+    )]
+    pub collateral_bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, collateral_mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [borrow_mint.key().as_ref()],
+        bump,
+    )]
+    pub borrow_bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, borrow_bank.key().as_ref()],
+        bump,
+    )]
+    pub borrow_bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, borrow_mint.key().as_ref()],
+        bump,
+    )]
+    pub borrow_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [signer.key().as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+        constraint = !user_account.is_tokenized() @ ErrorCode::AccountTokenized,
+    )]
+    pub user_account: Account<'info, User>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), collateral_mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_position: Account<'info, UserPosition>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), borrow_mint.key().as_ref()],
+        bump,
+    )]
+    pub borrow_position: Account<'info, UserPosition>,
+    /// Source of the initial deposit and destination of the swap output
+    #[account(
+        mut,
+        associated_token::mint = collateral_mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_collateral_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// Receives the borrowed tokens, which the swap then spends
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = borrow_mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_borrow_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct LeverageDepositParams {
+    /// Collateral deposited from the user's own tokens before levering up, may be zero
+    pub deposit_amount: u64,
+    /// Tokens borrowed and swapped into collateral
+    pub borrow_amount: u64,
+    /// Fewest collateral tokens the swap may return
+    pub min_collateral_out: u64,
+    /// Number of leading remaining accounts passed to the swap program
+    pub swap_accounts_len: u8,
+    /// Instruction data of the swap, built by the client for the swap program
+    pub swap_data: Vec<u8>,
+}
+
+// Opens a leveraged position in one go. Since health is only checked at the end, a single borrow sized
+// off-chain for the target LTV replaces the usual rounds of borrowing, swapping and re-depositing.
+// 1. Accrue interest on both banks and deposit the user's own collateral, if any
+// 2. Borrow from the borrow bank into the user's token account
+// 3. CPI into the approved swap program to swap the borrowed tokens into collateral
+// 4. Deposit everything the swap returned into the collateral bank
+// 5. Check once, at the end, that the user's collateral covers the new debt at max LTV
+//    (remaining accounts: the swap's accounts, then bank, position, price update and fixed loans for each of the
+//    user's positions after the deposit and borrow have opened theirs)
+
+pub fn process_leverage_deposit(ctx: Context<LeverageDeposit>, params: LeverageDepositParams) -> Result<()> {
+    ctx.accounts.collateral_bank.require_not_paused(PAUSE_DEPOSITS)?;
+    ctx.accounts.borrow_bank.require_not_paused(PAUSE_BORROWS)?;
+    ctx.accounts.protocol_config.require_not_deleveraging()?;
+    ctx.accounts.collateral_bank.require_ungated()?;
+    ctx.accounts.borrow_bank.require_ungated()?;
+
+    let swap_accounts_len = params.swap_accounts_len as usize;
+    if ctx.remaining_accounts.len() < swap_accounts_len {
+        msg!("Expected at least {} swap accounts, got {}", swap_accounts_len, ctx.remaining_accounts.len());
+        return Err(ErrorCode::InvalidHealthAccounts.into());
+    }
+    let (swap_accounts, health_accounts) = ctx.remaining_accounts.split_at(swap_accounts_len);
+
+    let clock = Clock::get()?;
+    let now = clock.unix_timestamp;
+    accrue_interest(&mut ctx.accounts.collateral_bank, now)?;
+    accrue_interest(&mut ctx.accounts.borrow_bank, now)?;
+
+    let borrow_bank = &ctx.accounts.borrow_bank;
+    borrow_bank.require_borrow_cap(params.borrow_amount)?;
+
+    let cpi_program = ctx.accounts.token_program.to_account_info();
+    let collateral_decimals = ctx.accounts.collateral_mint.decimals;
+
+    if params.deposit_amount > 0 {
+        let deposit_cpi_accounts = TransferChecked {
+            from: ctx.accounts.user_collateral_token_account.to_account_info(),
+            mint: ctx.accounts.collateral_mint.to_account_info(),
+            to: ctx.accounts.collateral_bank_token_account.to_account_info(),
+            authority: ctx.accounts.signer.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(cpi_program.clone(), deposit_cpi_accounts);
+        token_interface::transfer_checked(cpi_ctx, params.deposit_amount, collateral_decimals)?;
+
+        let received = params.deposit_amount.safe_sub(calculate_transfer_fee(&ctx.accounts.collateral_mint.to_account_info(), params.deposit_amount)?)?;
+        reconcile_vault(&mut ctx.accounts.collateral_bank, &mut ctx.accounts.collateral_bank_token_account, received, 0)?;
+        reserve_position(
+            &ctx.accounts.protocol_config,
+            &ctx.accounts.user_account,
+            &ctx.accounts.collateral_bank.key(),
+            &ctx.accounts.signer.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        record_deposit(
+            &mut ctx.accounts.collateral_bank,
+            &mut ctx.accounts.collateral_bank_stats,
+            &mut ctx.accounts.user_account,
+            &mut ctx.accounts.collateral_position,
+            ctx.accounts.signer.key(),
+            received,
+            now,
+            None,
+        )?;
+    }
+
+    let borrow_cpi_accounts = TransferChecked {
+        from: ctx.accounts.borrow_bank_token_account.to_account_info(),
+        mint: ctx.accounts.borrow_mint.to_account_info(),
+        to: ctx.accounts.user_borrow_token_account.to_account_info(),
+        authority: ctx.accounts.borrow_bank_token_account.to_account_info(),
+    };
+    let borrow_mint_key = ctx.accounts.borrow_mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[
+        &[
+            TREASURY_SEED,
+            borrow_mint_key.as_ref(),
+            &[ctx.bumps.borrow_bank_token_account],
+        ],
+    ];
+    let cpi_ctx = CpiContext::new(cpi_program.clone(), borrow_cpi_accounts).with_signer(signer_seeds);
+    token_interface::transfer_checked(cpi_ctx, params.borrow_amount, ctx.accounts.borrow_mint.decimals)?;
+    reconcile_vault(&mut ctx.accounts.borrow_bank, &mut ctx.accounts.borrow_bank_token_account, 0, params.borrow_amount)?;
+
+    reserve_position(
+        &ctx.accounts.protocol_config,
+        &ctx.accounts.user_account,
+        &ctx.accounts.borrow_bank.key(),
+        &ctx.accounts.signer.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+    )?;
+
+    let borrow_shares = mint_borrow_shares(
+        &mut ctx.accounts.borrow_bank,
+        &mut ctx.accounts.borrow_bank_stats,
+        &mut ctx.accounts.user_account,
+        &mut ctx.accounts.borrow_position,
+        params.borrow_amount,
+        now,
+    )?;
+
+    let collateral_before = ctx.accounts.user_collateral_token_account.amount;
+    invoke_swap(&ctx.accounts.swap_program, swap_accounts, params.swap_data)?;
+
+    ctx.accounts.user_collateral_token_account.reload()?;
+    let swapped_collateral = ctx.accounts.user_collateral_token_account.amount.saturating_sub(collateral_before);
+    if swapped_collateral < params.min_collateral_out {
+        msg!("Swap returned {}, expected at least {}", swapped_collateral, params.min_collateral_out);
+        return Err(ErrorCode::SlippageExceeded.into());
+    }
+
+    let collateral_bank = &ctx.accounts.collateral_bank;
+    collateral_bank.require_deposit_cap(swapped_collateral)?;
+
+    let deposit_cpi_accounts = TransferChecked {
+        from: ctx.accounts.user_collateral_token_account.to_account_info(),
+        mint: ctx.accounts.collateral_mint.to_account_info(),
+        to: ctx.accounts.collateral_bank_token_account.to_account_info(),
+        authority: ctx.accounts.signer.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(cpi_program, deposit_cpi_accounts);
+    token_interface::transfer_checked(cpi_ctx, swapped_collateral, collateral_decimals)?;
+
+    let received = swapped_collateral.safe_sub(calculate_transfer_fee(&ctx.accounts.collateral_mint.to_account_info(), swapped_collateral)?)?;
+    reconcile_vault(&mut ctx.accounts.collateral_bank, &mut ctx.accounts.collateral_bank_token_account, received, 0)?;
+    reserve_position(
+        &ctx.accounts.protocol_config,
+        &ctx.accounts.user_account,
+        &ctx.accounts.collateral_bank.key(),
+        &ctx.accounts.signer.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+    )?;
+    record_deposit(
+        &mut ctx.accounts.collateral_bank,
+        &mut ctx.accounts.collateral_bank_stats,
+        &mut ctx.accounts.user_account,
+        &mut ctx.accounts.collateral_position,
+        ctx.accounts.signer.key(),
+        received,
+        now,
+        None,
+    )?;
+
+    // Persist the updated accounts so the health check below sees the state after every leg
+    ctx.accounts.collateral_bank.exit(&crate::ID)?;
+    ctx.accounts.collateral_position.exit(&crate::ID)?;
+    ctx.accounts.borrow_bank.exit(&crate::ID)?;
+    ctx.accounts.borrow_position.exit(&crate::ID)?;
+
+    let user = &mut ctx.accounts.user_account;
+    let health = calculate_health(user, &user.key(), health_accounts, &clock)?;
+    health.require_within_borrow_headroom(ctx.accounts.protocol_config.borrow_headroom_bps, ErrorCode::InsufficientCollateral)?;
+    user.health_factor = health.health_factor();
+    user.last_updated = now;
+
+    let borrow_bank = &mut ctx.accounts.borrow_bank;
+    emit!(BorrowEvent {
+        user: user.owner,
+        borrower: user.owner,
+        mint: borrow_mint_key,
+        amount: params.borrow_amount,
+        shares: borrow_shares,
+        user_borrowed: borrow_bank.borrow_amount_for(ctx.accounts.borrow_position.borrow_shares)?,
+        position_pnl: ctx.accounts.borrow_position.pnl,
+        bank_total_borrowed: borrow_bank.total_borrowed,
+        client_tag: None,
+        event_seq: borrow_bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    emit!(LeverageDepositEvent {
+        user: user.owner,
+        collateral_mint: ctx.accounts.collateral_mint.key(),
+        borrow_mint: borrow_mint_key,
+        deposit_amount: params.deposit_amount,
+        borrow_amount: params.borrow_amount,
+        swap_program: ctx.accounts.swap_program.key(),
+        swapped_collateral,
+        health_factor: user.health_factor,
+        event_seq: ctx.accounts.collateral_bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
+
+/// CPIs into an approved swap program, which runs with the user's signature only, so the bank's token
+/// accounts can't be moved by it
+pub(crate) fn invoke_swap(swap_program: &AccountInfo, swap_accounts: &[AccountInfo], data: Vec<u8>) -> Result<()> {
+    let swap_ix = Instruction {
+        program_id: swap_program.key(),
+        accounts: swap_accounts
+            .iter()
+            .map(|info| AccountMeta {
+                pubkey: info.key(),
+                is_signer: info.is_signer,
+                is_writable: info.is_writable,
+            })
+            .collect(),
+        data,
+    };
+    invoke(&swap_ix, swap_accounts)?;
+    Ok(())
+}
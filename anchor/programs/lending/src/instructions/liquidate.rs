@@ -1,17 +1,32 @@
 use anchor_lang::prelude::*;
 use anchor_spl::associated_token::AssociatedToken;
 use anchor_spl::token_interface::{ self, Mint, TokenAccount, TokenInterface, TransferChecked };
-use pyth_solana_receiver_sdk::price_update::{get_feed_id_from_hex, PriceUpdateV2};
-use crate::constants::{MAXIMUM_AGE, SOL_USD_FEED_ID, USDC_USD_FEED_ID};
+use crate::constants::{FULL_AMOUNT, PAUSE_LIQUIDATIONS, POSITION_SEED, PROTOCOL_SEED, STATS_SEED, TREASURY_SEED, UNHEALTHY_SEED};
 use crate::state::*;
 use crate::error::ErrorCode;
+use crate::interest::{accrue_fixed_loan, accrue_interest};
+use crate::events::LiquidateEvent;
+use crate::oracle::get_bank_price_mut;
+use crate::math::{aggregate_health, liquidation_protocol_fee, lowest_weight_collateral, max_liquidation_amount, seized_collateral, PositionRisk};
+use crate::pricing::{bank_amount, bank_value};
+use crate::health::value_positions;
+use crate::mint::{calculate_transfer_fee, reconcile_vault};
+use crate::rewards::settle_rewards;
+use crate::referral::{settle_referral_fees, snapshot_debt};
+use crate::safe_math::SafeMath;
+use super::admin::reserve_position;
+use super::fixed_rate::remove_fixed_debt;
 
 #[derive(Accounts)]
 pub struct Liquidate<'info> {
     #[account(mut)]
     pub liquidator: Signer<'info>,
-    pub price_update: Account<'info, PriceUpdateV2>,
+    /// CHECK: Price account of one of the collateral bank's oracles or its `OverridePrice`, matched by `get_bank_price`
+    pub collateral_price_update: UncheckedAccount<'info>,
+    /// CHECK: Price account of one of the borrowed bank's oracles or its `OverridePrice`, matched by `get_bank_price`
+    pub borrowed_price_update: UncheckedAccount<'info>,
     pub collateral_mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = borrowed_mint.key() != collateral_mint.key())]
     pub borrowed_mint: InterfaceAccount<'info, Mint>,
     #[account(
         mut, 
@@ -19,9 +34,15 @@ pub struct Liquidate<'info> {
         bump,
     )]  
     pub collateral_bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, collateral_bank.key().as_ref()],
+        bump,
+    )]
+    pub collateral_bank_stats: Account<'info, BankStats>,
     #[account(
         mut, 
-        seeds = [b"treasury", collateral_mint.key().as_ref()],
+        seeds = [TREASURY_SEED, collateral_mint.key().as_ref()],
         bump, 
     )]  
     pub collateral_bank_token_account: InterfaceAccount<'info, TokenAccount>,
@@ -31,18 +52,38 @@ pub struct Liquidate<'info> {
         bump,
     )]  
     pub borrowed_bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, borrowed_bank.key().as_ref()],
+        bump,
+    )]
+    pub borrowed_bank_stats: Account<'info, BankStats>,
     #[account(
         mut, 
-        seeds = [b"treasury", borrowed_mint.key().as_ref()],
+        seeds = [TREASURY_SEED, borrowed_mint.key().as_ref()],
         bump, 
     )]  
     pub borrowed_bank_token_account: InterfaceAccount<'info, TokenAccount>,
     #[account(
         mut, 
-        seeds = [liquidator.key().as_ref()],
+        seeds = [user_account.owner.as_ref(), user_account.sub_account_seed().as_slice()],
         bump,
     )]  
+    /// Account of the borrower being liquidated
     pub user_account: Account<'info, User>,
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), collateral_mint.key().as_ref()],
+        bump,
+        constraint = collateral_position.collateral_enabled && collateral_bank.is_collateral() @ ErrorCode::CollateralDisabled,
+    )]
+    pub collateral_position: Account<'info, UserPosition>,
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), borrowed_mint.key().as_ref()],
+        bump,
+    )]
+    pub borrowed_position: Account<'info, UserPosition>,
     #[account( 
         init_if_needed, 
         payer = liquidator,
@@ -59,78 +100,393 @@ pub struct Liquidate<'info> {
         associated_token::token_program = token_program,
     )]
     pub liquidator_borrowed_token_account: InterfaceAccount<'info, TokenAccount>, 
+    /// Liquidator's own account, required to receive the seized collateral as a deposit
+    #[account(
+        mut,
+        seeds = [liquidator.key().as_ref(), liquidator_user_account.sub_account_seed().as_slice()],
+        bump,
+    )]
+    pub liquidator_user_account: Option<Account<'info, User>>,
+    /// Liquidator's position in the collateral bank, credited with the seized deposit shares
+    #[account(
+        init_if_needed,
+        payer = liquidator,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [POSITION_SEED, liquidator_user_account.as_ref().map(|user| user.key()).unwrap_or_default().as_ref(), collateral_mint.key().as_ref()],
+        bump,
+    )]
+    pub liquidator_collateral_position: Option<Account<'info, UserPosition>>,
+    /// Registry the liquidated user is dropped from, if the liquidator passes it
+    #[account(
+        mut,
+        seeds = [UNHEALTHY_SEED],
+        bump = unhealthy_registry.bump,
+    )]
+    pub unhealthy_registry: Option<Account<'info, UnhealthyRegistry>>,
     pub token_program: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    /// Fixed-rate loan of the user in the borrowed bank, repaid once the variable debt is
+    #[account(
+        mut,
+        constraint = borrowed_fixed_loan.user == user_account.key() && borrowed_fixed_loan.bank == borrowed_bank.key() @ ErrorCode::PositionNotFound,
+    )]
+    pub borrowed_fixed_loan: Option<Account<'info, FixedLoan>>,
 }
 
-// 1. Check if user is undercollateralized
-// 2. Calculate liquidation amount
-// 3. Make a CPI transfer from the user's token account to the bank's token account
-// 4. Update the user and bank states
-// 5. Handle fees and rewards 
+// 1. Check if user is undercollateralized across all positions and past the collateral bank's grace window
+//    (remaining accounts: bank, position, price update and fixed loans for each of the user's positions), and that the
+//    collateral bank is one the protocol's `liquidation_order` lets the liquidator seize from
+// 2. Check the repaid amount is within the close factor of the borrowed bank, `FULL_AMOUNT` repays up to it.
+//    The close factor applies to the user's variable debt plus the passed fixed loan, accrued up to now
+// 3. Make a CPI transfer from the liquidator's token account to the bank's token account
+// 4. Transfer the equivalent collateral plus the liquidation bonus to the liquidator, or with `receive_as_deposit`
+//    move the deposit shares it is worth to the liquidator's own position in the collateral bank
+// 5. Update the user's positions and bank states, repaying the variable debt before the fixed loan. A fixed loan
+//    repaid in full is left open without debt for `repay_fixed` to close
 
-pub fn process_liquidate(ctx: Context<Liquidate>) -> Result<()> { 
-    let collateral_bank = &mut ctx.accounts.collateral_bank;
-    let user = &mut ctx.accounts.user_account;
+pub fn process_liquidate(ctx: Context<Liquidate>, amount: u64, min_collateral_out: u64, receive_as_deposit: bool) -> Result<()> {
+    let bonus_bps = ctx.accounts.collateral_bank.liquidation_bonus;
+    execute_liquidation(ctx, amount, min_collateral_out, bonus_bps, receive_as_deposit)
+}
 
-    let price_update = &mut ctx.accounts.price_update;
+// Same as `liquidate`, but the bonus starts at the collateral bank's `auction_start_bonus` when the user
+// is marked unhealthy and grows by `auction_bonus_step` every slot, up to the fixed liquidation bonus.
+// Liquidators compete to step in as soon as the bonus covers their costs.
 
-    let sol_feed_id = get_feed_id_from_hex(SOL_USD_FEED_ID)?; 
-    let usdc_feed_id = get_feed_id_from_hex(USDC_USD_FEED_ID)?;
+pub fn process_liquidate_auction(ctx: Context<Liquidate>, amount: u64, min_collateral_out: u64, receive_as_deposit: bool) -> Result<()> {
+    let unhealthy_since_slot = ctx.accounts.user_account.unhealthy_since_slot;
+    if unhealthy_since_slot == 0 {
+        return Err(ErrorCode::NotMarkedUnhealthy.into());
+    }
+    let elapsed_slots = Clock::get()?.slot.saturating_sub(unhealthy_since_slot);
+    let bonus_bps = ctx.accounts.collateral_bank.auction_bonus(elapsed_slots);
+    execute_liquidation(ctx, amount, min_collateral_out, bonus_bps, receive_as_deposit)
+}
 
-    let sol_price = price_update.get_price_no_older_than(&Clock::get()?, MAXIMUM_AGE, &sol_feed_id)?;
-    let usdc_price = price_update.get_price_no_older_than(&Clock::get()?, MAXIMUM_AGE, &usdc_feed_id)?;
+/// Checks `collateral_bank` is a bank the protocol's liquidation order lets a liquidation seize from, given the
+/// user's valued `positions`
+pub(crate) fn require_liquidation_order(order: LiquidationOrder, positions: &[PositionRisk], collateral_bank: &Pubkey) -> Result<()> {
+    if order == LiquidationOrder::LiquidatorChoice {
+        return Ok(());
+    }
+    match lowest_weight_collateral(positions) {
+        Some(lowest) if lowest != *collateral_bank => {
+            msg!("Seizing from bank {} while bank {} has the lowest liquidation weight", collateral_bank, lowest);
+            Err(ErrorCode::CollateralNotLowestWeight.into())
+        }
+        _ => Ok(()),
+    }
+}
 
-    // Note: For simplicity, interest is not being included in these calculations. 
+fn execute_liquidation(ctx: Context<Liquidate>, amount: u64, min_collateral_out: u64, bonus_bps: u64, receive_as_deposit: bool) -> Result<()> {
+    ctx.accounts.collateral_bank.require_not_paused(PAUSE_LIQUIDATIONS)?;
+    ctx.accounts.borrowed_bank.require_not_paused(PAUSE_LIQUIDATIONS)?;
 
-    let total_collateral = (sol_price.price as u64 * user.deposited_sol) + (usdc_price.price as u64 * user.deposited_usdc);
-    let total_borrowed = (sol_price.price as u64 * user.borrowed_sol) + (usdc_price.price as u64 * user.borrowed_usdc);    
+    let clock = Clock::get()?;
+    accrue_interest(&mut ctx.accounts.collateral_bank, clock.unix_timestamp)?;
+    accrue_interest(&mut ctx.accounts.borrowed_bank, clock.unix_timestamp)?;
 
-    let health_factor = (total_collateral * collateral_bank.liquidation_threshold)/total_borrowed;
+    let user = &ctx.accounts.user_account;
+    let positions = value_positions(&user.positions, &user.key(), ctx.remaining_accounts, &clock)?;
+    let health = aggregate_health(&positions, user.emode_category)?;
 
-    if health_factor >= 1 {
+    if !health.is_liquidatable() {
+        msg!("Debt value {} is within liquidation limit {}", health.liquidation_debt_value, health.liquidation_limit);
         return Err(ErrorCode::NotUndercollateralized.into());
     }
+    if !ctx.accounts.collateral_bank.liquidation_grace_elapsed(user.unhealthy_since_slot, clock.slot) {
+        msg!(
+            "User flagged at slot {} is within the {} slot grace window, now {}",
+            user.unhealthy_since_slot, ctx.accounts.collateral_bank.liquidation_grace_slots, clock.slot,
+        );
+        return Err(ErrorCode::LiquidationGracePeriod.into());
+    }
+    require_liquidation_order(ctx.accounts.protocol_config.liquidation_order, &positions, &ctx.accounts.collateral_bank.key())?;
+
+    let accounts = ctx.accounts;
+    let receiver = if receive_as_deposit {
+        let (Some(liquidator_user), Some(liquidator_position)) =
+            (accounts.liquidator_user_account.as_mut(), accounts.liquidator_collateral_position.as_mut())
+        else {
+            msg!("Receiving the collateral as a deposit needs the liquidator's user account and collateral position");
+            return Err(ErrorCode::LiquidatorPositionRequired.into());
+        };
+        if liquidator_user.key() == accounts.user_account.key() {
+            msg!("Liquidator {} can't receive their own collateral as a deposit", accounts.liquidator.key());
+            return Err(ErrorCode::LiquidatorPositionRequired.into());
+        }
+        // Seized collateral can only be taken in tokens from a permissioned bank
+        accounts.collateral_bank.require_ungated()?;
+        reserve_position(
+            &accounts.protocol_config,
+            liquidator_user,
+            &accounts.collateral_bank.key(),
+            &accounts.liquidator.to_account_info(),
+            &accounts.system_program.to_account_info(),
+        )?;
+        Some(DepositReceiver { user: liquidator_user, position: liquidator_position })
+    } else {
+        None
+    };
+    let mut liquidation = LiquidationAccounts {
+        liquidator: &accounts.liquidator,
+        collateral_price_update: &accounts.collateral_price_update,
+        borrowed_price_update: &accounts.borrowed_price_update,
+        collateral_mint: &accounts.collateral_mint,
+        borrowed_mint: &accounts.borrowed_mint,
+        collateral_bank: &mut accounts.collateral_bank,
+        collateral_bank_stats: &mut accounts.collateral_bank_stats,
+        collateral_bank_token_account: &mut accounts.collateral_bank_token_account,
+        collateral_bank_token_account_bump: ctx.bumps.collateral_bank_token_account,
+        borrowed_bank: &mut accounts.borrowed_bank,
+        borrowed_bank_stats: &mut accounts.borrowed_bank_stats,
+        borrowed_bank_token_account: &mut accounts.borrowed_bank_token_account,
+        liquidator_collateral_token_account: &accounts.liquidator_collateral_token_account,
+        liquidator_borrowed_token_account: &accounts.liquidator_borrowed_token_account,
+        token_program: &accounts.token_program,
+    };
+    liquidate_user(
+        &mut liquidation,
+        &mut accounts.user_account,
+        &mut accounts.collateral_position,
+        &mut accounts.borrowed_position,
+        accounts.borrowed_fixed_loan.as_mut(),
+        amount,
+        min_collateral_out,
+        bonus_bps,
+        health.health_factor(),
+        receiver,
+        &clock,
+    )?;
+
+    if let Some(registry) = &mut accounts.unhealthy_registry {
+        registry.remove(accounts.user_account.key());
+    }
+
+    Ok(())
+}
+
+/// Accounts shared by every liquidation between the same collateral and borrowed banks
+pub(crate) struct LiquidationAccounts<'a, 'info> {
+    pub liquidator: &'a Signer<'info>,
+    pub collateral_price_update: &'a UncheckedAccount<'info>,
+    pub borrowed_price_update: &'a UncheckedAccount<'info>,
+    pub collateral_mint: &'a InterfaceAccount<'info, Mint>,
+    pub borrowed_mint: &'a InterfaceAccount<'info, Mint>,
+    pub collateral_bank: &'a mut Account<'info, Bank>,
+    pub collateral_bank_stats: &'a mut Account<'info, BankStats>,
+    pub collateral_bank_token_account: &'a mut InterfaceAccount<'info, TokenAccount>,
+    pub collateral_bank_token_account_bump: u8,
+    pub borrowed_bank: &'a mut Account<'info, Bank>,
+    pub borrowed_bank_stats: &'a mut Account<'info, BankStats>,
+    pub borrowed_bank_token_account: &'a mut InterfaceAccount<'info, TokenAccount>,
+    pub liquidator_collateral_token_account: &'a InterfaceAccount<'info, TokenAccount>,
+    pub liquidator_borrowed_token_account: &'a InterfaceAccount<'info, TokenAccount>,
+    pub token_program: &'a Interface<'info, TokenInterface>,
+}
+
+/// Liquidator's own user account and position in the collateral bank, credited with seized deposit shares
+pub(crate) struct DepositReceiver<'a, 'info> {
+    pub user: &'a mut Account<'info, User>,
+    pub position: &'a mut Account<'info, UserPosition>,
+}
 
-    let liquidation_amount = total_borrowed * collateral_bank.liquidation_close_factor;
+/// Repays `amount` of the user's debt and seizes the equivalent collateral plus `bonus_bps` for the liquidator,
+/// paid out in tokens or, with a `receiver`, as deposit shares moved to the liquidator's position.
+/// The collateral bank's protocol fee share of the bonus is kept as its reserves.
+/// Debt is repaid from the variable debt first, then from the user's `borrowed_fixed_loan`, if passed.
+/// The caller must have accrued both banks and checked the user is liquidatable.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn liquidate_user<'info>(
+    accounts: &mut LiquidationAccounts<'_, 'info>,
+    user: &mut Account<'info, User>,
+    collateral_position: &mut Account<'info, UserPosition>,
+    borrowed_position: &mut Account<'info, UserPosition>,
+    mut borrowed_fixed_loan: Option<&mut Account<'info, FixedLoan>>,
+    amount: u64,
+    min_collateral_out: u64,
+    bonus_bps: u64,
+    health_factor: u64,
+    receiver: Option<DepositReceiver<'_, 'info>>,
+    clock: &Clock,
+) -> Result<()> {
+    let collateral_bank = &mut *accounts.collateral_bank;
+    let borrowed_bank = &mut *accounts.borrowed_bank;
+
+    let collateral_price = get_bank_price_mut(collateral_bank, accounts.collateral_price_update, clock)?;
+    let borrowed_price = get_bank_price_mut(borrowed_bank, accounts.borrowed_price_update, clock)?;
+    let collateral_decimals = accounts.collateral_mint.decimals;
+    let borrowed_decimals = accounts.borrowed_mint.decimals;
+
+    let deposited_collateral = collateral_bank.deposit_amount_for(collateral_position.deposit_shares)?;
+    let borrowed_asset = borrowed_bank.borrow_amount_for(borrowed_position.borrow_shares)?;
+    let fixed_debt = match borrowed_fixed_loan.as_deref_mut() {
+        Some(loan) => {
+            if loan.user != user.key() || loan.bank != borrowed_bank.key() {
+                msg!("Fixed loan {} does not belong to user {} in bank {}", loan.key(), user.key(), borrowed_bank.key());
+                return Err(ErrorCode::PositionNotFound.into());
+            }
+            accrue_fixed_loan(borrowed_bank, loan, borrowed_position, clock)?;
+            loan.debt
+        }
+        None => 0,
+    };
+
+    // Only a portion of the debt can be repaid in one liquidation
+    let max_liquidation_amount = max_liquidation_amount(borrowed_asset.safe_add(fixed_debt)?, borrowed_bank.liquidation_close_factor)?;
+    let amount = if amount == FULL_AMOUNT { max_liquidation_amount } else { amount };
+
+    if amount == 0 || amount > max_liquidation_amount {
+        msg!("Liquidation amount {} must be between 1 and {}", amount, max_liquidation_amount);
+        return Err(ErrorCode::OverCloseFactor.into());
+    }
 
     // liquidator pays back the borrowed amount back to the bank 
 
     let transfer_to_bank = TransferChecked {
-        from: ctx.accounts.liquidator_borrowed_token_account.to_account_info(),
-        mint: ctx.accounts.borrowed_mint.to_account_info(),
-        to: ctx.accounts.borrowed_bank_token_account.to_account_info(),
-        authority: ctx.accounts.liquidator.to_account_info(),
+        from: accounts.liquidator_borrowed_token_account.to_account_info(),
+        mint: accounts.borrowed_mint.to_account_info(),
+        to: accounts.borrowed_bank_token_account.to_account_info(),
+        authority: accounts.liquidator.to_account_info(),
     };
 
-    let cpi_program = ctx.accounts.token_program.to_account_info();
+    let cpi_program = accounts.token_program.to_account_info();
     let cpi_ctx_to_bank = CpiContext::new(cpi_program.clone(), transfer_to_bank);
-    let decimals = ctx.accounts.borrowed_mint.decimals;
-
-    token_interface::transfer_checked(cpi_ctx_to_bank, liquidation_amount, decimals)?;
-
-    // Transfer liquidation value and bonus to liquidator
-    let liquidation_bonus = (liquidation_amount * collateral_bank.liquidation_bonus) + liquidation_amount;
-    
-    let transfer_to_liquidator = TransferChecked {
-        from: ctx.accounts.collateral_bank_token_account.to_account_info(),
-        mint: ctx.accounts.collateral_mint.to_account_info(),
-        to: ctx.accounts.liquidator_collateral_token_account.to_account_info(),
-        authority: ctx.accounts.collateral_bank_token_account.to_account_info(),
+
+    token_interface::transfer_checked(cpi_ctx_to_bank, amount, borrowed_decimals)?;
+
+    // Only the tokens that reach the bank after any Token-2022 transfer fee count as repaid
+    let repaid = amount.safe_sub(calculate_transfer_fee(&accounts.borrowed_mint.to_account_info(), amount)?)?;
+    reconcile_vault(borrowed_bank, accounts.borrowed_bank_token_account, repaid, 0)?;
+
+    // Transfer liquidation value and bonus to liquidator, valued in collateral tokens at oracle prices.
+    // The seized amount is capped at the borrower's collateral so the bank never pays out more than it holds for them.
+    let repaid_value = bank_value(borrowed_bank, repaid, &borrowed_price)?;
+    let repaid_collateral = bank_amount(collateral_bank, repaid_value, &collateral_price)?;
+    let (total_seized, liquidation_bonus) = seized_collateral(repaid_collateral, bonus_bps, deposited_collateral)?;
+    let protocol_fee = liquidation_protocol_fee(total_seized, repaid_collateral, collateral_bank.liquidation_protocol_fee_bps)?;
+    let seized_collateral = total_seized.safe_sub(protocol_fee)?;
+
+    // Collateral the liquidator ends up with after any Token-2022 transfer fee, none applies to shares kept in the bank
+    let collateral_out = if receiver.is_some() {
+        seized_collateral
+    } else {
+        seized_collateral.safe_sub(calculate_transfer_fee(&accounts.collateral_mint.to_account_info(), seized_collateral)?)?
     };
+    if collateral_out < min_collateral_out {
+        msg!("Collateral out {} is below the minimum {}", collateral_out, min_collateral_out);
+        return Err(ErrorCode::SlippageExceeded.into());
+    }
 
-    let mint_key = ctx.accounts.collateral_mint.key();
-    let signer_seeds: &[&[&[u8]]] = &[
-        &[
-            b"treasury",
-            mint_key.as_ref(),
-            &[ctx.bumps.collateral_bank_token_account],
-        ],
-    ];
-    let cpi_ctx_to_liquidator = CpiContext::new(cpi_program.clone(), transfer_to_liquidator).with_signer(signer_seeds);
-    let collateral_decimals = ctx.accounts.collateral_mint.decimals;   
-    token_interface::transfer_checked(cpi_ctx_to_liquidator, liquidation_bonus, collateral_decimals)?;
+    if receiver.is_none() {
+        let transfer_to_liquidator = TransferChecked {
+            from: accounts.collateral_bank_token_account.to_account_info(),
+            mint: accounts.collateral_mint.to_account_info(),
+            to: accounts.liquidator_collateral_token_account.to_account_info(),
+            authority: accounts.collateral_bank_token_account.to_account_info(),
+        };
+
+        let mint_key = accounts.collateral_mint.key();
+        let signer_seeds: &[&[&[u8]]] = &[
+            &[
+                TREASURY_SEED,
+                mint_key.as_ref(),
+                &[accounts.collateral_bank_token_account_bump],
+            ],
+        ];
+        let cpi_ctx_to_liquidator = CpiContext::new(cpi_program.clone(), transfer_to_liquidator).with_signer(signer_seeds);
+        token_interface::transfer_checked(cpi_ctx_to_liquidator, seized_collateral, collateral_decimals)?;
+        reconcile_vault(collateral_bank, accounts.collateral_bank_token_account, 0, seized_collateral)?;
+    }
+
+    // Remove the repaid debt and seized collateral from the borrower and both banks, the variable debt first
+    // Debt shares burned round down and collateral shares seized round up, both in the protocol's favor
+    let variable_repaid = repaid.min(borrowed_asset);
+    let fixed_repaid = repaid.safe_sub(variable_repaid)?;
+    settle_referral_fees(borrowed_bank, user, borrowed_position)?;
+    let repaid_shares = if variable_repaid == borrowed_asset {
+        borrowed_position.borrow_shares
+    } else {
+        borrowed_bank.borrow_shares_burned(variable_repaid)?.min(borrowed_position.borrow_shares)
+    };
+    borrowed_bank.total_borrowed = borrowed_bank.total_borrowed.safe_sub(variable_repaid)?;
+    borrowed_bank.total_borrowed_shares = borrowed_bank.total_borrowed_shares.safe_sub(repaid_shares)?;
+    if let Some(loan) = borrowed_fixed_loan {
+        remove_fixed_debt(borrowed_bank, loan, borrowed_position, fixed_repaid)?;
+    }
+
+    let seized_shares = collateral_bank
+        .deposit_shares_burned(total_seized)?
+        .min(collateral_position.deposit_shares);
+    // The fee's shares are burned and its tokens move from the deposits to the reserves
+    let fee_shares = collateral_bank.deposit_shares_burned(protocol_fee)?.min(seized_shares);
+    let liquidator_shares = seized_shares.safe_sub(fee_shares)?;
+
+    borrowed_position.borrow_shares = borrowed_position.borrow_shares.safe_sub(repaid_shares)?;
+    borrowed_position.touch(borrowed_bank, clock.unix_timestamp);
+    snapshot_debt(borrowed_bank, borrowed_position)?;
+    if borrowed_position.is_empty() {
+        user.remove_position(borrowed_bank.key());
+    }
+
+    settle_rewards(collateral_bank, collateral_position)?;
+    collateral_position.deposit_shares = collateral_position.deposit_shares.safe_sub(seized_shares)?;
+    collateral_position.touch(collateral_bank, clock.unix_timestamp);
+    if collateral_position.is_empty() {
+        user.remove_position(collateral_bank.key());
+    }
+
+    // Seized shares either leave the bank with the tokens paid out or change hands within it
+    let received_as_deposit = receiver.is_some();
+    if let Some(DepositReceiver { user: liquidator_user, position: liquidator_position }) = receiver {
+        collateral_bank.require_self_borrow_allowed(liquidator_position, true)?;
+        if liquidator_position.user == Pubkey::default() {
+            liquidator_position.user = liquidator_user.key();
+            liquidator_position.bank = collateral_bank.key();
+            liquidator_position.collateral_enabled = collateral_bank.is_collateral();
+            accounts.collateral_bank_stats.unique_users = accounts.collateral_bank_stats.unique_users.safe_add(1)?;
+        }
+        liquidator_user.add_position(collateral_bank.key())?;
+        settle_rewards(collateral_bank, liquidator_position)?;
+        liquidator_position.deposit_shares = liquidator_position.deposit_shares.safe_add(liquidator_shares)?;
+        liquidator_position.last_deposit_slot = clock.slot;
+        liquidator_position.touch(collateral_bank, clock.unix_timestamp);
+        collateral_bank.total_deposits = collateral_bank.total_deposits.checked_sub(protocol_fee).ok_or(ErrorCode::InsufficientFunds)?;
+        collateral_bank.total_deposit_shares = collateral_bank.total_deposit_shares.safe_sub(fee_shares)?;
+    } else {
+        collateral_bank.total_deposits = collateral_bank.total_deposits.checked_sub(total_seized).ok_or(ErrorCode::InsufficientFunds)?;
+        collateral_bank.total_deposit_shares = collateral_bank.total_deposit_shares.safe_sub(seized_shares)?;
+    }
+    collateral_bank.total_reserves = collateral_bank.total_reserves.safe_add(protocol_fee)?;
+
+    let borrowed_bank_stats = &mut accounts.borrowed_bank_stats;
+    borrowed_bank_stats.liquidation_count = borrowed_bank_stats.liquidation_count.safe_add(1)?;
+    borrowed_bank_stats.cumulative_liquidated_debt = borrowed_bank_stats.cumulative_liquidated_debt.safe_add(repaid as u128)?;
+    borrowed_bank_stats.last_updated = clock.unix_timestamp;
+    let collateral_bank_stats = &mut accounts.collateral_bank_stats;
+    collateral_bank_stats.cumulative_seized_collateral = collateral_bank_stats.cumulative_seized_collateral.safe_add(total_seized as u128)?;
+    collateral_bank_stats.last_updated = clock.unix_timestamp;
+
+    emit!(LiquidateEvent {
+        liquidator: accounts.liquidator.key(),
+        user: user.owner,
+        collateral_mint: accounts.collateral_mint.key(),
+        borrowed_mint: accounts.borrowed_mint.key(),
+        repaid_amount: repaid,
+        seized_collateral,
+        liquidation_bonus,
+        protocol_fee,
+        health_factor,
+        received_as_deposit,
+        event_seq: borrowed_bank.next_event_seq(),
+        timestamp: clock.unix_timestamp,
+    });
 
     Ok(())
-}
\ No newline at end of file
+}
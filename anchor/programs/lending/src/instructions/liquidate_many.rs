@@ -0,0 +1,206 @@
+use anchor_lang::prelude::*;
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+use crate::constants::{PAUSE_LIQUIDATIONS, PROTOCOL_SEED, STATS_SEED, TREASURY_SEED, UNHEALTHY_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::interest::accrue_interest;
+use crate::health::value_leading_positions;
+use crate::math::aggregate_health;
+use super::liquidate::{liquidate_user, require_liquidation_order, LiquidationAccounts};
+
+#[derive(Accounts)]
+pub struct LiquidateMany<'info> {
+    #[account(mut)]
+    pub liquidator: Signer<'info>,
+    /// CHECK: Price account of one of the collateral bank's oracles or its `OverridePrice`, matched by `get_bank_price`
+    pub collateral_price_update: UncheckedAccount<'info>,
+    /// CHECK: Price account of one of the borrowed bank's oracles or its `OverridePrice`, matched by `get_bank_price`
+    pub borrowed_price_update: UncheckedAccount<'info>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = borrowed_mint.key() != collateral_mint.key())]
+    pub borrowed_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [collateral_mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, collateral_bank.key().as_ref()],
+        bump,
+    )]
+    pub collateral_bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, collateral_mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [borrowed_mint.key().as_ref()],
+        bump,
+    )]
+    pub borrowed_bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, borrowed_bank.key().as_ref()],
+        bump,
+    )]
+    pub borrowed_bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, borrowed_mint.key().as_ref()],
+        bump,
+    )]
+    pub borrowed_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = liquidator,
+        associated_token::mint = collateral_mint,
+        associated_token::authority = liquidator,
+        associated_token::token_program = token_program,
+    )]
+    pub liquidator_collateral_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = liquidator,
+        associated_token::mint = borrowed_mint,
+        associated_token::authority = liquidator,
+        associated_token::token_program = token_program,
+    )]
+    pub liquidator_borrowed_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// Registry the liquidated user is dropped from, if the liquidator passes it
+    #[account(
+        mut,
+        seeds = [UNHEALTHY_SEED],
+        bump = unhealthy_registry.bump,
+    )]
+    pub unhealthy_registry: Option<Account<'info, UnhealthyRegistry>>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+/// Debt to repay for one borrower, `FULL_AMOUNT` repays up to the close factor
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct LiquidationTarget {
+    pub amount: u64,
+    pub min_collateral_out: u64,
+}
+
+// Liquidates several borrowers between the same collateral and borrowed banks at the fixed liquidation bonus.
+// Remaining accounts hold, for each target and in the same order, the user account, the user's collateral position,
+// the user's borrowed position, the user's fixed-rate loan in the borrowed bank or the program id for none, and then
+// the user's health accounts: the bank, position, price update and fixed loans for each of the user's positions.
+// Borrowers that are healthy or still within the grace window by the time the transaction lands are skipped
+// instead of failing the batch, a target the protocol's `liquidation_order` doesn't let the collateral bank seize
+// from fails it.
+
+pub fn process_liquidate_many<'info>(
+    ctx: Context<'_, '_, 'info, 'info, LiquidateMany<'info>>,
+    targets: Vec<LiquidationTarget>,
+) -> Result<()> {
+    ctx.accounts.collateral_bank.require_not_paused(PAUSE_LIQUIDATIONS)?;
+    ctx.accounts.borrowed_bank.require_not_paused(PAUSE_LIQUIDATIONS)?;
+
+    let clock = Clock::get()?;
+    accrue_interest(&mut ctx.accounts.collateral_bank, clock.unix_timestamp)?;
+    accrue_interest(&mut ctx.accounts.borrowed_bank, clock.unix_timestamp)?;
+
+    let bonus_bps = ctx.accounts.collateral_bank.liquidation_bonus;
+    let collateral_bank_key = ctx.accounts.collateral_bank.key();
+    let borrowed_bank_key = ctx.accounts.borrowed_bank.key();
+    let liquidation_order = ctx.accounts.protocol_config.liquidation_order;
+
+    let accounts = ctx.accounts;
+    let mut liquidation = LiquidationAccounts {
+        liquidator: &accounts.liquidator,
+        collateral_price_update: &accounts.collateral_price_update,
+        borrowed_price_update: &accounts.borrowed_price_update,
+        collateral_mint: &accounts.collateral_mint,
+        borrowed_mint: &accounts.borrowed_mint,
+        collateral_bank: &mut accounts.collateral_bank,
+        collateral_bank_stats: &mut accounts.collateral_bank_stats,
+        collateral_bank_token_account: &mut accounts.collateral_bank_token_account,
+        collateral_bank_token_account_bump: ctx.bumps.collateral_bank_token_account,
+        borrowed_bank: &mut accounts.borrowed_bank,
+        borrowed_bank_stats: &mut accounts.borrowed_bank_stats,
+        borrowed_bank_token_account: &mut accounts.borrowed_bank_token_account,
+        liquidator_collateral_token_account: &accounts.liquidator_collateral_token_account,
+        liquidator_borrowed_token_account: &accounts.liquidator_borrowed_token_account,
+        token_program: &accounts.token_program,
+    };
+
+    let mut remaining = ctx.remaining_accounts;
+    for target in targets {
+        if remaining.len() < 4 {
+            msg!("Expected at least 4 accounts for the next target, got {}", remaining.len());
+            return Err(ErrorCode::InvalidHealthAccounts.into());
+        }
+        let mut user: Account<User> = Account::try_from(&remaining[0])?;
+        let mut collateral_position: Account<UserPosition> = Account::try_from(&remaining[1])?;
+        let mut borrowed_position: Account<UserPosition> = Account::try_from(&remaining[2])?;
+        let mut borrowed_fixed_loan: Option<Account<FixedLoan>> = if *remaining[3].key == crate::ID {
+            None
+        } else {
+            Some(Account::try_from(&remaining[3])?)
+        };
+
+        if collateral_position.user != user.key() || collateral_position.bank != collateral_bank_key
+            || borrowed_position.user != user.key() || borrowed_position.bank != borrowed_bank_key
+        {
+            msg!("Positions {} and {} do not belong to user {}", collateral_position.key(), borrowed_position.key(), user.key());
+            return Err(ErrorCode::PositionNotFound.into());
+        }
+        if !collateral_position.collateral_enabled || !liquidation.collateral_bank.is_collateral() {
+            return Err(ErrorCode::CollateralDisabled.into());
+        }
+
+        // Persist both banks so the health check sees them as left by earlier liquidations in the batch
+        liquidation.collateral_bank.exit(&crate::ID)?;
+        liquidation.borrowed_bank.exit(&crate::ID)?;
+
+        let (positions, health_accounts_len) = value_leading_positions(&user.positions, &user.key(), &remaining[4..], &clock)?;
+        remaining = &remaining[4 + health_accounts_len..];
+        let health = aggregate_health(&positions, user.emode_category)?;
+        if !health.is_liquidatable() || !liquidation.collateral_bank.liquidation_grace_elapsed(user.unhealthy_since_slot, clock.slot) {
+            continue;
+        }
+        require_liquidation_order(liquidation_order, &positions, &collateral_bank_key)?;
+
+        liquidate_user(
+            &mut liquidation,
+            &mut user,
+            &mut collateral_position,
+            &mut borrowed_position,
+            borrowed_fixed_loan.as_mut(),
+            target.amount,
+            target.min_collateral_out,
+            bonus_bps,
+            health.health_factor(),
+            None,
+            &clock,
+        )?;
+
+        user.exit(&crate::ID)?;
+        collateral_position.exit(&crate::ID)?;
+        borrowed_position.exit(&crate::ID)?;
+        if let Some(loan) = &borrowed_fixed_loan {
+            loan.exit(&crate::ID)?;
+        }
+
+        if let Some(registry) = &mut accounts.unhealthy_registry {
+            registry.remove(user.key());
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,173 @@
+use anchor_lang::prelude::*;
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+use crate::constants::{POSITION_SEED, REWARD_VAULT_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::{RewardsClaimed, RewardsFunded};
+use crate::interest::accrue_interest;
+use crate::rewards::settle_rewards;
+use crate::safe_math::SafeMath;
+
+#[derive(Accounts)]
+pub struct FundRewards<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        constraint = bank.reward_mint == Pubkey::default()
+            || bank.reward_mint == reward_mint.key() @ ErrorCode::RewardMintMismatch,
+    )]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init_if_needed,
+        payer = authority,
+        token::mint = reward_mint,
+        token::authority = reward_vault,
+        token::token_program = token_program,
+        seeds = [REWARD_VAULT_SEED, bank.key().as_ref()],
+        bump,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = reward_mint,
+        token::authority = authority,
+        token::token_program = token_program,
+    )]
+    pub funder_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimRewards<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(address = bank.reward_mint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [REWARD_VAULT_SEED, bank.key().as_ref()],
+        bump,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        seeds = [signer.key().as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+        constraint = !user_account.is_tokenized() @ ErrorCode::AccountTokenized,
+    )]
+    pub user_account: Account<'info, User>,
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub user_position: Account<'info, UserPosition>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = reward_mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_reward_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+// 1. Emit rewards up to now at the old rate
+// 2. CPI transfer the funded rewards into the bank's reward vault
+// 3. Add them to the rewards left to emit and set the new emissions rate
+
+pub fn process_fund_rewards(ctx: Context<FundRewards>, amount: u64, reward_rate: u64) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    accrue_interest(&mut ctx.accounts.bank, now)?;
+
+    if amount > 0 {
+        let transfer_cpi_accounts = TransferChecked {
+            from: ctx.accounts.funder_token_account.to_account_info(),
+            mint: ctx.accounts.reward_mint.to_account_info(),
+            to: ctx.accounts.reward_vault.to_account_info(),
+            authority: ctx.accounts.authority.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), transfer_cpi_accounts);
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.reward_mint.decimals)?;
+    }
+
+    let bank = &mut ctx.accounts.bank;
+    bank.reward_mint = ctx.accounts.reward_mint.key();
+    bank.reward_rate = reward_rate;
+    bank.rewards_remaining = bank.rewards_remaining.safe_add(amount)?;
+
+    emit!(RewardsFunded {
+        bank: bank.key(),
+        reward_mint: bank.reward_mint,
+        amount,
+        reward_rate,
+        rewards_remaining: bank.rewards_remaining,
+        event_seq: bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
+
+// 1. Emit rewards up to now and settle them into the user's position
+// 2. CPI transfer everything the position has earned from the reward vault to the user
+
+pub fn process_claim_rewards(ctx: Context<ClaimRewards>) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    accrue_interest(&mut ctx.accounts.bank, now)?;
+
+    let position = &mut ctx.accounts.user_position;
+    settle_rewards(&ctx.accounts.bank, position)?;
+
+    let amount = position.rewards_accrued;
+    if amount == 0 {
+        return Err(ErrorCode::NoRewardsToClaim.into());
+    }
+    position.rewards_accrued = 0;
+    position.last_updated = now;
+
+    let transfer_cpi_accounts = TransferChecked {
+        from: ctx.accounts.reward_vault.to_account_info(),
+        mint: ctx.accounts.reward_mint.to_account_info(),
+        to: ctx.accounts.user_reward_account.to_account_info(),
+        authority: ctx.accounts.reward_vault.to_account_info(),
+    };
+    let bank_key = ctx.accounts.bank.key();
+    let signer_seeds: &[&[&[u8]]] = &[
+        &[
+            REWARD_VAULT_SEED,
+            bank_key.as_ref(),
+            &[ctx.bumps.reward_vault],
+        ],
+    ];
+    let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), transfer_cpi_accounts).with_signer(signer_seeds);
+    token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.reward_mint.decimals)?;
+
+    emit!(RewardsClaimed {
+        user: ctx.accounts.user_account.owner,
+        bank: bank_key,
+        reward_mint: ctx.accounts.reward_mint.key(),
+        amount,
+        event_seq: ctx.accounts.bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
@@ -0,0 +1,144 @@
+use anchor_lang::prelude::*;
+use anchor_lang::system_program::{self, Transfer};
+use anchor_lang::Discriminator;
+use crate::constants::{ACCOUNT_VERSION, INITIAL_POSITIONS, MAX_POSITIONS};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::AccountMigrated;
+
+#[derive(Accounts)]
+pub struct MigrateAccount<'info> {
+    /// Pays for any extra rent the upgraded layout needs
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// CHECK: Bank, User or UserPosition account owned by the program, told apart by its discriminator in the handler
+    #[account(mut, owner = crate::ID)]
+    pub account: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+// Permissionless, upgrades a Bank, User or UserPosition account written by an older program version in place.
+// Positions carry no version, they only ever grow by appended fields.
+// New fields either take over zeroed padding or are appended to the layout, and growing the account with zeroed
+// space leaves them zeroed before the version specific upgrade steps run. Only fields whose zeroed bytes decode
+// at their full size take over padding: fixed-size fields and enums whose first variant is the largest. A zeroed
+// `Option` reads back as a one byte `None` and a zeroed `PriceMode` as a one byte `Raw`, which would shift
+// `version` and every field behind it, so such fields are only ever appended.
+// Layouts that moved or removed fields are read through their legacy struct instead.
+// 1. Grow the account to the current layout size, topping up its rent from the payer
+// 2. Run the upgrade steps between the account's version and the current one and store the new version
+
+pub fn process_migrate_account(ctx: Context<MigrateAccount>) -> Result<()> {
+    let info = ctx.accounts.account.to_account_info();
+    let discriminator = info.try_borrow_data()?.get(..8).map(|d| d.to_vec()).ok_or(ErrorCode::InvalidMigrationAccount)?;
+
+    let size = if discriminator == Bank::DISCRIMINATOR {
+        8 + Bank::INIT_SPACE
+    } else if discriminator == User::DISCRIMINATOR {
+        // Every layout starts with the owner followed by the positions list, the account only needs room for the positions held
+        let positions = info
+            .try_borrow_data()?
+            .get(40..44)
+            .map(|len| u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize)
+            .ok_or(ErrorCode::InvalidMigrationAccount)?;
+        User::space_for(positions.clamp(INITIAL_POSITIONS, MAX_POSITIONS))
+    } else if discriminator == UserPosition::DISCRIMINATOR {
+        8 + UserPosition::INIT_SPACE
+    } else {
+        msg!("Account discriminator {:?} is neither a Bank, a User nor a UserPosition", discriminator);
+        return Err(ErrorCode::InvalidMigrationAccount.into());
+    };
+
+    if info.data_len() < size {
+        let rent_due = Rent::get()?.minimum_balance(size).saturating_sub(info.lamports());
+        if rent_due > 0 {
+            let transfer_accounts = Transfer {
+                from: ctx.accounts.payer.to_account_info(),
+                to: info.clone(),
+            };
+            system_program::transfer(CpiContext::new(ctx.accounts.system_program.to_account_info(), transfer_accounts), rent_due)?;
+        }
+        info.realloc(size, true)?;
+    }
+
+    let mut data = info.try_borrow_mut_data()?;
+    let (from_version, event_seq) = if discriminator == Bank::DISCRIMINATOR {
+        let mut bank = Bank::try_deserialize(&mut &data[..])?;
+        let from_version = bank.version;
+        // Version 2 replaced the Pyth feed id with an oracle source
+        if from_version < 2 {
+            bank.oracle = OracleSource::Pyth { feed_id: bank.price_feed };
+        }
+        bank.version = ACCOUNT_VERSION;
+        let event_seq = bank.next_event_seq();
+        bank.try_serialize(&mut &mut data[..])?;
+        (from_version, event_seq)
+    } else if discriminator == UserPosition::DISCRIMINATOR {
+        // Appended fields start zeroed, nothing to upgrade, positions keep no event sequence
+        (0, 0)
+    } else {
+        // Version 3 dropped the USDC mint, balances in every asset live in position PDAs
+        // An older account read with the current layout either fails or finds a zero version in its padding
+        let mut user = match User::try_deserialize(&mut &data[..]) {
+            Ok(user) if user.version >= 3 => user,
+            _ => UserV2::deserialize(&mut &data[8..])?.into_user(),
+        };
+        let from_version = user.version;
+        // The position count took over padding, accounts from before it start counting from their list
+        user.active_positions = user.positions.len() as u8;
+        user.version = ACCOUNT_VERSION;
+        let event_seq = user.next_event_seq();
+        user.try_serialize(&mut &mut data[..])?;
+        (from_version, event_seq)
+    };
+    drop(data);
+
+    emit!(AccountMigrated {
+        account: info.key(),
+        from_version,
+        to_version: ACCOUNT_VERSION,
+        size: size as u64,
+        event_seq,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+/// User layout up to version 2, which held the USDC mint address after the positions
+#[derive(AnchorDeserialize)]
+struct UserV2 {
+    owner: Pubkey,
+    positions: Vec<Pubkey>,
+    _usdc_address: Pubkey,
+    referrer: Pubkey,
+    delegations: Vec<Delegation>,
+    emode_category: u8,
+    health_factor: u64,
+    unhealthy_since_slot: u64,
+    last_updated: i64,
+    _padding: [u8; 128],
+    version: u8,
+}
+
+impl UserV2 {
+    fn into_user(self) -> User {
+        User {
+            owner: self.owner,
+            positions: self.positions,
+            referrer: self.referrer,
+            delegations: self.delegations,
+            emode_category: self.emode_category,
+            health_factor: self.health_factor,
+            unhealthy_since_slot: self.unhealthy_since_slot,
+            last_updated: self.last_updated,
+            position_nft: Pubkey::default(),
+            last_health_checkpoint: HealthCheckpoint::default(),
+            active_positions: 0,
+            sub_account_id: 0,
+            event_seq: 0,
+            padding: [0; 29],
+            version: self.version,
+        }
+    }
+}
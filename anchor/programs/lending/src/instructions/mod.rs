@@ -1,17 +1,116 @@
 pub use admin::*;
 pub mod admin;
 
+pub use authority::*;
+pub mod authority;
+
+pub use oracle_registry::*;
+pub mod oracle_registry;
+
+pub use permissionless::*;
+pub mod permissionless;
+
 pub use deposit::*;
 pub mod deposit;
 
 pub use withdraw::*;
 pub mod withdraw;
 
+pub use native_sol::*;
+pub mod native_sol;
+
 pub use borrow::*;
 pub mod borrow;
 
 pub use repay::*;
 pub mod repay;
 
+pub use receipt::*;
+pub mod receipt;
+
+pub use composite::*;
+pub mod composite;
+
+pub use leverage::*;
+pub mod leverage;
+
+pub use swap_collateral::*;
+pub mod swap_collateral;
+
+pub use self_liquidate::*;
+pub mod self_liquidate;
+
+pub use swap_debt::*;
+pub mod swap_debt;
+
+pub use fixed_rate::*;
+pub mod fixed_rate;
+
 pub use liquidate::*;
-pub mod liquidate;
\ No newline at end of file
+pub mod liquidate;
+
+pub use liquidate_many::*;
+pub mod liquidate_many;
+
+pub use bad_debt::*;
+pub mod bad_debt;
+
+pub use insurance::*;
+pub mod insurance;
+
+pub use gate::*;
+pub mod gate;
+
+pub use guarded_launch::*;
+pub mod guarded_launch;
+
+pub use timelock::*;
+pub mod timelock;
+
+pub use pause::*;
+pub mod pause;
+
+pub use reserves::*;
+pub mod reserves;
+
+pub use referral_fees::*;
+pub mod referral_fees;
+
+pub use liquidity_mining::*;
+pub mod liquidity_mining;
+
+pub use flash_loan::*;
+pub mod flash_loan;
+
+pub use collateral::*;
+pub mod collateral;
+
+pub use emode::*;
+pub mod emode;
+
+pub use delegation::*;
+pub mod delegation;
+
+pub use close::*;
+pub mod close;
+
+pub use crank::*;
+pub mod crank;
+
+pub use unhealthy_registry::*;
+pub mod unhealthy_registry;
+
+pub use position_nft::*;
+pub mod position_nft;
+
+pub use migrate::*;
+pub mod migrate;
+
+pub use view::*;
+pub mod view;
+
+pub use deleveraging::*;
+pub mod deleveraging;
+
+pub use price_override::*;
+pub mod price_override;
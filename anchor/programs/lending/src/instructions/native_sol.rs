@@ -0,0 +1,190 @@
+use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token::spl_token::native_mint;
+use anchor_spl::token_interface::{self, CloseAccount, Mint, SyncNative, TokenAccount, TokenInterface, TransferChecked};
+use crate::constants::{PAUSE_DEPOSITS, PAUSE_WITHDRAWALS, POSITION_SEED, PROTOCOL_SEED, STATS_SEED, TREASURY_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::interest::accrue_interest;
+use crate::mint::reconcile_vault;
+use super::admin::reserve_position;
+use super::deposit::record_deposit;
+use super::view::WithdrawPreview;
+use super::withdraw::{quote_withdrawal, record_withdrawal};
+
+#[derive(Accounts)]
+pub struct NativeSol<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(address = native_mint::ID)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [mint.key().as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, bank.key().as_ref()],
+        bump,
+    )]
+    pub bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, mint.key().as_ref()],
+        bump
+    )]
+    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [signer.key().as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+        constraint = !user_account.is_tokenized() @ ErrorCode::AccountTokenized,
+    )]
+    pub user_account: Account<'info, User>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), mint.key().as_ref()],
+        bump,
+    )]
+    pub user_position: Account<'info, UserPosition>,
+    /// User's WSOL account, used to wrap and unwrap lamports and closed again after a withdrawal
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(address = anchor_spl::token::ID)]
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+// 1. Accrue interest on the WSOL bank up to now
+// 2. Check the deposit stays within the bank's deposit cap
+// 3. Wrap the lamports by transferring them to the user's WSOL account and syncing its balance
+// 4. CPI transfer the wrapped SOL from the user's WSOL account to the bank's token account
+// 5. Credit the deposit shares to the user's position
+
+pub fn process_deposit_sol(ctx: Context<NativeSol>, amount: u64) -> Result<()> {
+    ctx.accounts.bank.require_not_paused(PAUSE_DEPOSITS)?;
+    ctx.accounts.bank.require_ungated()?;
+
+    let now = Clock::get()?.unix_timestamp;
+    accrue_interest(&mut ctx.accounts.bank, now)?;
+
+    let bank = &ctx.accounts.bank;
+    bank.require_deposit_cap(amount)?;
+
+    let wrap_accounts = system_program::Transfer {
+        from: ctx.accounts.signer.to_account_info(),
+        to: ctx.accounts.user_token_account.to_account_info(),
+    };
+    system_program::transfer(CpiContext::new(ctx.accounts.system_program.to_account_info(), wrap_accounts), amount)?;
+
+    let sync_accounts = SyncNative {
+        account: ctx.accounts.user_token_account.to_account_info(),
+    };
+    token_interface::sync_native(CpiContext::new(ctx.accounts.token_program.to_account_info(), sync_accounts))?;
+
+    let transfer_cpi_accounts = TransferChecked {
+        from: ctx.accounts.user_token_account.to_account_info(),
+        mint: ctx.accounts.mint.to_account_info(),
+        to: ctx.accounts.bank_token_account.to_account_info(),
+        authority: ctx.accounts.signer.to_account_info()
+    };
+    let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), transfer_cpi_accounts);
+    token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+    reconcile_vault(&mut ctx.accounts.bank, &mut ctx.accounts.bank_token_account, amount, 0)?;
+
+    reserve_position(
+        &ctx.accounts.protocol_config,
+        &ctx.accounts.user_account,
+        &ctx.accounts.bank.key(),
+        &ctx.accounts.signer.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+    )?;
+
+    record_deposit(
+        &mut ctx.accounts.bank,
+        &mut ctx.accounts.bank_stats,
+        &mut ctx.accounts.user_account,
+        &mut ctx.accounts.user_position,
+        ctx.accounts.signer.key(),
+        amount,
+        now,
+        None,
+    )
+}
+
+// 1. Accrue interest on the WSOL bank up to now
+// 2. CPI transfer the wrapped SOL from the bank's token account to the user's WSOL account, less the bank's withdrawal fee
+// 3. Close the user's WSOL account, unwrapping its whole balance back to the user's wallet
+// 4. Burn the withdrawn shares and check the user's health across all positions
+//    (remaining accounts: bank, position, price update and fixed loans for each of the user's positions)
+
+pub fn process_withdraw_sol(ctx: Context<NativeSol>, amount: u64) -> Result<()> {
+    ctx.accounts.bank.require_not_paused(PAUSE_WITHDRAWALS)?;
+    ctx.accounts.bank.require_no_withdraw_cooldown()?;
+
+    let clock = Clock::get()?;
+    accrue_interest(&mut ctx.accounts.bank, clock.unix_timestamp)?;
+
+    let WithdrawPreview { amount, fee, amount_sent, .. } = quote_withdrawal(
+        &ctx.accounts.bank,
+        &ctx.accounts.user_position,
+        &ctx.accounts.mint.to_account_info(),
+        amount,
+    )?;
+
+    let transfer_cpi_accounts = TransferChecked {
+        from: ctx.accounts.bank_token_account.to_account_info(),
+        mint: ctx.accounts.mint.to_account_info(),
+        to: ctx.accounts.user_token_account.to_account_info(),
+        authority: ctx.accounts.bank_token_account.to_account_info()
+    };
+    let mint_key = ctx.accounts.mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[
+        &[
+            TREASURY_SEED,
+            mint_key.as_ref(),
+            &[ctx.bumps.bank_token_account],
+        ]
+    ];
+    let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), transfer_cpi_accounts).with_signer(signer_seeds);
+    token_interface::transfer_checked(cpi_ctx, amount_sent, ctx.accounts.mint.decimals)?;
+    reconcile_vault(&mut ctx.accounts.bank, &mut ctx.accounts.bank_token_account, 0, amount_sent)?;
+
+    let unwrap_accounts = CloseAccount {
+        account: ctx.accounts.user_token_account.to_account_info(),
+        destination: ctx.accounts.signer.to_account_info(),
+        authority: ctx.accounts.signer.to_account_info(),
+    };
+    token_interface::close_account(CpiContext::new(ctx.accounts.token_program.to_account_info(), unwrap_accounts))?;
+
+    record_withdrawal(
+        &mut ctx.accounts.bank,
+        &mut ctx.accounts.bank_stats,
+        &mut ctx.accounts.user_account,
+        &mut ctx.accounts.user_position,
+        amount,
+        fee,
+        &ctx.accounts.protocol_config,
+        ctx.remaining_accounts,
+        &clock,
+        ctx.accounts.signer.key(),
+        None,
+    )
+}
@@ -0,0 +1,163 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::Mint;
+use crate::constants::{ORACLE_SEED, PROTOCOL_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::{BankOraclesUpdated, DepegSwitchUpdated, OracleApproved, OracleRemoved};
+use crate::oracle::validate_oracle_source;
+
+#[derive(Accounts)]
+pub struct SetOracle<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        has_one = admin @ ErrorCode::Unauthorized,
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = 8 + ApprovedOracle::INIT_SPACE,
+        seeds = [ORACLE_SEED, mint.key().as_ref()],
+        bump,
+    )]
+    pub approved_oracle: Account<'info, ApprovedOracle>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveOracle<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        has_one = admin @ ErrorCode::Unauthorized,
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(
+        mut,
+        close = admin,
+        seeds = [ORACLE_SEED, approved_oracle.mint.as_ref()],
+        bump = approved_oracle.bump,
+    )]
+    pub approved_oracle: Account<'info, ApprovedOracle>,
+}
+
+#[derive(Accounts)]
+pub struct SetBankOracles<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        has_one = admin @ ErrorCode::Unauthorized,
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(
+        mut,
+        seeds = [bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+}
+
+#[derive(Accounts)]
+pub struct SetDepeg<'info> {
+    pub risk_manager: Signer<'info>,
+    #[account(
+        has_one = risk_manager @ ErrorCode::Unauthorized,
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(
+        mut,
+        seeds = [bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+}
+
+// Approves the oracle `init_bank` reads for a mint, replacing any approved before.
+// Banks already created keep the oracle they were created with, `set_bank_oracles` rotates them.
+
+pub fn process_set_oracle(ctx: Context<SetOracle>, oracle: OracleSource) -> Result<()> {
+    validate_oracle_source(&oracle)?;
+
+    let approved_oracle = &mut ctx.accounts.approved_oracle;
+    approved_oracle.mint = ctx.accounts.mint.key();
+    approved_oracle.oracle = oracle;
+    approved_oracle.bump = ctx.bumps.approved_oracle;
+
+    emit!(OracleApproved {
+        admin: ctx.accounts.admin.key(),
+        mint: approved_oracle.mint,
+        oracle,
+        event_seq: ctx.accounts.protocol_config.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn process_remove_oracle(ctx: Context<RemoveOracle>) -> Result<()> {
+    emit!(OracleRemoved {
+        admin: ctx.accounts.admin.key(),
+        mint: ctx.accounts.approved_oracle.mint,
+        event_seq: ctx.accounts.protocol_config.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Rotates the oracles of an existing bank, the fallback is tried whenever the primary can't provide a usable price
+pub fn process_set_bank_oracles(ctx: Context<SetBankOracles>, oracle: OracleSource, fallback_oracle: Option<OracleSource>) -> Result<()> {
+    validate_oracle_source(&oracle)?;
+    if let Some(fallback) = &fallback_oracle {
+        validate_oracle_source(fallback)?;
+        if *fallback == oracle {
+            msg!("Fallback oracle of {} is the same as its primary oracle", ctx.accounts.bank.mint_address);
+            return Err(ErrorCode::InvalidOracleConfig.into());
+        }
+    }
+
+    let bank = &mut ctx.accounts.bank;
+    bank.oracle = oracle;
+    bank.fallback_oracle = fallback_oracle;
+    // A rate cached for another pool must not carry over, `refresh_stake_pool_rate` caches the new one
+    bank.stake_pool_rate = 0;
+    bank.stake_pool_rate_epoch = 0;
+
+    emit!(BankOraclesUpdated {
+        bank: bank.key(),
+        admin: ctx.accounts.admin.key(),
+        oracle,
+        fallback_oracle,
+        event_seq: bank.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Kill switch for pegged banks, a depegged bank is valued at the raw oracle price until the switch is flipped back
+pub fn process_set_depeg(ctx: Context<SetDepeg>, depegged: bool) -> Result<()> {
+    let bank = &mut ctx.accounts.bank;
+    bank.depegged = depegged;
+
+    emit!(DepegSwitchUpdated {
+        bank: bank.key(),
+        risk_manager: ctx.accounts.risk_manager.key(),
+        depegged,
+        event_seq: bank.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use anchor_lang::prelude::*;
+use crate::constants::{PAUSE_ALL, PROTOCOL_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::{BankPauseUpdated, BorrowRatePinned};
+use crate::interest::accrue_interest;
+
+#[derive(Accounts)]
+pub struct SetPause<'info> {
+    pub emergency_authority: Signer<'info>,
+    #[account(
+        has_one = emergency_authority,
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(
+        mut,
+        seeds = [bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+}
+
+// `operations` is a bitmask of the PAUSE_* flags, other bits are rejected
+pub fn process_pause(ctx: Context<SetPause>, operations: u8) -> Result<()> {
+    if operations & !PAUSE_ALL != 0 {
+        msg!("Unknown pause flags {:#04x}", operations & !PAUSE_ALL);
+        return Err(ErrorCode::InvalidPauseFlags.into());
+    }
+    let bank = &mut ctx.accounts.bank;
+    bank.paused_operations |= operations;
+    emit_pause_update(bank, &ctx.accounts.emergency_authority)
+}
+
+pub fn process_unpause(ctx: Context<SetPause>, operations: u8) -> Result<()> {
+    if operations & !PAUSE_ALL != 0 {
+        msg!("Unknown pause flags {:#04x}", operations & !PAUSE_ALL);
+        return Err(ErrorCode::InvalidPauseFlags.into());
+    }
+    let bank = &mut ctx.accounts.bank;
+    bank.paused_operations &= !operations;
+    emit_pause_update(bank, &ctx.accounts.emergency_authority)
+}
+
+// Pins the variable borrow rate for `duration_secs` during incidents, overriding the curve and its clamps.
+// Interest up to now is accrued at the previous rate first, a zero duration ends the pin.
+pub fn process_pin_borrow_rate(ctx: Context<SetPause>, rate: u64, duration_secs: i64) -> Result<()> {
+    if duration_secs < 0 {
+        msg!("Pin duration {} is negative", duration_secs);
+        return Err(ErrorCode::InvalidInterestRateConfig.into());
+    }
+    let now = Clock::get()?.unix_timestamp;
+    let bank = &mut ctx.accounts.bank;
+    accrue_interest(bank, now)?;
+
+    bank.pinned_borrow_rate = rate;
+    bank.rate_pinned_until = if duration_secs == 0 { 0 } else { now.saturating_add(duration_secs) };
+
+    emit!(BorrowRatePinned {
+        bank: bank.key(),
+        authority: ctx.accounts.emergency_authority.key(),
+        rate,
+        pinned_until: bank.rate_pinned_until,
+        event_seq: bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
+
+fn emit_pause_update(bank: &mut Account<Bank>, authority: &Signer) -> Result<()> {
+    emit!(BankPauseUpdated {
+        bank: bank.key(),
+        authority: authority.key(),
+        paused_operations: bank.paused_operations,
+        event_seq: bank.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    Ok(())
+}
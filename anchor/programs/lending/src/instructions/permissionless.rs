@@ -0,0 +1,199 @@
+use anchor_lang::prelude::*;
+use anchor_lang::system_program::{self, Transfer};
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+use crate::constants::{COMMUNITY_BASE_RATE, COMMUNITY_LIQUIDATION_THRESHOLD, COMMUNITY_MAX_LTV, COMMUNITY_OPTIMAL_UTILIZATION, COMMUNITY_RESERVE_FACTOR, COMMUNITY_SLOPE1, COMMUNITY_SLOPE2, ORACLE_SEED, PROTOCOL_SEED, PROTOCOL_STATS_SEED, STATS_SEED, TREASURY_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::{CommunityBankListed, CommunityBankPromoted};
+use crate::safe_math::SafeMath;
+use crate::instructions::admin::{initialize_bank, InitBankParams};
+
+#[derive(Accounts)]
+pub struct CreatePermissionlessBank<'info> {
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    /// Receives the listing fee
+    #[account(mut, address = protocol_config.fee_receiver)]
+    pub fee_receiver: SystemAccount<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    /// Listings still need a price feed approved by the admin
+    #[account(
+        seeds = [ORACLE_SEED, mint.key().as_ref()],
+        bump = approved_oracle.bump,
+    )]
+    pub approved_oracle: Account<'info, ApprovedOracle>,
+    #[account(
+        init,
+        space = 8 + Bank::INIT_SPACE,
+        payer = creator,
+        seeds = [mint.key().as_ref()],
+        bump
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        init,
+        space = 8 + BankStats::INIT_SPACE,
+        payer = creator,
+        seeds = [STATS_SEED, bank.key().as_ref()],
+        bump
+    )]
+    pub bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [PROTOCOL_STATS_SEED],
+        bump,
+    )]
+    pub protocol_stats: Account<'info, ProtocolStats>,
+    #[account(
+        init,
+        token::mint = mint,
+        token::authority = bank_token_account,
+        payer = creator,
+        seeds = [TREASURY_SEED, mint.key().as_ref()],
+        bump
+    )]
+    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PromoteBank<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        has_one = admin @ ErrorCode::Unauthorized,
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(
+        mut,
+        seeds = [bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+}
+
+// Lets anyone list a mint whose price feed the admin approved, for a SOL listing fee.
+// The creator picks no risk parameters: the bank starts isolated with the protocol defaults capped at the
+// community limits, and is administered by the protocol admin like any other bank.
+// 1. Pay the listing fee to the fee receiver
+// 2. Initialize the bank with the conservative parameters and flag it as a community listing
+
+pub fn process_create_permissionless_bank(ctx: Context<CreatePermissionlessBank>, isolated_debt_bank: Pubkey) -> Result<()> {
+    let protocol_config = &ctx.accounts.protocol_config;
+
+    if protocol_config.listing_fee_lamports > 0 {
+        let transfer_accounts = Transfer {
+            from: ctx.accounts.creator.to_account_info(),
+            to: ctx.accounts.fee_receiver.to_account_info(),
+        };
+        system_program::transfer(
+            CpiContext::new(ctx.accounts.system_program.to_account_info(), transfer_accounts),
+            protocol_config.listing_fee_lamports,
+        )?;
+    }
+
+    let defaults = &protocol_config.default_risk;
+    let params = InitBankParams {
+        liquidation_threshold: defaults.liquidation_threshold.min(COMMUNITY_LIQUIDATION_THRESHOLD),
+        max_ltv: defaults.max_ltv.min(COMMUNITY_MAX_LTV),
+        liquidation_close_factor: defaults.liquidation_close_factor,
+        liquidation_bonus: defaults.liquidation_bonus,
+        base_rate: COMMUNITY_BASE_RATE,
+        slope1: COMMUNITY_SLOPE1,
+        slope2: COMMUNITY_SLOPE2,
+        optimal_utilization: COMMUNITY_OPTIMAL_UTILIZATION,
+        max_total_deposits: 0,
+        max_total_borrows: 0,
+        reserve_factor: COMMUNITY_RESERVE_FACTOR,
+        flash_loan_fee: 0,
+        max_price_age_secs: defaults.max_price_age_secs,
+        max_confidence_bps: defaults.max_confidence_bps,
+        isolated: true,
+        isolated_debt_bank,
+        referral_fee_bps: 0,
+        min_deposit_amount: 0,
+        min_borrow_amount: 0,
+        auction_start_bonus: 0,
+        auction_bonus_step: 0,
+        fixed_rate_premium: 0,
+        fixed_penalty_rate: 0,
+        max_fixed_term_slots: 0,
+        allow_self_borrow: false,
+        withdraw_cooldown_secs: 0,
+        liquidation_grace_slots: 0,
+        origination_fee_bps: 0,
+        skim_origination_fee: false,
+        price_mode: PriceMode::Raw,
+        max_utilization_for_withdraw: 0,
+        price_basis: PriceBasis::Spot,
+        bank_type: BankType::Standard,
+        same_slot_deposit_lock: true,
+        min_borrow_rate: 0,
+        max_borrow_rate: 0,
+        insurance_factor: 0,
+        max_insurance_withdrawal_bps: 0,
+        gate: BankGate::Open,
+        max_rate_change_per_sec: 0,
+        interest_mode: InterestMode::Simple,
+        min_accrual_interval_secs: 0,
+        liquidation_protocol_fee_bps: 0,
+        deposit_fee_bps: 0,
+        withdraw_fee_bps: 0,
+        guarded_launch: GuardedLaunch::default(),
+        collateral_weight_bps: 0,
+        debt_weight_bps: 0,
+        fixed_rate_rebalance_bps: 0,
+        fixed_early_repay_fee_bps: 0,
+        risk_tier: None,
+        ema_window_secs: 0,
+        emode_category: 0,
+        emode_max_ltv: 0,
+        emode_liquidation_threshold: 0,
+    };
+
+    let bank = &mut ctx.accounts.bank;
+    initialize_bank(bank, &ctx.accounts.mint, protocol_config.admin, &ctx.accounts.approved_oracle, defaults, &params)?;
+    bank.community = true;
+
+    ctx.accounts.bank_stats.bank = bank.key();
+    ctx.accounts.bank_stats.last_updated = bank.last_updated;
+    ctx.accounts.protocol_stats.total_banks = ctx.accounts.protocol_stats.total_banks.safe_add(1)?;
+
+    emit!(CommunityBankListed {
+        bank: bank.key(),
+        mint: bank.mint_address,
+        creator: ctx.accounts.creator.key(),
+        isolated_debt_bank,
+        listing_fee_lamports: protocol_config.listing_fee_lamports,
+        event_seq: bank.next_event_seq(),
+        timestamp: bank.last_updated,
+    });
+
+    Ok(())
+}
+
+// Clears the community flag, after which the bank's config can take it out of isolation
+pub fn process_promote_bank(ctx: Context<PromoteBank>) -> Result<()> {
+    let bank = &mut ctx.accounts.bank;
+    if !bank.community {
+        msg!("Bank {} was not listed permissionlessly", bank.mint_address);
+        return Err(ErrorCode::NotCommunityBank.into());
+    }
+    bank.community = false;
+
+    emit!(CommunityBankPromoted {
+        bank: bank.key(),
+        admin: ctx.accounts.admin.key(),
+        event_seq: bank.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
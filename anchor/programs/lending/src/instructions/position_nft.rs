@@ -0,0 +1,203 @@
+use anchor_lang::prelude::*;
+use anchor_lang::system_program::{self, CreateAccount};
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token_interface::{self, Burn, Mint, MintTo, TokenAccount, TokenInterface};
+use crate::constants::{find_position_address, sub_account_seed, INITIAL_POSITIONS, POSITION_NFT_SEED, POSITION_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::{AccountTokenized, PositionTransferred};
+
+// A user account can be tokenized into a position NFT, so its positions and their debt can change hands
+// without being unwound. While tokenized, the owner's wallet can no longer borrow, withdraw or otherwise
+// act on the account, only deposits, repayments and liquidations go through. Whoever holds the NFT moves
+// the account and its positions under their own wallet with `transfer_position`, burning the NFT.
+
+#[derive(Accounts)]
+pub struct TokenizeAccount<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [signer.key().as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+        constraint = !user_account.is_tokenized() @ ErrorCode::AccountTokenized,
+    )]
+    pub user_account: Account<'info, User>,
+    #[account(
+        init,
+        payer = signer,
+        seeds = [POSITION_NFT_SEED, user_account.key().as_ref()],
+        bump,
+        mint::decimals = 0,
+        mint::authority = user_account,
+        mint::token_program = token_program,
+    )]
+    pub position_nft_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = position_nft_mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub signer_nft_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct TransferPosition<'info> {
+    /// Holder of the position NFT, takes over the account under their own wallet
+    #[account(mut)]
+    pub holder: Signer<'info>,
+    #[account(
+        mut,
+        close = holder,
+        seeds = [user_account.owner.as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+        constraint = user_account.is_tokenized() @ ErrorCode::AccountNotTokenized,
+    )]
+    /// Tokenized account being transferred, closed once its positions have moved
+    pub user_account: Account<'info, User>,
+    #[account(
+        mut,
+        address = user_account.position_nft @ ErrorCode::AccountNotTokenized,
+    )]
+    pub position_nft_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        token::mint = position_nft_mint,
+        token::authority = holder,
+        token::token_program = token_program,
+        constraint = holder_nft_account.amount == 1 @ ErrorCode::Unauthorized,
+    )]
+    pub holder_nft_account: InterfaceAccount<'info, TokenAccount>,
+    /// Holder's new account under the same sub-account id, the holder can't already have one
+    #[account(
+        init,
+        payer = holder,
+        space = User::space_for(user_account.positions.len().max(INITIAL_POSITIONS)),
+        seeds = [holder.key().as_ref(), sub_account_seed(user_account.sub_account_id).as_slice()],
+        bump,
+    )]
+    pub new_user_account: Account<'info, User>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+// 1. Mint the account's position NFT to the owner, the account is frozen for the owner's wallet from now on
+pub fn process_tokenize_account(ctx: Context<TokenizeAccount>) -> Result<()> {
+    let owner_key = ctx.accounts.signer.key();
+    let sub_account_seed = ctx.accounts.user_account.sub_account_seed();
+    let signer_seeds: &[&[&[u8]]] = &[&[owner_key.as_ref(), &sub_account_seed, &[ctx.bumps.user_account]]];
+    let mint_cpi_accounts = MintTo {
+        mint: ctx.accounts.position_nft_mint.to_account_info(),
+        to: ctx.accounts.signer_nft_account.to_account_info(),
+        authority: ctx.accounts.user_account.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), mint_cpi_accounts, signer_seeds);
+    token_interface::mint_to(cpi_ctx, 1)?;
+
+    let user = &mut ctx.accounts.user_account;
+    user.position_nft = ctx.accounts.position_nft_mint.key();
+
+    emit!(AccountTokenized {
+        user: user.key(),
+        owner: user.owner,
+        position_nft: user.position_nft,
+        event_seq: user.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// 1. Burn the holder's position NFT
+// 2. Recreate each position under the holder's new account and close the old one
+//    (remaining accounts: bank, old position and new position for each of the user's positions)
+// 3. Copy the account to the holder's new account, delegations are dropped, and close the old account
+// Fixed-rate loans are keyed by the user account and have to be repaid before the transfer.
+
+pub fn process_transfer_position<'info>(ctx: Context<'_, '_, 'info, 'info, TransferPosition<'info>>) -> Result<()> {
+    let burn_cpi_accounts = Burn {
+        mint: ctx.accounts.position_nft_mint.to_account_info(),
+        from: ctx.accounts.holder_nft_account.to_account_info(),
+        authority: ctx.accounts.holder.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), burn_cpi_accounts);
+    token_interface::burn(cpi_ctx, 1)?;
+
+    let old_user = &ctx.accounts.user_account;
+    let new_user_key = ctx.accounts.new_user_account.key();
+    if ctx.remaining_accounts.len() != old_user.positions.len() * 3 {
+        msg!("Expected {} position accounts for {} positions, got {}", old_user.positions.len() * 3, old_user.positions.len(), ctx.remaining_accounts.len());
+        return Err(ErrorCode::InvalidHealthAccounts.into());
+    }
+
+    for (bank_key, position_accounts) in old_user.positions.iter().zip(ctx.remaining_accounts.chunks(3)) {
+        let (bank_info, old_position_info, new_position_info) = (&position_accounts[0], &position_accounts[1], &position_accounts[2]);
+        let bank: Account<Bank> = Account::try_from(bank_info)?;
+        let old_position: Account<UserPosition> = Account::try_from(old_position_info)?;
+        if bank.key() != *bank_key || old_position.user != old_user.key() || old_position.bank != *bank_key {
+            msg!("Position {} does not belong to user {} in bank {}", old_position.key(), old_user.key(), bank_key);
+            return Err(ErrorCode::PositionNotFound.into());
+        }
+        if old_position.fixed_debt > 0 {
+            msg!("Position {} has {} of fixed-rate debt, repay it before transferring", old_position.key(), old_position.fixed_debt);
+            return Err(ErrorCode::PositionNotEmpty.into());
+        }
+
+        let (new_position_key, bump) = find_position_address(&new_user_key, &bank.mint_address);
+        if new_position_info.key() != new_position_key {
+            msg!("Expected new position {}, got {}", new_position_key, new_position_info.key());
+            return Err(ErrorCode::PositionNotFound.into());
+        }
+
+        let space = 8 + UserPosition::INIT_SPACE;
+        let create_accounts = CreateAccount {
+            from: ctx.accounts.holder.to_account_info(),
+            to: new_position_info.clone(),
+        };
+        let signer_seeds: &[&[&[u8]]] = &[&[POSITION_SEED, new_user_key.as_ref(), bank.mint_address.as_ref(), &[bump]]];
+        system_program::create_account(
+            CpiContext::new_with_signer(ctx.accounts.system_program.to_account_info(), create_accounts, signer_seeds),
+            Rent::get()?.minimum_balance(space),
+            space as u64,
+            &crate::ID,
+        )?;
+
+        let new_position = UserPosition {
+            user: new_user_key,
+            ..old_position.clone().into_inner()
+        };
+        new_position.try_serialize(&mut &mut new_position_info.try_borrow_mut_data()?[..])?;
+        old_position.close(ctx.accounts.holder.to_account_info())?;
+    }
+
+    let old_user = &ctx.accounts.user_account;
+    let new_user = &mut ctx.accounts.new_user_account;
+    let now = Clock::get()?.unix_timestamp;
+    new_user.owner = ctx.accounts.holder.key();
+    new_user.sub_account_id = old_user.sub_account_id;
+    new_user.positions = old_user.positions.clone();
+    new_user.active_positions = old_user.active_positions;
+    new_user.referrer = old_user.referrer;
+    new_user.emode_category = old_user.emode_category;
+    new_user.health_factor = old_user.health_factor;
+    new_user.unhealthy_since_slot = old_user.unhealthy_since_slot;
+    new_user.last_updated = now;
+    new_user.version = old_user.version;
+
+    emit!(PositionTransferred {
+        old_user: old_user.key(),
+        new_user: new_user.key(),
+        previous_owner: old_user.owner,
+        new_owner: new_user.owner,
+        positions: new_user.positions.len() as u8,
+        event_seq: new_user.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
@@ -0,0 +1,129 @@
+use anchor_lang::prelude::*;
+use crate::constants::{MAX_PRICE_OVERRIDE_SLOTS, PRICE_OVERRIDE_SEED, PROTOCOL_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::{PriceOverrideCleared, PriceOverrideSet};
+use crate::oracle::get_oracle_price;
+
+// A bank whose oracles all fail can't be valued, freezing every borrow, withdrawal and liquidation touching it.
+// The risk manager can set an emergency price for it until a slot in the bank's `OverridePrice`. While it is set
+// the bank is flagged `price_overridden` and valuations only take the `OverridePrice` in place of a price account,
+// so callers can't pick between the override and the oracles. Every valuation at the override logs it and emits
+// `PriceOverrideUsed`, and once it expired valuations fail until it is cleared. Once an oracle works again anyone
+// can clear the override by passing that oracle's price account, as they can once it expired.
+
+#[derive(Accounts)]
+pub struct SetPriceOverride<'info> {
+    #[account(mut)]
+    pub risk_manager: Signer<'info>,
+    #[account(
+        has_one = risk_manager @ ErrorCode::Unauthorized,
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(
+        mut,
+        seeds = [bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        init_if_needed,
+        payer = risk_manager,
+        space = 8 + OverridePrice::INIT_SPACE,
+        seeds = [PRICE_OVERRIDE_SEED, bank.key().as_ref()],
+        bump,
+    )]
+    pub price_override: Account<'info, OverridePrice>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClearPriceOverride<'info> {
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(
+        mut,
+        seeds = [bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        close = rent_receiver,
+        seeds = [PRICE_OVERRIDE_SEED, bank.key().as_ref()],
+        bump = price_override.bump,
+    )]
+    pub price_override: Account<'info, OverridePrice>,
+    /// Risk manager who set the override
+    #[account(mut, address = price_override.set_by)]
+    pub rent_receiver: SystemAccount<'info>,
+    /// CHECK: Price account of one of the bank's oracles, validated by `get_oracle_price`
+    pub price_update: Option<UncheckedAccount<'info>>,
+}
+
+// Setting the override again replaces its price and expiry, the expiry must be within `MAX_PRICE_OVERRIDE_SLOTS`
+pub fn process_set_price_override(ctx: Context<SetPriceOverride>, price: i64, exponent: i32, expiry_slot: u64) -> Result<()> {
+    let clock = Clock::get()?;
+    if price <= 0 || expiry_slot <= clock.slot || expiry_slot - clock.slot > MAX_PRICE_OVERRIDE_SLOTS {
+        msg!("Override price {} until slot {} must be positive, after slot {} and within {} slots", price, expiry_slot, clock.slot, MAX_PRICE_OVERRIDE_SLOTS);
+        return Err(ErrorCode::InvalidPriceOverride.into());
+    }
+
+    let bank = &mut ctx.accounts.bank;
+    let price_override = &mut ctx.accounts.price_override;
+    price_override.mint = bank.mint_address;
+    price_override.price = price;
+    price_override.exponent = exponent;
+    price_override.expiry_slot = expiry_slot;
+    price_override.set_by = ctx.accounts.risk_manager.key();
+    price_override.bump = ctx.bumps.price_override;
+    bank.price_overridden = true;
+
+    emit!(PriceOverrideSet {
+        bank: bank.key(),
+        risk_manager: price_override.set_by,
+        price,
+        exponent,
+        expiry_slot,
+        event_seq: bank.next_event_seq(),
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// The risk manager can clear the override at any time, anyone else once it expired or with a price account
+// one of the bank's oracles reads a usable price from
+pub fn process_clear_price_override(ctx: Context<ClearPriceOverride>) -> Result<()> {
+    let clock = Clock::get()?;
+    let bank = &mut ctx.accounts.bank;
+    let signer = ctx.accounts.signer.key();
+    let expiry_slot = ctx.accounts.price_override.expiry_slot;
+    let expired = clock.slot >= expiry_slot;
+    let oracle_works = ctx
+        .accounts
+        .price_update
+        .as_ref()
+        .is_some_and(|price_update| get_oracle_price(bank, &price_update.to_account_info(), &clock).is_ok());
+    if !expired && !oracle_works && signer != ctx.accounts.protocol_config.risk_manager {
+        msg!("Price override of {} applies until slot {}, now {}", bank.mint_address, expiry_slot, clock.slot);
+        return Err(ErrorCode::PriceOverrideInUse.into());
+    }
+    bank.price_overridden = false;
+
+    emit!(PriceOverrideCleared {
+        bank: bank.key(),
+        signer,
+        expired,
+        event_seq: bank.next_event_seq(),
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
@@ -0,0 +1,348 @@
+use anchor_lang::prelude::*;
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token_interface::{self, Burn, Mint, MintTo, TokenAccount, TokenInterface, TransferChecked};
+use crate::constants::{PAUSE_DEPOSITS, PAUSE_WITHDRAWALS, POSITION_SEED, PROTOCOL_SEED, RECEIPT_SEED, STATS_SEED, TREASURY_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::{ReceiptDeposited, ReceiptStaked, ReceiptWithdrawn};
+use crate::health::calculate_health;
+use crate::interest::accrue_interest;
+use crate::mint::{calculate_transfer_fee, reconcile_vault};
+use crate::rewards::settle_rewards;
+use crate::safe_math::SafeMath;
+use super::admin::reserve_position;
+
+// Receipt tokens are deposit shares held in a wallet instead of a position, one token per share, so they can be
+// used by other protocols. They earn interest like any share but back no debt and earn no liquidity mining rewards
+// until they are staked back into a position.
+
+#[derive(Accounts)]
+pub struct InitReceiptMint<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        has_one = authority @ ErrorCode::Unauthorized,
+        seeds = [bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        init,
+        payer = authority,
+        seeds = [RECEIPT_SEED, bank.key().as_ref()],
+        bump,
+        mint::decimals = bank.mint_decimals,
+        mint::authority = bank,
+        mint::token_program = token_program,
+    )]
+    pub receipt_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ReceiptTransfer<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [mint.key().as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, bank.key().as_ref()],
+        bump,
+    )]
+    pub bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, mint.key().as_ref()],
+        bump
+    )]
+    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, address = bank.receipt_mint)]
+    pub receipt_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = receipt_mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_receipt_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ReceiptStake<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(mut, address = bank.receipt_mint)]
+    pub receipt_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [signer.key().as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+        constraint = !user_account.is_tokenized() @ ErrorCode::AccountTokenized,
+    )]
+    pub user_account: Account<'info, User>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub user_position: Account<'info, UserPosition>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = receipt_mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_receipt_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+pub fn process_init_receipt_mint(ctx: Context<InitReceiptMint>) -> Result<()> {
+    ctx.accounts.bank.receipt_mint = ctx.accounts.receipt_mint.key();
+    Ok(())
+}
+
+// 1. Accrue interest and check the deposit stays within the bank's deposit cap
+// 2. CPI transfer from the user's token account to the bank's token account
+// 3. Mint receipt tokens for the shares of the tokens received, rounded down, without opening a position
+
+pub fn process_deposit_receipt(ctx: Context<ReceiptTransfer>, amount: u64) -> Result<()> {
+    ctx.accounts.bank.require_not_paused(PAUSE_DEPOSITS)?;
+
+    let now = Clock::get()?.unix_timestamp;
+    accrue_interest(&mut ctx.accounts.bank, now)?;
+    ctx.accounts.bank.require_deposit_cap(amount)?;
+
+    let transfer_cpi_accounts = TransferChecked {
+        from: ctx.accounts.user_token_account.to_account_info(),
+        mint: ctx.accounts.mint.to_account_info(),
+        to: ctx.accounts.bank_token_account.to_account_info(),
+        authority: ctx.accounts.signer.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), transfer_cpi_accounts);
+    token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+
+    let received = amount.safe_sub(calculate_transfer_fee(&ctx.accounts.mint.to_account_info(), amount)?)?;
+    reconcile_vault(&mut ctx.accounts.bank, &mut ctx.accounts.bank_token_account, received, 0)?;
+    let bank = &mut ctx.accounts.bank;
+    bank.require_deposit_minimum(received)?;
+    let shares = bank.deposit_shares_minted(received)?;
+    bank.total_deposits = bank.total_deposits.safe_add(received)?;
+    bank.total_deposit_shares = bank.total_deposit_shares.safe_add(shares)?;
+
+    let stats = &mut ctx.accounts.bank_stats;
+    stats.cumulative_deposits = stats.cumulative_deposits.safe_add(received as u128)?;
+    stats.last_updated = now;
+
+    let mint_key = ctx.accounts.mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[&[mint_key.as_ref(), &[ctx.bumps.bank]]];
+    let mint_cpi_accounts = MintTo {
+        mint: ctx.accounts.receipt_mint.to_account_info(),
+        to: ctx.accounts.user_receipt_account.to_account_info(),
+        authority: ctx.accounts.bank.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), mint_cpi_accounts).with_signer(signer_seeds);
+    token_interface::mint_to(cpi_ctx, shares)?;
+
+    emit!(ReceiptDeposited {
+        user: ctx.accounts.signer.key(),
+        mint: mint_key,
+        amount: received,
+        shares,
+        event_seq: ctx.accounts.bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
+
+// 1. Accrue interest and burn the receipt tokens from the user's receipt account
+// 2. CPI transfer the tokens the shares are worth, rounded down, from the bank's token account to the user
+
+pub fn process_withdraw_receipt(ctx: Context<ReceiptTransfer>, shares: u64) -> Result<()> {
+    ctx.accounts.bank.require_not_paused(PAUSE_WITHDRAWALS)?;
+    ctx.accounts.bank.require_no_withdraw_cooldown()?;
+
+    let now = Clock::get()?.unix_timestamp;
+    accrue_interest(&mut ctx.accounts.bank, now)?;
+
+    let burn_cpi_accounts = Burn {
+        mint: ctx.accounts.receipt_mint.to_account_info(),
+        from: ctx.accounts.user_receipt_account.to_account_info(),
+        authority: ctx.accounts.signer.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), burn_cpi_accounts);
+    token_interface::burn(cpi_ctx, shares)?;
+
+    let bank = &mut ctx.accounts.bank;
+    let amount = bank.deposit_amount_for(shares)?;
+    bank.total_deposits = bank.total_deposits.safe_sub(amount)?;
+    bank.total_deposit_shares = bank.total_deposit_shares.safe_sub(shares)?;
+    bank.require_withdraw_liquidity()?;
+
+    let stats = &mut ctx.accounts.bank_stats;
+    stats.cumulative_withdrawals = stats.cumulative_withdrawals.safe_add(amount as u128)?;
+    stats.last_updated = now;
+
+    let mint_key = ctx.accounts.mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[&[TREASURY_SEED, mint_key.as_ref(), &[ctx.bumps.bank_token_account]]];
+    let transfer_cpi_accounts = TransferChecked {
+        from: ctx.accounts.bank_token_account.to_account_info(),
+        mint: ctx.accounts.mint.to_account_info(),
+        to: ctx.accounts.user_token_account.to_account_info(),
+        authority: ctx.accounts.bank_token_account.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), transfer_cpi_accounts).with_signer(signer_seeds);
+    token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+    reconcile_vault(&mut ctx.accounts.bank, &mut ctx.accounts.bank_token_account, 0, amount)?;
+
+    emit!(ReceiptWithdrawn {
+        user: ctx.accounts.signer.key(),
+        mint: mint_key,
+        amount,
+        shares,
+        event_seq: ctx.accounts.bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
+
+// Burns receipt tokens and credits their shares to the user's position, where they count as collateral again
+pub fn process_stake_receipt(ctx: Context<ReceiptStake>, shares: u64) -> Result<()> {
+    reserve_position(
+        &ctx.accounts.protocol_config,
+        &ctx.accounts.user_account,
+        &ctx.accounts.bank.key(),
+        &ctx.accounts.signer.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+    )?;
+    let now = Clock::get()?.unix_timestamp;
+    accrue_interest(&mut ctx.accounts.bank, now)?;
+
+    let burn_cpi_accounts = Burn {
+        mint: ctx.accounts.receipt_mint.to_account_info(),
+        from: ctx.accounts.user_receipt_account.to_account_info(),
+        authority: ctx.accounts.signer.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), burn_cpi_accounts);
+    token_interface::burn(cpi_ctx, shares)?;
+
+    let bank = &mut ctx.accounts.bank;
+    let user = &mut ctx.accounts.user_account;
+    let position = &mut ctx.accounts.user_position;
+    bank.require_self_borrow_allowed(position, true)?;
+    if position.user == Pubkey::default() {
+        position.user = user.key();
+        position.bank = bank.key();
+        position.collateral_enabled = bank.is_collateral();
+    }
+    user.add_position(bank.key())?;
+
+    settle_rewards(bank, position)?;
+    position.deposit_shares = position.deposit_shares.safe_add(shares)?;
+    position.last_deposit_slot = Clock::get()?.slot;
+    position.touch(bank, now);
+    user.last_updated = now;
+
+    emit!(ReceiptStaked {
+        user: user.owner,
+        mint: bank.mint_address,
+        shares,
+        staked: true,
+        event_seq: bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
+
+// Takes shares out of the user's position as receipt tokens, checking the remaining collateral still covers the
+// user's debt across all positions
+// (remaining accounts: bank, position, price update and fixed loans for each of the user's positions)
+pub fn process_unstake_receipt(ctx: Context<ReceiptStake>, shares: u64) -> Result<()> {
+    let clock = Clock::get()?;
+    let now = clock.unix_timestamp;
+    accrue_interest(&mut ctx.accounts.bank, now)?;
+
+    let bank = &mut ctx.accounts.bank;
+    let user = &mut ctx.accounts.user_account;
+    let position = &mut ctx.accounts.user_position;
+    bank.require_deposit_unlocked(position, clock.slot)?;
+    if shares > position.deposit_shares {
+        msg!("Unstaking {} shares but the position holds {}", shares, position.deposit_shares);
+        return Err(ErrorCode::InsufficientFunds.into());
+    }
+
+    settle_rewards(bank, position)?;
+    position.deposit_shares = position.deposit_shares.safe_sub(shares)?;
+    position.touch(bank, now);
+    if position.is_empty() {
+        user.remove_position(bank.key());
+    }
+
+    // Persist the updated accounts so the health check below sees the post-unstake state
+    bank.exit(&crate::ID)?;
+    position.exit(&crate::ID)?;
+
+    let health = calculate_health(user, &user.key(), ctx.remaining_accounts, &clock)?;
+    health.require_within_borrow_limit(ErrorCode::HealthFactorTooLow)?;
+    user.health_factor = health.health_factor();
+    user.last_updated = now;
+
+    let mint_address = bank.mint_address;
+    let signer_seeds: &[&[&[u8]]] = &[&[mint_address.as_ref(), &[ctx.bumps.bank]]];
+    let mint_cpi_accounts = MintTo {
+        mint: ctx.accounts.receipt_mint.to_account_info(),
+        to: ctx.accounts.user_receipt_account.to_account_info(),
+        authority: ctx.accounts.bank.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), mint_cpi_accounts).with_signer(signer_seeds);
+    token_interface::mint_to(cpi_ctx, shares)?;
+
+    emit!(ReceiptStaked {
+        user: ctx.accounts.user_account.owner,
+        mint: mint_address,
+        shares,
+        staked: false,
+        event_seq: ctx.accounts.bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use anchor_lang::prelude::*;
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+use crate::constants::{POSITION_SEED, TREASURY_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::ReferralFeesClaimed;
+use crate::interest::accrue_interest;
+use crate::referral::{settle_referral_fees, snapshot_debt};
+use crate::mint::reconcile_vault;
+use crate::safe_math::SafeMath;
+
+#[derive(Accounts)]
+pub struct ClaimReferralFees<'info> {
+    #[account(mut)]
+    pub referrer: Signer<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [mint.key().as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, mint.key().as_ref()],
+        bump,
+    )]
+    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// Account of the referred borrower
+    #[account(
+        seeds = [user_account.owner.as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+        constraint = user_account.referrer == referrer.key() @ ErrorCode::Unauthorized,
+    )]
+    pub user_account: Account<'info, User>,
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), mint.key().as_ref()],
+        bump,
+    )]
+    pub user_position: Account<'info, UserPosition>,
+    #[account(
+        init_if_needed,
+        payer = referrer,
+        associated_token::mint = mint,
+        associated_token::authority = referrer,
+        associated_token::token_program = token_program,
+    )]
+    pub referrer_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+// 1. Accrue interest on the bank and settle the referral fees owed on the position's debt
+// 2. CPI transfer everything the referrer has earned on the position from the bank's token account
+
+pub fn process_claim_referral_fees(ctx: Context<ClaimReferralFees>) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    accrue_interest(&mut ctx.accounts.bank, now)?;
+
+    let bank = &mut ctx.accounts.bank;
+    let position = &mut ctx.accounts.user_position;
+    settle_referral_fees(bank, &ctx.accounts.user_account, position)?;
+    snapshot_debt(bank, position)?;
+
+    let amount = position.referral_fees_accrued;
+    if amount == 0 {
+        return Err(ErrorCode::NoReferralFees.into());
+    }
+    position.referral_fees_accrued = 0;
+    position.last_updated = now;
+    bank.total_referral_fees = bank.total_referral_fees.safe_sub(amount)?;
+
+    let transfer_cpi_accounts = TransferChecked {
+        from: ctx.accounts.bank_token_account.to_account_info(),
+        mint: ctx.accounts.mint.to_account_info(),
+        to: ctx.accounts.referrer_token_account.to_account_info(),
+        authority: ctx.accounts.bank_token_account.to_account_info(),
+    };
+    let mint_key = ctx.accounts.mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[
+        &[
+            TREASURY_SEED,
+            mint_key.as_ref(),
+            &[ctx.bumps.bank_token_account],
+        ],
+    ];
+    let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), transfer_cpi_accounts).with_signer(signer_seeds);
+    token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+    reconcile_vault(&mut ctx.accounts.bank, &mut ctx.accounts.bank_token_account, 0, amount)?;
+
+    emit!(ReferralFeesClaimed {
+        referrer: ctx.accounts.referrer.key(),
+        user: ctx.accounts.user_account.owner,
+        bank: ctx.accounts.bank.key(),
+        amount,
+        event_seq: ctx.accounts.bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
@@ -1,11 +1,18 @@
 use anchor_lang::prelude::*;
 use anchor_spl::associated_token::AssociatedToken;
 use anchor_spl::token_interface::{ self, Mint, TokenAccount, TokenInterface, TransferChecked };
+use crate::constants::{FULL_AMOUNT, PAUSE_REPAYS, POSITION_SEED, STATS_SEED, TREASURY_SEED};
 use crate::state::*;
 use crate::error::ErrorCode;
+use crate::interest::accrue_interest;
+use crate::mint::{calculate_transfer_fee, gross_up_for_transfer_fee, reconcile_vault};
+use crate::events::RepayEvent;
+use crate::referral::{settle_referral_fees, snapshot_debt};
+use crate::safe_math::SafeMath;
 
 #[derive(Accounts)]
 pub struct Repay<'info> {
+    /// Payer of the repayment, may repay another user's debt
     #[account(mut)]
     pub signer: Signer<'info>,
     pub mint: InterfaceAccount<'info, Mint>,
@@ -15,18 +22,31 @@ pub struct Repay<'info> {
         bump,
     )]  
     pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, bank.key().as_ref()],
+        bump,
+    )]
+    pub bank_stats: Account<'info, BankStats>,
     #[account(
         mut, 
-        seeds = [b"treasury", mint.key().as_ref()],
+        seeds = [TREASURY_SEED, mint.key().as_ref()],
         bump, 
     )]  
     pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
     #[account(
         mut, 
-        seeds = [signer.key().as_ref()],
+        seeds = [user_account.owner.as_ref(), user_account.sub_account_seed().as_slice()],
         bump,
     )]  
+    /// Account of the borrower whose debt is repaid
     pub user_account: Account<'info, User>,
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), mint.key().as_ref()],
+        bump,
+    )]
+    pub user_position: Account<'info, UserPosition>,
     #[account( 
         init_if_needed, 
         payer = signer,
@@ -40,26 +60,34 @@ pub struct Repay<'info> {
     pub system_program: Program<'info, System>,
 }
 
-// Repay function just needs to make a CPI transfer from the user's token account into the bank's token account
-pub fn process_repay(ctx: Context<Repay>, amount: u64) -> Result<()> {
-    let user = &mut ctx.accounts.user_account;
+// Repay function just needs to make a CPI transfer from the payer's token account into the bank's token account,
+// the payer does not need to be the borrower
+pub fn process_repay(ctx: Context<Repay>, amount: u64, max_amount_in: u64, client_tag: Option<[u8; 16]>) -> Result<()> {
+    ctx.accounts.bank.require_not_paused(PAUSE_REPAYS)?;
 
-    let borrowed_asset; 
+    let now = Clock::get()?.unix_timestamp;
+    accrue_interest(&mut ctx.accounts.bank, now)?;
 
-    // Note: For simplicity, interest fees are not included in this calculation
+    // Debt includes the interest accrued on the user's borrow shares
+    let borrowed_asset = ctx.accounts.bank.borrow_amount_for(ctx.accounts.user_position.borrow_shares)?;
 
-    match ctx.accounts.mint.to_account_info().key() {
-        key if key == user.usdc_address => {
-            borrowed_asset = user.borrowed_usdc;
-        },
-        _ => {
-            borrowed_asset = user.borrowed_sol;
-        }
-    }
+    // Debt is reduced by the tokens that reach the vault after any Token-2022 transfer fee.
+    // Repaying everything sends enough to cover the whole debt, resolved after accrual so no dust is left.
+    let mint_info = ctx.accounts.mint.to_account_info();
+    let (amount, repaid) = if amount == FULL_AMOUNT {
+        (gross_up_for_transfer_fee(&mint_info, borrowed_asset)?, borrowed_asset)
+    } else {
+        (amount, amount.safe_sub(calculate_transfer_fee(&mint_info, amount)?)?)
+    };
 
-    if amount > borrowed_asset {
+    if repaid > borrowed_asset {
+        msg!("Repaying {} but only {} is borrowed", repaid, borrowed_asset);
         return Err(ErrorCode::OverRepay.into());
     }
+    if amount > max_amount_in {
+        msg!("Amount in {} exceeds the maximum {}", amount, max_amount_in);
+        return Err(ErrorCode::SlippageExceeded.into());
+    }
 
     let transfer_cpi_accounts = TransferChecked {
         from: ctx.accounts.user_token_account.to_account_info(),
@@ -73,33 +101,58 @@ pub fn process_repay(ctx: Context<Repay>, amount: u64) -> Result<()> {
     let decimals = ctx.accounts.mint.decimals;
 
     token_interface::transfer_checked(cpi_ctx, amount, decimals)?;
-
-    // Note: The checked_ prefix in Rust is used to perform operations safely by checking for potential 
-    // arithmetic overflow or other errors that could occur during the computation. If such an error occurs, these methods
-    // return None instead of causing a panic.
+    let received = amount.safe_sub(calculate_transfer_fee(&mint_info, amount)?)?;
+    reconcile_vault(&mut ctx.accounts.bank, &mut ctx.accounts.bank_token_account, received, 0)?;
 
     let bank = &mut ctx.accounts.bank;
-
-    let borrowed_ratio = amount.checked_div(bank.total_borrowed).unwrap();
-    let users_shares = bank.total_borrowed_shares.checked_mul(borrowed_ratio).unwrap();
-    
     let user = &mut ctx.accounts.user_account;
-    
-    match ctx.accounts.mint.to_account_info().key() {
-        key if key == user.usdc_address => {
-            user.borrowed_usdc -= amount;
-            user.borrowed_usdc_shares -= users_shares;
-        },
-        _ => {
-            user.borrowed_sol -= amount;
-            user.borrowed_sol_shares -= users_shares; 
-        }
+    let position = &mut ctx.accounts.user_position;
+    let users_shares = burn_borrow_shares(bank, &mut ctx.accounts.bank_stats, user, position, repaid, borrowed_asset, now)?;
+
+    emit!(RepayEvent {
+        user: user.owner,
+        payer: ctx.accounts.signer.key(),
+        mint: ctx.accounts.mint.key(),
+        amount: repaid,
+        shares: users_shares,
+        user_borrowed: bank.borrow_amount_for(position.borrow_shares)?,
+        position_pnl: position.pnl,
+        bank_total_borrowed: bank.total_borrowed,
+        client_tag,
+        event_seq: bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
+
+/// Removes `repaid` tokens from the position's debt of `borrowed_asset` and the bank totals, returning the burned shares
+pub(crate) fn burn_borrow_shares(bank: &mut Account<Bank>, stats: &mut BankStats, user: &mut User, position: &mut UserPosition, repaid: u64, borrowed_asset: u64, now: i64) -> Result<u64> {
+    settle_referral_fees(bank, user, position)?;
+
+    // Round the burned shares down so a repayment never clears more debt than it pays for,
+    // unless the full debt is repaid
+    let users_shares = if repaid == borrowed_asset {
+        position.borrow_shares
+    } else {
+        bank.borrow_shares_burned(repaid)?
+    };
+
+    position.borrow_shares = position.borrow_shares.safe_sub(users_shares)?;
+    position.touch(bank, now);
+    if position.is_empty() {
+        user.remove_position(bank.key());
     }
 
-    // Add in "update health factor" function here
+    bank.total_borrowed = bank.total_borrowed.safe_sub(repaid)?;
+    bank.total_borrowed_shares = bank.total_borrowed_shares.safe_sub(users_shares)?;
+    snapshot_debt(bank, position)?;
 
-    bank.total_borrowed -= amount;
-    bank.total_borrowed_shares -= users_shares;
+    // A partial repayment can't leave dust debt behind
+    bank.require_borrow_minimum(position.debt_snapshot)?;
 
-    Ok(())
+    stats.cumulative_repays = stats.cumulative_repays.safe_add(repaid as u128)?;
+    stats.last_updated = now;
+
+    Ok(users_shares)
 }
\ No newline at end of file
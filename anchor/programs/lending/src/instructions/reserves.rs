@@ -0,0 +1,204 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::constants::{BPS_DENOMINATOR, PROTOCOL_SEED, STATS_SEED, TREASURY_SEED};
+use crate::events::{FeesDistributed, ReservesWithdrawn};
+use crate::interest::accrue_interest;
+use crate::mint::reconcile_vault;
+use crate::safe_math::{mul_div, SafeMath};
+
+#[derive(Accounts)]
+pub struct WithdrawReserves<'info> {
+    pub authority: Signer<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [mint.key().as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, bank.key().as_ref()],
+        bump,
+    )]
+    pub bank_stats: Account<'info, BankStats>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, mint.key().as_ref()],
+        bump,
+    )]
+    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// Token account of the protocol's fee receiver
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = protocol_config.fee_receiver,
+        token::token_program = token_program,
+    )]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct DistributeFees<'info> {
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [mint.key().as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, bank.key().as_ref()],
+        bump,
+    )]
+    pub bank_stats: Account<'info, BankStats>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, mint.key().as_ref()],
+        bump,
+    )]
+    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// Token account of the staking rewards vault
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = protocol_config.fee_splits.staking_vault,
+        token::token_program = token_program,
+    )]
+    pub staking_destination: InterfaceAccount<'info, TokenAccount>,
+    /// Token account of the DAO treasury
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = protocol_config.fee_splits.dao_treasury,
+        token::token_program = token_program,
+    )]
+    pub treasury_destination: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+// 1. Accrue interest so reserves include the protocol's cut up to now
+// 2. CPI transfer of the collected reserves from the bank's token account to the destination
+pub fn process_withdraw_reserves(ctx: Context<WithdrawReserves>, amount: u64) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    accrue_interest(&mut ctx.accounts.bank, now)?;
+
+    if amount > ctx.accounts.bank.total_reserves {
+        msg!("Withdrawing {} but reserves are {}", amount, ctx.accounts.bank.total_reserves);
+        return Err(ErrorCode::InsufficientReserves.into());
+    }
+
+    let transfer_cpi_accounts = TransferChecked {
+        from: ctx.accounts.bank_token_account.to_account_info(),
+        mint: ctx.accounts.mint.to_account_info(),
+        to: ctx.accounts.destination.to_account_info(),
+        authority: ctx.accounts.bank_token_account.to_account_info(),
+    };
+    let cpi_program = ctx.accounts.token_program.to_account_info();
+    let mint_key = ctx.accounts.mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[
+        &[
+            TREASURY_SEED,
+            mint_key.as_ref(),
+            &[ctx.bumps.bank_token_account],
+        ],
+    ];
+    let cpi_ctx = CpiContext::new(cpi_program, transfer_cpi_accounts).with_signer(signer_seeds);
+    token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+    reconcile_vault(&mut ctx.accounts.bank, &mut ctx.accounts.bank_token_account, 0, amount)?;
+
+    let bank = &mut ctx.accounts.bank;
+    bank.total_reserves = bank.total_reserves.safe_sub(amount)?;
+
+    let stats = &mut ctx.accounts.bank_stats;
+    stats.cumulative_reserves_withdrawn = stats.cumulative_reserves_withdrawn.safe_add(amount as u128)?;
+    stats.last_updated = Clock::get()?.unix_timestamp;
+
+    emit!(ReservesWithdrawn {
+        bank: bank.key(),
+        authority: ctx.accounts.authority.key(),
+        destination: ctx.accounts.destination.key(),
+        amount,
+        remaining_reserves: bank.total_reserves,
+        event_seq: bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
+
+// Permissionless, the destinations are fixed by the protocol config
+// 1. Accrue interest so reserves include the protocol's cut up to now
+// 2. Split all of the bank's reserves by the protocol's fee splits, the insurance share also takes the rounding dust
+// 3. Keep the insurance share in the bank's token account as insurance fund and CPI transfer the staking
+//    and treasury shares to their destinations
+pub fn process_distribute_fees(ctx: Context<DistributeFees>) -> Result<()> {
+    let splits = ctx.accounts.protocol_config.fee_splits.clone();
+    splits.validate()?;
+
+    let now = Clock::get()?.unix_timestamp;
+    accrue_interest(&mut ctx.accounts.bank, now)?;
+
+    let reserves = ctx.accounts.bank.total_reserves;
+    let staking = mul_div(reserves, splits.staking_bps, BPS_DENOMINATOR, false)?;
+    let treasury = mul_div(reserves, splits.treasury_bps, BPS_DENOMINATOR, false)?;
+    let insurance = reserves.safe_sub(staking)?.safe_sub(treasury)?;
+
+    let mint_key = ctx.accounts.mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[
+        &[
+            TREASURY_SEED,
+            mint_key.as_ref(),
+            &[ctx.bumps.bank_token_account],
+        ],
+    ];
+    for (destination, amount) in [(&ctx.accounts.staking_destination, staking), (&ctx.accounts.treasury_destination, treasury)] {
+        if amount == 0 {
+            continue;
+        }
+        let transfer_cpi_accounts = TransferChecked {
+            from: ctx.accounts.bank_token_account.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to: destination.to_account_info(),
+            authority: ctx.accounts.bank_token_account.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), transfer_cpi_accounts).with_signer(signer_seeds);
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+    }
+    reconcile_vault(&mut ctx.accounts.bank, &mut ctx.accounts.bank_token_account, 0, staking.safe_add(treasury)?)?;
+
+    let bank = &mut ctx.accounts.bank;
+    bank.total_reserves = 0;
+    bank.insurance_fund = bank.insurance_fund.safe_add(insurance)?;
+
+    let stats = &mut ctx.accounts.bank_stats;
+    stats.cumulative_reserves_withdrawn = stats.cumulative_reserves_withdrawn.safe_add(staking.safe_add(treasury)? as u128)?;
+    stats.last_updated = now;
+
+    emit!(FeesDistributed {
+        bank: bank.key(),
+        insurance,
+        staking,
+        treasury,
+        insurance_fund: bank.insurance_fund,
+        event_seq: bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
@@ -0,0 +1,331 @@
+use anchor_lang::prelude::*;
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+use crate::constants::{BPS_DENOMINATOR, FULL_AMOUNT, PAUSE_REPAYS, PAUSE_WITHDRAWALS, POSITION_SEED, PROTOCOL_SEED, STATS_SEED, TREASURY_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::SelfLiquidated;
+use crate::interest::{accrue_fixed_loan, accrue_interest};
+use crate::oracle::get_bank_price_mut;
+use crate::pricing::{bank_amount, bank_value};
+use crate::health::calculate_health;
+use crate::mint::{calculate_transfer_fee, gross_up_for_transfer_fee, reconcile_vault};
+use crate::safe_math::{mul_div, SafeMath};
+use super::leverage::invoke_swap;
+use super::fixed_rate::remove_fixed_debt;
+use super::repay::burn_borrow_shares;
+use super::withdraw::burn_deposit_shares;
+
+#[derive(Accounts)]
+pub struct SelfLiquidate<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    /// CHECK: Only called when approved in the protocol config. Without a swap program the signer pays
+    /// the debt tokens from their wallet and keeps the withdrawn collateral
+    #[account(
+        executable,
+        constraint = protocol_config.swap_programs.contains(&swap_program.key()) @ ErrorCode::SwapProgramNotApproved,
+    )]
+    pub swap_program: Option<UncheckedAccount<'info>>,
+    /// CHECK: Price account of one of the collateral bank's oracles or its `OverridePrice`, matched by `get_bank_price`
+    pub collateral_price_update: UncheckedAccount<'info>,
+    /// CHECK: Price account of one of the debt bank's oracles or its `OverridePrice`, matched by `get_bank_price`
+    pub debt_price_update: UncheckedAccount<'info>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = debt_mint.key() != collateral_mint.key())]
+    pub debt_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [collateral_mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, collateral_bank.key().as_ref()],
+        bump,
+    )]
+    pub collateral_bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, collateral_mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [debt_mint.key().as_ref()],
+        bump,
+    )]
+    pub debt_bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, debt_bank.key().as_ref()],
+        bump,
+    )]
+    pub debt_bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, debt_mint.key().as_ref()],
+        bump,
+    )]
+    pub debt_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [signer.key().as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+        constraint = !user_account.is_tokenized() @ ErrorCode::AccountTokenized,
+    )]
+    pub user_account: Account<'info, User>,
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), collateral_mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_position: Account<'info, UserPosition>,
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), debt_mint.key().as_ref()],
+        bump,
+    )]
+    pub debt_position: Account<'info, UserPosition>,
+    /// Receives the withdrawn collateral, which the swap then spends
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = collateral_mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_collateral_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// Destination of the swap output, or the signer's own debt tokens without a swap, repaid to the debt bank
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = debt_mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_debt_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    /// Fixed-rate loan of the user in the debt bank, repaid once the variable debt is
+    #[account(
+        mut,
+        constraint = debt_fixed_loan.user == user_account.key() && debt_fixed_loan.bank == debt_bank.key() @ ErrorCode::PositionNotFound,
+    )]
+    pub debt_fixed_loan: Option<Account<'info, FixedLoan>>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SelfLiquidateParams {
+    /// Collateral withdrawn from the collateral bank, `FULL_AMOUNT` for the whole position
+    pub withdraw_amount: u64,
+    /// Fewest debt tokens that must be repaid
+    pub min_repaid: u64,
+    /// Number of leading remaining accounts passed to the swap program, 0 without a swap
+    pub swap_accounts_len: u8,
+    /// Instruction data of the swap, built by the client for the swap program, ignored without a swap
+    pub swap_data: Vec<u8>,
+}
+
+// Lets a user close down their own risk without a liquidator and without paying the liquidation bonus.
+// Collateral is withdrawn even while the user is above their borrow limit, as long as the debt repaid
+// is worth at least the collateral's weight in the liquidation limit, so the user never ends up closer
+// to liquidation than before.
+// 1. Accrue interest on both banks and the passed fixed loan and withdraw the collateral into the user's
+//    collateral token account
+// 2. CPI into the approved swap program to swap it into the debt asset, or without a swap program take
+//    debt tokens worth the withdrawn collateral at oracle prices from the user's wallet
+// 3. Repay the debt with those tokens, the variable debt before the fixed loan, which pays the bank's early
+//    repayment fee like `repay_fixed` before maturity. Anything above the debt stays in the user's wallet, a fixed
+//    loan repaid in full is left open without debt for `repay_fixed` to close
+// 4. Check the repaid value covers the withdrawn collateral at the collateral bank's liquidation threshold
+//    and refresh the user's health factor
+//    (remaining accounts: the swap's accounts, then bank, position, price update and fixed loans for each of the
+//    user's positions after the repayment has closed any)
+
+pub fn process_self_liquidate(ctx: Context<SelfLiquidate>, params: SelfLiquidateParams) -> Result<()> {
+    ctx.accounts.collateral_bank.require_not_paused(PAUSE_WITHDRAWALS)?;
+    ctx.accounts.debt_bank.require_not_paused(PAUSE_REPAYS)?;
+
+    let swap_accounts_len = params.swap_accounts_len as usize;
+    if ctx.remaining_accounts.len() < swap_accounts_len {
+        msg!("Expected at least {} swap accounts, got {}", swap_accounts_len, ctx.remaining_accounts.len());
+        return Err(ErrorCode::InvalidHealthAccounts.into());
+    }
+    let (swap_accounts, health_accounts) = ctx.remaining_accounts.split_at(swap_accounts_len);
+
+    let clock = Clock::get()?;
+    let now = clock.unix_timestamp;
+    accrue_interest(&mut ctx.accounts.collateral_bank, now)?;
+    accrue_interest(&mut ctx.accounts.debt_bank, now)?;
+
+    let collateral_price = get_bank_price_mut(&mut ctx.accounts.collateral_bank, &ctx.accounts.collateral_price_update, &clock)?;
+    let debt_price = get_bank_price_mut(&mut ctx.accounts.debt_bank, &ctx.accounts.debt_price_update, &clock)?;
+
+    let deposited = ctx.accounts.collateral_bank.deposit_amount_for(ctx.accounts.collateral_position.deposit_shares)?;
+    let withdraw_amount = if params.withdraw_amount == FULL_AMOUNT { deposited } else { params.withdraw_amount };
+    if withdraw_amount == 0 || withdraw_amount > deposited {
+        msg!("Withdrawing {} but {} is deposited", withdraw_amount, deposited);
+        return Err(ErrorCode::InsufficientFunds.into());
+    }
+    let borrowed_asset = ctx.accounts.debt_bank.borrow_amount_for(ctx.accounts.debt_position.borrow_shares)?;
+    // Tokens clearing the fixed loan in full, its debt plus any early repayment fee
+    let (fixed_debt, fixed_due, fee_bps) = match ctx.accounts.debt_fixed_loan.as_mut() {
+        Some(loan) => {
+            accrue_fixed_loan(&mut ctx.accounts.debt_bank, loan, &mut ctx.accounts.debt_position, &clock)?;
+            let fee_bps = if clock.slot < loan.maturity_slot { ctx.accounts.debt_bank.fixed_early_repay_fee_bps } else { 0 };
+            (loan.debt, loan.debt.safe_add(mul_div(loan.debt, fee_bps, BPS_DENOMINATOR, true)?)?, fee_bps)
+        }
+        None => (0, 0, 0),
+    };
+    let debt_due = borrowed_asset.safe_add(fixed_due)?;
+    if debt_due == 0 {
+        msg!("No debt to repay in bank {}", ctx.accounts.debt_bank.key());
+        return Err(ErrorCode::PositionNotFound.into());
+    }
+
+    let cpi_program = ctx.accounts.token_program.to_account_info();
+    let withdraw_cpi_accounts = TransferChecked {
+        from: ctx.accounts.collateral_bank_token_account.to_account_info(),
+        mint: ctx.accounts.collateral_mint.to_account_info(),
+        to: ctx.accounts.user_collateral_token_account.to_account_info(),
+        authority: ctx.accounts.collateral_bank_token_account.to_account_info(),
+    };
+    let collateral_mint_key = ctx.accounts.collateral_mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[
+        &[
+            TREASURY_SEED,
+            collateral_mint_key.as_ref(),
+            &[ctx.bumps.collateral_bank_token_account],
+        ],
+    ];
+    let cpi_ctx = CpiContext::new(cpi_program.clone(), withdraw_cpi_accounts).with_signer(signer_seeds);
+    token_interface::transfer_checked(cpi_ctx, withdraw_amount, ctx.accounts.collateral_mint.decimals)?;
+    reconcile_vault(&mut ctx.accounts.collateral_bank, &mut ctx.accounts.collateral_bank_token_account, 0, withdraw_amount)?;
+
+    burn_deposit_shares(
+        &mut ctx.accounts.collateral_bank,
+        &mut ctx.accounts.collateral_bank_stats,
+        &mut ctx.accounts.user_account,
+        &mut ctx.accounts.collateral_position,
+        withdraw_amount,
+        now,
+    )?;
+
+    // Debt tokens available for the repayment, the swap output or the oracle value of the collateral
+    let withdrawn_value = bank_value(&ctx.accounts.collateral_bank, withdraw_amount, &collateral_price)?;
+    let repay_amount = match &ctx.accounts.swap_program {
+        Some(swap_program) => {
+            let debt_before = ctx.accounts.user_debt_token_account.amount;
+            invoke_swap(swap_program, swap_accounts, params.swap_data)?;
+            ctx.accounts.user_debt_token_account.reload()?;
+            ctx.accounts.user_debt_token_account.amount.saturating_sub(debt_before)
+        }
+        None => bank_amount(&ctx.accounts.debt_bank, withdrawn_value, &debt_price)?,
+    };
+
+    // Only what reaches the bank after any Token-2022 transfer fee counts as repaid, capped at the debt.
+    // Tokens past the variable debt go to the fixed loan, less the early repayment fee on them, rounded up
+    let debt_mint_info = ctx.accounts.debt_mint.to_account_info();
+    let repay_amount = repay_amount.min(gross_up_for_transfer_fee(&debt_mint_info, debt_due)?);
+    let paid = repay_amount.safe_sub(calculate_transfer_fee(&debt_mint_info, repay_amount)?)?.min(debt_due);
+    let variable_repaid = paid.min(borrowed_asset);
+    let fixed_paid = paid.safe_sub(variable_repaid)?;
+    let early_repay_fee = if fixed_paid == fixed_due {
+        fixed_due.safe_sub(fixed_debt)?
+    } else {
+        mul_div(fixed_paid, fee_bps, BPS_DENOMINATOR.safe_add(fee_bps)?, true)?
+    };
+    let fixed_repaid = fixed_paid.safe_sub(early_repay_fee)?;
+    let repaid = variable_repaid.safe_add(fixed_repaid)?;
+    if repaid < params.min_repaid {
+        msg!("Repaying {}, expected at least {}", repaid, params.min_repaid);
+        return Err(ErrorCode::SlippageExceeded.into());
+    }
+
+    // Withdrawn collateral only ever leaves the bank against at least its weight in the liquidation limit,
+    // taken at the higher of the regular and e-mode thresholds so it holds in either mode, both sides
+    // weighted like the health check weights them
+    let collateral_bank = &ctx.accounts.collateral_bank;
+    let threshold = collateral_bank.liquidation_threshold.max(collateral_bank.emode_liquidation_threshold);
+    let repaid_value = bank_value(&ctx.accounts.debt_bank, repaid, &debt_price)?
+        .safe_mul(ctx.accounts.debt_bank.debt_weight() as u128)?
+        .safe_div(BPS_DENOMINATOR as u128)?;
+    let required_value = withdrawn_value
+        .safe_mul(collateral_bank.collateral_weight() as u128)?
+        .safe_div(BPS_DENOMINATOR as u128)?
+        .safe_mul(threshold as u128)?
+        .safe_div(BPS_DENOMINATOR as u128)?;
+    if repaid_value < required_value {
+        msg!("Repaid value {} is below {}, the withdrawn collateral's weight in the liquidation limit", repaid_value, required_value);
+        return Err(ErrorCode::HealthFactorTooLow.into());
+    }
+
+    let repay_cpi_accounts = TransferChecked {
+        from: ctx.accounts.user_debt_token_account.to_account_info(),
+        mint: debt_mint_info,
+        to: ctx.accounts.debt_bank_token_account.to_account_info(),
+        authority: ctx.accounts.signer.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(cpi_program, repay_cpi_accounts);
+    token_interface::transfer_checked(cpi_ctx, repay_amount, ctx.accounts.debt_mint.decimals)?;
+    let received = repay_amount.safe_sub(calculate_transfer_fee(&ctx.accounts.debt_mint.to_account_info(), repay_amount)?)?;
+    reconcile_vault(&mut ctx.accounts.debt_bank, &mut ctx.accounts.debt_bank_token_account, received, 0)?;
+
+    if variable_repaid > 0 {
+        burn_borrow_shares(
+            &mut ctx.accounts.debt_bank,
+            &mut ctx.accounts.debt_bank_stats,
+            &mut ctx.accounts.user_account,
+            &mut ctx.accounts.debt_position,
+            variable_repaid,
+            borrowed_asset,
+            now,
+        )?;
+    }
+    if let Some(loan) = ctx.accounts.debt_fixed_loan.as_mut() {
+        let debt_bank = &mut ctx.accounts.debt_bank;
+        let debt_position = &mut ctx.accounts.debt_position;
+        remove_fixed_debt(debt_bank, loan, debt_position, fixed_repaid)?;
+        debt_bank.total_reserves = debt_bank.total_reserves.safe_add(early_repay_fee)?;
+        debt_position.pnl.fees_paid = debt_position.pnl.fees_paid.safe_add(early_repay_fee)?;
+        debt_position.touch(debt_bank, now);
+        if debt_position.is_empty() {
+            ctx.accounts.user_account.remove_position(debt_bank.key());
+        }
+        loan.exit(&crate::ID)?;
+    }
+
+    // Persist the updated accounts so the health refresh below sees the state after both legs
+    ctx.accounts.collateral_bank.exit(&crate::ID)?;
+    ctx.accounts.collateral_position.exit(&crate::ID)?;
+    ctx.accounts.debt_bank.exit(&crate::ID)?;
+    ctx.accounts.debt_position.exit(&crate::ID)?;
+
+    let user = &mut ctx.accounts.user_account;
+    let health = calculate_health(user, &user.key(), health_accounts, &clock)?;
+    user.health_factor = health.health_factor();
+    user.last_updated = now;
+
+    emit!(SelfLiquidated {
+        user: user.owner,
+        collateral_mint: collateral_mint_key,
+        debt_mint: ctx.accounts.debt_mint.key(),
+        withdrawn: withdraw_amount,
+        swap_program: ctx.accounts.swap_program.as_ref().map(|program| program.key()),
+        repaid,
+        health_factor: user.health_factor,
+        event_seq: ctx.accounts.debt_bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
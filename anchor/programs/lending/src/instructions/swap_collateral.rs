@@ -0,0 +1,255 @@
+use anchor_lang::prelude::*;
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+use crate::constants::{FULL_AMOUNT, PAUSE_DEPOSITS, PAUSE_WITHDRAWALS, POSITION_SEED, PROTOCOL_SEED, STATS_SEED, TREASURY_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::CollateralSwapped;
+use crate::interest::accrue_interest;
+use crate::health::calculate_health;
+use crate::mint::{calculate_transfer_fee, reconcile_vault};
+use crate::safe_math::SafeMath;
+use super::admin::reserve_position;
+use super::deposit::record_deposit;
+use super::leverage::invoke_swap;
+use super::withdraw::burn_deposit_shares;
+
+#[derive(Accounts)]
+pub struct SwapCollateral<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    /// CHECK: Only called when approved in the protocol config
+    #[account(
+        executable,
+        constraint = protocol_config.swap_programs.contains(&swap_program.key()) @ ErrorCode::SwapProgramNotApproved,
+    )]
+    pub swap_program: UncheckedAccount<'info>,
+    pub source_mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = target_mint.key() != source_mint.key())]
+    pub target_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [source_mint.key().as_ref()],
+        bump,
+    )]
+    pub source_bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, source_bank.key().as_ref()],
+        bump,
+    )]
+    pub source_bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, source_mint.key().as_ref()],
+        bump,
+    )]
+    pub source_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [target_mint.key().as_ref()],
+        bump,
+    )]
+    pub target_bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, target_bank.key().as_ref()],
+        bump,
+    )]
+    pub target_bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, target_mint.key().as_ref()],
+        bump,
+    )]
+    pub target_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [signer.key().as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+        constraint = !user_account.is_tokenized() @ ErrorCode::AccountTokenized,
+    )]
+    pub user_account: Account<'info, User>,
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), source_mint.key().as_ref()],
+        bump,
+    )]
+    pub source_position: Account<'info, UserPosition>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), target_mint.key().as_ref()],
+        bump,
+    )]
+    pub target_position: Account<'info, UserPosition>,
+    /// Receives the withdrawn collateral, which the swap then spends
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = source_mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_source_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// Destination of the swap output, deposited into the target bank
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = target_mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_target_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SwapCollateralParams {
+    /// Collateral withdrawn from the source bank and swapped, `FULL_AMOUNT` for the whole position
+    pub withdraw_amount: u64,
+    /// Fewest target tokens the swap may return
+    pub min_target_out: u64,
+    /// Number of leading remaining accounts passed to the swap program
+    pub swap_accounts_len: u8,
+    /// Instruction data of the swap, built by the client for the swap program
+    pub swap_data: Vec<u8>,
+}
+
+// Moves collateral from one bank to another without repaying debt first. The user's health is only
+// checked at the end, so it may dip below the borrow limit between the withdrawal and the deposit.
+// 1. Accrue interest on both banks and withdraw the collateral into the user's source token account
+// 2. CPI into the approved swap program to swap it into the target asset
+// 3. Deposit everything the swap returned into the target bank
+// 4. Check once, at the end, that the user's collateral still covers their debt at max LTV
+//    (remaining accounts: the swap's accounts, then bank, position, price update and fixed loans for each of the
+//    user's positions after the swap has opened or closed theirs)
+
+pub fn process_swap_collateral(ctx: Context<SwapCollateral>, params: SwapCollateralParams) -> Result<()> {
+    ctx.accounts.source_bank.require_not_paused(PAUSE_WITHDRAWALS)?;
+    ctx.accounts.source_bank.require_no_withdraw_cooldown()?;
+    ctx.accounts.target_bank.require_not_paused(PAUSE_DEPOSITS)?;
+    ctx.accounts.target_bank.require_ungated()?;
+
+    let swap_accounts_len = params.swap_accounts_len as usize;
+    if ctx.remaining_accounts.len() < swap_accounts_len {
+        msg!("Expected at least {} swap accounts, got {}", swap_accounts_len, ctx.remaining_accounts.len());
+        return Err(ErrorCode::InvalidHealthAccounts.into());
+    }
+    let (swap_accounts, health_accounts) = ctx.remaining_accounts.split_at(swap_accounts_len);
+
+    let clock = Clock::get()?;
+    let now = clock.unix_timestamp;
+    accrue_interest(&mut ctx.accounts.source_bank, now)?;
+    accrue_interest(&mut ctx.accounts.target_bank, now)?;
+
+    let deposited_value = ctx.accounts.source_bank.deposit_amount_for(ctx.accounts.source_position.deposit_shares)?;
+    let withdraw_amount = if params.withdraw_amount == FULL_AMOUNT { deposited_value } else { params.withdraw_amount };
+    if withdraw_amount > deposited_value {
+        msg!("Swapping {} but only {} is deposited", withdraw_amount, deposited_value);
+        return Err(ErrorCode::InsufficientFunds.into());
+    }
+
+    let cpi_program = ctx.accounts.token_program.to_account_info();
+    let withdraw_cpi_accounts = TransferChecked {
+        from: ctx.accounts.source_bank_token_account.to_account_info(),
+        mint: ctx.accounts.source_mint.to_account_info(),
+        to: ctx.accounts.user_source_token_account.to_account_info(),
+        authority: ctx.accounts.source_bank_token_account.to_account_info(),
+    };
+    let source_mint_key = ctx.accounts.source_mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[
+        &[
+            TREASURY_SEED,
+            source_mint_key.as_ref(),
+            &[ctx.bumps.source_bank_token_account],
+        ],
+    ];
+    let cpi_ctx = CpiContext::new(cpi_program.clone(), withdraw_cpi_accounts).with_signer(signer_seeds);
+    token_interface::transfer_checked(cpi_ctx, withdraw_amount, ctx.accounts.source_mint.decimals)?;
+    reconcile_vault(&mut ctx.accounts.source_bank, &mut ctx.accounts.source_bank_token_account, 0, withdraw_amount)?;
+
+    burn_deposit_shares(
+        &mut ctx.accounts.source_bank,
+        &mut ctx.accounts.source_bank_stats,
+        &mut ctx.accounts.user_account,
+        &mut ctx.accounts.source_position,
+        withdraw_amount,
+        now,
+    )?;
+
+    let target_before = ctx.accounts.user_target_token_account.amount;
+    invoke_swap(&ctx.accounts.swap_program, swap_accounts, params.swap_data)?;
+
+    ctx.accounts.user_target_token_account.reload()?;
+    let swapped = ctx.accounts.user_target_token_account.amount.saturating_sub(target_before);
+    if swapped < params.min_target_out {
+        msg!("Swap returned {}, expected at least {}", swapped, params.min_target_out);
+        return Err(ErrorCode::SlippageExceeded.into());
+    }
+
+    ctx.accounts.target_bank.require_deposit_cap(swapped)?;
+
+    let deposit_cpi_accounts = TransferChecked {
+        from: ctx.accounts.user_target_token_account.to_account_info(),
+        mint: ctx.accounts.target_mint.to_account_info(),
+        to: ctx.accounts.target_bank_token_account.to_account_info(),
+        authority: ctx.accounts.signer.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(cpi_program, deposit_cpi_accounts);
+    token_interface::transfer_checked(cpi_ctx, swapped, ctx.accounts.target_mint.decimals)?;
+
+    let received = swapped.safe_sub(calculate_transfer_fee(&ctx.accounts.target_mint.to_account_info(), swapped)?)?;
+    reconcile_vault(&mut ctx.accounts.target_bank, &mut ctx.accounts.target_bank_token_account, received, 0)?;
+    reserve_position(
+        &ctx.accounts.protocol_config,
+        &ctx.accounts.user_account,
+        &ctx.accounts.target_bank.key(),
+        &ctx.accounts.signer.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+    )?;
+    record_deposit(
+        &mut ctx.accounts.target_bank,
+        &mut ctx.accounts.target_bank_stats,
+        &mut ctx.accounts.user_account,
+        &mut ctx.accounts.target_position,
+        ctx.accounts.signer.key(),
+        received,
+        now,
+        None,
+    )?;
+
+    // Persist the updated accounts so the health check below sees the state after every leg
+    ctx.accounts.source_bank.exit(&crate::ID)?;
+    ctx.accounts.source_position.exit(&crate::ID)?;
+    ctx.accounts.target_bank.exit(&crate::ID)?;
+    ctx.accounts.target_position.exit(&crate::ID)?;
+
+    let user = &mut ctx.accounts.user_account;
+    let health = calculate_health(user, &user.key(), health_accounts, &clock)?;
+    health.require_within_borrow_limit(ErrorCode::HealthFactorTooLow)?;
+    user.health_factor = health.health_factor();
+    user.last_updated = now;
+
+    emit!(CollateralSwapped {
+        user: user.owner,
+        source_mint: source_mint_key,
+        target_mint: ctx.accounts.target_mint.key(),
+        withdrawn: withdraw_amount,
+        swap_program: ctx.accounts.swap_program.key(),
+        deposited: received,
+        health_factor: user.health_factor,
+        event_seq: ctx.accounts.source_bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
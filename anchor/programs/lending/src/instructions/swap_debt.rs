@@ -0,0 +1,260 @@
+use anchor_lang::prelude::*;
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+use crate::constants::{PAUSE_BORROWS, PAUSE_REPAYS, POSITION_SEED, PROTOCOL_SEED, STATS_SEED, TREASURY_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::DebtSwapped;
+use crate::interest::accrue_interest;
+use crate::health::calculate_health;
+use crate::mint::{calculate_transfer_fee, gross_up_for_transfer_fee, reconcile_vault};
+use crate::safe_math::SafeMath;
+use super::admin::reserve_position;
+use super::borrow::mint_borrow_shares;
+use super::leverage::invoke_swap;
+use super::repay::burn_borrow_shares;
+
+#[derive(Accounts)]
+pub struct SwapDebt<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    /// CHECK: Only called when approved in the protocol config
+    #[account(
+        executable,
+        constraint = protocol_config.swap_programs.contains(&swap_program.key()) @ ErrorCode::SwapProgramNotApproved,
+    )]
+    pub swap_program: UncheckedAccount<'info>,
+    /// Asset of the debt being refinanced
+    pub repay_mint: InterfaceAccount<'info, Mint>,
+    /// Asset of the new debt
+    #[account(constraint = borrow_mint.key() != repay_mint.key())]
+    pub borrow_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [repay_mint.key().as_ref()],
+        bump,
+    )]
+    pub repay_bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, repay_bank.key().as_ref()],
+        bump,
+    )]
+    pub repay_bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, repay_mint.key().as_ref()],
+        bump,
+    )]
+    pub repay_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [borrow_mint.key().as_ref()],
+        bump,
+    )]
+    pub borrow_bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [STATS_SEED, borrow_bank.key().as_ref()],
+        bump,
+    )]
+    pub borrow_bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, borrow_mint.key().as_ref()],
+        bump,
+    )]
+    pub borrow_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [signer.key().as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+        constraint = !user_account.is_tokenized() @ ErrorCode::AccountTokenized,
+    )]
+    pub user_account: Account<'info, User>,
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), repay_mint.key().as_ref()],
+        bump,
+    )]
+    pub repay_position: Account<'info, UserPosition>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), borrow_mint.key().as_ref()],
+        bump,
+    )]
+    pub borrow_position: Account<'info, UserPosition>,
+    /// Destination of the swap output, repaid into the repay bank
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = repay_mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_repay_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// Receives the borrowed tokens, which the swap then spends
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = borrow_mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_borrow_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SwapDebtParams {
+    /// Tokens of the new asset borrowed and swapped into the old one
+    pub borrow_amount: u64,
+    /// Fewest tokens of the old asset the swap may return
+    pub min_repay_out: u64,
+    /// Number of leading remaining accounts passed to the swap program
+    pub swap_accounts_len: u8,
+    /// Instruction data of the swap, built by the client for the swap program
+    pub swap_data: Vec<u8>,
+}
+
+// Refinances debt from one asset into another without outside capital. Swap output beyond the old debt is
+// left in the user's token account.
+// 1. Accrue interest on both banks and borrow the new asset into the user's token account
+// 2. CPI into the approved swap program to swap it into the old debt's asset
+// 3. Repay the old debt with the swap output, up to the full debt
+// 4. Check once, at the end, that the user's collateral covers the new debt at max LTV
+//    (remaining accounts: the swap's accounts, then bank, position, price update and fixed loans for each of the
+//    user's positions after the borrow has opened and the repayment closed theirs)
+
+pub fn process_swap_debt(ctx: Context<SwapDebt>, params: SwapDebtParams) -> Result<()> {
+    ctx.accounts.borrow_bank.require_not_paused(PAUSE_BORROWS)?;
+    ctx.accounts.protocol_config.require_not_deleveraging()?;
+    ctx.accounts.repay_bank.require_not_paused(PAUSE_REPAYS)?;
+    ctx.accounts.borrow_bank.require_ungated()?;
+
+    let swap_accounts_len = params.swap_accounts_len as usize;
+    if ctx.remaining_accounts.len() < swap_accounts_len {
+        msg!("Expected at least {} swap accounts, got {}", swap_accounts_len, ctx.remaining_accounts.len());
+        return Err(ErrorCode::InvalidHealthAccounts.into());
+    }
+    let (swap_accounts, health_accounts) = ctx.remaining_accounts.split_at(swap_accounts_len);
+
+    let clock = Clock::get()?;
+    let now = clock.unix_timestamp;
+    accrue_interest(&mut ctx.accounts.repay_bank, now)?;
+    accrue_interest(&mut ctx.accounts.borrow_bank, now)?;
+
+    ctx.accounts.borrow_bank.require_borrow_cap(params.borrow_amount)?;
+
+    let cpi_program = ctx.accounts.token_program.to_account_info();
+    let borrow_cpi_accounts = TransferChecked {
+        from: ctx.accounts.borrow_bank_token_account.to_account_info(),
+        mint: ctx.accounts.borrow_mint.to_account_info(),
+        to: ctx.accounts.user_borrow_token_account.to_account_info(),
+        authority: ctx.accounts.borrow_bank_token_account.to_account_info(),
+    };
+    let borrow_mint_key = ctx.accounts.borrow_mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[
+        &[
+            TREASURY_SEED,
+            borrow_mint_key.as_ref(),
+            &[ctx.bumps.borrow_bank_token_account],
+        ],
+    ];
+    let cpi_ctx = CpiContext::new(cpi_program.clone(), borrow_cpi_accounts).with_signer(signer_seeds);
+    token_interface::transfer_checked(cpi_ctx, params.borrow_amount, ctx.accounts.borrow_mint.decimals)?;
+    reconcile_vault(&mut ctx.accounts.borrow_bank, &mut ctx.accounts.borrow_bank_token_account, 0, params.borrow_amount)?;
+
+    reserve_position(
+        &ctx.accounts.protocol_config,
+        &ctx.accounts.user_account,
+        &ctx.accounts.borrow_bank.key(),
+        &ctx.accounts.signer.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+    )?;
+
+    mint_borrow_shares(
+        &mut ctx.accounts.borrow_bank,
+        &mut ctx.accounts.borrow_bank_stats,
+        &mut ctx.accounts.user_account,
+        &mut ctx.accounts.borrow_position,
+        params.borrow_amount,
+        now,
+    )?;
+
+    let repay_before = ctx.accounts.user_repay_token_account.amount;
+    invoke_swap(&ctx.accounts.swap_program, swap_accounts, params.swap_data)?;
+
+    ctx.accounts.user_repay_token_account.reload()?;
+    let swapped = ctx.accounts.user_repay_token_account.amount.saturating_sub(repay_before);
+    if swapped < params.min_repay_out {
+        msg!("Swap returned {}, expected at least {}", swapped, params.min_repay_out);
+        return Err(ErrorCode::SlippageExceeded.into());
+    }
+
+    // Send at most what clears the whole debt after any Token-2022 transfer fee
+    let borrowed_asset = ctx.accounts.repay_bank.borrow_amount_for(ctx.accounts.repay_position.borrow_shares)?;
+    let repay_mint_info = ctx.accounts.repay_mint.to_account_info();
+    let full_repay_amount = gross_up_for_transfer_fee(&repay_mint_info, borrowed_asset)?;
+    let (repay_amount, repaid) = if swapped >= full_repay_amount {
+        (full_repay_amount, borrowed_asset)
+    } else {
+        (swapped, swapped.safe_sub(calculate_transfer_fee(&repay_mint_info, swapped)?)?.min(borrowed_asset))
+    };
+
+    let repay_cpi_accounts = TransferChecked {
+        from: ctx.accounts.user_repay_token_account.to_account_info(),
+        mint: ctx.accounts.repay_mint.to_account_info(),
+        to: ctx.accounts.repay_bank_token_account.to_account_info(),
+        authority: ctx.accounts.signer.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(cpi_program, repay_cpi_accounts);
+    token_interface::transfer_checked(cpi_ctx, repay_amount, ctx.accounts.repay_mint.decimals)?;
+    let received = repay_amount.safe_sub(calculate_transfer_fee(&repay_mint_info, repay_amount)?)?;
+    reconcile_vault(&mut ctx.accounts.repay_bank, &mut ctx.accounts.repay_bank_token_account, received, 0)?;
+
+    burn_borrow_shares(
+        &mut ctx.accounts.repay_bank,
+        &mut ctx.accounts.repay_bank_stats,
+        &mut ctx.accounts.user_account,
+        &mut ctx.accounts.repay_position,
+        repaid,
+        borrowed_asset,
+        now,
+    )?;
+
+    // Persist the updated accounts so the health check below sees the state after every leg
+    ctx.accounts.repay_bank.exit(&crate::ID)?;
+    ctx.accounts.repay_position.exit(&crate::ID)?;
+    ctx.accounts.borrow_bank.exit(&crate::ID)?;
+    ctx.accounts.borrow_position.exit(&crate::ID)?;
+
+    let user = &mut ctx.accounts.user_account;
+    let health = calculate_health(user, &user.key(), health_accounts, &clock)?;
+    health.require_within_borrow_headroom(ctx.accounts.protocol_config.borrow_headroom_bps, ErrorCode::InsufficientCollateral)?;
+    user.health_factor = health.health_factor();
+    user.last_updated = now;
+
+    emit!(DebtSwapped {
+        user: user.owner,
+        repay_mint: ctx.accounts.repay_mint.key(),
+        borrow_mint: borrow_mint_key,
+        borrowed: params.borrow_amount,
+        swap_program: ctx.accounts.swap_program.key(),
+        repaid,
+        health_factor: user.health_factor,
+        event_seq: ctx.accounts.repay_bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
@@ -0,0 +1,130 @@
+use anchor_lang::prelude::*;
+use crate::constants::{PENDING_CONFIG_SEED, PROTOCOL_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::{BankConfigProposed, ConfigTimelockUpdated};
+use super::admin::{apply_bank_config, UpdateBankConfigParams};
+use super::authority::AdminOnly;
+
+#[derive(Accounts)]
+pub struct ProposeBankConfig<'info> {
+    /// Either the bank's authority or the protocol's risk manager
+    #[account(
+        mut,
+        constraint = authority.key() == bank.authority
+            || authority.key() == protocol_config.risk_manager @ ErrorCode::Unauthorized,
+    )]
+    pub authority: Signer<'info>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(
+        mut,
+        seeds = [bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    /// A new proposal for the bank replaces the queued one and restarts the timelock
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + PendingBankConfig::INIT_SPACE,
+        seeds = [PENDING_CONFIG_SEED, bank.key().as_ref()],
+        bump,
+    )]
+    pub pending_config: Account<'info, PendingBankConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteBankConfig<'info> {
+    /// Either the bank's authority or the protocol's risk manager
+    #[account(
+        constraint = authority.key() == bank.authority
+            || authority.key() == protocol_config.risk_manager @ ErrorCode::Unauthorized,
+    )]
+    pub authority: Signer<'info>,
+    /// CHECK: receives the pending config's rent, checked against the recorded proposer
+    #[account(mut, address = pending_config.proposer)]
+    pub proposer: UncheckedAccount<'info>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(
+        mut,
+        seeds = [bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        close = proposer,
+        has_one = bank,
+        seeds = [PENDING_CONFIG_SEED, bank.key().as_ref()],
+        bump,
+    )]
+    pub pending_config: Account<'info, PendingBankConfig>,
+}
+
+// Queues a bank config change that can only be executed once the protocol's timelock has passed
+pub fn process_propose_bank_config(ctx: Context<ProposeBankConfig>, params: UpdateBankConfigParams) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    let executable_at = now
+        .checked_add(ctx.accounts.protocol_config.config_timelock_secs)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    let pending_config = &mut ctx.accounts.pending_config;
+    pending_config.bank = ctx.accounts.bank.key();
+    pending_config.proposer = ctx.accounts.authority.key();
+    pending_config.params = params;
+    pending_config.executable_at = executable_at;
+
+    emit!(BankConfigProposed {
+        bank: pending_config.bank,
+        proposer: pending_config.proposer,
+        executable_at,
+        event_seq: ctx.accounts.bank.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
+
+pub fn process_execute_bank_config(ctx: Context<ExecuteBankConfig>) -> Result<()> {
+    let pending_config = &ctx.accounts.pending_config;
+    let now = Clock::get()?.unix_timestamp;
+    if now < pending_config.executable_at {
+        msg!("Config is executable at {}, now is {}", pending_config.executable_at, now);
+        return Err(ErrorCode::TimelockNotElapsed.into());
+    }
+
+    let params = pending_config.params.clone();
+    let authority = ctx.accounts.authority.key();
+    apply_bank_config(&mut ctx.accounts.bank, &params, authority)
+}
+
+// Cancelling is an execute that never applies, so it shares its accounts and skips the delay
+pub fn process_cancel_bank_config(_ctx: Context<ExecuteBankConfig>) -> Result<()> {
+    Ok(())
+}
+
+pub fn process_set_config_timelock(ctx: Context<AdminOnly>, config_timelock_secs: i64) -> Result<()> {
+    if config_timelock_secs < 0 {
+        return Err(ErrorCode::InvalidTimelock.into());
+    }
+    let protocol_config = &mut ctx.accounts.protocol_config;
+    protocol_config.config_timelock_secs = config_timelock_secs;
+
+    emit!(ConfigTimelockUpdated {
+        admin: protocol_config.admin,
+        config_timelock_secs,
+        event_seq: protocol_config.next_event_seq(),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
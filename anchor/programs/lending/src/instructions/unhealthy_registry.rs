@@ -0,0 +1,80 @@
+use anchor_lang::prelude::*;
+use crate::constants::{PROTOCOL_SEED, UNHEALTHY_SEED};
+use crate::state::*;
+use crate::error::ErrorCode;
+use crate::events::UnhealthyReported;
+use crate::health::calculate_health;
+
+#[derive(Accounts)]
+pub struct InitUnhealthyRegistry<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        has_one = admin @ ErrorCode::Unauthorized,
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + UnhealthyRegistry::INIT_SPACE,
+        seeds = [UNHEALTHY_SEED],
+        bump,
+    )]
+    pub unhealthy_registry: Account<'info, UnhealthyRegistry>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ReportUnhealthy<'info> {
+    #[account(
+        mut,
+        seeds = [UNHEALTHY_SEED],
+        bump = unhealthy_registry.bump,
+    )]
+    pub unhealthy_registry: Account<'info, UnhealthyRegistry>,
+    #[account(
+        mut,
+        seeds = [user_account.owner.as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+    )]
+    pub user_account: Account<'info, User>,
+}
+
+pub fn process_init_unhealthy_registry(ctx: Context<InitUnhealthyRegistry>) -> Result<()> {
+    ctx.accounts.unhealthy_registry.bump = ctx.bumps.unhealthy_registry;
+    Ok(())
+}
+
+// Permissionless, lists a liquidatable user in the registry or drops them once they are healthy again.
+// Liquidations drop the users they liquidate, keepers report those still liquidatable afterwards again.
+// (remaining accounts: bank, position, price update and fixed loans for each of the user's positions)
+pub fn process_report_unhealthy(ctx: Context<ReportUnhealthy>) -> Result<()> {
+    let clock = Clock::get()?;
+    let user = &mut ctx.accounts.user_account;
+    let health = calculate_health(user, &user.key(), ctx.remaining_accounts, &clock)?;
+    let health_factor = health.health_factor();
+
+    let registry = &mut ctx.accounts.unhealthy_registry;
+    let listed = if health.is_liquidatable() {
+        registry.report(UnhealthyEntry {
+            user: user.key(),
+            health_factor,
+            reported_slot: clock.slot,
+        })
+    } else {
+        registry.remove(user.key());
+        false
+    };
+
+    emit!(UnhealthyReported {
+        user: user.key(),
+        health_factor,
+        listed,
+        event_seq: user.next_event_seq(),
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
@@ -0,0 +1,281 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount};
+use crate::constants::{BPS_DENOMINATOR, POSITION_SEED, PROTOCOL_SEED, SECONDS_PER_YEAR, TREASURY_SEED};
+use crate::state::*;
+use crate::health::calculate_health;
+use crate::interest::{accrue_interest_view, borrow_rate, interest_growth, utilization_rate};
+use crate::math::{loan_to_value, max_borrow_value};
+use crate::oracle::get_bank_price;
+use crate::safe_math::mul_div;
+use crate::wad::{wad_pow, wad_to_bps, WAD};
+use super::borrow::quote_borrow;
+use super::withdraw::quote_withdrawal;
+
+#[derive(Accounts)]
+pub struct GetUserHealth<'info> {
+    pub user_account: Account<'info, User>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+#[derive(Accounts)]
+pub struct GetRates<'info> {
+    pub bank: Account<'info, Bank>,
+}
+
+#[derive(Accounts)]
+pub struct SnapshotBank<'info> {
+    #[account(
+        seeds = [bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        seeds = [TREASURY_SEED, bank.mint_address.as_ref()],
+        bump,
+    )]
+    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: Price account of one of the bank's oracles or its `OverridePrice`, matched by `get_bank_price`
+    pub price_update: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct PreviewWithdraw<'info> {
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        seeds = [mint.key().as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    pub user_account: Account<'info, User>,
+    #[account(
+        seeds = [POSITION_SEED, user_account.key().as_ref(), mint.key().as_ref()],
+        bump,
+    )]
+    pub user_position: Account<'info, UserPosition>,
+}
+
+#[derive(Accounts)]
+pub struct PreviewBorrow<'info> {
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        seeds = [mint.key().as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+}
+
+/// Outcome of a `withdraw` executed now, in bank tokens
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct WithdrawPreview {
+    /// Tokens debited from the position, `u64::MAX` resolved to the whole deposit
+    pub amount: u64,
+    /// Withdrawal fee kept as reserves
+    pub fee: u64,
+    /// Deposit shares burned
+    pub shares: u64,
+    /// Tokens sent out of the bank's vault
+    pub amount_sent: u64,
+    /// Tokens the user receives after any Token-2022 transfer fee
+    pub amount_out: u64,
+}
+
+/// Outcome of a `borrow` executed now, in bank tokens
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct BorrowPreview {
+    /// Tokens added to the user's debt, including an origination fee that isn't skimmed
+    pub debt_amount: u64,
+    /// Origination fee added to the reserves
+    pub fee: u64,
+    /// Borrow shares minted
+    pub shares: u64,
+    /// Tokens sent out of the bank's vault
+    pub amount_sent: u64,
+    /// Tokens the borrower receives after any Token-2022 transfer fee
+    pub amount_out: u64,
+}
+
+/// Current rates of a bank, all in basis points
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct BankRates {
+    /// Total debt over total deposits
+    pub utilization: u64,
+    /// Annual borrow rate without compounding
+    pub borrow_apr: u64,
+    /// Annual borrow rate compounded every second
+    pub borrow_apy: u64,
+    /// Annual rate earned by depositors without compounding, after the reserve factor
+    pub supply_apr: u64,
+    /// Annual rate earned by depositors compounded every second
+    pub supply_apy: u64,
+}
+
+/// State of a bank a monitoring tool would otherwise assemble from the bank, its vault and its oracle,
+/// amounts are in bank tokens and rates in basis points
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct BankSnapshot {
+    pub mint: Pubkey,
+    pub mint_decimals: u8,
+    pub total_deposits: u64,
+    /// Variable and fixed-rate debt
+    pub total_debt: u64,
+    pub total_reserves: u64,
+    pub insurance_fund: u64,
+    /// Tokens held by the bank's token account
+    pub vault_amount: u64,
+    /// Deposit and borrow caps, zero for no cap
+    pub max_total_deposits: u64,
+    pub max_total_borrows: u64,
+    pub rates: BankRates,
+    /// Price the bank is valued at now, zero with `price_valid` unset when neither its oracles nor a price
+    /// override give one
+    pub price: i64,
+    pub price_exponent: i32,
+    pub price_valid: bool,
+    /// Whether the price is the risk manager's `OverridePrice`
+    pub price_overridden: bool,
+    pub ema_price: i64,
+    pub ema_exponent: i32,
+    /// Bitmask of paused operations, see the `PAUSE_*` constants
+    pub paused_operations: u8,
+    pub bank_type: BankType,
+    pub depegged: bool,
+    pub rate_clamped: bool,
+    pub deprecated_at: i64,
+    /// Sequence number of the bank's last event, a change tells the bank was written since the last snapshot
+    pub event_seq: u64,
+    pub timestamp: i64,
+}
+
+/// Health of a user across all positions, values are in USD with `VALUE_DECIMALS`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct UserHealth {
+    pub total_collateral_value: u128,
+    pub total_debt_value: u128,
+    /// Max debt value allowed when borrowing or withdrawing
+    pub borrow_limit: u128,
+    /// Debt value above which the user can be liquidated
+    pub liquidation_limit: u128,
+    /// Debt value new borrows may not exceed, the liquidation limit less the protocol's borrow headroom
+    pub borrow_headroom_limit: u128,
+    /// Debt value the user can still borrow before reaching the borrow limit or the borrow headroom limit
+    pub available_borrow_value: u128,
+    /// Current LTV, in basis points
+    pub ltv: u64,
+    /// Health factor, in basis points, `u64::MAX` without debt
+    pub health_factor: u64,
+    /// Health factor as a WAD, `u128::MAX` without debt
+    pub health_factor_wad: u128,
+}
+
+// Read-only, meant to be simulated: the result is returned through `set_return_data`.
+// Remaining accounts: bank, position, price update and fixed loans for each of the user's positions.
+pub fn process_get_user_health(ctx: Context<GetUserHealth>) -> Result<UserHealth> {
+    let clock = Clock::get()?;
+    let user = &ctx.accounts.user_account;
+    let health = calculate_health(user, &user.key(), ctx.remaining_accounts, &clock)?;
+    let borrow_headroom_limit = health.headroom_limit(ctx.accounts.protocol_config.borrow_headroom_bps);
+
+    Ok(UserHealth {
+        total_collateral_value: health.collateral_value,
+        total_debt_value: health.debt_value,
+        borrow_limit: health.borrow_limit,
+        liquidation_limit: health.liquidation_limit,
+        borrow_headroom_limit,
+        available_borrow_value: max_borrow_value(&health).min(borrow_headroom_limit.saturating_sub(health.liquidation_debt_value)),
+        ltv: loan_to_value(&health),
+        health_factor: health.health_factor(),
+        health_factor_wad: health.health_factor_wad(),
+    })
+}
+
+// Read-only, meant to be simulated: the result is returned through `set_return_data`.
+// Rates are taken after accruing interest up to now, at the bank's current utilization.
+pub fn process_get_rates(ctx: Context<GetRates>) -> Result<BankRates> {
+    let mut bank = ctx.accounts.bank.clone().into_inner();
+    accrue_interest_view(&mut bank, Clock::get()?.unix_timestamp)?;
+    bank_rates(&bank)
+}
+
+// Read-only, meant to be simulated: the result is returned through `set_return_data`.
+// Totals and rates are taken after accruing interest up to now. An oracle failing doesn't fail the snapshot,
+// it comes back without a valid price.
+pub fn process_snapshot_bank(ctx: Context<SnapshotBank>) -> Result<BankSnapshot> {
+    let clock = Clock::get()?;
+    let mut bank = ctx.accounts.bank.clone().into_inner();
+    accrue_interest_view(&mut bank, clock.unix_timestamp)?;
+
+    let price_info = ctx.accounts.price_update.to_account_info();
+    let price = get_bank_price(&bank, &price_info, &clock).ok();
+
+    Ok(BankSnapshot {
+        mint: bank.mint_address,
+        mint_decimals: bank.mint_decimals,
+        total_deposits: bank.total_deposits,
+        total_debt: bank.total_debt(),
+        total_reserves: bank.total_reserves,
+        insurance_fund: bank.insurance_fund,
+        vault_amount: ctx.accounts.bank_token_account.amount,
+        max_total_deposits: bank.max_total_deposits,
+        max_total_borrows: bank.max_total_borrows,
+        rates: bank_rates(&bank)?,
+        price: price.map_or(0, |price| price.price),
+        price_exponent: price.map_or(0, |price| price.exponent),
+        price_valid: price.is_some(),
+        price_overridden: price.is_some() && bank.price_overridden,
+        ema_price: bank.ema_price,
+        ema_exponent: bank.ema_exponent,
+        paused_operations: bank.paused_operations,
+        bank_type: bank.bank_type,
+        depegged: bank.depegged,
+        rate_clamped: bank.rate_clamped,
+        deprecated_at: bank.deprecated_at,
+        event_seq: bank.event_seq,
+        timestamp: clock.unix_timestamp,
+    })
+}
+
+/// Rates of a bank at its current utilization, the bank must have accrued interest up to now
+fn bank_rates(bank: &Bank) -> Result<BankRates> {
+    let utilization = utilization_rate(bank.total_debt(), bank.total_deposits);
+    let borrow_apr = borrow_rate(bank);
+    let supply_apr = mul_div(
+        mul_div(borrow_apr, utilization, BPS_DENOMINATOR, false)?,
+        BPS_DENOMINATOR.saturating_sub(bank.reserve_factor),
+        BPS_DENOMINATOR,
+        false,
+    )?;
+
+    Ok(BankRates {
+        utilization,
+        borrow_apr,
+        borrow_apy: compounded_apy(borrow_apr)?,
+        supply_apr,
+        supply_apy: compounded_apy(supply_apr)?,
+    })
+}
+
+// Read-only, meant to be simulated: the result is returned through `set_return_data`.
+// Runs the withdrawal math of `withdraw` after accruing interest up to now, without checking health or pauses.
+pub fn process_preview_withdraw(ctx: Context<PreviewWithdraw>, amount: u64) -> Result<WithdrawPreview> {
+    let mut bank = ctx.accounts.bank.clone().into_inner();
+    accrue_interest_view(&mut bank, Clock::get()?.unix_timestamp)?;
+    quote_withdrawal(&bank, &ctx.accounts.user_position, &ctx.accounts.mint.to_account_info(), amount)
+}
+
+// Read-only, meant to be simulated: the result is returned through `set_return_data`.
+// Runs the borrow math of `borrow` after accruing interest up to now, without checking health, caps or pauses.
+pub fn process_preview_borrow(ctx: Context<PreviewBorrow>, amount: u64) -> Result<BorrowPreview> {
+    let mut bank = ctx.accounts.bank.clone().into_inner();
+    accrue_interest_view(&mut bank, Clock::get()?.unix_timestamp)?;
+    quote_borrow(&bank, &ctx.accounts.mint.to_account_info(), amount)
+}
+
+/// APY of an annual rate compounded every second, both in basis points
+fn compounded_apy(apr: u64) -> Result<u64> {
+    let growth = wad_pow(WAD + interest_growth(apr, 1)?, SECONDS_PER_YEAR)?;
+    wad_to_bps(growth - WAD)
+}
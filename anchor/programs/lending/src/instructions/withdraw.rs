@@ -1,7 +1,11 @@
 use anchor_lang::prelude::*;
 use anchor_spl::associated_token::AssociatedToken;
 use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
-use crate::{state::*, error::ErrorCode};
+use crate::constants::{FULL_AMOUNT, PAUSE_WITHDRAWALS, POSITION_SEED, PROTOCOL_SEED, STATS_SEED, TREASURY_SEED};
+use crate::{state::*, error::ErrorCode, interest::accrue_interest, events::{WithdrawEvent, WithdrawRequested}, health::{calculate_health, Health}, rewards::settle_rewards};
+use crate::mint::{calculate_transfer_fee, reconcile_vault};
+use crate::safe_math::SafeMath;
+use super::view::WithdrawPreview;
 
 #[derive(Accounts)]
 pub struct Withdraw<'info> {
@@ -16,16 +20,29 @@ pub struct Withdraw<'info> {
     pub bank: Account<'info, Bank>,
     #[account(
         mut,
-        seeds = [b"treasury", mint.key().as_ref()],
+        seeds = [STATS_SEED, bank.key().as_ref()],
+        bump,
+    )]
+    pub bank_stats: Account<'info, BankStats>,
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, mint.key().as_ref()],
         bump
     )]
     pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
     #[account(
         mut,
-        seeds = [signer.key().as_ref()],
-        bump
+        seeds = [signer.key().as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+        constraint = !user_account.is_tokenized() @ ErrorCode::AccountTokenized,
     )]
     pub user_account: Account<'info, User>,
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), mint.key().as_ref()],
+        bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
     #[account(
         init_if_needed,
         payer = signer,
@@ -37,54 +54,281 @@ pub struct Withdraw<'info> {
     pub token_program: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
+    /// Token account receiving the tokens instead of the signer's associated token account,
+    /// owned by the signer or anyone they pay out to
+    #[account(
+        mut,
+        token::mint = mint,
+        token::token_program = token_program,
+    )]
+    pub destination: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        seeds = [PROTOCOL_SEED],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
 }
 
-pub fn process_withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
-    let user = &mut ctx.accounts.user_account;
-    let deposited_value;
-    // FIXME: Change from if statement to match statement?? Use PDA deserialization to get the mint address??
-    if ctx.accounts.mint.to_account_info().key() == user.usdc_address {
-        deposited_value = user.deposited_usdc;
-    } else {
-        deposited_value = user.deposited_sol;
-    }
+#[derive(Accounts)]
+pub struct RequestWithdraw<'info> {
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [bank.mint_address.as_ref()],
+        bump
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [signer.key().as_ref(), user_account.sub_account_seed().as_slice()],
+        bump,
+        constraint = !user_account.is_tokenized() @ ErrorCode::AccountTokenized,
+    )]
+    pub user_account: Account<'info, User>,
+    #[account(
+        mut,
+        seeds = [POSITION_SEED, user_account.key().as_ref(), bank.mint_address.as_ref()],
+        bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+}
 
-    if amount > deposited_value {
+pub fn process_withdraw(ctx: Context<Withdraw>, amount: u64, min_amount_out: u64, client_tag: Option<[u8; 16]>) -> Result<()> {
+    ctx.accounts.bank.require_not_paused(PAUSE_WITHDRAWALS)?;
+    ctx.accounts.bank.require_no_withdraw_cooldown()?;
+
+    withdraw_from_bank(ctx, amount, min_amount_out, client_tag)
+}
+
+// Queues a withdrawal from a bank with a cooldown, replacing any pending request and restarting its cooldown.
+// Nothing leaves the position until `execute_withdraw`, so the tokens keep earning interest and backing debt.
+// A zero amount cancels the pending request.
+
+pub fn process_request_withdraw(ctx: Context<RequestWithdraw>, amount: u64) -> Result<()> {
+    let bank = &ctx.accounts.bank;
+    let position = &mut ctx.accounts.user_position;
+    let now = Clock::get()?.unix_timestamp;
+
+    let deposited_value = bank.deposit_amount_for(position.deposit_shares)?;
+    if amount != FULL_AMOUNT && amount > deposited_value {
+        msg!("Requesting {} but only {} is deposited", amount, deposited_value);
         return Err(ErrorCode::InsufficientFunds.into());
     }
 
+    position.pending_withdraw_amount = amount;
+    position.withdraw_requested_at = now;
+
+    emit!(WithdrawRequested {
+        user: ctx.accounts.user_account.owner,
+        mint: bank.mint_address,
+        amount,
+        executable_at: now.saturating_add(bank.withdraw_cooldown_secs),
+        event_seq: ctx.accounts.user_account.next_event_seq(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
+
+// Withdraws the pending amount once the bank's cooldown has elapsed since the request, capped at what the
+// position still holds in case it was liquidated in between. Health is checked like any other withdrawal.
+
+pub fn process_execute_withdraw(ctx: Context<Withdraw>, min_amount_out: u64, client_tag: Option<[u8; 16]>) -> Result<()> {
+    ctx.accounts.bank.require_not_paused(PAUSE_WITHDRAWALS)?;
+
+    let position = &mut ctx.accounts.user_position;
+    let pending = position.pending_withdraw_amount;
+    if pending == 0 {
+        msg!("Position in {} has no pending withdrawal", ctx.accounts.bank.mint_address);
+        return Err(ErrorCode::NoPendingWithdrawal.into());
+    }
+    let now = Clock::get()?.unix_timestamp;
+    let executable_at = position.withdraw_requested_at.saturating_add(ctx.accounts.bank.withdraw_cooldown_secs);
+    if now < executable_at {
+        msg!("Withdrawal executable at {}, now {}", executable_at, now);
+        return Err(ErrorCode::WithdrawCooldownActive.into());
+    }
+
+    position.pending_withdraw_amount = 0;
+    position.withdraw_requested_at = 0;
+
+    accrue_interest(&mut ctx.accounts.bank, now)?;
+    let deposited_value = ctx.accounts.bank.deposit_amount_for(ctx.accounts.user_position.deposit_shares)?;
+    let amount = if pending >= deposited_value { FULL_AMOUNT } else { pending };
+
+    withdraw_from_bank(ctx, amount, min_amount_out, client_tag)
+}
+
+// 1. Accrue interest on the bank up to now
+// 2. CPI transfer from the bank's token account to the destination, the user's token account if none is given,
+//    withholding the bank's withdrawal fee for the reserves
+// 3. Burn the withdrawn shares from the user's position and the bank
+// 4. Check the remaining collateral still covers the user's debt across all positions
+//    (remaining accounts: bank, position, price update and fixed loans for each of the user's positions)
+
+fn withdraw_from_bank(ctx: Context<Withdraw>, amount: u64, min_amount_out: u64, client_tag: Option<[u8; 16]>) -> Result<()> {
+    let clock = Clock::get()?;
+    let now = clock.unix_timestamp;
+    accrue_interest(&mut ctx.accounts.bank, now)?;
+
+    let WithdrawPreview { amount, fee, amount_sent, amount_out, .. } = quote_withdrawal(
+        &ctx.accounts.bank,
+        &ctx.accounts.user_position,
+        &ctx.accounts.mint.to_account_info(),
+        amount,
+    )?;
+    if amount_out < min_amount_out {
+        msg!("Amount out {} is below the minimum {}", amount_out, min_amount_out);
+        return Err(ErrorCode::SlippageExceeded.into());
+    }
+
+    let destination = match &ctx.accounts.destination {
+        Some(destination) => destination.to_account_info(),
+        None => ctx.accounts.user_token_account.to_account_info(),
+    };
     let transfer_cpi_accounts = TransferChecked {
         from: ctx.accounts.bank_token_account.to_account_info(),
         mint: ctx.accounts.mint.to_account_info(),
-        to: ctx.accounts.user_token_account.to_account_info(),
+        to: destination.clone(),
         authority: ctx.accounts.bank_token_account.to_account_info()
     };
     let cpi_program = ctx.accounts.token_program.to_account_info();
     let mint_key = ctx.accounts.mint.key();
     let signer_seeds: &[&[&[u8]]] = &[
         &[
-            b"treasury",
+            TREASURY_SEED,
             mint_key.as_ref(),
             &[ctx.bumps.bank_token_account],
         ]
     ];
     let cpi_ctx = CpiContext::new(cpi_program, transfer_cpi_accounts).with_signer(signer_seeds);
     let decimals = ctx.accounts.mint.decimals;
-    token_interface::transfer_checked(cpi_ctx, amount, decimals)?;
-    let bank = &mut ctx.accounts.bank;
-    let shares_to_remove = (amount as f64 / bank.total_deposits as f64) * bank.total_deposit_shares as f64;
+    // Fees are withheld from what the user receives, the position is debited the full amount
+    token_interface::transfer_checked(cpi_ctx, amount_sent, decimals)?;
+    reconcile_vault(&mut ctx.accounts.bank, &mut ctx.accounts.bank_token_account, 0, amount_sent)?;
+
+    record_withdrawal(
+        &mut ctx.accounts.bank,
+        &mut ctx.accounts.bank_stats,
+        &mut ctx.accounts.user_account,
+        &mut ctx.accounts.user_position,
+        amount,
+        fee,
+        &ctx.accounts.protocol_config,
+        ctx.remaining_accounts,
+        &clock,
+        destination.key(),
+        client_tag,
+    )
+}
+
+/// Resolves a withdrawal of `amount` tokens, `u64::MAX` for the whole position, into the tokens debited,
+/// the bank's withdrawal fee, the shares burned and the tokens the user ends up with.
+/// Shared with `preview_withdraw`, the bank must have accrued interest up to now.
+pub(crate) fn quote_withdrawal(bank: &Bank, position: &UserPosition, mint: &AccountInfo, amount: u64) -> Result<WithdrawPreview> {
+    let deposited_value = bank.deposit_amount_for(position.deposit_shares)?;
 
-    let user = &mut ctx.accounts.user_account;
+    // Resolved after accrual so the whole position can be closed without leaving dust
+    let amount = if amount == FULL_AMOUNT { deposited_value } else { amount };
 
-    if ctx.accounts.mint.to_account_info().key() == user.usdc_address {
-        user.deposited_usdc -= shares_to_remove as u64;
-    } else {
-        user.deposited_sol -= shares_to_remove as u64;
+    if amount > deposited_value {
+        msg!("Withdrawing {} but only {} is deposited", amount, deposited_value);
+        return Err(ErrorCode::InsufficientFunds.into());
     }
 
-    bank.total_deposits -= amount;
-    bank.total_deposit_shares -= shares_to_remove as u64;
-    
+    // Tokens the user ends up with after the bank's withdrawal fee and any Token-2022 transfer fee
+    let fee = bank.withdraw_fee(amount)?;
+    let amount_sent = amount.safe_sub(fee)?;
+    let amount_out = amount_sent.safe_sub(calculate_transfer_fee(mint, amount_sent)?)?;
+
+    Ok(WithdrawPreview {
+        amount,
+        fee,
+        shares: bank.deposit_shares_withdrawn(amount, position.deposit_shares)?,
+        amount_sent,
+        amount_out,
+    })
+}
+
+/// Burns the deposit shares for `amount` tokens, of which all but the `fee` kept as reserves were already sent
+/// out of the bank's vault, then checks the remaining collateral still covers the user's debt across all positions.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn record_withdrawal<'info>(
+    bank: &mut Account<'info, Bank>,
+    stats: &mut BankStats,
+    user: &mut Account<'info, User>,
+    position: &mut Account<'info, UserPosition>,
+    amount: u64,
+    fee: u64,
+    protocol_config: &ProtocolConfig,
+    health_accounts: &[AccountInfo],
+    clock: &Clock,
+    destination: Pubkey,
+    client_tag: Option<[u8; 16]>,
+) -> Result<()> {
+    let now = clock.unix_timestamp;
+    let shares_to_remove = burn_deposit_shares(bank, stats, user, position, amount, now)?;
+    bank.total_reserves = bank.total_reserves.safe_add(fee)?;
+    position.pnl.fees_paid = position.pnl.fees_paid.safe_add(fee)?;
+
+    // Persist the updated accounts so the health check below sees the post-withdrawal state
+    bank.exit(&crate::ID)?;
+    position.exit(&crate::ID)?;
+
+    let health = calculate_health(user, &user.key(), health_accounts, clock)?;
+    health.require_within_borrow_limit(ErrorCode::HealthFactorTooLow)?;
+    require_withdrawable_while_deleveraging(protocol_config, &health, clock)?;
+    user.health_factor = health.health_factor();
+    user.last_updated = now;
+
+    emit!(WithdrawEvent {
+        user: user.owner,
+        mint: bank.mint_address,
+        amount,
+        fee,
+        shares: shares_to_remove,
+        user_deposited: bank.deposit_amount_for(position.deposit_shares)?,
+        position_pnl: position.pnl,
+        bank_total_deposits: bank.total_deposits,
+        destination,
+        client_tag,
+        event_seq: bank.next_event_seq(),
+        timestamp: now,
+    });
+
     Ok(())
+}
+
+/// Removes `amount` tokens from the position's deposits and the bank totals, returning the burned shares
+pub(crate) fn burn_deposit_shares(bank: &mut Account<Bank>, stats: &mut BankStats, user: &mut User, position: &mut UserPosition, amount: u64, now: i64) -> Result<u64> {
+    bank.require_deposit_unlocked(position, Clock::get()?.slot)?;
 
-}
\ No newline at end of file
+    let shares_to_remove = bank.deposit_shares_withdrawn(amount, position.deposit_shares)?;
+
+    settle_rewards(bank, position)?;
+    position.deposit_shares = position.deposit_shares.safe_sub(shares_to_remove)?;
+    position.touch(bank, now);
+    if position.is_empty() {
+        user.remove_position(bank.key());
+    }
+
+    bank.total_deposits = bank.total_deposits.safe_sub(amount)?;
+    bank.total_deposit_shares = bank.total_deposit_shares.safe_sub(shares_to_remove)?;
+    bank.require_withdraw_liquidity()?;
+
+    stats.cumulative_withdrawals = stats.cumulative_withdrawals.safe_add(amount as u128)?;
+    stats.last_updated = now;
+
+    // A partial withdrawal can't leave dust behind
+    bank.require_deposit_minimum(bank.deposit_amount_for(position.deposit_shares)?)?;
+
+    Ok(shares_to_remove)
+}
+
+/// Fails while the protocol is deleveraging if the user still has debt after withdrawing, the withdrawn tokens were backing it
+pub(crate) fn require_withdrawable_while_deleveraging(protocol_config: &ProtocolConfig, health: &Health, clock: &Clock) -> Result<()> {
+    if protocol_config.is_deleveraging(clock.slot) && health.debt_value > 0 {
+        msg!("Protocol is deleveraging until slot {}, debt value {} is left", protocol_config.deleveraging_until_slot, health.debt_value);
+        return Err(ErrorCode::DeleveragingMode.into());
+    }
+    Ok(())
+}
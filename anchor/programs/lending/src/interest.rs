@@ -0,0 +1,227 @@
+use anchor_lang::prelude::*;
+use crate::constants::{SECONDS_PER_YEAR, SLOTS_PER_YEAR};
+use crate::state::{Bank, FixedLoan, InterestMode, UserPosition};
+use crate::safe_math::{bps_of, SafeMath};
+use crate::wad::{bps_to_wad, split_wad, wad_mul, wad_pow, WAD};
+use crate::rewards::accrue_rewards;
+use crate::math::{accrue_totals, smooth_rate, InterestTotals};
+use crate::events::BorrowRateClamped;
+pub use crate::math::utilization_rate;
+
+// All rates are annualized and expressed in basis points, the curve itself lives in `math`.
+
+/// Annualized borrow rate of the bank in basis points at its current utilization, or the smoothed rate
+/// catching up with it, or the emergency rate while it is pinned as of the bank's last update
+pub fn borrow_rate(bank: &Bank) -> u64 {
+    if bank.rate_pinned_until > bank.last_updated {
+        return bank.pinned_borrow_rate;
+    }
+    if bank.max_rate_change_per_sec > 0 {
+        return bank.smoothed_borrow_rate;
+    }
+    curve_borrow_rate(bank)
+}
+
+fn curve_borrow_rate(bank: &Bank) -> u64 {
+    let utilization = utilization_rate(bank.total_debt(), bank.total_deposits);
+    bank.rate_curve().rate_at(utilization)
+}
+
+/// Accrues borrower interest since the last update into the bank totals.
+/// Interest owed by borrowers is credited to depositors, minus the reserve factor cut kept by the protocol.
+/// Reward emissions are accrued along with it so every interaction with the bank checkpoints both.
+/// Within the bank's min accrual interval of the last update nothing accrues, the next accrual catches up.
+pub fn accrue_interest(bank: &mut Bank, now: i64) -> Result<()> {
+    if now.saturating_sub(bank.last_updated) < bank.min_accrual_interval_secs {
+        return accrue_rewards(bank, now);
+    }
+    checkpoint_interest(bank, now)
+}
+
+/// Accrues interest like `accrue_interest` on a copy of a bank that is never written back, such as the read-only
+/// banks of a health check, without emitting events numbered past the bank's recorded `event_seq`
+pub fn accrue_interest_view(bank: &mut Bank, now: i64) -> Result<()> {
+    if now.saturating_sub(bank.last_updated) < bank.min_accrual_interval_secs {
+        return accrue_rewards(bank, now);
+    }
+    checkpoint(bank, now, false)
+}
+
+/// Accrues interest like `accrue_interest` regardless of the bank's min accrual interval
+pub fn checkpoint_interest(bank: &mut Bank, now: i64) -> Result<()> {
+    checkpoint(bank, now, true)
+}
+
+fn checkpoint(bank: &mut Bank, now: i64, emit_events: bool) -> Result<()> {
+    accrue_rewards(bank, now)?;
+
+    let time_elapsed = now.saturating_sub(bank.last_updated);
+    if time_elapsed <= 0 {
+        return Ok(());
+    }
+
+    if bank.total_borrowed > 0 {
+        // A pin expiring within the period only applies up to its expiry, the curve takes over from there
+        if bank.rate_pinned_until > bank.last_updated && bank.rate_pinned_until < now {
+            accrue_period(bank, bank.rate_pinned_until - bank.last_updated)?;
+            bank.last_updated = bank.rate_pinned_until;
+        }
+        accrue_period(bank, now - bank.last_updated)?;
+    }
+    update_rate_clamp(bank, now, emit_events);
+    update_smoothed_rate(bank, time_elapsed, now);
+
+    bank.supply_index = bank.deposit_exchange_rate()?;
+    bank.borrow_index = bank.borrow_exchange_rate()?;
+    bank.last_updated = now;
+    Ok(())
+}
+
+fn accrue_period(bank: &mut Bank, time_elapsed: i64) -> Result<()> {
+    let rate = borrow_rate(bank);
+    // Carry the fraction of a token left over so frequent small accruals add up instead of rounding to zero
+    let mut totals = InterestTotals {
+        total_deposits: bank.total_deposits,
+        total_borrowed: bank.total_borrowed,
+        total_reserves: bank.total_reserves,
+        interest_remainder: bank.interest_remainder,
+    };
+    let interest = accrue_totals(&mut totals, bank.interest_mode, rate, bank.reserve_factor, time_elapsed)?;
+    bank.total_deposits = totals.total_deposits;
+    bank.total_borrowed = totals.total_borrowed;
+    bank.total_reserves = totals.total_reserves;
+    bank.interest_remainder = totals.interest_remainder;
+    set_aside_insurance(bank, interest)
+}
+
+/// Moves the insurance fund's share of `interest` out of the reserves, which were credited the whole reserve cut
+fn set_aside_insurance(bank: &mut Bank, interest: u64) -> Result<()> {
+    let insurance_cut = bps_of(interest, bank.insurance_factor)?.min(bank.total_reserves);
+    bank.total_reserves = bank.total_reserves.safe_sub(insurance_cut)?;
+    bank.insurance_fund = bank.insurance_fund.safe_add(insurance_cut)?;
+    Ok(())
+}
+
+/// Moves the smoothed rate towards the curve rate for the time elapsed, a pinned rate is taken over as is
+/// so the curve is eased back into once the pin ends
+fn update_smoothed_rate(bank: &mut Bank, time_elapsed: i64, now: i64) {
+    bank.smoothed_borrow_rate = if bank.rate_pinned_until > now {
+        bank.pinned_borrow_rate
+    } else {
+        smooth_rate(bank.smoothed_borrow_rate, curve_borrow_rate(bank), bank.max_rate_change_per_sec, time_elapsed)
+    };
+}
+
+/// Records whether the rate floor or cap binds at the bank's current utilization, emitting an event when that changes
+fn update_rate_clamp(bank: &mut Bank, now: i64, emit_event: bool) {
+    let utilization = utilization_rate(bank.total_debt(), bank.total_deposits);
+    let curve = bank.rate_curve();
+    let clamped = curve.is_clamped_at(utilization);
+    if clamped != bank.rate_clamped {
+        bank.rate_clamped = clamped;
+        if !emit_event {
+            return;
+        }
+        emit!(BorrowRateClamped {
+            bank: bank.mint_address,
+            utilization,
+            rate: curve.rate_at(utilization),
+            clamped,
+            event_seq: bank.next_event_seq(),
+            timestamp: now,
+        });
+    }
+}
+
+/// Accrues interest on a fixed-rate loan since it was last updated, at the locked in rate until
+/// maturity and at the bank's variable rate plus its penalty rate after. The interest is added to
+/// the loan, the user's position and the bank's fixed debt and credited to depositors like variable interest.
+/// Unlike variable debt, which accrues bank-wide, fixed loans only accrue when they are touched.
+pub fn accrue_fixed_loan(bank: &mut Bank, loan: &mut FixedLoan, position: &mut UserPosition, clock: &Clock) -> Result<()> {
+    let time_elapsed = clock.unix_timestamp.saturating_sub(loan.last_updated);
+    if time_elapsed <= 0 {
+        return Ok(());
+    }
+
+    let rate = if clock.slot < loan.maturity_slot {
+        loan.rate
+    } else {
+        borrow_rate(bank).safe_add(bank.fixed_penalty_rate)?
+    };
+    let (interest, _) = split_wad((loan.debt as u128).safe_mul(accrual_growth(bank.interest_mode, rate, time_elapsed)?)?)?;
+    let reserve_cut = bps_of(interest, bank.reserve_factor)?;
+
+    loan.debt = loan.debt.safe_add(interest)?;
+    position.fixed_debt = position.fixed_debt.safe_add(interest)?;
+    bank.total_fixed_borrowed = bank.total_fixed_borrowed.safe_add(interest)?;
+    bank.total_deposits = bank.total_deposits.safe_add(interest.safe_sub(reserve_cut)?)?;
+    bank.total_reserves = bank.total_reserves.safe_add(reserve_cut)?;
+    set_aside_insurance(bank, interest)?;
+
+    loan.last_updated = clock.unix_timestamp;
+    Ok(())
+}
+
+/// Fraction of the principal accrued at the annualized `rate` (in basis points) over `time_elapsed` seconds, as a WAD
+pub fn interest_growth(rate: u64, time_elapsed: i64) -> Result<u128> {
+    Ok(bps_to_wad(rate).safe_mul(time_elapsed as u128)? / SECONDS_PER_YEAR as u128)
+}
+
+/// Fraction of the principal accrued at `rate` compounded every slot over `time_elapsed` seconds, as a WAD.
+/// The slot left partially elapsed accrues simple interest.
+pub fn compounded_interest_growth(rate: u64, time_elapsed: i64) -> Result<u128> {
+    let slot_rate = bps_to_wad(rate) / SLOTS_PER_YEAR as u128;
+    let elapsed_slots = (time_elapsed as u128).safe_mul(SLOTS_PER_YEAR as u128)?;
+    let (slots, partial_slot) = (elapsed_slots / SECONDS_PER_YEAR as u128, elapsed_slots % SECONDS_PER_YEAR as u128);
+
+    let compounded = wad_pow(WAD.safe_add(slot_rate)?, slots as u64)?;
+    let partial = WAD.safe_add(slot_rate.safe_mul(partial_slot)? / SECONDS_PER_YEAR as u128)?;
+    wad_mul(compounded, partial)?.safe_sub(WAD)
+}
+
+/// Growth of the principal over `time_elapsed` seconds under the bank's interest mode, as a WAD
+pub fn accrual_growth(mode: InterestMode, rate: u64, time_elapsed: i64) -> Result<u128> {
+    match mode {
+        InterestMode::Simple => interest_growth(rate, time_elapsed),
+        InterestMode::Compound => compounded_interest_growth(rate, time_elapsed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::BPS_DENOMINATOR;
+
+    #[test]
+    fn growth_matches_f64() {
+        for rate in [0u64, 1, 250, 1_000, 10_000, 50_000] {
+            for time_elapsed in [0i64, 1, 60, 3_600, 86_400, SECONDS_PER_YEAR as i64, 10 * SECONDS_PER_YEAR as i64] {
+                let growth = interest_growth(rate, time_elapsed).unwrap() as f64 / WAD as f64;
+                let expected = rate as f64 / BPS_DENOMINATOR as f64 * time_elapsed as f64 / SECONDS_PER_YEAR as f64;
+                assert!((growth - expected).abs() <= expected * 1e-12 + 1e-17, "rate {rate}, {time_elapsed}s: got {growth}, expected {expected}");
+            }
+        }
+    }
+
+    #[test]
+    fn compounding_matches_f64() {
+        for rate in [1u64, 250, 1_000, 10_000] {
+            for time_elapsed in [1i64, 3_600, 86_400, SECONDS_PER_YEAR as i64] {
+                let growth = compounded_interest_growth(rate, time_elapsed).unwrap() as f64 / WAD as f64;
+                let slots = time_elapsed as f64 * SLOTS_PER_YEAR as f64 / SECONDS_PER_YEAR as f64;
+                let expected = (slots * (rate as f64 / BPS_DENOMINATOR as f64 / SLOTS_PER_YEAR as f64).ln_1p()).exp_m1();
+                assert!((growth - expected).abs() <= expected * 1e-6, "rate {rate}, {time_elapsed}s: got {growth}, expected {expected}");
+                assert!(growth >= interest_growth(rate, time_elapsed).unwrap() as f64 / WAD as f64 * (1.0 - 1e-6));
+            }
+        }
+    }
+
+    #[test]
+    fn one_second_accrual_is_not_lost() {
+        // 1% a year on 1000 base units over one second is well below one token, the bps math rounded it to zero
+        let growth = interest_growth(100, 1).unwrap();
+        let interest_wad = 1_000 * growth;
+        assert!(interest_wad > 0);
+        assert!(interest_wad < WAD);
+    }
+}
@@ -0,0 +1,136 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::InstructionData;
+use anchor_spl::associated_token::get_associated_token_address_with_program_id;
+use crate::constants::{
+    find_bank_address, find_bank_stats_address, find_bank_token_account_address, find_position_address,
+    find_protocol_config_address, find_user_address,
+};
+
+// Entry points for programs and clients composing with the lending program without copying its source.
+//
+// On-chain programs depend on the crate with the `cpi` feature and call the generated CPI helpers:
+//   lending = { path = "...", features = ["cpi"] }
+//   lending::interface::cpi::deposit(CpiContext::new(lending_program, lending::interface::accounts::Deposit { .. }), amount)
+// Clients build instructions with the builders below, which derive every PDA from the wallet and mint.
+//
+// Account order is the field order of each instruction's accounts struct and is kept stable, new accounts
+// are only ever appended. Health checked instructions expect the health accounts as remaining accounts:
+// the bank, the user's position, a price account of the bank's oracle and the position's fixed-rate loans for
+// each of `User::positions`, in that order, the loans until their debts add up to the position's fixed debt.
+// While the risk manager overrides a bank's price, the bank's `OverridePrice` takes the place of its price account.
+//
+// PDA seeds, the prefixes are the `*_SEED` constants in `crate::constants` along with `find_*_address` helpers:
+//   bank                  [mint]
+//   bank token account    [b"treasury", mint]
+//   bank stats            [b"stats", bank]
+//   user account          [owner, sub account id as little endian u16, omitted for sub-account zero]
+//   user position         [b"position", user account, mint]
+//   fixed-rate loan       [b"fixed_loan", user account, mint, loan id as little endian u64]
+//   receipt mint          [b"receipt", bank]
+//   approved oracle       [b"oracle", mint]
+//   protocol config       [b"protocol"]
+//   protocol stats        [b"protocol_stats"]
+//   gate entry            [b"gate", bank, wallet]
+//   price override        [b"price_override", bank]
+
+#[cfg(feature = "cpi")]
+pub use crate::cpi;
+pub use crate::{accounts, instruction, ID};
+pub use crate::state::{Bank, BankStats, User, UserPosition};
+
+/// Wallet, mint and token program shared by the deposit, withdraw, borrow and repay builders
+pub struct TokenAccounts {
+    /// Wallet signing the instruction and holding the tokens moved
+    pub signer: Pubkey,
+    /// Owner of the user account the instruction acts on, the signer itself unless depositing, borrowing or repaying for someone else
+    pub owner: Pubkey,
+    /// Sub-account of the owner the instruction acts on, zero for the main account
+    pub sub_account_id: u16,
+    pub mint: Pubkey,
+    pub token_program: Pubkey,
+    /// Owner's gate pass for a permissioned bank, their `GateEntry` or a token account of the gate mint
+    pub gate_pass: Option<Pubkey>,
+}
+
+impl TokenAccounts {
+    fn bank(&self) -> Pubkey {
+        find_bank_address(&self.mint).0
+    }
+
+    fn user_account(&self) -> Pubkey {
+        find_user_address(&self.owner, self.sub_account_id).0
+    }
+
+    fn build(&self, data: impl InstructionData, gated: bool, health_accounts: &[AccountMeta]) -> Instruction {
+        let bank = self.bank();
+        let user_account = self.user_account();
+        let mut accounts = accounts::Deposit {
+            signer: self.signer,
+            mint: self.mint,
+            bank,
+            bank_stats: find_bank_stats_address(&bank).0,
+            bank_token_account: find_bank_token_account_address(&self.mint).0,
+            user_account,
+            user_position: find_position_address(&user_account, &self.mint).0,
+            user_token_account: get_associated_token_address_with_program_id(&self.signer, &self.mint, &self.token_program),
+            token_program: self.token_program,
+            associated_token_program: anchor_spl::associated_token::ID,
+            system_program: anchor_lang::system_program::ID,
+            gate_pass: self.gate_pass,
+            protocol_config: find_protocol_config_address().0,
+        }
+        .to_account_metas(None);
+        // Withdraw and repay end before the gate pass and protocol config
+        if !gated {
+            accounts.truncate(accounts.len() - 2);
+        }
+        accounts.extend_from_slice(health_accounts);
+        Instruction {
+            program_id: ID,
+            accounts,
+            data: data.data(),
+        }
+    }
+}
+
+// Deposit, withdraw, borrow and repay share the account layout of `accounts::Deposit`,
+// only deposit and borrow take its trailing gate pass and protocol config, withdraw takes its destination
+// and the protocol config instead
+
+// `client_tag` is echoed in the instruction's event for the client's own accounting
+
+/// `deposit` of `amount` tokens into the owner's position
+pub fn deposit_instruction(accounts: &TokenAccounts, amount: u64, client_tag: Option<[u8; 16]>) -> Instruction {
+    accounts.build(instruction::Deposit { amount, client_tag }, true, &[])
+}
+
+/// `withdraw` of `amount` tokens to `destination`, the signer's associated token account if `None`,
+/// `health_accounts` covering each of the owner's positions
+pub fn withdraw_instruction(
+    accounts: &TokenAccounts,
+    amount: u64,
+    min_amount_out: u64,
+    destination: Option<Pubkey>,
+    client_tag: Option<[u8; 16]>,
+    health_accounts: &[AccountMeta],
+) -> Instruction {
+    // An omitted optional account is passed as the program id
+    let mut trailing = vec![match destination {
+        Some(destination) => AccountMeta::new(destination, false),
+        None => AccountMeta::new_readonly(ID, false),
+    }];
+    trailing.push(AccountMeta::new_readonly(find_protocol_config_address().0, false));
+    trailing.extend_from_slice(health_accounts);
+    accounts.build(instruction::Withdraw { amount, min_amount_out, client_tag }, false, &trailing)
+}
+
+/// `borrow` of `amount` tokens, `health_accounts` covering each of the owner's positions
+pub fn borrow_instruction(accounts: &TokenAccounts, amount: u64, client_tag: Option<[u8; 16]>, health_accounts: &[AccountMeta]) -> Instruction {
+    accounts.build(instruction::Borrow { amount, client_tag }, true, health_accounts)
+}
+
+/// `repay` of `amount` tokens of the owner's debt
+pub fn repay_instruction(accounts: &TokenAccounts, amount: u64, max_amount_in: u64, client_tag: Option<[u8; 16]>) -> Instruction {
+    accounts.build(instruction::Repay { amount, max_amount_in, client_tag }, false, &[])
+}
@@ -3,11 +3,24 @@
 
 use anchor_lang::prelude::*;
 use instructions::*;
+use state::{FeeSplits, LiquidationOrder, OracleSource, RiskParams, RiskTier};
 
 mod state;
 mod error;
-mod constants;
+mod events;
+mod health;
+pub mod constants;
 mod instructions;
+pub mod interface;
+mod interest;
+mod math;
+mod mint;
+mod oracle;
+mod pricing;
+mod referral;
+mod rewards;
+mod safe_math;
+mod wad;
 
 declare_id!("CsKLRFCLjqjtvLeUp61dGs6PvX1iQM23o5PWviFFZkdt");
 
@@ -15,31 +28,404 @@ declare_id!("CsKLRFCLjqjtvLeUp61dGs6PvX1iQM23o5PWviFFZkdt");
 pub mod lending {
     use super::*;
 
-    pub fn init_bank(ctx: Context<InitBank>, liquidate_threshold: u64, max_ltv: u64) -> Result<()> {
-        process_init_bank(ctx, liquidate_threshold, max_ltv)
+    pub fn init_protocol(ctx: Context<InitProtocol>, params: InitProtocolParams) -> Result<()> {
+        process_init_protocol(ctx, params)
     }
 
-    pub fn init_user(ctx: Context<InitUser>, usdc_address: Pubkey) -> Result<()> {
-        process_init_user(ctx, usdc_address)
+    pub fn nominate_authority(ctx: Context<AdminOnly>, new_admin: Pubkey) -> Result<()> {
+        process_nominate_authority(ctx, new_admin)
     }
 
-    pub fn deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
-        process_deposit(ctx, amount)
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+        process_accept_authority(ctx)
     }
 
-    pub fn withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
-        process_withdraw(ctx, amount)
+    pub fn set_protocol_roles(ctx: Context<AdminOnly>, params: ProtocolRolesParams) -> Result<()> {
+        process_set_protocol_roles(ctx, params)
     }
 
-    pub fn borrow(ctx: Context<Borrow>, amount: u64) -> Result<()> {
-        process_borrow(ctx, amount)
+    pub fn set_swap_program(ctx: Context<AdminOnly>, program: Pubkey, approved: bool) -> Result<()> {
+        process_set_swap_program(ctx, program, approved)
     }
 
-    pub fn repay(ctx: Context<Repay>, amount: u64) -> Result<()> {
-        process_repay(ctx, amount)
+    pub fn set_default_risk_params(ctx: Context<AdminOnly>, params: RiskParams) -> Result<()> {
+        process_set_default_risk_params(ctx, params)
     }
 
-    pub fn liquidate(ctx: Context<Liquidate>) -> Result<()> {
-        process_liquidate(ctx)
+    pub fn set_risk_tier(ctx: Context<AdminOnly>, tier: RiskTier, params: RiskParams) -> Result<()> {
+        process_set_risk_tier(ctx, tier, params)
+    }
+
+    pub fn set_listing_fee(ctx: Context<AdminOnly>, listing_fee_lamports: u64) -> Result<()> {
+        process_set_listing_fee(ctx, listing_fee_lamports)
+    }
+
+    pub fn set_fee_splits(ctx: Context<AdminOnly>, splits: FeeSplits) -> Result<()> {
+        process_set_fee_splits(ctx, splits)
+    }
+
+    pub fn set_max_positions_per_user(ctx: Context<AdminOnly>, max_positions_per_user: u8) -> Result<()> {
+        process_set_max_positions_per_user(ctx, max_positions_per_user)
+    }
+
+    pub fn set_borrow_headroom(ctx: Context<AdminOnly>, borrow_headroom_bps: u64) -> Result<()> {
+        process_set_borrow_headroom(ctx, borrow_headroom_bps)
+    }
+
+    pub fn set_dust_threshold(ctx: Context<AdminOnly>, dust_threshold_value: u128) -> Result<()> {
+        process_set_dust_threshold(ctx, dust_threshold_value)
+    }
+
+    pub fn set_liquidation_order(ctx: Context<AdminOnly>, liquidation_order: LiquidationOrder) -> Result<()> {
+        process_set_liquidation_order(ctx, liquidation_order)
+    }
+
+    pub fn set_oracle(ctx: Context<SetOracle>, oracle: OracleSource) -> Result<()> {
+        process_set_oracle(ctx, oracle)
+    }
+
+    pub fn remove_oracle(ctx: Context<RemoveOracle>) -> Result<()> {
+        process_remove_oracle(ctx)
+    }
+
+    pub fn set_depeg(ctx: Context<SetDepeg>, depegged: bool) -> Result<()> {
+        process_set_depeg(ctx, depegged)
+    }
+
+    pub fn graduate_bank(ctx: Context<GraduateBank>) -> Result<()> {
+        process_graduate_bank(ctx)
+    }
+
+    pub fn set_deleveraging_mode(ctx: Context<SetDeleveragingMode>, until_slot: u64) -> Result<()> {
+        process_set_deleveraging_mode(ctx, until_slot)
+    }
+
+    pub fn set_price_override(ctx: Context<SetPriceOverride>, price: i64, exponent: i32, expiry_slot: u64) -> Result<()> {
+        process_set_price_override(ctx, price, exponent, expiry_slot)
+    }
+
+    pub fn clear_price_override(ctx: Context<ClearPriceOverride>) -> Result<()> {
+        process_clear_price_override(ctx)
+    }
+
+    pub fn set_bank_oracles(ctx: Context<SetBankOracles>, oracle: OracleSource, fallback_oracle: Option<OracleSource>) -> Result<()> {
+        process_set_bank_oracles(ctx, oracle, fallback_oracle)
+    }
+
+    pub fn init_bank(ctx: Context<InitBank>, params: InitBankParams) -> Result<()> {
+        process_init_bank(ctx, params)
+    }
+
+    pub fn create_permissionless_bank(ctx: Context<CreatePermissionlessBank>, isolated_debt_bank: Pubkey) -> Result<()> {
+        process_create_permissionless_bank(ctx, isolated_debt_bank)
+    }
+
+    pub fn promote_bank(ctx: Context<PromoteBank>) -> Result<()> {
+        process_promote_bank(ctx)
+    }
+
+    pub fn update_bank_config(ctx: Context<UpdateBankConfig>, params: UpdateBankConfigParams) -> Result<()> {
+        process_update_bank_config(ctx, params)
+    }
+
+    pub fn set_config_timelock(ctx: Context<AdminOnly>, config_timelock_secs: i64) -> Result<()> {
+        process_set_config_timelock(ctx, config_timelock_secs)
+    }
+
+    pub fn propose_bank_config(ctx: Context<ProposeBankConfig>, params: UpdateBankConfigParams) -> Result<()> {
+        process_propose_bank_config(ctx, params)
+    }
+
+    pub fn execute_bank_config(ctx: Context<ExecuteBankConfig>) -> Result<()> {
+        process_execute_bank_config(ctx)
+    }
+
+    pub fn cancel_bank_config(ctx: Context<ExecuteBankConfig>) -> Result<()> {
+        process_cancel_bank_config(ctx)
+    }
+
+    pub fn pause(ctx: Context<SetPause>, operations: u8) -> Result<()> {
+        process_pause(ctx, operations)
+    }
+
+    pub fn unpause(ctx: Context<SetPause>, operations: u8) -> Result<()> {
+        process_unpause(ctx, operations)
+    }
+
+    pub fn pin_borrow_rate(ctx: Context<SetPause>, rate: u64, duration_secs: i64) -> Result<()> {
+        process_pin_borrow_rate(ctx, rate, duration_secs)
+    }
+
+    pub fn init_user(ctx: Context<InitUser>, referrer: Option<Pubkey>, sub_account_id: u16) -> Result<()> {
+        process_init_user(ctx, referrer, sub_account_id)
+    }
+
+    pub fn deposit(ctx: Context<Deposit>, amount: u64, client_tag: Option<[u8; 16]>) -> Result<()> {
+        process_deposit(ctx, amount, client_tag)
+    }
+
+    pub fn deposit_with_init(
+        ctx: Context<DepositWithInit>,
+        amount: u64,
+        referrer: Option<Pubkey>,
+        client_tag: Option<[u8; 16]>,
+        sub_account_id: u16,
+    ) -> Result<()> {
+        process_deposit_with_init(ctx, amount, referrer, client_tag, sub_account_id)
+    }
+
+    pub fn withdraw(ctx: Context<Withdraw>, amount: u64, min_amount_out: u64, client_tag: Option<[u8; 16]>) -> Result<()> {
+        process_withdraw(ctx, amount, min_amount_out, client_tag)
+    }
+
+    pub fn request_withdraw(ctx: Context<RequestWithdraw>, amount: u64) -> Result<()> {
+        process_request_withdraw(ctx, amount)
+    }
+
+    pub fn execute_withdraw(ctx: Context<Withdraw>, min_amount_out: u64, client_tag: Option<[u8; 16]>) -> Result<()> {
+        process_execute_withdraw(ctx, min_amount_out, client_tag)
+    }
+
+    pub fn deposit_sol(ctx: Context<NativeSol>, amount: u64) -> Result<()> {
+        process_deposit_sol(ctx, amount)
+    }
+
+    pub fn withdraw_sol(ctx: Context<NativeSol>, amount: u64) -> Result<()> {
+        process_withdraw_sol(ctx, amount)
+    }
+
+    pub fn borrow(ctx: Context<Borrow>, amount: u64, client_tag: Option<[u8; 16]>) -> Result<()> {
+        process_borrow(ctx, amount, client_tag)
+    }
+
+    pub fn repay(ctx: Context<Repay>, amount: u64, max_amount_in: u64, client_tag: Option<[u8; 16]>) -> Result<()> {
+        process_repay(ctx, amount, max_amount_in, client_tag)
+    }
+
+    pub fn borrow_fixed(ctx: Context<BorrowFixed>, loan_id: u64, amount: u64, term_slots: u64, max_rate: u64) -> Result<()> {
+        process_borrow_fixed(ctx, loan_id, amount, term_slots, max_rate)
+    }
+
+    pub fn repay_fixed(ctx: Context<RepayFixed>, loan_id: u64, amount: u64, max_amount_in: u64) -> Result<()> {
+        process_repay_fixed(ctx, loan_id, amount, max_amount_in)
+    }
+
+    pub fn swap_borrow_rate_mode(ctx: Context<SwapBorrowRateMode>, loan_id: u64, mode: BorrowRateMode) -> Result<()> {
+        process_swap_borrow_rate_mode(ctx, loan_id, mode)
+    }
+
+    pub fn init_receipt_mint(ctx: Context<InitReceiptMint>) -> Result<()> {
+        process_init_receipt_mint(ctx)
+    }
+
+    pub fn deposit_receipt(ctx: Context<ReceiptTransfer>, amount: u64) -> Result<()> {
+        process_deposit_receipt(ctx, amount)
+    }
+
+    pub fn withdraw_receipt(ctx: Context<ReceiptTransfer>, shares: u64) -> Result<()> {
+        process_withdraw_receipt(ctx, shares)
+    }
+
+    pub fn stake_receipt(ctx: Context<ReceiptStake>, shares: u64) -> Result<()> {
+        process_stake_receipt(ctx, shares)
+    }
+
+    pub fn unstake_receipt(ctx: Context<ReceiptStake>, shares: u64) -> Result<()> {
+        process_unstake_receipt(ctx, shares)
+    }
+
+    pub fn deposit_and_borrow(ctx: Context<DepositAndBorrow>, deposit_amount: u64, borrow_amount: u64) -> Result<()> {
+        process_deposit_and_borrow(ctx, deposit_amount, borrow_amount)
+    }
+
+    pub fn repay_and_withdraw(ctx: Context<RepayAndWithdraw>, repay_amount: u64, withdraw_amount: u64) -> Result<()> {
+        process_repay_and_withdraw(ctx, repay_amount, withdraw_amount)
+    }
+
+    pub fn leverage_deposit(ctx: Context<LeverageDeposit>, params: LeverageDepositParams) -> Result<()> {
+        process_leverage_deposit(ctx, params)
+    }
+
+    pub fn swap_collateral(ctx: Context<SwapCollateral>, params: SwapCollateralParams) -> Result<()> {
+        process_swap_collateral(ctx, params)
+    }
+
+    pub fn swap_debt(ctx: Context<SwapDebt>, params: SwapDebtParams) -> Result<()> {
+        process_swap_debt(ctx, params)
+    }
+
+    pub fn self_liquidate(ctx: Context<SelfLiquidate>, params: SelfLiquidateParams) -> Result<()> {
+        process_self_liquidate(ctx, params)
+    }
+
+    pub fn liquidate(ctx: Context<Liquidate>, amount: u64, min_collateral_out: u64, receive_as_deposit: bool) -> Result<()> {
+        process_liquidate(ctx, amount, min_collateral_out, receive_as_deposit)
+    }
+
+    pub fn liquidate_auction(ctx: Context<Liquidate>, amount: u64, min_collateral_out: u64, receive_as_deposit: bool) -> Result<()> {
+        process_liquidate_auction(ctx, amount, min_collateral_out, receive_as_deposit)
+    }
+
+    pub fn liquidate_many<'info>(
+        ctx: Context<'_, '_, 'info, 'info, LiquidateMany<'info>>,
+        targets: Vec<LiquidationTarget>,
+    ) -> Result<()> {
+        process_liquidate_many(ctx, targets)
+    }
+
+    pub fn tokenize_account(ctx: Context<TokenizeAccount>) -> Result<()> {
+        process_tokenize_account(ctx)
+    }
+
+    pub fn transfer_position<'info>(ctx: Context<'_, '_, 'info, 'info, TransferPosition<'info>>) -> Result<()> {
+        process_transfer_position(ctx)
+    }
+
+    pub fn init_unhealthy_registry(ctx: Context<InitUnhealthyRegistry>) -> Result<()> {
+        process_init_unhealthy_registry(ctx)
+    }
+
+    pub fn report_unhealthy(ctx: Context<ReportUnhealthy>) -> Result<()> {
+        process_report_unhealthy(ctx)
+    }
+
+    pub fn flag_position(ctx: Context<FlagPosition>) -> Result<()> {
+        process_flag_position(ctx)
+    }
+
+    pub fn refresh_positions(ctx: Context<RefreshPositions>, start: u8) -> Result<()> {
+        process_refresh_positions(ctx, start)
+    }
+
+    pub fn handle_bad_debt(ctx: Context<HandleBadDebt>) -> Result<()> {
+        process_handle_bad_debt(ctx)
+    }
+
+    pub fn add_to_gate(ctx: Context<AddToGate>, wallet: Pubkey) -> Result<()> {
+        process_add_to_gate(ctx, wallet)
+    }
+
+    pub fn remove_from_gate(ctx: Context<RemoveFromGate>) -> Result<()> {
+        process_remove_from_gate(ctx)
+    }
+
+    pub fn cover_bad_debt(ctx: Context<CoverBadDebt>) -> Result<()> {
+        process_cover_bad_debt(ctx)
+    }
+
+    pub fn clear_dust_position(ctx: Context<ClearDustPosition>) -> Result<()> {
+        process_clear_dust_position(ctx)
+    }
+
+    pub fn fund_insurance(ctx: Context<FundInsurance>, amount: u64) -> Result<()> {
+        process_fund_insurance(ctx, amount)
+    }
+
+    pub fn request_insurance_withdrawal(ctx: Context<RequestInsuranceWithdrawal>, amount: u64) -> Result<()> {
+        process_request_insurance_withdrawal(ctx, amount)
+    }
+
+    pub fn execute_insurance_withdrawal(ctx: Context<ExecuteInsuranceWithdrawal>) -> Result<()> {
+        process_execute_insurance_withdrawal(ctx)
+    }
+
+    pub fn claim_referral_fees(ctx: Context<ClaimReferralFees>) -> Result<()> {
+        process_claim_referral_fees(ctx)
+    }
+
+    pub fn withdraw_reserves(ctx: Context<WithdrawReserves>, amount: u64) -> Result<()> {
+        process_withdraw_reserves(ctx, amount)
+    }
+
+    pub fn distribute_fees(ctx: Context<DistributeFees>) -> Result<()> {
+        process_distribute_fees(ctx)
+    }
+
+    pub fn fund_rewards(ctx: Context<FundRewards>, amount: u64, reward_rate: u64) -> Result<()> {
+        process_fund_rewards(ctx, amount, reward_rate)
+    }
+
+    pub fn claim_rewards(ctx: Context<ClaimRewards>) -> Result<()> {
+        process_claim_rewards(ctx)
+    }
+
+    pub fn flash_borrow(ctx: Context<FlashLoan>, amount: u64) -> Result<()> {
+        process_flash_borrow(ctx, amount)
+    }
+
+    pub fn flash_repay(ctx: Context<FlashLoan>) -> Result<()> {
+        process_flash_repay(ctx)
+    }
+
+    pub fn set_collateral(ctx: Context<SetCollateral>, enabled: bool) -> Result<()> {
+        process_set_collateral(ctx, enabled)
+    }
+
+    pub fn set_user_emode(ctx: Context<SetUserEMode>, emode_category: u8) -> Result<()> {
+        process_set_user_emode(ctx, emode_category)
+    }
+
+    pub fn approve_delegate(ctx: Context<UpdateDelegate>, delegate: Pubkey, bank: Pubkey, allowance: u64) -> Result<()> {
+        process_approve_delegate(ctx, delegate, bank, allowance)
+    }
+
+    pub fn revoke_delegate(ctx: Context<UpdateDelegate>, delegate: Pubkey, bank: Pubkey) -> Result<()> {
+        process_revoke_delegate(ctx, delegate, bank)
+    }
+
+    pub fn close_position(ctx: Context<ClosePosition>) -> Result<()> {
+        process_close_position(ctx)
+    }
+
+    pub fn close_user_account(ctx: Context<CloseUserAccount>) -> Result<()> {
+        process_close_user_account(ctx)
+    }
+
+    pub fn deprecate_bank(ctx: Context<DeprecateBank>, min_borrow_rate: Option<u64>) -> Result<()> {
+        process_deprecate_bank(ctx, min_borrow_rate)
+    }
+
+    pub fn close_bank<'info>(ctx: Context<'_, '_, 'info, 'info, CloseBank<'info>>) -> Result<()> {
+        process_close_bank(ctx)
+    }
+
+    pub fn accrue_interest(ctx: Context<AccrueInterest>) -> Result<()> {
+        process_accrue_interest(ctx)
+    }
+
+    pub fn checkpoint_bank(ctx: Context<CheckpointBank>) -> Result<()> {
+        process_checkpoint_bank(ctx)
+    }
+
+    pub fn update_price_ema(ctx: Context<UpdatePriceEma>) -> Result<()> {
+        process_update_price_ema(ctx)
+    }
+
+    pub fn refresh_stake_pool_rate(ctx: Context<RefreshStakePoolRate>) -> Result<()> {
+        process_refresh_stake_pool_rate(ctx)
+    }
+
+    pub fn migrate_account(ctx: Context<MigrateAccount>) -> Result<()> {
+        process_migrate_account(ctx)
+    }
+
+    pub fn get_user_health(ctx: Context<GetUserHealth>) -> Result<UserHealth> {
+        process_get_user_health(ctx)
+    }
+
+    pub fn get_rates(ctx: Context<GetRates>) -> Result<BankRates> {
+        process_get_rates(ctx)
+    }
+
+    pub fn snapshot_bank(ctx: Context<SnapshotBank>) -> Result<BankSnapshot> {
+        process_snapshot_bank(ctx)
+    }
+
+    pub fn preview_withdraw(ctx: Context<PreviewWithdraw>, amount: u64) -> Result<WithdrawPreview> {
+        process_preview_withdraw(ctx, amount)
+    }
+
+    pub fn preview_borrow(ctx: Context<PreviewBorrow>, amount: u64) -> Result<BorrowPreview> {
+        process_preview_borrow(ctx, amount)
     }
 }
@@ -0,0 +1,736 @@
+use anchor_lang::prelude::*;
+use crate::constants::BPS_DENOMINATOR;
+use crate::health::Health;
+use crate::interest::accrual_growth;
+use crate::state::InterestMode;
+use crate::safe_math::{bps_of, mul_div, SafeMath};
+use crate::wad::{bps_to_wad, split_wad, wad_mul};
+
+// Lending math on plain values, free of accounts, sysvars and CPIs so it can be tested without a validator.
+// `interest`, `health` and the liquidation instructions load the inputs from accounts and store the results.
+
+// Kinked utilization curve:
+//   utilization <= optimal: rate = base + slope1 * utilization / optimal
+//   utilization >  optimal: rate = base + slope1 + slope2 * (utilization - optimal) / (100% - optimal)
+
+/// Borrow rate curve of a bank, all in basis points
+#[derive(Clone, Copy)]
+pub struct RateCurve {
+    pub base_rate: u64,
+    pub slope1: u64,
+    pub slope2: u64,
+    pub optimal_utilization: u64,
+    /// Floor and cap the curve's rate is clamped to, a zero cap leaves it uncapped
+    pub min_rate: u64,
+    pub max_rate: u64,
+}
+
+impl RateCurve {
+    /// Annualized borrow rate at `utilization`, in basis points, clamped to the floor and cap
+    pub fn rate_at(&self, utilization: u64) -> u64 {
+        let rate = self.curve_rate_at(utilization).max(self.min_rate);
+        match self.max_rate {
+            0 => rate,
+            max_rate => rate.min(max_rate),
+        }
+    }
+
+    /// Whether the floor or cap binds at `utilization`
+    pub fn is_clamped_at(&self, utilization: u64) -> bool {
+        self.rate_at(utilization) != self.curve_rate_at(utilization)
+    }
+
+    fn curve_rate_at(&self, utilization: u64) -> u64 {
+        let utilization = utilization.min(BPS_DENOMINATOR);
+        if utilization <= self.optimal_utilization {
+            let variable = (self.slope1 as u128 * utilization as u128)
+                .checked_div(self.optimal_utilization as u128)
+                .unwrap_or(0);
+            self.base_rate.saturating_add(variable as u64)
+        } else {
+            let excess = (utilization - self.optimal_utilization) as u128;
+            let excess_range = (BPS_DENOMINATOR - self.optimal_utilization) as u128;
+            let variable = (self.slope2 as u128 * excess) / excess_range;
+            // Rates are bounded by config, saturating keeps a misconfigured curve from aborting every instruction
+            self.base_rate.saturating_add(self.slope1).saturating_add(variable as u64)
+        }
+    }
+}
+
+/// Rate moved from `current` towards `target` by at most `max_change_per_sec` for each of `time_elapsed` seconds,
+/// zero `max_change_per_sec` jumps straight to the target
+pub fn smooth_rate(current: u64, target: u64, max_change_per_sec: u64, time_elapsed: i64) -> u64 {
+    if max_change_per_sec == 0 {
+        return target;
+    }
+    let max_change = max_change_per_sec.saturating_mul(time_elapsed.max(0) as u64);
+    if target > current {
+        current.saturating_add(max_change).min(target)
+    } else {
+        current.saturating_sub(max_change).max(target)
+    }
+}
+
+/// Utilization in basis points (total debt / total deposits), capped at 100%
+pub fn utilization_rate(total_borrowed: u64, total_deposits: u64) -> u64 {
+    if total_deposits == 0 {
+        return 0;
+    }
+    let utilization = (total_borrowed as u128 * BPS_DENOMINATOR as u128) / total_deposits as u128;
+    utilization.min(BPS_DENOMINATOR as u128) as u64
+}
+
+/// Bank totals moved by variable interest accrual
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct InterestTotals {
+    pub total_deposits: u64,
+    pub total_borrowed: u64,
+    pub total_reserves: u64,
+    /// Fraction of a token of interest not yet added to the totals, as a WAD
+    pub interest_remainder: u64,
+}
+
+/// Accrues `time_elapsed` seconds of interest at `rate` on the variable debt, simple or compounded per `mode`. The interest is added to
+/// the debt and credited to depositors minus the `reserve_factor` cut, fractions of a token carry over.
+/// Returns the whole tokens of interest accrued.
+pub fn accrue_totals(totals: &mut InterestTotals, mode: InterestMode, rate: u64, reserve_factor: u64, time_elapsed: i64) -> Result<u64> {
+    if time_elapsed <= 0 || totals.total_borrowed == 0 {
+        return Ok(0);
+    }
+
+    let interest_wad = (totals.total_borrowed as u128)
+        .safe_mul(accrual_growth(mode, rate, time_elapsed)?)?
+        .safe_add(totals.interest_remainder as u128)?;
+    let (interest, remainder) = split_wad(interest_wad)?;
+    let reserve_cut = bps_of(interest, reserve_factor)?;
+
+    totals.interest_remainder = remainder as u64;
+    totals.total_borrowed = totals.total_borrowed.safe_add(interest)?;
+    totals.total_deposits = totals.total_deposits.safe_add(interest.safe_sub(reserve_cut)?)?;
+    totals.total_reserves = totals.total_reserves.safe_add(reserve_cut)?;
+    Ok(interest)
+}
+
+// Share conversions round in the protocol's favor, so no sequence of deposits, withdrawals, borrows and repays
+// can take out more tokens than it put in:
+//   deposit: shares minted round down     withdraw: shares burned round up, tokens owed round down
+//   borrow:  shares minted round up       repay:    shares burned round down, debt owed rounds up
+
+/// Shares worth `amount` tokens, rounded down. The first depositor or borrower sets the exchange rate at 1:1.
+pub fn to_shares_round_down(amount: u64, total_amount: u64, total_shares: u64) -> Result<u64> {
+    if total_shares == 0 || total_amount == 0 {
+        return Ok(amount);
+    }
+    mul_div(amount, total_shares, total_amount, false)
+}
+
+/// Shares worth `amount` tokens, rounded up
+pub fn to_shares_round_up(amount: u64, total_amount: u64, total_shares: u64) -> Result<u64> {
+    if total_shares == 0 || total_amount == 0 {
+        return Ok(amount);
+    }
+    mul_div(amount, total_shares, total_amount, true)
+}
+
+/// Tokens `shares` shares are worth, rounded down
+pub fn to_amount_round_down(shares: u64, total_amount: u64, total_shares: u64) -> Result<u64> {
+    if total_shares == 0 {
+        return Ok(0);
+    }
+    mul_div(shares, total_amount, total_shares, false)
+}
+
+/// Tokens `shares` shares are worth, rounded up
+pub fn to_amount_round_up(shares: u64, total_amount: u64, total_shares: u64) -> Result<u64> {
+    if total_shares == 0 {
+        return Ok(0);
+    }
+    mul_div(shares, total_amount, total_shares, true)
+}
+
+/// Value and risk settings of one of a user's positions
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub struct PositionRisk {
+    pub bank: Pubkey,
+    pub collateral_enabled: bool,
+    /// Value of the deposit, in USD with `VALUE_DECIMALS`
+    pub collateral_value: u128,
+    /// Value of the debt, in USD with `VALUE_DECIMALS`
+    pub debt_value: u128,
+    /// Deposit value at the price liquidation checks use, same as `collateral_value` unless valued at the price EMA
+    pub liquidation_collateral_value: u128,
+    /// Debt value at the price liquidation checks use
+    pub liquidation_debt_value: u128,
+    /// Whether the deposit was made in the current slot of a bank with a same-slot deposit lock, it then
+    /// counts towards the liquidation limit but backs no new debt
+    pub deposit_locked: bool,
+    /// Valuation haircut on the deposit and markup on the debt, in basis points, applied before the max LTV and
+    /// liquidation threshold
+    pub collateral_weight: u64,
+    pub debt_weight: u64,
+    /// Max LTV and liquidation threshold of the bank, in basis points
+    pub max_ltv: u64,
+    pub liquidation_threshold: u64,
+    pub emode_category: u8,
+    pub emode_max_ltv: u64,
+    pub emode_liquidation_threshold: u64,
+    /// Only bank besides its own the collateral may back debt in, none when the bank isn't isolated
+    pub isolated_debt_bank: Option<Pubkey>,
+}
+
+/// Sums collateral and debt across the positions, weighting collateral enabled deposits by each bank's
+/// collateral weight and max LTV or liquidation threshold, and debt by each bank's debt weight.
+///
+/// With a non-zero `emode_category` shared by every position, the e-mode weights are used instead.
+/// Isolated collateral only counts while all of the debt is in its own bank or its `isolated_debt_bank`.
+pub fn aggregate_health(positions: &[PositionRisk], emode_category: u8) -> Result<Health> {
+    let emode_active = emode_category != 0 && positions.iter().all(|position| position.emode_category == emode_category);
+
+    let mut health = Health::default();
+    for position in positions {
+        let debt_weight = bps_to_wad(position.debt_weight);
+        health.collateral_value = health.collateral_value.safe_add(position.collateral_value)?;
+        health.debt_value = health.debt_value.safe_add(wad_mul(position.debt_value, debt_weight)?)?;
+        health.liquidation_debt_value = health.liquidation_debt_value.safe_add(wad_mul(position.liquidation_debt_value, debt_weight)?)?;
+
+        // Deposits the user opted out of using as collateral only earn yield
+        if !position.collateral_enabled {
+            continue;
+        }
+
+        if let Some(isolated_debt_bank) = position.isolated_debt_bank {
+            let backs_all_debt = positions
+                .iter()
+                .filter(|other| other.debt_value > 0)
+                .all(|other| other.bank == position.bank || other.bank == isolated_debt_bank);
+            if !backs_all_debt {
+                continue;
+            }
+        }
+
+        let (max_ltv, liquidation_threshold) = if emode_active {
+            (position.emode_max_ltv, position.emode_liquidation_threshold)
+        } else {
+            (position.max_ltv, position.liquidation_threshold)
+        };
+        let collateral_weight = bps_to_wad(position.collateral_weight);
+        let liquidation_collateral = wad_mul(position.liquidation_collateral_value, collateral_weight)?;
+        let liquidation_limit = wad_mul(liquidation_collateral, bps_to_wad(liquidation_threshold))?;
+        if !position.deposit_locked {
+            let borrow_limit = wad_mul(wad_mul(position.collateral_value, collateral_weight)?, bps_to_wad(max_ltv))?;
+            health.borrow_limit = health.borrow_limit.safe_add(borrow_limit)?;
+        }
+        health.liquidation_limit = health.liquidation_limit.safe_add(liquidation_limit)?;
+    }
+
+    Ok(health)
+}
+
+/// Value of debt the user can still take on before reaching the borrow limit
+pub fn max_borrow_value(health: &Health) -> u128 {
+    health.borrow_limit.saturating_sub(health.debt_value)
+}
+
+/// Debt over collateral in basis points, zero without collateral
+pub fn loan_to_value(health: &Health) -> u64 {
+    (health.debt_value.saturating_mul(BPS_DENOMINATOR as u128))
+        .checked_div(health.collateral_value)
+        .unwrap_or(0)
+        .min(u64::MAX as u128) as u64
+}
+
+/// Most debt a single liquidation may repay, the close factor share of the debt rounded down
+pub fn max_liquidation_amount(debt: u64, close_factor: u64) -> Result<u64> {
+    bps_of(debt, close_factor)
+}
+
+/// Collateral seized for `repaid_collateral` worth of repaid debt: the repaid amount plus the liquidation bonus,
+/// rounded down and capped at the borrower's deposit. Returns the seized collateral and the bonus.
+pub fn seized_collateral(repaid_collateral: u64, bonus_bps: u64, deposited_collateral: u64) -> Result<(u64, u64)> {
+    let bonus = bps_of(repaid_collateral, bonus_bps)?;
+    Ok((repaid_collateral.safe_add(bonus)?.min(deposited_collateral), bonus))
+}
+
+/// Share of a deposit's value counting towards the liquidation limit, in basis points: the bank's collateral weight
+/// applied to its regular liquidation threshold
+pub fn liquidation_weight(position: &PositionRisk) -> u64 {
+    (position.collateral_weight as u128 * position.liquidation_threshold as u128 / BPS_DENOMINATOR as u128) as u64
+}
+
+/// Bank `LiquidationOrder::LowestWeightFirst` seizes from: the collateral position with the lowest liquidation
+/// weight, the earliest of the user's positions among equal weights. None without collateral left to seize.
+pub fn lowest_weight_collateral(positions: &[PositionRisk]) -> Option<Pubkey> {
+    positions
+        .iter()
+        .filter(|position| position.collateral_enabled && position.liquidation_collateral_value > 0)
+        .min_by_key(|position| liquidation_weight(position))
+        .map(|position| position.bank)
+}
+
+/// Protocol's `fee_bps` share of the bonus actually paid within `seized_collateral`, rounded down in the liquidator's favor
+pub fn liquidation_protocol_fee(seized_collateral: u64, repaid_collateral: u64, fee_bps: u64) -> Result<u64> {
+    bps_of(seized_collateral.saturating_sub(repaid_collateral), fee_bps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::SECONDS_PER_YEAR;
+
+    const CURVE: RateCurve = RateCurve {
+        base_rate: 100,
+        slope1: 400,
+        slope2: 6_000,
+        optimal_utilization: 8_000,
+        min_rate: 0,
+        max_rate: 0,
+    };
+
+    fn position(collateral_value: u128, debt_value: u128) -> PositionRisk {
+        PositionRisk {
+            bank: Pubkey::new_unique(),
+            collateral_enabled: true,
+            collateral_value,
+            debt_value,
+            liquidation_collateral_value: collateral_value,
+            liquidation_debt_value: debt_value,
+            deposit_locked: false,
+            collateral_weight: BPS_DENOMINATOR,
+            debt_weight: BPS_DENOMINATOR,
+            max_ltv: 7_500,
+            liquidation_threshold: 8_000,
+            emode_category: 0,
+            emode_max_ltv: 0,
+            emode_liquidation_threshold: 0,
+            isolated_debt_bank: None,
+        }
+    }
+
+    /// Totals of one side of a bank, deposits or debt
+    #[derive(Clone, Copy)]
+    struct Pool {
+        amount: u64,
+        shares: u64,
+    }
+
+    impl Pool {
+        /// Exchange rates of pools with odd amounts per share, where every conversion has to round
+        const RATES: [Pool; 4] = [
+            Pool { amount: 1_000, shares: 1_000 },
+            Pool { amount: 3_000, shares: 2_000 },
+            Pool { amount: 2_000, shares: 3_000 },
+            Pool { amount: 1_000_003, shares: 999_983 },
+        ];
+
+        /// Whether `self` is worth at least `before` per share, compared without rounding
+        fn per_share_at_least(&self, before: &Pool) -> bool {
+            self.amount as u128 * before.shares as u128 >= before.amount as u128 * self.shares as u128
+        }
+    }
+
+    #[test]
+    fn share_rounding_directions() {
+        // 3 tokens per 2 shares: 1 token is 2/3 of a share and 1 share is 1.5 tokens
+        assert_eq!(to_shares_round_down(1, 3_000, 2_000).unwrap(), 0);
+        assert_eq!(to_shares_round_up(1, 3_000, 2_000).unwrap(), 1);
+        assert_eq!(to_amount_round_down(1, 3_000, 2_000).unwrap(), 1);
+        assert_eq!(to_amount_round_up(1, 3_000, 2_000).unwrap(), 2);
+        // Exact conversions agree either way
+        assert_eq!(to_shares_round_down(3, 3_000, 2_000).unwrap(), 2);
+        assert_eq!(to_shares_round_up(3, 3_000, 2_000).unwrap(), 2);
+        // An empty pool converts 1:1 and shares of an empty pool are worth nothing
+        assert_eq!(to_shares_round_down(7, 0, 0).unwrap(), 7);
+        assert_eq!(to_shares_round_up(7, 0, 0).unwrap(), 7);
+        assert_eq!(to_amount_round_down(7, 0, 0).unwrap(), 0);
+        assert_eq!(to_amount_round_up(7, 0, 0).unwrap(), 0);
+    }
+
+    #[test]
+    fn dust_deposits_and_withdrawals_extract_nothing() {
+        for rate in Pool::RATES {
+            let mut pool = rate;
+            let (mut tokens_in, mut tokens_out, mut shares) = (0u64, 0u64, 0u64);
+
+            for round in 0..5_000 {
+                let before = pool;
+                if round % 3 == 2 {
+                    // Withdraw a lamport, or whatever the shares are still worth
+                    let amount = to_amount_round_down(shares, pool.amount, pool.shares).unwrap().min(1);
+                    let burned = to_shares_round_up(amount, pool.amount, pool.shares).unwrap().min(shares);
+                    pool.amount -= amount;
+                    pool.shares -= burned;
+                    shares -= burned;
+                    tokens_out += amount;
+                } else {
+                    let minted = to_shares_round_down(1, pool.amount, pool.shares).unwrap();
+                    pool.amount += 1;
+                    pool.shares += minted;
+                    shares += minted;
+                    tokens_in += 1;
+                }
+                // Every rounding leftover stays with the other depositors
+                assert!(pool.per_share_at_least(&before), "share value dropped at {}:{}", rate.amount, rate.shares);
+            }
+
+            let redeemable = to_amount_round_down(shares, pool.amount, pool.shares).unwrap();
+            assert!(tokens_out + redeemable <= tokens_in, "extracted {} of {} at {}:{}", tokens_out + redeemable, tokens_in, rate.amount, rate.shares);
+        }
+    }
+
+    #[test]
+    fn dust_withdrawals_of_an_existing_deposit_extract_nothing() {
+        for rate in Pool::RATES {
+            let mut pool = rate;
+            let mut shares = pool.shares / 10;
+            let deposited = to_amount_round_down(shares, pool.amount, pool.shares).unwrap();
+            let mut tokens_out = 0u64;
+
+            while shares > 0 {
+                let burned = to_shares_round_up(1, pool.amount, pool.shares).unwrap();
+                if burned > shares {
+                    break;
+                }
+                pool.amount -= 1;
+                pool.shares -= burned;
+                shares -= burned;
+                tokens_out += 1;
+            }
+            assert!(tokens_out <= deposited, "withdrew {} of {} at {}:{}", tokens_out, deposited, rate.amount, rate.shares);
+            assert!(pool.per_share_at_least(&rate));
+        }
+    }
+
+    #[test]
+    fn dust_borrows_and_repays_extract_nothing() {
+        for rate in Pool::RATES {
+            let mut pool = rate;
+            let (mut tokens_borrowed, mut tokens_repaid, mut shares) = (0u64, 0u64, 0u64);
+
+            for round in 0..5_000 {
+                if round % 2 == 1 && shares > 0 {
+                    let burned = to_shares_round_down(1, pool.amount, pool.shares).unwrap().min(shares);
+                    pool.amount -= 1;
+                    pool.shares -= burned;
+                    shares -= burned;
+                    tokens_repaid += 1;
+                } else {
+                    let minted = to_shares_round_up(1, pool.amount, pool.shares).unwrap();
+                    pool.amount += 1;
+                    pool.shares += minted;
+                    shares += minted;
+                    tokens_borrowed += 1;
+                }
+                // The borrower's share of the bank's debt, without rounding, never falls below what they still hold
+                let unpaid = (tokens_borrowed - tokens_repaid.min(tokens_borrowed)) as u128;
+                assert!(
+                    shares as u128 * pool.amount as u128 >= unpaid * pool.shares as u128,
+                    "debt shed at {}:{} after {} rounds", rate.amount, rate.shares, round,
+                );
+            }
+
+            // The debt still owed covers every token taken out and not paid back
+            let owed = to_amount_round_up(shares, pool.amount, pool.shares).unwrap();
+            assert!(owed + tokens_repaid >= tokens_borrowed, "owes {} after {} borrowed and {} repaid", owed, tokens_borrowed, tokens_repaid);
+        }
+    }
+
+    #[test]
+    fn utilization_without_liquidity() {
+        assert_eq!(utilization_rate(0, 0), 0);
+        // An emptied bank with debt left behind reads as unutilized rather than dividing by zero
+        assert_eq!(utilization_rate(1, 0), 0);
+        assert_eq!(utilization_rate(0, 1_000), 0);
+    }
+
+    #[test]
+    fn utilization_is_capped_at_max() {
+        assert_eq!(utilization_rate(1_000, 1_000), BPS_DENOMINATOR);
+        assert_eq!(utilization_rate(u64::MAX, 1), BPS_DENOMINATOR);
+        assert_eq!(utilization_rate(u64::MAX, u64::MAX), BPS_DENOMINATOR);
+        // Rounded down, 2 of 3 tokens is 66.66%
+        assert_eq!(utilization_rate(2, 3), 6_666);
+    }
+
+    #[test]
+    fn rate_curve_kinks_at_optimal() {
+        assert_eq!(CURVE.rate_at(0), 100);
+        assert_eq!(CURVE.rate_at(4_000), 300);
+        assert_eq!(CURVE.rate_at(8_000), 500);
+        assert_eq!(CURVE.rate_at(9_000), 3_500);
+        assert_eq!(CURVE.rate_at(BPS_DENOMINATOR), 6_500);
+        // Utilization above 100% is read as 100%
+        assert_eq!(CURVE.rate_at(u64::MAX), 6_500);
+    }
+
+    #[test]
+    fn rate_curve_is_monotonic() {
+        let mut previous = 0;
+        for utilization in (0..=BPS_DENOMINATOR).step_by(50) {
+            let rate = CURVE.rate_at(utilization);
+            assert!(rate >= previous, "rate dropped to {rate} at {utilization}");
+            previous = rate;
+        }
+    }
+
+    #[test]
+    fn rate_curve_saturates() {
+        let curve = RateCurve { base_rate: u64::MAX, slope1: u64::MAX, slope2: u64::MAX, optimal_utilization: 5_000, min_rate: 0, max_rate: 0 };
+        assert_eq!(curve.rate_at(BPS_DENOMINATOR), u64::MAX);
+        let zero_optimal = RateCurve { optimal_utilization: 0, ..CURVE };
+        assert_eq!(zero_optimal.rate_at(0), CURVE.base_rate);
+    }
+
+    #[test]
+    fn rate_curve_clamps() {
+        let clamped = RateCurve { min_rate: 300, max_rate: 2_000, ..CURVE };
+        assert_eq!(clamped.rate_at(0), 300);
+        assert!(clamped.is_clamped_at(0));
+        assert_eq!(clamped.rate_at(8_000), CURVE.rate_at(8_000));
+        assert!(!clamped.is_clamped_at(8_000));
+        assert_eq!(clamped.rate_at(BPS_DENOMINATOR), 2_000);
+        assert!(clamped.is_clamped_at(BPS_DENOMINATOR));
+        assert!(!CURVE.is_clamped_at(BPS_DENOMINATOR));
+    }
+
+    #[test]
+    fn smoothed_rate_moves_gradually() {
+        assert_eq!(smooth_rate(500, 5_000, 0, 1), 5_000);
+        assert_eq!(smooth_rate(500, 5_000, 10, 60), 1_100);
+        assert_eq!(smooth_rate(500, 5_000, 10, 3_600), 5_000);
+        assert_eq!(smooth_rate(5_000, 500, 10, 60), 4_400);
+        assert_eq!(smooth_rate(5_000, 500, 10, 3_600), 500);
+        assert_eq!(smooth_rate(500, 5_000, 10, 0), 500);
+        assert_eq!(smooth_rate(500, 5_000, 10, -5), 500);
+        assert_eq!(smooth_rate(500, 5_000, u64::MAX, i64::MAX), 5_000);
+    }
+
+    #[test]
+    fn accrual_without_debt_or_time() {
+        let initial = InterestTotals { total_deposits: 1_000, ..Default::default() };
+        let mut totals = initial;
+        assert_eq!(accrue_totals(&mut totals, InterestMode::Simple, 10_000, 1_000, SECONDS_PER_YEAR as i64).unwrap(), 0);
+        assert_eq!(totals, initial);
+
+        let initial = InterestTotals { total_deposits: 1_000, total_borrowed: 1_000, ..Default::default() };
+        let mut totals = initial;
+        assert_eq!(accrue_totals(&mut totals, InterestMode::Simple, 10_000, 1_000, 0).unwrap(), 0);
+        assert_eq!(accrue_totals(&mut totals, InterestMode::Simple, 10_000, 1_000, -5).unwrap(), 0);
+        assert_eq!(totals, initial);
+    }
+
+    #[test]
+    fn accrual_splits_reserves() {
+        let mut totals = InterestTotals { total_deposits: 2_000_000, total_borrowed: 1_000_000, ..Default::default() };
+        // 10% a year on 1M over a year is 100k, a 20% reserve factor keeps 20k
+        let interest = accrue_totals(&mut totals, InterestMode::Simple, 1_000, 2_000, SECONDS_PER_YEAR as i64).unwrap();
+        assert_eq!(interest, 100_000);
+        assert_eq!(totals.total_borrowed, 1_100_000);
+        assert_eq!(totals.total_deposits, 2_080_000);
+        assert_eq!(totals.total_reserves, 20_000);
+        assert_eq!(totals.interest_remainder, 0);
+    }
+
+    #[test]
+    fn accrual_at_max_utilization() {
+        let mut totals = InterestTotals { total_deposits: 1_000_000, total_borrowed: 1_000_000, ..Default::default() };
+        let rate = CURVE.rate_at(utilization_rate(totals.total_borrowed, totals.total_deposits));
+        accrue_totals(&mut totals, InterestMode::Simple, rate, 0, SECONDS_PER_YEAR as i64).unwrap();
+        // Without a reserve cut every token of interest owed by borrowers is owed to depositors
+        assert_eq!(totals.total_borrowed, 1_650_000);
+        assert_eq!(totals.total_deposits, totals.total_borrowed);
+    }
+
+    #[test]
+    fn accrual_carries_fractions() {
+        let mut stepped = InterestTotals { total_deposits: 1_000_000, total_borrowed: 1_000_000, ..Default::default() };
+        let mut once = stepped;
+        // 1% a year on a million tokens accrues far less than a token per second
+        for _ in 0..86_400 {
+            accrue_totals(&mut stepped, InterestMode::Simple, 100, 0, 1).unwrap();
+        }
+        accrue_totals(&mut once, InterestMode::Simple, 100, 0, 86_400).unwrap();
+        assert_eq!(once.total_borrowed, 1_000_027);
+        // Stepping compounds the carried fractions, but ends within a token of a single accrual
+        assert!(stepped.total_borrowed.abs_diff(once.total_borrowed) <= 1);
+    }
+
+    #[test]
+    fn accrual_overflow_fails() {
+        let mut totals = InterestTotals { total_deposits: u64::MAX, total_borrowed: u64::MAX, ..Default::default() };
+        assert!(accrue_totals(&mut totals, InterestMode::Simple, 10_000, 0, SECONDS_PER_YEAR as i64).is_err());
+    }
+
+    #[test]
+    fn health_without_debt() {
+        let health = aggregate_health(&[position(1_000, 0)], 0).unwrap();
+        assert_eq!(health.health_factor(), u64::MAX);
+        assert_eq!(health.borrow_limit, 750);
+        assert_eq!(health.liquidation_limit, 800);
+        assert!(!health.is_liquidatable());
+        assert_eq!(aggregate_health(&[], 0).unwrap().health_factor(), u64::MAX);
+    }
+
+    #[test]
+    fn health_liquidation_boundary() {
+        let at_threshold = aggregate_health(&[position(1_000, 800)], 0).unwrap();
+        assert!(!at_threshold.is_liquidatable());
+        assert_eq!(at_threshold.health_factor(), BPS_DENOMINATOR);
+        let above = aggregate_health(&[position(1_000, 801)], 0).unwrap();
+        assert!(above.is_liquidatable());
+        assert!(above.health_factor() < BPS_DENOMINATOR);
+    }
+
+    #[test]
+    fn liquidation_checks_use_their_own_values() {
+        // Borrow limit at the conservative values, liquidation at the user favorable ones
+        let mut position = position(1_000, 760);
+        position.debt_value = 800;
+        position.liquidation_collateral_value = 1_100;
+        position.liquidation_debt_value = 760;
+        let health = aggregate_health(&[position], 0).unwrap();
+        assert_eq!(health.borrow_limit, 750);
+        assert_eq!(health.liquidation_limit, 880);
+        assert!(!health.is_within_borrow_limit());
+        assert!(!health.is_liquidatable());
+        assert!(health.health_factor() > BPS_DENOMINATOR);
+    }
+
+    #[test]
+    fn locked_deposit_only_counts_for_liquidation() {
+        let mut locked = position(1_000, 0);
+        locked.deposit_locked = true;
+        let health = aggregate_health(&[locked, position(0, 500)], 0).unwrap();
+        assert_eq!(health.borrow_limit, 0);
+        assert_eq!(health.liquidation_limit, 800);
+        assert!(!health.is_within_borrow_limit());
+        assert!(!health.is_liquidatable());
+    }
+
+    #[test]
+    fn health_limits_round_down() {
+        // 75% of 3 is 2.25, the limits never round in the borrower's favor
+        let health = aggregate_health(&[position(3, 0)], 0).unwrap();
+        assert_eq!(health.borrow_limit, 2);
+        assert_eq!(health.liquidation_limit, 2);
+    }
+
+    #[test]
+    fn weights_scale_limits_and_debt() {
+        let mut collateral = position(1_000, 0);
+        collateral.collateral_weight = 9_500;
+        let mut debt = position(0, 500);
+        debt.debt_weight = 12_000;
+        let health = aggregate_health(&[collateral, debt], 0).unwrap();
+        assert_eq!(health.collateral_value, 1_000);
+        assert_eq!(health.borrow_limit, 712);
+        assert_eq!(health.liquidation_limit, 760);
+        assert_eq!(health.debt_value, 600);
+        assert_eq!(health.liquidation_debt_value, 600);
+    }
+
+    #[test]
+    fn disabled_collateral_only_earns() {
+        let mut disabled = position(1_000, 0);
+        disabled.collateral_enabled = false;
+        let health = aggregate_health(&[disabled, position(0, 100)], 0).unwrap();
+        assert_eq!(health.collateral_value, 1_000);
+        assert_eq!(health.borrow_limit, 0);
+        assert!(health.is_liquidatable());
+    }
+
+    #[test]
+    fn isolated_collateral_backs_only_its_debt_bank() {
+        let debt_bank = position(0, 100);
+        let other_debt = position(0, 100);
+        let mut isolated = position(1_000, 0);
+        isolated.isolated_debt_bank = Some(debt_bank.bank);
+
+        let health = aggregate_health(&[isolated, debt_bank], 0).unwrap();
+        assert_eq!(health.borrow_limit, 750);
+        let health = aggregate_health(&[isolated, debt_bank, other_debt], 0).unwrap();
+        assert_eq!(health.borrow_limit, 0);
+    }
+
+    #[test]
+    fn emode_needs_every_position_in_category() {
+        let mut collateral = position(1_000, 0);
+        let mut debt = position(0, 500);
+        for p in [&mut collateral, &mut debt] {
+            p.emode_category = 1;
+            p.emode_max_ltv = 9_000;
+            p.emode_liquidation_threshold = 9_500;
+        }
+        let health = aggregate_health(&[collateral, debt], 1).unwrap();
+        assert_eq!(health.borrow_limit, 900);
+        assert_eq!(health.liquidation_limit, 950);
+
+        let outside = position(0, 0);
+        let health = aggregate_health(&[collateral, debt, outside], 1).unwrap();
+        assert_eq!(health.borrow_limit, 750);
+        assert_eq!(aggregate_health(&[collateral, debt], 0).unwrap().borrow_limit, 750);
+    }
+
+    #[test]
+    fn max_borrow_saturates() {
+        let health = aggregate_health(&[position(1_000, 700)], 0).unwrap();
+        assert_eq!(max_borrow_value(&health), 50);
+        let health = aggregate_health(&[position(1_000, 900)], 0).unwrap();
+        assert_eq!(max_borrow_value(&health), 0);
+        assert_eq!(max_borrow_value(&Health::default()), 0);
+    }
+
+    #[test]
+    fn ltv_without_collateral() {
+        assert_eq!(loan_to_value(&Health::default()), 0);
+        let health = aggregate_health(&[position(0, 100)], 0).unwrap();
+        assert_eq!(loan_to_value(&health), 0);
+        let health = aggregate_health(&[position(1_000, 333)], 0).unwrap();
+        assert_eq!(loan_to_value(&health), 3_330);
+    }
+
+    #[test]
+    fn liquidation_amount_rounds_down() {
+        assert_eq!(max_liquidation_amount(1_000, 5_000).unwrap(), 500);
+        assert_eq!(max_liquidation_amount(3, 5_000).unwrap(), 1);
+        assert_eq!(max_liquidation_amount(1, 5_000).unwrap(), 0);
+        assert_eq!(max_liquidation_amount(u64::MAX, BPS_DENOMINATOR).unwrap(), u64::MAX);
+    }
+
+    #[test]
+    fn seizure_is_capped_at_deposit() {
+        assert_eq!(seized_collateral(1_000, 500, 10_000).unwrap(), (1_050, 50));
+        assert_eq!(seized_collateral(1_000, 500, 1_020).unwrap(), (1_020, 50));
+        // The bonus rounds down, a 5% bonus on 19 tokens is under one token
+        assert_eq!(seized_collateral(19, 500, 10_000).unwrap(), (19, 0));
+        assert_eq!(seized_collateral(0, 500, 0).unwrap(), (0, 0));
+        assert!(seized_collateral(u64::MAX, 500, u64::MAX).is_err());
+    }
+
+    #[test]
+    fn lowest_weight_collateral_is_seized_first() {
+        let stable = position(1_000, 0);
+        let volatile = PositionRisk { liquidation_threshold: 6_500, ..position(1_000, 0) };
+        let haircut = PositionRisk { collateral_weight: 8_000, liquidation_threshold: 8_125, ..position(1_000, 0) };
+        let debt = position(0, 1_500);
+        assert_eq!(liquidation_weight(&volatile), 6_500);
+        assert_eq!(liquidation_weight(&haircut), 6_500);
+
+        // Equal weights go to the earliest position, debt-only positions are never picked
+        assert_eq!(lowest_weight_collateral(&[debt, stable, volatile, haircut]), Some(volatile.bank));
+        assert_eq!(lowest_weight_collateral(&[haircut, stable, volatile]), Some(haircut.bank));
+        // Fully seized or disabled collateral moves on to the next lowest weight
+        let seized = PositionRisk { liquidation_collateral_value: 0, ..volatile };
+        let disabled = PositionRisk { collateral_enabled: false, ..haircut };
+        assert_eq!(lowest_weight_collateral(&[stable, seized, disabled]), Some(stable.bank));
+        assert_eq!(lowest_weight_collateral(&[debt]), None);
+    }
+
+    #[test]
+    fn protocol_fee_only_takes_from_the_paid_bonus() {
+        assert_eq!(liquidation_protocol_fee(1_050, 1_000, 2_000).unwrap(), 10);
+        // A seizure capped at the deposit pays a smaller bonus and so a smaller fee
+        assert_eq!(liquidation_protocol_fee(1_020, 1_000, 2_000).unwrap(), 4);
+        assert_eq!(liquidation_protocol_fee(900, 1_000, 2_000).unwrap(), 0);
+        assert_eq!(liquidation_protocol_fee(1_050, 1_000, 0).unwrap(), 0);
+    }
+}
@@ -0,0 +1,86 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_2022::spl_token_2022::{
+    self,
+    extension::{transfer_fee::TransferFeeConfig, BaseStateWithExtensions, ExtensionType, StateWithExtensions},
+};
+use anchor_spl::token_interface::TokenAccount;
+use crate::error::ErrorCode;
+use crate::state::Bank;
+
+// Extensions that would break vault accounting or need accounts the handlers don't pass
+const UNSUPPORTED_EXTENSIONS: [ExtensionType; 3] = [
+    ExtensionType::TransferHook,
+    ExtensionType::NonTransferable,
+    ExtensionType::PermanentDelegate,
+];
+
+/// Rejects Token-2022 mints with extensions the bank can't safely hold
+pub fn validate_mint_extensions(mint: &AccountInfo) -> Result<()> {
+    if *mint.owner != spl_token_2022::ID {
+        return Ok(());
+    }
+    let data = mint.try_borrow_data()?;
+    let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+    for extension in state.get_extension_types()? {
+        if UNSUPPORTED_EXTENSIONS.contains(&extension) {
+            msg!("Mint {} has unsupported extension {:?}", mint.key, extension);
+            return Err(ErrorCode::UnsupportedMintExtension.into());
+        }
+    }
+    Ok(())
+}
+
+/// Fee withheld by a Token-2022 transfer fee extension when transferring `amount`, zero for other mints
+pub fn calculate_transfer_fee(mint: &AccountInfo, amount: u64) -> Result<u64> {
+    if *mint.owner != spl_token_2022::ID {
+        return Ok(0);
+    }
+    let data = mint.try_borrow_data()?;
+    let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+    match state.get_extension::<TransferFeeConfig>() {
+        Ok(config) => config
+            .calculate_epoch_fee(Clock::get()?.epoch, amount)
+            .ok_or(ErrorCode::MathOverflow.into()),
+        Err(_) => Ok(0),
+    }
+}
+
+/// Amount to send so that `net_amount` arrives after the transfer fee
+pub fn gross_up_for_transfer_fee(mint: &AccountInfo, net_amount: u64) -> Result<u64> {
+    if *mint.owner != spl_token_2022::ID {
+        return Ok(net_amount);
+    }
+    let data = mint.try_borrow_data()?;
+    let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+    match state.get_extension::<TransferFeeConfig>() {
+        Ok(config) => {
+            let fee = config
+                .calculate_inverse_epoch_fee(Clock::get()?.epoch, net_amount)
+                .ok_or(ErrorCode::MathOverflow)?;
+            net_amount.checked_add(fee).ok_or(ErrorCode::MathOverflow.into())
+        }
+        Err(_) => Ok(net_amount),
+    }
+}
+
+/// Reloads the bank's token account after the handler's transfers and checks it moved by exactly `received - sent`
+/// tokens, and that it held no less than the bank's mirror of its balance beforehand. Tokens sent to the account
+/// directly only ever show up as a surplus and are taken into the mirror, a shortfall or a transfer that moved a
+/// different amount than the handler accounts for fails the instruction.
+pub fn reconcile_vault(bank: &mut Bank, vault: &mut InterfaceAccount<TokenAccount>, received: u64, sent: u64) -> Result<()> {
+    let before = vault.amount;
+    vault.reload()?;
+    let expected = before
+        .checked_add(received)
+        .and_then(|balance| balance.checked_sub(sent))
+        .ok_or(ErrorCode::MathOverflow)?;
+    if before < bank.vault_balance || vault.amount != expected {
+        msg!(
+            "Vault {} held {} against {} recorded, now holds {} instead of {}",
+            vault.key(), before, bank.vault_balance, vault.amount, expected,
+        );
+        return Err(ErrorCode::VaultBalanceMismatch.into());
+    }
+    bank.vault_balance = vault.amount;
+    Ok(())
+}
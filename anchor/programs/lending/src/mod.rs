@@ -1,4 +1,14 @@
 pub mod state;
 pub mod error;
+pub mod events;
+pub mod health;
 pub mod constants;
-pub mod instructions;
\ No newline at end of file
+pub mod instructions;
+pub mod interest;
+pub mod mint;
+pub mod oracle;
+pub mod pricing;
+pub mod referral;
+pub mod rewards;
+pub mod safe_math;
+pub mod wad;
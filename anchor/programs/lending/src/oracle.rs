@@ -0,0 +1,351 @@
+use anchor_lang::prelude::*;
+use pyth_solana_receiver_sdk::error::GetPriceError;
+use pyth_solana_receiver_sdk::price_update::{get_feed_id_from_hex, Price, PriceUpdateV2};
+use crate::constants::{BPS_DENOMINATOR, SOL_USD_FEED_ID};
+use crate::state::{Bank, OracleSource, OverridePrice, PriceBasis, PriceMode};
+use crate::events::PriceOverrideUsed;
+use crate::error::ErrorCode;
+
+// Switchboard On-Demand pull feeds are read by offset instead of through the Switchboard SDK.
+// `PullFeedAccountData` stores its latest result as an i128 scaled by 10^18.
+const SWITCHBOARD_ON_DEMAND_ID: Pubkey = pubkey!("SBondMDrcV3K4kxZR1HNVT7osZxAHVHgYXL5Ze1oMUv");
+const SWITCHBOARD_DISCRIMINATOR: [u8; 8] = [196, 27, 108, 196, 10, 215, 219, 40];
+const SWITCHBOARD_LAST_UPDATE_OFFSET: usize = 2216;
+const SWITCHBOARD_RESULT_OFFSET: usize = 2264;
+const SWITCHBOARD_STD_DEV_OFFSET: usize = SWITCHBOARD_RESULT_OFFSET + 16;
+// Switchboard results are converted to 10 decimal prices, which fit an i64 up to ~$900M
+const SWITCHBOARD_EXPONENT: i32 = -10;
+const SWITCHBOARD_SCALE_DOWN: i128 = 100_000_000;
+
+// SPL stake pools are read by offset from the Borsh layout of `StakePool`, whose fixed size fields come first
+const SPL_STAKE_POOL_ID: Pubkey = pubkey!("SPoo1Ku8WFXoNDMHPsrGSTSG1Y47rzgn41SLUNakuHy");
+const STAKE_POOL_ACCOUNT_TYPE: u8 = 1;
+const STAKE_POOL_MINT_OFFSET: usize = 162;
+const STAKE_POOL_TOTAL_LAMPORTS_OFFSET: usize = 258;
+const STAKE_POOL_TOKEN_SUPPLY_OFFSET: usize = 266;
+const STAKE_POOL_LAST_UPDATE_EPOCH_OFFSET: usize = 274;
+// Stake pool rates are scaled by 10^9, SOL's decimals
+const STAKE_POOL_RATE_SCALE: u128 = 1_000_000_000;
+
+/// Loads the bank's price from `price_info` with `get_oracle_price`. While the risk manager overrides the bank's
+/// price, `price_info` has to be the bank's unexpired `OverridePrice` instead, which is taken as is. Every valuation
+/// at the override is logged and emits `PriceOverrideUsed`, unnumbered as the bank is only read.
+pub fn get_bank_price(bank: &Bank, price_info: &AccountInfo, clock: &Clock) -> Result<Price> {
+    let Some(price_override) = read_price_override(bank, price_info, clock)? else {
+        return get_oracle_price(bank, price_info, clock);
+    };
+    Ok(use_price_override(&price_override, None, clock))
+}
+
+/// Same as `get_bank_price` for a bank the instruction writes, which numbers `PriceOverrideUsed` in its stream
+pub fn get_bank_price_mut(bank: &mut Bank, price_info: &AccountInfo, clock: &Clock) -> Result<Price> {
+    let Some(price_override) = read_price_override(bank, price_info, clock)? else {
+        return get_oracle_price(bank, price_info, clock);
+    };
+    Ok(use_price_override(&price_override, Some(bank.next_event_seq()), clock))
+}
+
+/// Reads the bank's `OverridePrice` from `price_info` while the bank is overridden, none while it isn't
+fn read_price_override(bank: &Bank, price_info: &AccountInfo, clock: &Clock) -> Result<Option<OverridePrice>> {
+    if !bank.price_overridden {
+        return Ok(None);
+    }
+    let price_override = if *price_info.owner == crate::ID {
+        OverridePrice::try_deserialize(&mut &price_info.try_borrow_data()?[..]).ok()
+    } else {
+        None
+    };
+    let Some(price_override) = price_override.filter(|price_override| price_override.mint == bank.mint_address) else {
+        msg!("Price of {} is overridden, {} is not its OverridePrice", bank.mint_address, price_info.key);
+        return Err(ErrorCode::InvalidOracleAccount.into());
+    };
+    if clock.slot >= price_override.expiry_slot {
+        msg!("Price override {} of {} expired at slot {}", price_info.key, price_override.mint, price_override.expiry_slot);
+        return Err(ErrorCode::PriceOverrideExpired.into());
+    }
+    Ok(Some(price_override))
+}
+
+fn use_price_override(price_override: &OverridePrice, event_seq: Option<u64>, clock: &Clock) -> Price {
+    msg!(
+        "PRICE OVERRIDE ACTIVE: valuing {} at {} x 10^{} until slot {}",
+        price_override.mint, price_override.price, price_override.exponent, price_override.expiry_slot,
+    );
+    emit!(PriceOverrideUsed {
+        mint: price_override.mint,
+        price: price_override.price,
+        exponent: price_override.exponent,
+        expiry_slot: price_override.expiry_slot,
+        event_seq,
+        timestamp: clock.unix_timestamp,
+    });
+    Price {
+        price: price_override.price,
+        conf: 0,
+        exponent: price_override.exponent,
+        publish_time: clock.unix_timestamp,
+    }
+}
+
+/// Loads the bank's price from `price_info`, trying the bank's oracle and then its fallback.
+/// An oracle whose account wasn't passed is skipped, fixed prices need no account, so callers pass
+/// the primary oracle's account unless it is unavailable.
+/// The price must be fresh, positive and have a confidence interval within the bank's max confidence,
+/// pegged banks then clamp it to their band around $1.
+pub fn get_oracle_price(bank: &Bank, price_info: &AccountInfo, clock: &Clock) -> Result<Price> {
+    let mut last_error = None;
+    for source in std::iter::once(bank.oracle).chain(bank.fallback_oracle) {
+        let price = match read_oracle(bank, &source, price_info, clock) {
+            Ok(Some(price)) => price,
+            Ok(None) => continue,
+            Err(err) => {
+                last_error = Some(err);
+                continue;
+            }
+        };
+        match validate_price(bank, &price) {
+            Ok(()) => return Ok(apply_price_mode(bank, price)),
+            Err(err) => last_error = Some(err),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        msg!("Price account {} belongs to none of the oracles of {}", price_info.key, bank.mint_address);
+        ErrorCode::InvalidOracleAccount.into()
+    }))
+}
+
+/// Prices a bank's deposits and debt are valued at, which only differ from the oracle price for banks valued at their EMA
+#[derive(Clone, Copy)]
+pub struct ValuationPrices {
+    /// Price of deposits counted towards the borrow limit
+    pub borrow_collateral: Price,
+    /// Price of debt checked against the borrow limit
+    pub borrow_debt: Price,
+    /// Price of deposits counted towards the liquidation limit
+    pub liquidation_collateral: Price,
+    /// Price of debt checked against the liquidation limit
+    pub liquidation_debt: Price,
+}
+
+/// Loads the bank's oracle price with `get_bank_price` and combines it with the bank's EMA as its price basis asks.
+/// The EMA has to have been refreshed within the bank's EMA window.
+pub fn get_valuation_prices(bank: &Bank, price_info: &AccountInfo, clock: &Clock) -> Result<ValuationPrices> {
+    let spot = get_bank_price(bank, price_info, clock)?;
+    if bank.price_basis == PriceBasis::Spot {
+        return Ok(ValuationPrices {
+            borrow_collateral: spot,
+            borrow_debt: spot,
+            liquidation_collateral: spot,
+            liquidation_debt: spot,
+        });
+    }
+
+    let age = clock.unix_timestamp.saturating_sub(bank.ema_updated_at);
+    if bank.ema_updated_at == 0 || age < 0 || age as u64 > bank.ema_window_secs {
+        msg!("Price EMA for {} was last updated at {}, older than {} seconds", bank.mint_address, bank.ema_updated_at, bank.ema_window_secs);
+        return Err(ErrorCode::StaleOracle.into());
+    }
+    let ema = Price {
+        price: rescale_price(bank.ema_price, bank.ema_exponent, spot.exponent).ok_or(ErrorCode::OraclePriceInvalid)?,
+        conf: 0,
+        exponent: spot.exponent,
+        publish_time: bank.ema_updated_at,
+    };
+
+    // Liquidation checks always take the side favoring the user, so a spike of either price can't trigger them
+    let (low, high) = if ema.price < spot.price { (ema, spot) } else { (spot, ema) };
+    let (borrow_collateral, borrow_debt) = match bank.price_basis {
+        PriceBasis::Conservative => (low, high),
+        _ => (ema, ema),
+    };
+    Ok(ValuationPrices {
+        borrow_collateral,
+        borrow_debt,
+        liquidation_collateral: high,
+        liquidation_debt: low,
+    })
+}
+
+/// Moves the bank's price EMA towards `price` by the share of the EMA window elapsed since the last update,
+/// so prices held for a single slot barely move it. The first update starts the EMA at `price`.
+pub fn update_price_ema(bank: &mut Bank, price: &Price, now: i64) -> Result<()> {
+    let elapsed = now.saturating_sub(bank.ema_updated_at).max(0) as u64;
+    let previous = rescale_price(bank.ema_price, bank.ema_exponent, price.exponent);
+    bank.ema_price = match previous {
+        Some(previous) if bank.ema_updated_at != 0 && elapsed < bank.ema_window_secs => {
+            let step = (price.price as i128 - previous as i128) * elapsed as i128 / bank.ema_window_secs as i128;
+            i64::try_from(previous as i128 + step).map_err(|_| ErrorCode::MathOverflow)?
+        }
+        _ => price.price,
+    };
+    bank.ema_exponent = price.exponent;
+    bank.ema_updated_at = now;
+    Ok(())
+}
+
+/// Rejects oracle sources that could never return a price
+pub fn validate_oracle_source(source: &OracleSource) -> Result<()> {
+    let valid = match source {
+        OracleSource::Pyth { feed_id } => *feed_id != [0u8; 32],
+        OracleSource::Switchboard { feed } => *feed != Pubkey::default(),
+        OracleSource::Fixed { price, .. } => *price > 0,
+        OracleSource::StakePool { pool } => *pool != Pubkey::default(),
+    };
+    if !valid {
+        msg!("Oracle source has a zero feed or price");
+        return Err(ErrorCode::InvalidOracleConfig.into());
+    }
+    Ok(())
+}
+
+/// Price reported by the oracle, none when `price_info` isn't the oracle's account
+fn read_oracle(bank: &Bank, source: &OracleSource, price_info: &AccountInfo, clock: &Clock) -> Result<Option<Price>> {
+    match *source {
+        OracleSource::Pyth { feed_id } => {
+            if *price_info.owner != pyth_solana_receiver_sdk::ID {
+                return Ok(None);
+            }
+            let price_update = PriceUpdateV2::try_deserialize(&mut &price_info.try_borrow_data()?[..])?;
+            if price_update.price_message.feed_id != feed_id {
+                return Ok(None);
+            }
+            let price = price_update
+                .get_price_no_older_than(clock, bank.max_price_age_secs, &feed_id)
+                .map_err(|err| match err {
+                    GetPriceError::PriceTooOld => {
+                        msg!("Pyth price for {} is older than {} seconds", bank.mint_address, bank.max_price_age_secs);
+                        ErrorCode::StaleOracle.into()
+                    }
+                    err => Error::from(err),
+                })?;
+            Ok(Some(price))
+        }
+        OracleSource::Switchboard { feed } => {
+            if *price_info.key != feed {
+                return Ok(None);
+            }
+            if *price_info.owner != SWITCHBOARD_ON_DEMAND_ID {
+                msg!("Switchboard feed {} is owned by {}", feed, price_info.owner);
+                return Err(ErrorCode::InvalidOracleAccount.into());
+            }
+            read_switchboard(bank, &price_info.try_borrow_data()?, clock).map(Some)
+        }
+        OracleSource::Fixed { price, exponent } => Ok(Some(Price {
+            price,
+            conf: 0,
+            exponent,
+            publish_time: clock.unix_timestamp,
+        })),
+        OracleSource::StakePool { .. } => {
+            let sol_feed = OracleSource::Pyth { feed_id: get_feed_id_from_hex(SOL_USD_FEED_ID)? };
+            let Some(sol_price) = read_oracle(bank, &sol_feed, price_info, clock)? else {
+                return Ok(None);
+            };
+            // Rates only change once an epoch, the one cached stays usable until the pool's next update is due
+            if bank.stake_pool_rate == 0 || clock.epoch > bank.stake_pool_rate_epoch.saturating_add(1) {
+                msg!("Stake pool rate for {} was last refreshed for epoch {}, now {}", bank.mint_address, bank.stake_pool_rate_epoch, clock.epoch);
+                return Err(ErrorCode::StaleOracle.into());
+            }
+            let scale = |value: i128| value * bank.stake_pool_rate as i128 / STAKE_POOL_RATE_SCALE as i128;
+            Ok(Some(Price {
+                price: i64::try_from(scale(sol_price.price as i128)).map_err(|_| ErrorCode::OraclePriceInvalid)?,
+                conf: u64::try_from(scale(sol_price.conf as i128)).map_err(|_| ErrorCode::OraclePriceInvalid)?,
+                ..sol_price
+            }))
+        }
+    }
+}
+
+/// SOL per pool token, scaled by 10^9, and the epoch it was last updated in, of an SPL stake pool of `mint`
+pub fn read_stake_pool_rate(pool_info: &AccountInfo, mint: &Pubkey) -> Result<(u64, u64)> {
+    if *pool_info.owner != SPL_STAKE_POOL_ID {
+        msg!("Stake pool {} is owned by {}", pool_info.key, pool_info.owner);
+        return Err(ErrorCode::InvalidOracleAccount.into());
+    }
+    let data = pool_info.try_borrow_data()?;
+    if data.len() < STAKE_POOL_LAST_UPDATE_EPOCH_OFFSET + 8 || data[0] != STAKE_POOL_ACCOUNT_TYPE {
+        msg!("Account is not an SPL stake pool");
+        return Err(ErrorCode::InvalidOracleAccount.into());
+    }
+    let read_u64 = |offset: usize| u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+
+    let pool_mint = Pubkey::try_from(&data[STAKE_POOL_MINT_OFFSET..STAKE_POOL_MINT_OFFSET + 32]).unwrap();
+    if pool_mint != *mint {
+        msg!("Stake pool {} mints {}, not {}", pool_info.key, pool_mint, mint);
+        return Err(ErrorCode::InvalidOracleAccount.into());
+    }
+    let total_lamports = read_u64(STAKE_POOL_TOTAL_LAMPORTS_OFFSET);
+    let pool_token_supply = read_u64(STAKE_POOL_TOKEN_SUPPLY_OFFSET);
+    if total_lamports == 0 || pool_token_supply == 0 {
+        msg!("Stake pool {} holds {} lamports for {} pool tokens", pool_info.key, total_lamports, pool_token_supply);
+        return Err(ErrorCode::OraclePriceInvalid.into());
+    }
+    let rate = u64::try_from(total_lamports as u128 * STAKE_POOL_RATE_SCALE / pool_token_supply as u128)
+        .map_err(|_| ErrorCode::OraclePriceInvalid)?;
+    Ok((rate, read_u64(STAKE_POOL_LAST_UPDATE_EPOCH_OFFSET)))
+}
+
+fn read_switchboard(bank: &Bank, data: &[u8], clock: &Clock) -> Result<Price> {
+    if data.len() < SWITCHBOARD_STD_DEV_OFFSET + 16 || data[..8] != SWITCHBOARD_DISCRIMINATOR {
+        msg!("Account is not a Switchboard pull feed");
+        return Err(ErrorCode::InvalidOracleAccount.into());
+    }
+    let read_i64 = |offset: usize| i64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+    let read_i128 = |offset: usize| i128::from_le_bytes(data[offset..offset + 16].try_into().unwrap());
+
+    let publish_time = read_i64(SWITCHBOARD_LAST_UPDATE_OFFSET);
+    let age = clock.unix_timestamp.saturating_sub(publish_time);
+    if age < 0 || age as u64 > bank.max_price_age_secs {
+        msg!("Switchboard price for {} is {} seconds old, max {}", bank.mint_address, age, bank.max_price_age_secs);
+        return Err(ErrorCode::StaleOracle.into());
+    }
+
+    let price = i64::try_from(read_i128(SWITCHBOARD_RESULT_OFFSET) / SWITCHBOARD_SCALE_DOWN).map_err(|_| ErrorCode::OraclePriceInvalid)?;
+    let conf = u64::try_from(read_i128(SWITCHBOARD_STD_DEV_OFFSET) / SWITCHBOARD_SCALE_DOWN).map_err(|_| ErrorCode::OraclePriceInvalid)?;
+    Ok(Price {
+        price,
+        conf,
+        exponent: SWITCHBOARD_EXPONENT,
+        publish_time,
+    })
+}
+
+/// Clamps the price of a pegged bank to within its band of $1, unless the risk manager flagged the bank depegged
+fn apply_price_mode(bank: &Bank, mut price: Price) -> Price {
+    let PriceMode::Pegged { band_bps } = bank.price_mode else {
+        return price;
+    };
+    if bank.depegged || price.exponent > 0 {
+        return price;
+    }
+    let Some(one_usd) = 10i64.checked_pow(price.exponent.unsigned_abs()) else {
+        return price;
+    };
+    let band = (one_usd as i128 * band_bps as i128 / BPS_DENOMINATOR as i128) as i64;
+    price.price = price.price.clamp(one_usd - band, one_usd + band);
+    price
+}
+
+/// `price * 10^from_exponent` expressed with `to_exponent`, rounded down, none on overflow
+fn rescale_price(price: i64, from_exponent: i32, to_exponent: i32) -> Option<i64> {
+    let scale = 10i64.checked_pow(from_exponent.abs_diff(to_exponent))?;
+    if from_exponent >= to_exponent {
+        price.checked_mul(scale)
+    } else {
+        Some(price / scale)
+    }
+}
+
+fn validate_price(bank: &Bank, price: &Price) -> Result<()> {
+    if price.price <= 0 {
+        msg!("Oracle price for {} is {}, expected a positive price", bank.mint_address, price.price);
+        return Err(ErrorCode::OraclePriceInvalid.into());
+    }
+
+    let confidence_bps = price.conf as u128 * BPS_DENOMINATOR as u128 / price.price as u128;
+    if confidence_bps > bank.max_confidence_bps as u128 {
+        msg!("Price confidence {} bps exceeds max {} bps", confidence_bps, bank.max_confidence_bps);
+        return Err(ErrorCode::PriceConfidenceTooWide.into());
+    }
+    Ok(())
+}
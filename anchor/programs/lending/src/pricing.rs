@@ -0,0 +1,51 @@
+use anchor_lang::prelude::*;
+use pyth_solana_receiver_sdk::price_update::Price;
+use crate::constants::VALUE_DECIMALS;
+use crate::state::Bank;
+use crate::error::ErrorCode;
+
+// Banks hold mints with different decimals (6 for USDC, 9 for SOL) and Pyth prices come with
+// their own exponent, so token amounts are only comparable once converted to a common unit.
+// Every value used by borrow limits and liquidations is in USD scaled by 10^VALUE_DECIMALS,
+// computed from the mint decimals stored on the bank at init.
+
+/// USD value of `amount` of the bank's tokens, in `VALUE_DECIMALS`
+pub fn bank_value(bank: &Bank, amount: u64, price: &Price) -> Result<u128> {
+    token_value(amount, price, bank.mint_decimals)
+}
+
+/// Amount of the bank's tokens worth `value`, in `VALUE_DECIMALS`
+pub fn bank_amount(bank: &Bank, value: u128, price: &Price) -> Result<u64> {
+    value_to_amount(value, price, bank.mint_decimals)
+}
+
+/// USD value of `amount` base units of a token with `decimals` decimals, in `VALUE_DECIMALS`
+pub fn token_value(amount: u64, price: &Price, decimals: u8) -> Result<u128> {
+    let unit_price = u128::try_from(price.price).map_err(|_| ErrorCode::OraclePriceInvalid)?;
+    let raw = (amount as u128).checked_mul(unit_price).ok_or(ErrorCode::MathOverflow)?;
+    let exponent = price.exponent + VALUE_DECIMALS as i32 - decimals as i32;
+
+    if exponent >= 0 {
+        let scale = 10u128.checked_pow(exponent as u32).ok_or(ErrorCode::MathOverflow)?;
+        Ok(raw.checked_mul(scale).ok_or(ErrorCode::MathOverflow)?)
+    } else {
+        let scale = 10u128.checked_pow(exponent.unsigned_abs()).ok_or(ErrorCode::MathOverflow)?;
+        Ok(raw / scale)
+    }
+}
+
+/// Number of base units of a token worth `value` (in `VALUE_DECIMALS`), the inverse of `token_value`
+pub fn value_to_amount(value: u128, price: &Price, decimals: u8) -> Result<u64> {
+    let unit_price = u128::try_from(price.price).map_err(|_| ErrorCode::OraclePriceInvalid)?;
+    let exponent = decimals as i32 - price.exponent - VALUE_DECIMALS as i32;
+
+    let scaled = if exponent >= 0 {
+        let scale = 10u128.checked_pow(exponent as u32).ok_or(ErrorCode::MathOverflow)?;
+        value.checked_mul(scale).ok_or(ErrorCode::MathOverflow)?
+    } else {
+        let scale = 10u128.checked_pow(exponent.unsigned_abs()).ok_or(ErrorCode::MathOverflow)?;
+        value / scale
+    };
+
+    u64::try_from(scaled / unit_price).map_err(|_| ErrorCode::MathOverflow.into())
+}
@@ -0,0 +1,32 @@
+use anchor_lang::prelude::*;
+use crate::state::{Bank, User, UserPosition};
+use crate::safe_math::{bps_of, SafeMath};
+
+// Referrers earn `referral_fee_bps` of the interest paid by the borrowers they referred.
+// The fee is carved out of the protocol's reserve cut so depositors earn the same either way,
+// and is set aside in `total_referral_fees` until the referrer claims it from the position.
+
+/// Credits the position's referrer with their cut of the interest accrued on the position's
+/// debt since it was last settled, and adds the interest to the position's lifetime counter.
+/// Must be called before the position's borrow shares change, followed by `snapshot_debt` once they have.
+pub fn settle_referral_fees(bank: &mut Bank, user: &User, position: &mut UserPosition) -> Result<()> {
+    let debt = bank.borrow_amount_for(position.borrow_shares)?;
+    let interest = debt.saturating_sub(position.debt_snapshot);
+    position.pnl.interest_paid = position.pnl.interest_paid.saturating_add(interest);
+    if user.referrer == Pubkey::default() || bank.referral_fee_bps == 0 {
+        return Ok(());
+    }
+
+    let fee = bps_of(interest, bank.referral_fee_bps)?.min(bank.total_reserves);
+
+    bank.total_reserves = bank.total_reserves.safe_sub(fee)?;
+    bank.total_referral_fees = bank.total_referral_fees.safe_add(fee)?;
+    position.referral_fees_accrued = position.referral_fees_accrued.safe_add(fee)?;
+    Ok(())
+}
+
+/// Records the position's current debt as the base the next referral fee is charged from
+pub fn snapshot_debt(bank: &Bank, position: &mut UserPosition) -> Result<()> {
+    position.debt_snapshot = bank.borrow_amount_for(position.borrow_shares)?;
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use anchor_lang::prelude::*;
+use crate::constants::REWARD_INDEX_SCALE;
+use crate::state::{Bank, UserPosition};
+use crate::safe_math::{to_u64, SafeMath};
+use crate::wad::wad_mul;
+
+// Rewards are streamed to depositors at `reward_rate` tokens per second out of the funded
+// `rewards_remaining`. Each emission grows the bank's reward index (rewards per deposit share,
+// scaled by REWARD_INDEX_SCALE) and positions earn the index growth since they last settled.
+
+/// Emits rewards since the last update into the bank's reward index.
+/// Nothing is emitted while the bank has no deposit shares to split it between.
+pub fn accrue_rewards(bank: &mut Bank, now: i64) -> Result<()> {
+    let time_elapsed = now.saturating_sub(bank.rewards_last_updated);
+    if time_elapsed <= 0 {
+        return Ok(());
+    }
+
+    if bank.total_deposit_shares > 0 && bank.rewards_remaining > 0 {
+        let emitted = bank.reward_rate.saturating_mul(time_elapsed as u64).min(bank.rewards_remaining);
+        let index_growth = (emitted as u128)
+            .safe_mul(REWARD_INDEX_SCALE)?
+            .safe_div(bank.total_deposit_shares as u128)?;
+
+        bank.reward_index = bank.reward_index.safe_add(index_growth)?;
+        bank.rewards_remaining = bank.rewards_remaining.safe_sub(emitted)?;
+    }
+
+    bank.rewards_last_updated = now;
+    Ok(())
+}
+
+/// Credits the position with rewards earned by its deposit shares since it last settled,
+/// and adds the interest they earned to its lifetime counter.
+/// Must be called before the position's deposit shares change.
+pub fn settle_rewards(bank: &Bank, position: &mut UserPosition) -> Result<()> {
+    let index_growth = bank.reward_index.safe_sub(position.reward_index)?;
+    let earned = to_u64((position.deposit_shares as u128).safe_mul(index_growth)?.safe_div(REWARD_INDEX_SCALE)?)?;
+
+    position.rewards_accrued = position.rewards_accrued.safe_add(earned)?;
+    position.reward_index = bank.reward_index;
+
+    // Positions opened before the counter existed start counting from their first settlement,
+    // a supply index lowered by socialized bad debt earns nothing until it recovers
+    if position.supply_index_snapshot != 0 {
+        let supply_growth = bank.supply_index.saturating_sub(position.supply_index_snapshot);
+        let interest = to_u64(wad_mul(position.deposit_shares as u128, supply_growth)?)?;
+        position.pnl.interest_earned = position.pnl.interest_earned.saturating_add(interest);
+    }
+    if position.supply_index_snapshot == 0 || bank.supply_index > position.supply_index_snapshot {
+        position.supply_index_snapshot = bank.supply_index;
+    }
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use anchor_lang::prelude::*;
+use crate::constants::BPS_DENOMINATOR;
+use crate::error::ErrorCode;
+
+/// Checked arithmetic returning `MathOverflow` instead of wrapping or panicking
+pub trait SafeMath: Sized {
+    fn safe_add(self, rhs: Self) -> Result<Self>;
+    fn safe_sub(self, rhs: Self) -> Result<Self>;
+    fn safe_mul(self, rhs: Self) -> Result<Self>;
+    fn safe_div(self, rhs: Self) -> Result<Self>;
+}
+
+macro_rules! impl_safe_math {
+    ($($t:ty),*) => {$(
+        impl SafeMath for $t {
+            fn safe_add(self, rhs: Self) -> Result<Self> {
+                self.checked_add(rhs).ok_or_else(|| ErrorCode::MathOverflow.into())
+            }
+
+            fn safe_sub(self, rhs: Self) -> Result<Self> {
+                self.checked_sub(rhs).ok_or_else(|| ErrorCode::MathOverflow.into())
+            }
+
+            fn safe_mul(self, rhs: Self) -> Result<Self> {
+                self.checked_mul(rhs).ok_or_else(|| ErrorCode::MathOverflow.into())
+            }
+
+            fn safe_div(self, rhs: Self) -> Result<Self> {
+                self.checked_div(rhs).ok_or_else(|| ErrorCode::MathOverflow.into())
+            }
+        }
+    )*};
+}
+
+impl_safe_math!(u64, u128);
+
+/// `value * numerator / denominator` with a u128 intermediate, so the product can't overflow
+pub fn mul_div(value: u64, numerator: u64, denominator: u64, round_up: bool) -> Result<u64> {
+    if denominator == 0 {
+        return Err(ErrorCode::MathOverflow.into());
+    }
+    let product = value as u128 * numerator as u128;
+    let result = if round_up {
+        product.div_ceil(denominator as u128)
+    } else {
+        product / denominator as u128
+    };
+    u64::try_from(result).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+/// `bps` basis points of `amount`, rounded down
+pub fn bps_of(amount: u64, bps: u64) -> Result<u64> {
+    mul_div(amount, bps, BPS_DENOMINATOR, false)
+}
+
+/// Converts a u128 intermediate back to a token amount
+pub fn to_u64(value: u128) -> Result<u64> {
+    u64::try_from(value).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_at_boundary() {
+        assert_eq!(u64::MAX.safe_add(0).unwrap(), u64::MAX);
+        assert!(u64::MAX.safe_add(1).is_err());
+        assert!(u128::MAX.safe_add(1).is_err());
+    }
+
+    #[test]
+    fn sub_below_zero() {
+        assert_eq!(0u64.safe_sub(0).unwrap(), 0);
+        assert!(0u64.safe_sub(1).is_err());
+        assert_eq!(u64::MAX.safe_sub(u64::MAX).unwrap(), 0);
+    }
+
+    #[test]
+    fn mul_at_boundary() {
+        assert_eq!(u64::MAX.safe_mul(1).unwrap(), u64::MAX);
+        assert_eq!(u64::MAX.safe_mul(0).unwrap(), 0);
+        assert!(u64::MAX.safe_mul(2).is_err());
+    }
+
+    #[test]
+    fn div_by_zero() {
+        assert!(1u64.safe_div(0).is_err());
+        assert_eq!(0u64.safe_div(1).unwrap(), 0);
+    }
+
+    #[test]
+    fn mul_div_uses_wide_intermediate() {
+        // u64::MAX * u64::MAX overflows u64 but not the u128 intermediate
+        assert_eq!(mul_div(u64::MAX, u64::MAX, u64::MAX, false).unwrap(), u64::MAX);
+        assert_eq!(mul_div(u64::MAX, 2, 4, false).unwrap(), u64::MAX / 2);
+        assert!(mul_div(u64::MAX, 2, 1, false).is_err());
+    }
+
+    #[test]
+    fn mul_div_rounding() {
+        assert_eq!(mul_div(10, 1, 3, false).unwrap(), 3);
+        assert_eq!(mul_div(10, 1, 3, true).unwrap(), 4);
+        assert_eq!(mul_div(9, 1, 3, true).unwrap(), 3);
+        assert_eq!(mul_div(0, 5, 3, true).unwrap(), 0);
+    }
+
+    #[test]
+    fn max_deposit_share_conversion() {
+        // A u64::MAX deposit into a bank whose shares trade at 1:2 fits, at 2:1 it doesn't
+        assert_eq!(mul_div(u64::MAX, 1_000, 2_000, false).unwrap(), u64::MAX / 2);
+        assert!(mul_div(u64::MAX, 2_000, 1_000, false).is_err());
+        // Zero amounts convert to zero shares either way
+        assert_eq!(mul_div(0, u64::MAX, 1, true).unwrap(), 0);
+    }
+
+    #[test]
+    fn mul_div_zero_denominator() {
+        assert!(mul_div(1, 1, 0, false).is_err());
+        assert!(mul_div(0, 0, 0, true).is_err());
+    }
+
+    #[test]
+    fn bps_of_boundaries() {
+        assert_eq!(bps_of(u64::MAX, BPS_DENOMINATOR).unwrap(), u64::MAX);
+        assert_eq!(bps_of(u64::MAX, 0).unwrap(), 0);
+        assert_eq!(bps_of(0, BPS_DENOMINATOR).unwrap(), 0);
+        assert_eq!(bps_of(9_999, 1).unwrap(), 0);
+        assert_eq!(bps_of(10_000, 1).unwrap(), 1);
+    }
+
+    #[test]
+    fn to_u64_boundary() {
+        assert_eq!(to_u64(u64::MAX as u128).unwrap(), u64::MAX);
+        assert!(to_u64(u64::MAX as u128 + 1).is_err());
+    }
+}
@@ -1,4 +1,11 @@
 use anchor_lang::prelude::*;
+use crate::constants::{sub_account_seed, BPS_DENOMINATOR, MAX_DELEGATES, MAX_LAUNCH_STEPS, MAX_LIQUIDATION_BONUS, MAX_POSITIONS, MAX_SWAP_PROGRAMS, MAX_UNHEALTHY_USERS};
+use crate::error::ErrorCode;
+use crate::safe_math::{mul_div, SafeMath};
+use crate::wad::{wad_div, WAD};
+use crate::instructions::UpdateBankConfigParams;
+use crate::health::Health;
+use crate::math::{to_amount_round_down, to_amount_round_up, to_shares_round_down, to_shares_round_up, PositionRisk, RateCurve};
 
 #[account]
 #[derive(InitSpace)]
@@ -7,6 +14,14 @@ pub struct Bank {
     pub authority: Pubkey,
     /// Mint address of the assert
     pub mint_address: Pubkey,
+    /// Decimals of the mint, used to normalize values across banks
+    pub mint_decimals: u8,
+    /// Pyth price feed id of banks created before `oracle`, moved there by `migrate_account`
+    pub price_feed: [u8; 32],
+    /// Oldest price update accepted for the asset, in seconds
+    pub max_price_age_secs: u64,
+    /// Widest oracle confidence interval accepted, in basis points of the price
+    pub max_confidence_bps: u64,
     /// Current number of tokens in the bank
     pub total_deposits: u64,
     /// Current number of deposit shares in the bank
@@ -15,44 +30,1154 @@ pub struct Bank {
     pub total_borrowed: u64,
     /// Current number of borrowed shares in the bank
     pub total_borrowed_shares: u64,
-    /// LTV at which the loan is defined as under collateralized and can be liquidated 
+    /// LTV at which the loan is defined as under collateralized and can be liquidated, in basis points
     pub liquidation_threshold: u64,
-    /// Bonus percentage of collateral that can be liquidated
+    /// Bonus percentage of collateral that can be liquidated, in basis points
     pub liquidation_bonus: u64,
-    /// Percentage of collateral that can be liquidated
+    /// Max percentage of a borrower's debt that can be repaid in one liquidation, in basis points
     pub liquidation_close_factor: u64,
-    /// Max percentage of collateral that can be borrowed
+    /// Max percentage of collateral that can be borrowed, in basis points
     pub max_ltv: u64,
-    /// Last updated timestamp
+    /// Last updated timestamp, interest is accrued up to this point
     pub last_updated: i64,
-    pub interest_rate: u64,
+    /// Borrow rate at zero utilization, in basis points per year
+    pub base_rate: u64,
+    /// Rate added between zero and optimal utilization, in basis points per year
+    pub slope1: u64,
+    /// Rate added between optimal and full utilization, in basis points per year
+    pub slope2: u64,
+    /// Utilization at which the rate curve kinks, in basis points
+    pub optimal_utilization: u64,
+    /// Max tokens that can be deposited in the bank, zero for no cap
+    pub max_total_deposits: u64,
+    /// Max tokens that can be borrowed from the bank, zero for no cap
+    pub max_total_borrows: u64,
+    /// Share of borrower interest kept as protocol reserves, in basis points
+    pub reserve_factor: u64,
+    /// Tokens in the bank's vault owned by the protocol rather than depositors
+    pub total_reserves: u64,
+    /// Fee charged on flash loans, in basis points of the borrowed amount
+    pub flash_loan_fee: u64,
+    /// Flash loan currently outstanding within a transaction, zero otherwise
+    pub flash_loan_amount: u64,
+    /// Bitmask of paused operations, see the `PAUSE_*` constants
+    pub paused_operations: u8,
+    /// Collateral in an isolated bank only backs debt in this bank or in `isolated_debt_bank`
+    pub isolated: bool,
+    /// Bank whose debt isolated collateral may also back, default pubkey for none
+    pub isolated_debt_bank: Pubkey,
+    /// E-mode category of correlated assets the bank belongs to, zero for none
+    pub emode_category: u8,
+    /// Max LTV applied to users in the bank's e-mode category, in basis points
+    pub emode_max_ltv: u64,
+    /// Liquidation threshold applied to users in the bank's e-mode category, in basis points
+    pub emode_liquidation_threshold: u64,
+    /// Mint of the token rewarded to depositors, default pubkey until rewards are funded
+    pub reward_mint: Pubkey,
+    /// Reward tokens emitted to depositors per second
+    pub reward_rate: u64,
+    /// Funded reward tokens not yet emitted
+    pub rewards_remaining: u64,
+    /// Rewards emitted per deposit share, scaled by `REWARD_INDEX_SCALE`
+    pub reward_index: u128,
+    /// Timestamp rewards have been emitted up to
+    pub rewards_last_updated: i64,
+    /// Share of borrower interest paid to the borrower's referrer out of the reserve cut, in basis points
+    pub referral_fee_bps: u64,
+    /// Tokens in the bank's vault owed to referrers and not yet claimed
+    pub total_referral_fees: u64,
+    /// Smallest deposit accepted and smallest deposit position that can be left open, zero for no minimum
+    pub min_deposit_amount: u64,
+    /// Smallest borrow accepted and smallest debt that can be left open, zero for no minimum
+    pub min_borrow_amount: u64,
+    /// Bonus auction liquidations start at once a user is marked unhealthy, in basis points
+    pub auction_start_bonus: u64,
+    /// Bonus added per slot the user stays unhealthy, in basis points, capped at `liquidation_bonus`
+    pub auction_bonus_step: u64,
+    /// Tokens owed on fixed-rate loans, tracked apart from the variable rate debt in `total_borrowed`
+    pub total_fixed_borrowed: u64,
+    /// Premium over the variable borrow rate charged to lock in a fixed rate, in basis points per year
+    pub fixed_rate_premium: u64,
+    /// Rate added to the variable borrow rate on fixed loans past maturity, in basis points per year
+    pub fixed_penalty_rate: u64,
+    /// Longest fixed-rate loan term accepted, in slots, zero disables fixed-rate borrowing
+    pub max_fixed_term_slots: u64,
+    /// Whether a position may borrow the asset it deposits, enabling leveraged loops bounded by the max LTV
+    pub allow_self_borrow: bool,
+    /// Tokens per deposit share as a WAD, refreshed on every accrual
+    pub supply_index: u128,
+    /// Tokens of debt per borrow share as a WAD, refreshed on every accrual
+    pub borrow_index: u128,
+    /// Fraction of a token of variable interest not yet added to the totals, as a WAD
+    pub interest_remainder: u64,
+    /// Whether the bank was listed through `create_permissionless_bank` and is kept isolated until the admin promotes it
+    pub community: bool,
+    /// Delay between `request_withdraw` and `execute_withdraw`, in seconds, zero allows instant withdrawals
+    pub withdraw_cooldown_secs: i64,
+    /// Slots a user must stay flagged unhealthy by `flag_position` before the bank's collateral can be liquidated, zero for none
+    pub liquidation_grace_slots: u64,
+    /// Mint of the transferable receipt tokens for deposit shares held outside positions, default pubkey until `init_receipt_mint`
+    pub receipt_mint: Pubkey,
+    /// Oracle the asset is valued with
+    pub oracle: OracleSource,
+    /// Fee charged on every borrow, in basis points of the borrowed amount
+    pub origination_fee_bps: u64,
+    /// Whether the origination fee is withheld from the borrowed tokens rather than added to the debt
+    pub skim_origination_fee: bool,
+    /// Set by the risk manager when a pegged asset loses its peg, reverting the bank to raw oracle prices
+    pub depegged: bool,
+    /// Utilization withdrawals may not push the bank above, in basis points, zero for 100%, keeping liquidity for liquidations
+    pub max_utilization_for_withdraw: u64,
+    /// Which prices, oracle or its EMA, the bank's deposits and debt are valued at for borrow limits and liquidation checks
+    pub price_basis: PriceBasis,
+    /// Time for the EMA to fully catch up with the oracle price, in seconds
+    pub ema_window_secs: u64,
+    /// Exponential moving average of the oracle price, refreshed by `update_price_ema`
+    pub ema_price: i64,
+    /// Exponent of `ema_price`
+    pub ema_exponent: i32,
+    /// Timestamp of the last EMA update, zero before the first one
+    pub ema_updated_at: i64,
+    /// Whether the bank's asset can be borrowed, used as collateral or both
+    pub bank_type: BankType,
+    /// Whether deposits can't be withdrawn or borrowed against in the slot they were made, against oracle manipulation within a transaction
+    pub same_slot_deposit_lock: bool,
+    /// Floor of the variable borrow rate, in basis points per year
+    pub min_borrow_rate: u64,
+    /// Cap of the variable borrow rate, in basis points per year, zero for uncapped
+    pub max_borrow_rate: u64,
+    /// Variable borrow rate set by the emergency authority, applied instead of the curve until `rate_pinned_until`
+    pub pinned_borrow_rate: u64,
+    /// End of the emergency rate pin, zero for none
+    pub rate_pinned_until: i64,
+    /// Whether the curve rate was last clamped to the rate floor or cap, so clamp events are only emitted when this changes
+    pub rate_clamped: bool,
+    /// Space for new fields, which shrink it by their size so existing accounts keep their layout
+    pub padding: [u8; 51],
+    /// Layout version of the account, upgraded by `migrate_account`
+    pub version: u8,
+    // Fields below are appended to the layout, existing banks are grown by `migrate_account` before they load
+    /// Share of borrower interest set aside in the insurance fund, in basis points, paid out of the reserve cut
+    pub insurance_factor: u64,
+    /// Share of the insurance fund a single withdrawal may take, in basis points, zero to disallow withdrawals
+    pub max_insurance_withdrawal_bps: u64,
+    /// Tokens held in the bank's token account to cover bad debt before depositors are haircut
+    pub insurance_fund: u64,
+    /// Insurance fund withdrawal queued by the authority, zero for none
+    pub pending_insurance_withdrawal: u64,
+    /// Earliest time the queued insurance fund withdrawal can be executed
+    pub insurance_withdrawable_at: i64,
+    /// Who may deposit into and borrow from the bank, open to everyone unless it is permissioned
+    pub gate: BankGate,
+    /// Most the variable borrow rate moves towards the curve rate per second, in basis points per year, zero to follow the curve immediately
+    pub max_rate_change_per_sec: u64,
+    /// Variable borrow rate in effect since the last update, moving towards the curve rate at `max_rate_change_per_sec`
+    pub smoothed_borrow_rate: u64,
+    /// How borrower interest accrues, simple interest on the debt or compounded every slot
+    pub interest_mode: InterestMode,
+    /// Least time between interest accruals, shorter intervals leave it to the next interaction to save compute, zero to accrue every time
+    pub min_accrual_interval_secs: i64,
+    /// Share of the liquidation bonus on the bank's collateral kept as protocol reserves instead of paid to the liquidator, in basis points
+    pub liquidation_protocol_fee_bps: u64,
+    /// Fee on deposits added to the reserves, in basis points of the tokens received
+    pub deposit_fee_bps: u64,
+    /// Fee on withdrawals added to the reserves, in basis points of the tokens withdrawn
+    pub withdraw_fee_bps: u64,
+    /// Tighter caps the bank launches under until the risk manager graduates it
+    pub guarded_launch: GuardedLaunch,
+    /// Share of the oracle value deposits count for in the borrow and liquidation limits, in basis points, zero for 100%
+    pub collateral_weight_bps: u64,
+    /// Multiple of the oracle value debt counts for against the borrow and liquidation limits, in basis points, zero for 100%
+    pub debt_weight_bps: u64,
+    /// When the authority deprecated the bank, zero while it is listed, deprecated banks take no new deposits or borrows
+    pub deprecated_at: i64,
+    /// Tokens the bank's token account should hold by its own transfers, checked against the account after each one
+    pub vault_balance: u64,
+    /// Margin by which the fixed rate for a new loan may exceed an open loan's rate before anyone can move that loan to the variable rate, in basis points per year, zero disables rebalancing
+    pub fixed_rate_rebalance_bps: u64,
+    /// SOL per pool token of a `StakePool` oracle's pool with 9 decimals, cached by `refresh_stake_pool_rate`, zero until refreshed
+    pub stake_pool_rate: u64,
+    /// Epoch the stake pool last updated its balances in, `stake_pool_rate` is only used until the epoch after
+    pub stake_pool_rate_epoch: u64,
+    /// Fee on fixed loan debt repaid before maturity, in basis points of the debt repaid and paid into the reserves, zero for none
+    pub fixed_early_repay_fee_bps: u64,
+    /// Sequence number of the last event emitted about the bank, see `next_event_seq`
+    pub event_seq: u64,
+    /// Whether the risk manager set an `OverridePrice` for the bank, which valuations then read in place of its oracles
+    pub price_overridden: bool,
+    /// Space for new appended fields, which shrink it by their size
+    pub padding_ext: [u8; 4],
+    // Appended instead of taking over `padding`: zeroed, they read back as `None` and `PriceMode::Raw`, shorter than
+    // the space they reserve, and would move `version` and everything behind it for accounts from before them
+    /// Oracle tried when `oracle` can't provide a usable price, none for no fallback
+    pub fallback_oracle: Option<OracleSource>,
+    /// How the oracle price is turned into the price the bank is valued at
+    pub price_mode: PriceMode,
 }
 
+#[account]
+#[derive(InitSpace)]
+pub struct ProtocolConfig {
+    /// Authority over the protocol config and its roles
+    pub admin: Pubkey,
+    /// Admin nominated to take over, has to accept before the handoff takes effect
+    pub pending_admin: Pubkey,
+    /// Authority allowed to change bank risk parameters alongside each bank's own authority
+    pub risk_manager: Pubkey,
+    /// Authority allowed to pause and unpause bank operations during incidents
+    pub emergency_authority: Pubkey,
+    /// Owner of the token accounts protocol reserves are paid out to
+    pub fee_receiver: Pubkey,
+    /// Risk parameters new banks start with unless `init_bank` sets them
+    pub default_risk: RiskParams,
+    /// Delay between proposing and executing a bank config change, in seconds, zero for immediate updates
+    pub config_timelock_secs: i64,
+    /// Lamports paid to the fee receiver by `create_permissionless_bank`
+    pub listing_fee_lamports: u64,
+    /// Most banks a user may hold positions in, zero for the `MAX_POSITIONS` the account has room for
+    pub max_positions_per_user: u8,
+    /// How `distribute_fees` splits bank reserves, all zero until the admin sets them
+    pub fee_splits: FeeSplits,
+    /// Named risk presets `init_bank` may fall back to instead of `default_risk`, indexed by `RiskTier`, all zero until set
+    pub risk_tiers: [RiskParams; 3],
+    /// Total value, in USD with `VALUE_DECIMALS`, below which `clear_dust_position` may close an unhealthy user's positions, zero disables it
+    pub dust_threshold_value: u128,
+    /// Share of the liquidation limit borrowing keeps clear of, in basis points, so accruing interest
+    /// doesn't make a user who borrowed up to their limit liquidatable right away, zero for none
+    pub borrow_headroom_bps: u64,
+    /// Slot until which the protocol is deleveraging, blocking new borrows and withdrawals of collateral backing debt, zero when off
+    pub deleveraging_until_slot: u64,
+    /// Sequence number of the last protocol-wide event, see `next_event_seq`
+    pub event_seq: u64,
+    /// Which of a user's collateral liquidations may seize
+    pub liquidation_order: LiquidationOrder,
+    /// Programs `leverage_deposit` may swap borrowed tokens through
+    #[max_len(MAX_SWAP_PROGRAMS)]
+    pub swap_programs: Vec<Pubkey>,
+    pub bump: u8,
+}
+
+/// Shares of the reserves `distribute_fees` moves to each destination, in basis points adding up to 100%
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, InitSpace)]
+pub struct FeeSplits {
+    /// Share kept in the bank's insurance fund
+    pub insurance_bps: u64,
+    /// Share paid to the staking rewards vault
+    pub staking_bps: u64,
+    /// Share paid to the DAO treasury
+    pub treasury_bps: u64,
+    /// Owner of the token accounts the staking share is paid to
+    pub staking_vault: Pubkey,
+    /// Owner of the token accounts the treasury share is paid to
+    pub dao_treasury: Pubkey,
+}
+
+impl FeeSplits {
+    pub fn validate(&self) -> Result<()> {
+        let total = self.insurance_bps.saturating_add(self.staking_bps).saturating_add(self.treasury_bps);
+        if total != BPS_DENOMINATOR {
+            msg!("Fee splits add up to {}, expected {}", total, BPS_DENOMINATOR);
+            return Err(ErrorCode::InvalidFeeSplits.into());
+        }
+        Ok(())
+    }
+}
+
+/// Named risk preset of the protocol config a new bank can start from
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum RiskTier {
+    Conservative,
+    Standard,
+    Aggressive,
+}
+
+/// Risk parameters `init_bank` falls back to for every field passed as zero
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct RiskParams {
+    /// LTV at which positions become liquidatable, in basis points
+    pub liquidation_threshold: u64,
+    /// Max LTV a borrow may open at, in basis points
+    pub max_ltv: u64,
+    /// Max share of a borrower's debt repayable in one liquidation, in basis points
+    pub liquidation_close_factor: u64,
+    /// Extra collateral paid to liquidators on top of the repaid value, in basis points
+    pub liquidation_bonus: u64,
+    /// Oldest price update accepted, in seconds
+    pub max_price_age_secs: u64,
+    /// Widest Pyth confidence interval accepted, in basis points of the price
+    pub max_confidence_bps: u64,
+}
+
+impl RiskParams {
+    /// Same bounds `init_bank` applies to the fields, so every default is usable on its own
+    pub fn validate(&self) -> Result<()> {
+        if self.liquidation_threshold >= BPS_DENOMINATOR || self.max_ltv >= self.liquidation_threshold {
+            msg!("Default max LTV {} must be below liquidation threshold {}, below {}", self.max_ltv, self.liquidation_threshold, BPS_DENOMINATOR);
+            return Err(ErrorCode::InvalidRiskConfig.into());
+        }
+        if self.liquidation_close_factor == 0 || self.liquidation_close_factor > BPS_DENOMINATOR {
+            msg!("Default close factor {} must be between 1 and {}", self.liquidation_close_factor, BPS_DENOMINATOR);
+            return Err(ErrorCode::InvalidCloseFactor.into());
+        }
+        if self.liquidation_bonus > MAX_LIQUIDATION_BONUS {
+            msg!("Default liquidation bonus {} exceeds the maximum {}", self.liquidation_bonus, MAX_LIQUIDATION_BONUS);
+            return Err(ErrorCode::InvalidLiquidationBonus.into());
+        }
+        if self.max_price_age_secs == 0 || self.max_confidence_bps == 0 || self.max_confidence_bps > BPS_DENOMINATOR {
+            msg!("Default max price age {} and max confidence {} must be nonzero, confidence at most {}", self.max_price_age_secs, self.max_confidence_bps, BPS_DENOMINATOR);
+            return Err(ErrorCode::InvalidOracleConfig.into());
+        }
+        Ok(())
+    }
+}
+
+/// Where a bank reads its price from
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum OracleSource {
+    /// Pyth pull oracle price update for the feed id
+    Pyth { feed_id: [u8; 32] },
+    /// Switchboard On-Demand pull feed account
+    Switchboard { feed: Pubkey },
+    /// Price set by the admin for assets without a market feed, `price * 10^exponent` USD per token
+    Fixed { price: i64, exponent: i32 },
+    /// Pool token of an SPL stake pool, such as a liquid staking token, priced at the Pyth SOL/USD price update
+    /// times the pool's SOL per pool token, last cached on the bank by `refresh_stake_pool_rate`
+    StakePool { pool: Pubkey },
+}
+
+/// How a bank turns its oracle price into the price used for valuations
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum PriceMode {
+    /// Oracle price as reported
+    Raw,
+    /// Oracle price clamped to within `band_bps` of $1, so oracle noise on stablecoins can't trigger liquidations
+    Pegged { band_bps: u64 },
+}
+
+/// What a bank's asset can be used for
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum BankType {
+    /// Borrowable and usable as collateral
+    Standard,
+    /// Usable as collateral but never borrowed, for LP and governance tokens without a borrow market
+    CollateralOnly,
+    /// Borrowable, deposits only earn interest and never count as collateral
+    BorrowOnly,
+}
+
+/// Who may deposit into and borrow from a bank, checked against the owner of the user account credited
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum BankGate {
+    /// Permissionless
+    Open,
+    /// Only owners the bank authority added a `GateEntry` for
+    Whitelist,
+    /// Only owners holding a token of `mint`, such as a KYC credential or an NFT
+    TokenGate { mint: Pubkey },
+}
+
+/// Caps a newly listed bank launches under on top of its regular caps, until the risk manager graduates it
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, PartialEq, Eq, InitSpace)]
+pub struct GuardedLaunch {
+    /// Whether the caps apply, cleared by `graduate_bank`
+    pub active: bool,
+    /// Caps raised over time, each step in effect from its slot on and the first one also before it,
+    /// steps after the first left with a zero slot are unused
+    pub schedule: [LaunchCaps; MAX_LAUNCH_STEPS],
+}
+
+/// Caps of one step of a guarded launch, zero meaning uncapped
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, PartialEq, Eq, InitSpace)]
+pub struct LaunchCaps {
+    pub slot: u64,
+    /// Most tokens a single user account may have deposited
+    pub wallet_deposit_cap: u64,
+    pub deposit_cap: u64,
+    pub borrow_cap: u64,
+}
+
+impl GuardedLaunch {
+    /// Caps in effect at `slot`, `None` once the bank has graduated
+    pub fn caps_at(&self, slot: u64) -> Option<&LaunchCaps> {
+        if !self.active {
+            return None;
+        }
+        let mut caps = &self.schedule[0];
+        for step in &self.schedule[1..] {
+            if step.slot == 0 || step.slot > slot {
+                break;
+            }
+            caps = step;
+        }
+        Some(caps)
+    }
+
+    /// Checks the used steps come in slot order and only ever raise the caps
+    pub fn validate(&self) -> Result<()> {
+        let uncapped = |cap: u64| if cap == 0 { u64::MAX } else { cap };
+        let used = 1 + self.schedule[1..].iter().take_while(|step| step.slot != 0).count();
+        if self.schedule[used..].iter().any(|step| *step != LaunchCaps::default()) {
+            msg!("Guarded launch has {} steps in use followed by more", used);
+            return Err(ErrorCode::InvalidLaunchSchedule.into());
+        }
+        for pair in self.schedule[..used].windows(2) {
+            let (prev, next) = (&pair[0], &pair[1]);
+            if next.slot <= prev.slot
+                || uncapped(next.wallet_deposit_cap) < uncapped(prev.wallet_deposit_cap)
+                || uncapped(next.deposit_cap) < uncapped(prev.deposit_cap)
+                || uncapped(next.borrow_cap) < uncapped(prev.borrow_cap)
+            {
+                msg!("Guarded launch step at slot {} must come after slot {} and not lower its caps", next.slot, prev.slot);
+                return Err(ErrorCode::InvalidLaunchSchedule.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How a bank's borrower interest accrues between updates
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum InterestMode {
+    /// The annual rate applied linearly to the debt over each accrual period
+    Simple,
+    /// The annual rate split across the slots in a year and compounded every slot
+    Compound,
+}
+
+/// Which collateral liquidations seize from a user holding several
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, InitSpace)]
+pub enum LiquidationOrder {
+    /// Any of the user's collateral, the liquidator picks it through the collateral bank they pass
+    #[default]
+    LiquidatorChoice,
+    /// The collateral with the lowest liquidation weight first, see `math::lowest_weight_collateral`
+    LowestWeightFirst,
+}
+
+/// Which prices a bank's deposits and debt are valued at, the EMA resists short-lived manipulation of the oracle
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum PriceBasis {
+    /// Oracle price for everything
+    Spot,
+    /// EMA for borrow limits, liquidation checks take whichever of the oracle price and EMA favors the user
+    Ema,
+    /// Whichever of the oracle price and EMA is less favorable to the user for borrow limits,
+    /// the more favorable one for liquidation checks
+    Conservative,
+}
+
+/// Oracle registry entry, banks for `mint` can only be created with the approved oracle
+#[account]
+#[derive(InitSpace)]
+pub struct ApprovedOracle {
+    pub mint: Pubkey,
+    /// Oracle approved for the mint
+    pub oracle: OracleSource,
+    pub bump: u8,
+}
+
+/// Emergency price set by the risk manager for a bank whose oracles have failed. While it is set, valuations
+/// take it in place of a price account of the bank's oracles, up to `expiry_slot`
+#[account]
+#[derive(InitSpace)]
+pub struct OverridePrice {
+    /// Mint of the bank the override prices
+    pub mint: Pubkey,
+    /// `price * 10^exponent` USD per token
+    pub price: i64,
+    pub exponent: i32,
+    /// Slot the override stops applying at
+    pub expiry_slot: u64,
+    /// Risk manager who set the override, refunded its rent when it is cleared
+    pub set_by: Pubkey,
+    pub bump: u8,
+}
+
+/// Whitelist entry letting `wallet` deposit into and borrow from a bank gated by `BankGate::Whitelist`
+#[account]
+#[derive(InitSpace)]
+pub struct GateEntry {
+    pub bank: Pubkey,
+    pub wallet: Pubkey,
+    pub bump: u8,
+}
+
+/// Users reported liquidatable by `report_unhealthy`, so liquidators don't have to scan every user account
+#[account]
+#[derive(InitSpace)]
+pub struct UnhealthyRegistry {
+    #[max_len(MAX_UNHEALTHY_USERS)]
+    pub entries: Vec<UnhealthyEntry>,
+    pub bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct UnhealthyEntry {
+    /// User account reported liquidatable
+    pub user: Pubkey,
+    /// Health factor of the user when reported, in basis points
+    pub health_factor: u64,
+    /// Slot the user was last reported at
+    pub reported_slot: u64,
+}
+
+impl UnhealthyRegistry {
+    /// Adds or refreshes the user's entry. Once full, the entry with the highest health factor is replaced
+    /// when the new one is less healthy, so the registry keeps the users most in need of liquidation.
+    /// Returns whether the user is in the registry afterwards.
+    pub fn report(&mut self, entry: UnhealthyEntry) -> bool {
+        if let Some(existing) = self.entries.iter_mut().find(|e| e.user == entry.user) {
+            *existing = entry;
+            return true;
+        }
+        if self.entries.len() < MAX_UNHEALTHY_USERS {
+            self.entries.push(entry);
+            return true;
+        }
+        match self.entries.iter_mut().max_by_key(|e| e.health_factor) {
+            Some(healthiest) if healthiest.health_factor > entry.health_factor => {
+                *healthiest = entry;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn remove(&mut self, user: Pubkey) {
+        self.entries.retain(|e| e.user != user);
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct ProtocolStats {
+    /// User accounts ever created
+    pub total_users: u64,
+    /// Banks ever created
+    pub total_banks: u64,
+}
+
+/// Running totals of a bank's activity, in the bank's tokens unless noted otherwise
+#[account]
+#[derive(InitSpace)]
+pub struct BankStats {
+    /// Bank the stats are kept for
+    pub bank: Pubkey,
+    pub cumulative_deposits: u128,
+    pub cumulative_withdrawals: u128,
+    pub cumulative_borrows: u128,
+    pub cumulative_repays: u128,
+    /// Number of liquidations repaying debt in the bank
+    pub liquidation_count: u64,
+    /// Debt repaid by liquidators
+    pub cumulative_liquidated_debt: u128,
+    /// Collateral seized from the bank by liquidators, including the bonus
+    pub cumulative_seized_collateral: u128,
+    pub cumulative_flash_loan_fees: u128,
+    pub cumulative_reserves_withdrawn: u128,
+    /// Positions ever opened in the bank, a user closing and reopening their position is counted again
+    pub unique_users: u64,
+    /// Timestamp of the last update
+    pub last_updated: i64,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct PendingBankConfig {
+    /// Bank the queued change applies to
+    pub bank: Pubkey,
+    /// Authority that proposed the change, receives the rent back once it's executed or cancelled
+    pub proposer: Pubkey,
+    /// Queued bank settings
+    pub params: UpdateBankConfigParams,
+    /// Timestamp from which the change can be executed
+    pub executable_at: i64,
+}
+
+/// A user's account across all banks, balances in each asset live in the user's position PDA for the bank's mint
 #[account]
 #[derive(InitSpace)]
 pub struct User {
     /// Pubkey of the user's wallet 
     pub owner: Pubkey,
-    /// User's deposited tokens in the SOL bank
-    pub deposited_sol: u64,
-    /// User's deposited shares in the SOL bank 
-    pub deposited_sol_shares: u64,
-    /// User's borrowed tokens in the SOL bank
-    pub borrowed_sol: u64,
-    /// User's borrowed shares in the SOL bank
-    pub borrowed_sol_shares: u64, 
-    /// User's deposited tokens in the USDC bank
-    pub deposited_usdc: u64,
-    /// User's deposited shares in the USDC bank 
-    pub deposited_usdc_shares: u64, 
-    /// User's borrowed tokens in the USDC bank
-    pub borrowed_usdc: u64,
-    /// User's borrowed shares in the USDC bank
-    pub borrowed_usdc_shares: u64, 
-    /// USDC mint address
-    pub usdc_address: Pubkey,
-    /// Current health factor of the user
+    /// Banks the user has an open position in, health checks walk them in this order.
+    /// The account only has room for as many as it was last grown to, see `User::space_for`
+    #[max_len(MAX_POSITIONS)]
+    pub positions: Vec<Pubkey>,
+    /// Frontend or partner that referred the user, earns a share of their borrow interest, default pubkey for none
+    pub referrer: Pubkey,
+    /// Wallets allowed to borrow against the user's collateral, debt accrues to the user
+    #[max_len(MAX_DELEGATES)]
+    pub delegations: Vec<Delegation>,
+    /// E-mode category the user opted into, zero for none
+    pub emode_category: u8,
+    /// Current health factor of the user, in basis points
     pub health_factor: u64,
+    /// Slot the user was flagged liquidatable at by `flag_position`, zero while healthy
+    pub unhealthy_since_slot: u64,
     /// Last updated timestamp
     pub last_updated: i64,
-}
\ No newline at end of file
+    /// Mint of the NFT the account was tokenized into by `tokenize_account`, default pubkey while untokenized
+    pub position_nft: Pubkey,
+    /// Health aggregated by the last full health recalculation, kept so accounts with many positions can be refreshed across transactions
+    pub last_health_checkpoint: HealthCheckpoint,
+    /// Number of banks the user holds a position in, kept equal to the length of `positions`
+    pub active_positions: u8,
+    /// Index of the account among its owner's sub-accounts, each with its own positions and health, zero for the main account
+    pub sub_account_id: u16,
+    /// Sequence number of the last event emitted about the user account alone, see `next_event_seq`
+    pub event_seq: u64,
+    /// Space for new fields, which shrink it by their size so existing accounts keep their layout
+    pub padding: [u8; 29],
+    /// Layout version of the account, upgraded by `migrate_account`
+    pub version: u8,
+}
+
+/// Aggregate of a full health recalculation, in USD with `VALUE_DECIMALS`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct HealthCheckpoint {
+    pub collateral_value: u128,
+    pub borrow_limit: u128,
+    pub liquidation_limit: u128,
+    pub debt_value: u128,
+    pub liquidation_debt_value: u128,
+    /// Slot the oldest of the positions was valued at, zero before the first recalculation
+    pub slot: u64,
+}
+
+/// Positions of a user valued so far by `refresh_positions`, one page of positions per transaction
+#[account]
+#[derive(InitSpace)]
+pub struct HealthRefresh {
+    pub user: Pubkey,
+    /// Valued positions, in the order of the user's positions
+    #[max_len(MAX_POSITIONS)]
+    pub positions: Vec<PositionRisk>,
+    /// Slot the first page was valued at
+    pub started_slot: u64,
+    pub bump: u8,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct FixedLoan {
+    /// User account the loan belongs to
+    pub user: Pubkey,
+    /// Bank the loan was borrowed from
+    pub bank: Pubkey,
+    /// Id picked by the user to tell their loans in the same bank apart
+    pub loan_id: u64,
+    /// Tokens owed, principal plus interest accrued up to `last_updated`
+    pub debt: u64,
+    /// Rate locked in at borrow time, in basis points per year
+    pub rate: u64,
+    /// Slot from which the loan accrues the bank's variable rate plus its penalty rate instead
+    pub maturity_slot: u64,
+    /// Last updated timestamp, interest is accrued up to this point
+    pub last_updated: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct Delegation {
+    /// Wallet allowed to sign borrows for the user
+    pub delegate: Pubkey,
+    /// Bank the delegate may borrow from
+    pub bank: Pubkey,
+    /// Tokens the delegate may still borrow, decreases with every borrow
+    pub allowance: u64,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct UserPosition {
+    /// User account the position belongs to
+    pub user: Pubkey,
+    /// Bank the position is held in
+    pub bank: Pubkey,
+    /// User's deposited shares in the bank, redeemable for a growing amount of tokens as interest accrues
+    pub deposit_shares: u64,
+    /// User's borrowed shares in the bank, owing a growing amount of tokens as interest accrues
+    pub borrow_shares: u64,
+    /// Whether the position's deposits count as collateral in health checks
+    pub collateral_enabled: bool,
+    /// Bank reward index the position last settled its rewards at
+    pub reward_index: u128,
+    /// Rewards earned and not yet claimed
+    pub rewards_accrued: u64,
+    /// Tokens owed on the user's fixed-rate loans in the bank, as of each loan's last accrual
+    pub fixed_debt: u64,
+    /// Debt of the position when referral fees were last settled
+    pub debt_snapshot: u64,
+    /// Referral fees earned on the position's interest and not yet claimed by the referrer
+    pub referral_fees_accrued: u64,
+    /// Tokens requested through `request_withdraw`, still earning interest and counted as collateral until executed
+    pub pending_withdraw_amount: u64,
+    /// When the pending withdrawal was requested
+    pub withdraw_requested_at: i64,
+    /// Bank borrow index at `last_updated`, the rate paid since is the index growth over the time elapsed
+    pub borrow_index_snapshot: u128,
+    /// Last updated timestamp
+    pub last_updated: i64,
+    /// Slot deposit shares were last credited to the position at
+    pub last_deposit_slot: u64,
+    /// Bank supply index the deposit's interest was last settled at, zero until the first settlement
+    pub supply_index_snapshot: u128,
+    /// Lifetime interest and fees of the position
+    pub pnl: PositionPnl,
+    /// Space for new fields, which shrink it by their size so existing accounts keep their layout
+    pub padding: [u8; 24],
+}
+
+/// Lifetime counters of a position, in bank tokens
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct PositionPnl {
+    /// Interest earned by the position's deposits
+    pub interest_earned: u64,
+    /// Interest accrued on the position's variable debt
+    pub interest_paid: u64,
+    /// Deposit, withdrawal and origination fees charged to the position
+    pub fees_paid: u64,
+}
+
+// Deposits and borrows are tracked as shares of the bank totals. Accrued interest grows
+// `total_deposits` and `total_borrowed` while shares stay constant, so every share is
+// worth proportionally more and interest is split between users by their share count.
+impl Bank {
+    /// Sequence number of the next event about the bank, one past the last so indexers can tell a dropped log
+    /// from a quiet bank
+    pub fn next_event_seq(&mut self) -> u64 {
+        self.event_seq += 1;
+        self.event_seq
+    }
+
+    pub fn require_not_paused(&self, operation: u8) -> Result<()> {
+        if self.paused_operations & operation != 0 {
+            msg!("Operation {:#04x} is paused for {}, paused operations {:#04x}", operation, self.mint_address, self.paused_operations);
+            return Err(ErrorCode::BankPaused.into());
+        }
+        Ok(())
+    }
+
+    /// Rejects instant withdrawals from banks that only release deposits through `execute_withdraw`
+    pub fn require_no_withdraw_cooldown(&self) -> Result<()> {
+        if self.withdraw_cooldown_secs > 0 {
+            msg!("Withdrawals from {} must be requested {} seconds in advance", self.mint_address, self.withdraw_cooldown_secs);
+            return Err(ErrorCode::WithdrawCooldownRequired.into());
+        }
+        Ok(())
+    }
+
+    /// Checks the position doesn't end up holding both deposits and debt in the bank unless self-borrowing is allowed.
+    /// Looping through the same bank is still bounded by the health check, each round borrows at most the max LTV
+    /// of the round before, so leverage converges to 1 / (1 - max LTV).
+    pub fn require_self_borrow_allowed(&self, position: &UserPosition, depositing: bool) -> Result<()> {
+        let conflicting = if depositing {
+            position.borrow_shares > 0 || position.fixed_debt > 0
+        } else {
+            position.deposit_shares > 0
+        };
+        if conflicting && !self.allow_self_borrow {
+            msg!("Position in {} already holds {}", self.mint_address, if depositing { "debt" } else { "deposits" });
+            return Err(ErrorCode::SelfBorrowNotAllowed.into());
+        }
+        Ok(())
+    }
+
+    /// Variable and fixed-rate debt owed to the bank
+    pub fn total_debt(&self) -> u64 {
+        self.total_borrowed.saturating_add(self.total_fixed_borrowed)
+    }
+
+    /// Fails when the bank's debt exceeds its deposits, or its `max_utilization_for_withdraw` share of them.
+    /// Checked after a withdrawal is taken out of the totals.
+    pub fn require_withdraw_liquidity(&self) -> Result<()> {
+        let max_utilization = match self.max_utilization_for_withdraw {
+            0 => BPS_DENOMINATOR,
+            max_utilization => max_utilization,
+        };
+        let debt = self.total_debt() as u128;
+        if debt * BPS_DENOMINATOR as u128 > self.total_deposits as u128 * max_utilization as u128 {
+            msg!(
+                "Withdrawal would leave {} debt against {} deposits in {}, above {} bps utilization",
+                debt, self.total_deposits, self.mint_address, max_utilization,
+            );
+            return Err(ErrorCode::InsufficientLiquidity.into());
+        }
+        Ok(())
+    }
+
+    /// Checks a deposit or the deposit left in a position is either zero or at least the bank's minimum
+    pub fn require_deposit_minimum(&self, amount: u64) -> Result<()> {
+        if amount > 0 && amount < self.min_deposit_amount {
+            msg!("Deposit {} is below the minimum {}", amount, self.min_deposit_amount);
+            return Err(ErrorCode::DepositBelowMinimum.into());
+        }
+        Ok(())
+    }
+
+    /// Share of the oracle value deposits count for in the health limits, in basis points
+    pub fn collateral_weight(&self) -> u64 {
+        if self.collateral_weight_bps == 0 { BPS_DENOMINATOR } else { self.collateral_weight_bps }
+    }
+
+    /// Multiple of the oracle value debt counts for against the health limits, in basis points
+    pub fn debt_weight(&self) -> u64 {
+        if self.debt_weight_bps == 0 { BPS_DENOMINATOR } else { self.debt_weight_bps }
+    }
+
+    /// Whether deposits in the bank can count as collateral
+    pub fn is_collateral(&self) -> bool {
+        self.bank_type != BankType::BorrowOnly
+    }
+
+    /// Whether the position's deposits are locked for the rest of `slot`, banks with a same-slot deposit lock
+    /// don't let deposits be withdrawn or borrowed against in the slot they were made
+    pub fn is_deposit_locked(&self, position: &UserPosition, slot: u64) -> bool {
+        self.same_slot_deposit_lock && position.last_deposit_slot == slot
+    }
+
+    /// Rejects taking deposit shares out of a position in the slot they were deposited at
+    pub fn require_deposit_unlocked(&self, position: &UserPosition, slot: u64) -> Result<()> {
+        if self.is_deposit_locked(position, slot) {
+            msg!("Deposits in {} can't be withdrawn in slot {}, the slot they were made", self.mint_address, slot);
+            return Err(ErrorCode::DepositLocked.into());
+        }
+        Ok(())
+    }
+
+    /// Rejects deposits and borrows that don't go through the gate checked by `deposit`, `deposit_with_init` and `borrow`
+    pub fn require_ungated(&self) -> Result<()> {
+        if self.gate != BankGate::Open {
+            msg!("Bank {} is permissioned, use deposit or borrow with a gate pass", self.mint_address);
+            return Err(ErrorCode::GatePassRequired.into());
+        }
+        Ok(())
+    }
+
+    /// Rejects borrows from collateral-only banks
+    pub fn require_borrowable(&self) -> Result<()> {
+        if self.bank_type == BankType::CollateralOnly {
+            msg!("Bank {} is collateral-only and can't be borrowed from", self.mint_address);
+            return Err(ErrorCode::BankNotBorrowable.into());
+        }
+        Ok(())
+    }
+
+    /// Checks a borrow or the debt left in a position is either zero or at least the bank's minimum
+    pub fn require_borrow_minimum(&self, amount: u64) -> Result<()> {
+        if amount > 0 && amount < self.min_borrow_amount {
+            msg!("Debt {} is below the minimum {}", amount, self.min_borrow_amount);
+            return Err(ErrorCode::BorrowBelowMinimum.into());
+        }
+        Ok(())
+    }
+
+    /// Deposit fee on `amount` tokens received, rounded up
+    pub fn deposit_fee(&self, amount: u64) -> Result<u64> {
+        mul_div(amount, self.deposit_fee_bps, BPS_DENOMINATOR, true)
+    }
+
+    /// Withdrawal fee on `amount` tokens withdrawn, rounded up
+    pub fn withdraw_fee(&self, amount: u64) -> Result<u64> {
+        mul_div(amount, self.withdraw_fee_bps, BPS_DENOMINATOR, true)
+    }
+
+    /// Rejects new deposits and borrows once the bank is deprecated
+    pub fn require_not_deprecated(&self) -> Result<()> {
+        if self.deprecated_at != 0 {
+            msg!("Bank {} was deprecated at {}", self.mint_address, self.deprecated_at);
+            return Err(ErrorCode::BankDeprecated.into());
+        }
+        Ok(())
+    }
+
+    /// Checks depositing `amount` more keeps the bank within its deposit cap and any guarded launch cap, zero meaning uncapped,
+    /// and that the bank isn't deprecated
+    pub fn require_deposit_cap(&self, amount: u64) -> Result<()> {
+        self.require_not_deprecated()?;
+        let total = self.total_deposits.saturating_add(amount);
+        if self.max_total_deposits > 0 && total > self.max_total_deposits {
+            msg!("Deposits would reach {}, cap is {}", total, self.max_total_deposits);
+            return Err(ErrorCode::DepositCapExceeded.into());
+        }
+        if let Some(caps) = self.launch_caps()? {
+            if caps.deposit_cap > 0 && total > caps.deposit_cap {
+                msg!("Deposits would reach {}, guarded launch cap is {}", total, caps.deposit_cap);
+                return Err(ErrorCode::DepositCapExceeded.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks a user account holding `deposited` tokens after a deposit stays within the guarded launch's wallet cap
+    pub fn require_wallet_deposit_cap(&self, deposited: u64) -> Result<()> {
+        if let Some(caps) = self.launch_caps()? {
+            if caps.wallet_deposit_cap > 0 && deposited > caps.wallet_deposit_cap {
+                msg!("User deposits would reach {}, guarded launch wallet cap is {}", deposited, caps.wallet_deposit_cap);
+                return Err(ErrorCode::DepositCapExceeded.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Guarded launch caps in effect now, `None` if the bank isn't in a guarded launch
+    fn launch_caps(&self) -> Result<Option<&LaunchCaps>> {
+        if !self.guarded_launch.active {
+            return Ok(None);
+        }
+        Ok(self.guarded_launch.caps_at(Clock::get()?.slot))
+    }
+
+    /// Checks borrowing `amount` more keeps the bank's variable and fixed-rate debt within its borrow cap and any guarded launch cap,
+    /// zero meaning uncapped, and that the bank isn't deprecated
+    pub fn require_borrow_cap(&self, amount: u64) -> Result<()> {
+        self.require_not_deprecated()?;
+        let total = self.total_debt().saturating_add(amount);
+        if self.max_total_borrows > 0 && total > self.max_total_borrows {
+            msg!("Borrows would reach {}, cap is {}", total, self.max_total_borrows);
+            return Err(ErrorCode::BorrowCapExceeded.into());
+        }
+        if let Some(caps) = self.launch_caps()? {
+            if caps.borrow_cap > 0 && total > caps.borrow_cap {
+                msg!("Borrows would reach {}, guarded launch cap is {}", total, caps.borrow_cap);
+                return Err(ErrorCode::BorrowCapExceeded.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Deposit shares minted for depositing `amount` tokens, rounded down
+    pub fn deposit_shares_minted(&self, amount: u64) -> Result<u64> {
+        to_shares_round_down(amount, self.total_deposits, self.total_deposit_shares)
+    }
+
+    /// Deposit shares burned for withdrawing `amount` tokens, rounded up
+    pub fn deposit_shares_burned(&self, amount: u64) -> Result<u64> {
+        to_shares_round_up(amount, self.total_deposits, self.total_deposit_shares)
+    }
+
+    /// Tokens redeemable for `shares` deposit shares, rounded down
+    pub fn deposit_amount_for(&self, shares: u64) -> Result<u64> {
+        to_amount_round_down(shares, self.total_deposits, self.total_deposit_shares)
+    }
+
+    /// Deposit shares burned for withdrawing `amount` tokens out of a position holding `position_shares`,
+    /// rounded up so a withdrawal can never take more than the shares are worth, unless the full position is withdrawn
+    pub fn deposit_shares_withdrawn(&self, amount: u64, position_shares: u64) -> Result<u64> {
+        if amount == self.deposit_amount_for(position_shares)? {
+            return Ok(position_shares);
+        }
+        Ok(self.deposit_shares_burned(amount)?.min(position_shares))
+    }
+
+    /// Borrow shares minted for borrowing `amount` tokens, rounded up
+    pub fn borrow_shares_minted(&self, amount: u64) -> Result<u64> {
+        to_shares_round_up(amount, self.total_borrowed, self.total_borrowed_shares)
+    }
+
+    /// Borrow shares burned for repaying `amount` tokens of debt, rounded down
+    pub fn borrow_shares_burned(&self, amount: u64) -> Result<u64> {
+        to_shares_round_down(amount, self.total_borrowed, self.total_borrowed_shares)
+    }
+
+    /// Liquidation bonus offered by the auction `elapsed_slots` after the user was marked unhealthy
+    pub fn auction_bonus(&self, elapsed_slots: u64) -> u64 {
+        self.auction_bonus_step
+            .saturating_mul(elapsed_slots)
+            .saturating_add(self.auction_start_bonus)
+            .min(self.liquidation_bonus)
+    }
+
+    /// Borrow rate curve configured on the bank, with its rate floor and cap
+    pub fn rate_curve(&self) -> RateCurve {
+        RateCurve {
+            base_rate: self.base_rate,
+            slope1: self.slope1,
+            slope2: self.slope2,
+            optimal_utilization: self.optimal_utilization,
+            min_rate: self.min_borrow_rate,
+            max_rate: self.max_borrow_rate,
+        }
+    }
+
+    /// Whether the grace window of a user flagged unhealthy at `unhealthy_since_slot` is over at `slot`.
+    /// Users must be flagged first when the bank has a grace window, so a single wick can't liquidate them.
+    pub fn liquidation_grace_elapsed(&self, unhealthy_since_slot: u64, slot: u64) -> bool {
+        self.liquidation_grace_slots == 0
+            || (unhealthy_since_slot != 0 && slot >= unhealthy_since_slot.saturating_add(self.liquidation_grace_slots))
+    }
+
+    /// Tokens per deposit share as a WAD, one while the bank has no deposits
+    pub fn deposit_exchange_rate(&self) -> Result<u128> {
+        if self.total_deposit_shares == 0 {
+            return Ok(WAD);
+        }
+        wad_div(self.total_deposits as u128, self.total_deposit_shares as u128)
+    }
+
+    /// Tokens of debt per borrow share as a WAD, one while the bank has no debt
+    pub fn borrow_exchange_rate(&self) -> Result<u128> {
+        if self.total_borrowed_shares == 0 {
+            return Ok(WAD);
+        }
+        wad_div(self.total_borrowed as u128, self.total_borrowed_shares as u128)
+    }
+
+    /// Tokens of debt owed for `shares` borrow shares, rounded up
+    pub fn borrow_amount_for(&self, shares: u64) -> Result<u64> {
+        to_amount_round_up(shares, self.total_borrowed, self.total_borrowed_shares)
+    }
+}
+
+impl ProtocolConfig {
+    /// Sequence number of the next protocol-wide event, one past the last
+    pub fn next_event_seq(&mut self) -> u64 {
+        self.event_seq += 1;
+        self.event_seq
+    }
+
+    /// Whether the protocol is in deleveraging mode at `slot`, the mode expires on its own at `deleveraging_until_slot`
+    pub fn is_deleveraging(&self, slot: u64) -> bool {
+        slot < self.deleveraging_until_slot
+    }
+
+    /// Fails while the protocol is deleveraging, for instructions taking on new debt
+    pub fn require_not_deleveraging(&self) -> Result<()> {
+        let slot = Clock::get()?.slot;
+        if self.is_deleveraging(slot) {
+            msg!("Protocol is deleveraging until slot {}, now {}", self.deleveraging_until_slot, slot);
+            return Err(ErrorCode::DeleveragingMode.into());
+        }
+        Ok(())
+    }
+
+    /// Most banks a user may hold positions in
+    pub fn max_positions(&self) -> usize {
+        match self.max_positions_per_user {
+            0 => MAX_POSITIONS,
+            max => (max as usize).min(MAX_POSITIONS),
+        }
+    }
+
+    /// Risk parameters a new bank falls back to, those of `tier` if given or the protocol default
+    pub fn risk_defaults(&self, tier: Option<RiskTier>) -> Result<&RiskParams> {
+        let Some(tier) = tier else {
+            return Ok(&self.default_risk);
+        };
+        let params = &self.risk_tiers[tier as usize];
+        if params.liquidation_threshold == 0 {
+            msg!("Risk tier {} has not been set", tier as u8);
+            return Err(ErrorCode::RiskTierNotSet.into());
+        }
+        Ok(params)
+    }
+
+    /// Rejects opening a new position in `bank` once the user holds positions in the protocol's maximum number of banks
+    pub fn require_position_capacity(&self, user: &User, bank: &Pubkey) -> Result<()> {
+        if !user.positions.contains(bank) && user.active_positions as usize >= self.max_positions() {
+            msg!("User already holds {} of {} positions", user.active_positions, self.max_positions());
+            return Err(ErrorCode::MaxPositionsReached.into());
+        }
+        Ok(())
+    }
+}
+
+impl User {
+    /// Sequence number of the next event about the user account that names no bank
+    pub fn next_event_seq(&mut self) -> u64 {
+        self.event_seq += 1;
+        self.event_seq
+    }
+
+    /// Seed the account adds after its owner, see `sub_account_seed`
+    pub fn sub_account_seed(&self) -> Vec<u8> {
+        sub_account_seed(self.sub_account_id)
+    }
+
+    /// Account size with room for `capacity` positions, `INIT_SPACE` has room for `MAX_POSITIONS`
+    pub const fn space_for(capacity: usize) -> usize {
+        8 + Self::INIT_SPACE - (MAX_POSITIONS - capacity) * 32
+    }
+
+    /// Whether the account was tokenized into a position NFT and is controlled by its holder
+    pub fn is_tokenized(&self) -> bool {
+        self.position_nft != Pubkey::default()
+    }
+
+    /// Records an open position in `bank`, keeping the existing order of positions
+    pub fn add_position(&mut self, bank: Pubkey) -> Result<()> {
+        if self.positions.contains(&bank) {
+            return Ok(());
+        }
+        if self.positions.len() >= MAX_POSITIONS {
+            msg!("User already holds {} of {} positions", self.positions.len(), MAX_POSITIONS);
+            return Err(ErrorCode::MaxPositionsReached.into());
+        }
+        self.positions.push(bank);
+        self.active_positions = self.positions.len() as u8;
+        Ok(())
+    }
+
+    /// Caches the result of a health recalculation that started at `slot`
+    pub fn record_health_checkpoint(&mut self, health: &Health, slot: u64) {
+        self.health_factor = health.health_factor();
+        self.last_health_checkpoint = HealthCheckpoint {
+            collateral_value: health.collateral_value,
+            borrow_limit: health.borrow_limit,
+            liquidation_limit: health.liquidation_limit,
+            debt_value: health.debt_value,
+            liquidation_debt_value: health.liquidation_debt_value,
+            slot,
+        };
+    }
+
+    pub fn remove_position(&mut self, bank: Pubkey) {
+        self.positions.retain(|key| *key != bank);
+        self.active_positions = self.positions.len() as u8;
+    }
+
+    /// Sets the delegate's allowance in `bank`, replacing any earlier approval
+    pub fn approve_delegate(&mut self, delegate: Pubkey, bank: Pubkey, allowance: u64) -> Result<()> {
+        if let Some(delegation) = self.delegations.iter_mut().find(|d| d.delegate == delegate && d.bank == bank) {
+            delegation.allowance = allowance;
+            return Ok(());
+        }
+        if self.delegations.len() >= MAX_DELEGATES {
+            msg!("User already has {} of {} delegates", self.delegations.len(), MAX_DELEGATES);
+            return Err(ErrorCode::MaxDelegatesReached.into());
+        }
+        self.delegations.push(Delegation { delegate, bank, allowance });
+        Ok(())
+    }
+
+    pub fn revoke_delegate(&mut self, delegate: Pubkey, bank: Pubkey) {
+        self.delegations.retain(|d| d.delegate != delegate || d.bank != bank);
+    }
+
+    /// Spends `amount` of the delegate's allowance in `bank`
+    pub fn spend_allowance(&mut self, delegate: Pubkey, bank: Pubkey, amount: u64) -> Result<()> {
+        let delegation = self
+            .delegations
+            .iter_mut()
+            .find(|d| d.delegate == delegate && d.bank == bank)
+            .ok_or(ErrorCode::Unauthorized)?;
+        if amount > delegation.allowance {
+            msg!("Borrow {} exceeds the delegate's allowance {}", amount, delegation.allowance);
+            return Err(ErrorCode::DelegateAllowanceExceeded.into());
+        }
+        delegation.allowance = delegation.allowance.safe_sub(amount)?;
+        Ok(())
+    }
+}
+
+impl UserPosition {
+    pub fn is_empty(&self) -> bool {
+        self.deposit_shares == 0 && self.borrow_shares == 0 && self.fixed_debt == 0
+    }
+
+    /// Records an interaction at `now` along with the bank's borrow index at that time
+    pub fn touch(&mut self, bank: &Bank, now: i64) {
+        self.borrow_index_snapshot = bank.borrow_index;
+        self.last_updated = now;
+    }
+}
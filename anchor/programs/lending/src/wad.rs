@@ -0,0 +1,217 @@
+use anchor_lang::prelude::*;
+use crate::constants::BPS_DENOMINATOR;
+use crate::error::ErrorCode;
+use crate::safe_math::to_u64;
+
+// Fixed-point numbers scaled by WAD (10^18), stored in a u128. Used where basis point integer
+// math would round small values away: per-second interest growth, share exchange rates and the
+// health factor. Products and quotients fall back to a 256-bit intermediate, so they only fail
+// when the result itself doesn't fit.
+
+pub const WAD: u128 = 1_000_000_000_000_000_000;
+
+/// Basis points as a WAD fraction, 10_000 bps being `WAD`
+pub fn bps_to_wad(bps: u64) -> u128 {
+    bps as u128 * (WAD / BPS_DENOMINATOR as u128)
+}
+
+/// WAD fraction in basis points, rounded down
+pub fn wad_to_bps(value: u128) -> Result<u64> {
+    to_u64(value / (WAD / BPS_DENOMINATOR as u128))
+}
+
+/// `a * b` of two WADs, rounded down
+pub fn wad_mul(a: u128, b: u128) -> Result<u128> {
+    mul_div_wide(a, b, WAD)
+}
+
+/// `a / b` of two WADs, rounded down
+pub fn wad_div(a: u128, b: u128) -> Result<u128> {
+    mul_div_wide(a, WAD, b)
+}
+
+/// `a * b / c` rounded down, with a 256-bit intermediate when `a * b` doesn't fit in a u128
+fn mul_div_wide(a: u128, b: u128, c: u128) -> Result<u128> {
+    if c == 0 {
+        return Err(ErrorCode::MathOverflow.into());
+    }
+    if let Some(product) = a.checked_mul(b) {
+        return Ok(product / c);
+    }
+
+    // Schoolbook product of the 64-bit halves into a 256-bit (high, low) pair
+    const MASK: u128 = u64::MAX as u128;
+    let (a_hi, a_lo) = (a >> 64, a & MASK);
+    let (b_hi, b_lo) = (b >> 64, b & MASK);
+    let lo_lo = a_lo * b_lo;
+    let (hi_lo, lo_hi) = (a_hi * b_lo, a_lo * b_hi);
+    let mid = (lo_lo >> 64) + (hi_lo & MASK) + (lo_hi & MASK);
+    let low = (lo_lo & MASK) | (mid << 64);
+    let high = a_hi * b_hi + (hi_lo >> 64) + (lo_hi >> 64) + (mid >> 64);
+    if high >= c {
+        return Err(ErrorCode::MathOverflow.into());
+    }
+
+    // Long division of the low half into the remainder left by the high half, one bit at a time
+    let mut remainder = high;
+    let mut quotient = 0u128;
+    for bit in (0..128).rev() {
+        let carry = remainder >> 127;
+        remainder = (remainder << 1) | ((low >> bit) & 1);
+        if carry == 1 || remainder >= c {
+            remainder = remainder.wrapping_sub(c);
+            quotient |= 1 << bit;
+        }
+    }
+    Ok(quotient)
+}
+
+/// `base^exp` of a WAD by repeated squaring, rounded down at every step
+pub fn wad_pow(base: u128, exp: u64) -> Result<u128> {
+    let (mut result, mut base, mut exp) = (WAD, base, exp);
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = wad_mul(result, base)?;
+        }
+        exp >>= 1;
+        if exp > 0 {
+            base = wad_mul(base, base)?;
+        }
+    }
+    Ok(result)
+}
+
+/// Whole tokens in a WAD amount and the fraction of a token left over, also in WAD
+pub fn split_wad(value: u128) -> Result<(u64, u128)> {
+    Ok((to_u64(value / WAD)?, value % WAD))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLES: [u128; 12] = [
+        0,
+        1,
+        7,
+        999,
+        WAD / 3,
+        WAD / 2,
+        WAD,
+        WAD + 1,
+        3 * WAD / 2,
+        1_000 * WAD,
+        123_456_789 * WAD + 987_654_321,
+        u64::MAX as u128 * WAD,
+    ];
+
+    fn as_f64(value: u128) -> f64 {
+        value as f64 / WAD as f64
+    }
+
+    fn assert_close(actual: u128, expected: f64) {
+        let actual = as_f64(actual);
+        // f64 carries ~15 significant digits, WAD results are exact to 1e-18
+        let tolerance = expected.abs() * 1e-12 + 1e-17;
+        assert!((actual - expected).abs() <= tolerance, "got {actual}, expected {expected}");
+    }
+
+    #[test]
+    fn mul_matches_f64() {
+        for a in SAMPLES {
+            for b in SAMPLES {
+                let expected = as_f64(a) * as_f64(b);
+                match wad_mul(a, b) {
+                    Ok(product) => assert_close(product, expected),
+                    Err(_) => assert!(expected > u128::MAX as f64 / WAD as f64, "{a} * {b} should fit"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn div_matches_f64() {
+        for a in SAMPLES {
+            for b in SAMPLES {
+                if b == 0 {
+                    assert!(wad_div(a, b).is_err());
+                    continue;
+                }
+                let expected = as_f64(a) / as_f64(b);
+                match wad_div(a, b) {
+                    Ok(quotient) => assert_close(quotient, expected),
+                    Err(_) => assert!(expected > u128::MAX as f64 / WAD as f64, "{a} / {b} should fit"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mul_and_div_round_down() {
+        assert_eq!(wad_mul(1, 1).unwrap(), 0);
+        assert_eq!(wad_mul(WAD / 3, 3 * WAD).unwrap(), WAD - 1);
+        assert_eq!(wad_div(WAD, 3 * WAD).unwrap(), WAD / 3);
+        assert_eq!(wad_div(2 * WAD, 3 * WAD).unwrap(), 2 * WAD / 3);
+    }
+
+    #[test]
+    fn mul_beyond_intermediate() {
+        // The direct product overflows u128, the split one doesn't
+        let amount = u64::MAX as u128 * WAD;
+        assert_eq!(wad_mul(amount, WAD / 2).unwrap(), amount / 2);
+        assert_eq!(wad_mul(WAD / 2, amount).unwrap(), amount / 2);
+        assert_eq!(wad_div(amount, 2 * WAD).unwrap(), amount / 2);
+        assert!(wad_mul(u128::MAX, 2 * WAD).is_err());
+        assert!(wad_div(u128::MAX, WAD / 2).is_err());
+    }
+
+    #[test]
+    fn wide_matches_narrow() {
+        for a in SAMPLES {
+            for b in SAMPLES {
+                for c in [1, 3, WAD, u64::MAX as u128, u128::MAX] {
+                    if let Some(product) = a.checked_mul(b) {
+                        assert_eq!(mul_div_wide(a, b, c).unwrap(), product / c);
+                    }
+                }
+            }
+        }
+        assert_eq!(mul_div_wide(u128::MAX, u128::MAX, u128::MAX).unwrap(), u128::MAX);
+        assert_eq!(mul_div_wide(u128::MAX, 3, 6).unwrap(), u128::MAX / 2);
+        assert!(mul_div_wide(u128::MAX, 2, 1).is_err());
+    }
+
+    #[test]
+    fn bps_round_trip() {
+        for bps in [0, 1, 50, 9_999, 10_000, 65_535] {
+            assert_eq!(wad_to_bps(bps_to_wad(bps)).unwrap(), bps);
+            assert_close(bps_to_wad(bps), bps as f64 / BPS_DENOMINATOR as f64);
+        }
+        assert_eq!(bps_to_wad(BPS_DENOMINATOR), WAD);
+    }
+
+    #[test]
+    fn pow_matches_f64() {
+        for base in [WAD / 2, WAD, WAD + WAD / 1_000_000_000, 3 * WAD / 2] {
+            for exp in [0u64, 1, 2, 7, 365, 31_536_000] {
+                // exp(n * ln(1 + x)) keeps the precision of bases close to one, which powi loses
+                let x = (base as i128 - WAD as i128) as f64 / WAD as f64;
+                let expected = (exp as f64 * x.ln_1p()).exp();
+                if expected > 1e9 {
+                    continue;
+                }
+                let actual = as_f64(wad_pow(base, exp).unwrap());
+                // Rounding down at every squaring drifts by about one unit per step
+                assert!((actual - expected).abs() <= expected * 1e-9 + 1e-15, "{base}^{exp}: got {actual}, expected {expected}");
+            }
+        }
+    }
+
+    #[test]
+    fn split_keeps_fraction() {
+        assert_eq!(split_wad(42 * WAD + 5).unwrap(), (42, 5));
+        assert_eq!(split_wad(WAD - 1).unwrap(), (0, WAD - 1));
+        assert_eq!(split_wad(u64::MAX as u128 * WAD).unwrap(), (u64::MAX, 0));
+        assert!(split_wad((u64::MAX as u128 + 1) * WAD).is_err());
+    }
+}
@@ -60,6 +60,70 @@ describe("Lending Smart Contract Tests", () => {
     return mockPriceFeed.publicKey;
   };
 
+  // `init_bank` params with a plain rate curve, risk fields left at zero fall back to the protocol's defaults
+  const initBankParams = (overrides: Record<string, unknown> = {}) => {
+    const zero = new BN(0);
+    const launchCaps = { slot: zero, walletDepositCap: zero, depositCap: zero, borrowCap: zero };
+    return {
+      liquidationThreshold: zero,
+      maxLtv: zero,
+      liquidationCloseFactor: zero,
+      liquidationBonus: zero,
+      baseRate: new BN(200),
+      slope1: new BN(400),
+      slope2: new BN(7_500),
+      optimalUtilization: new BN(8_000),
+      maxTotalDeposits: zero,
+      maxTotalBorrows: zero,
+      reserveFactor: new BN(1_000),
+      flashLoanFee: zero,
+      maxPriceAgeSecs: zero,
+      maxConfidenceBps: zero,
+      isolated: false,
+      isolatedDebtBank: PublicKey.default,
+      referralFeeBps: zero,
+      minDepositAmount: zero,
+      minBorrowAmount: zero,
+      auctionStartBonus: zero,
+      auctionBonusStep: zero,
+      fixedRatePremium: zero,
+      fixedPenaltyRate: zero,
+      maxFixedTermSlots: zero,
+      allowSelfBorrow: false,
+      withdrawCooldownSecs: zero,
+      liquidationGraceSlots: zero,
+      originationFeeBps: zero,
+      skimOriginationFee: false,
+      priceMode: { raw: {} },
+      maxUtilizationForWithdraw: zero,
+      priceBasis: { spot: {} },
+      emaWindowSecs: zero,
+      bankType: { standard: {} },
+      sameSlotDepositLock: false,
+      minBorrowRate: zero,
+      maxBorrowRate: zero,
+      insuranceFactor: zero,
+      maxInsuranceWithdrawalBps: zero,
+      gate: { open: {} },
+      maxRateChangePerSec: zero,
+      interestMode: { simple: {} },
+      minAccrualIntervalSecs: zero,
+      liquidationProtocolFeeBps: zero,
+      depositFeeBps: zero,
+      withdrawFeeBps: zero,
+      guardedLaunch: { active: false, schedule: [launchCaps, launchCaps, launchCaps] },
+      collateralWeightBps: zero,
+      debtWeightBps: zero,
+      fixedRateRebalanceBps: zero,
+      fixedEarlyRepayFeeBps: zero,
+      emodeCategory: 0,
+      emodeMaxLtv: zero,
+      emodeLiquidationThreshold: zero,
+      riskTier: null,
+      ...overrides,
+    };
+  };
+
   beforeAll(async () => {
     // Generate or load keypair
     signer = Keypair.generate();
@@ -139,7 +203,7 @@ describe("Lending Smart Contract Tests", () => {
 
   it("Test Init User", async () => {
     const initUserTx = await program.methods
-      .initUser(usdcMint)
+      .initUser(null, 0)
       .accounts({
         signer: signer.publicKey,
       })
@@ -151,7 +215,7 @@ describe("Lending Smart Contract Tests", () => {
 
   it("Test Init and Fund USDC Bank", async () => {
     const initUSDCBankTx = await program.methods
-      .initBank(new BN(1), new BN(1))
+      .initBank(initBankParams())
       .accounts({
         signer: signer.publicKey,
         mint: usdcMint,
@@ -178,7 +242,7 @@ describe("Lending Smart Contract Tests", () => {
 
   it("Test Init and Fund SOL Bank", async () => {
     const initSOLBankTx = await program.methods
-      .initBank(new BN(1), new BN(1))
+      .initBank(initBankParams())
       .accounts({
         signer: signer.publicKey,
         mint: solMint,